@@ -6,9 +6,9 @@ import subprocess
 import sys
 from statistics import median
 
-FIRST_FRAME_RE = re.compile(r"first_frame_ms=([0-9]+\.[0-9]+)")
+METRIC_RE = re.compile(r"^(first_frame_ms|db_ready_ms)=([0-9]+\.[0-9]+)$", re.MULTILINE)
 
-def run_once(bin_path: str) -> float:
+def run_once(bin_path: str) -> dict:
     env = os.environ.copy()
     env["RALPH_STARTUP_BENCH"] = "1"
     result = subprocess.run(
@@ -19,10 +19,10 @@ def run_once(bin_path: str) -> float:
         text=True,
         timeout=15,
     )
-    match = FIRST_FRAME_RE.search(result.stdout)
-    if not match:
-        raise RuntimeError(f"missing first_frame_ms in output:\n{result.stdout}")
-    return float(match.group(1))
+    metrics = {name: float(value) for name, value in METRIC_RE.findall(result.stdout)}
+    if "first_frame_ms" not in metrics or "db_ready_ms" not in metrics:
+        raise RuntimeError(f"missing startup metrics in output:\n{result.stdout}")
+    return metrics
 
 
 def percentile(sorted_values, pct: float) -> float:
@@ -38,16 +38,20 @@ def main() -> int:
         runs = int(sys.argv[1])
     subprocess.run(["cargo", "build", "--quiet"], check=True)
     bin_path = os.path.join("target", "debug", "ralph")
-    values = []
+    runs_metrics = []
     for i in range(runs):
-        value = run_once(bin_path)
-        values.append(value)
-        print(f"run {i + 1}/{runs}: {value:.2f} ms")
-    values.sort()
-    p50 = median(values)
-    p95 = percentile(values, 0.95)
-    print(f"p50: {p50:.2f} ms")
-    print(f"p95: {p95:.2f} ms")
+        metrics = run_once(bin_path)
+        runs_metrics.append(metrics)
+        print(
+            f"run {i + 1}/{runs}: first frame {metrics['first_frame_ms']:.2f} ms, "
+            f"db ready {metrics['db_ready_ms']:.2f} ms"
+        )
+    for name in ("first_frame_ms", "db_ready_ms"):
+        values = sorted(metrics[name] for metrics in runs_metrics)
+        p50 = median(values)
+        p95 = percentile(values, 0.95)
+        print(f"{name} p50: {p50:.2f} ms")
+        print(f"{name} p95: {p95:.2f} ms")
     return 0
 
 
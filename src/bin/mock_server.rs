@@ -2,70 +2,53 @@ use std::{
     net::TcpListener,
     sync::{mpsc, Arc, Mutex},
     thread,
-    time::{Duration, SystemTime, UNIX_EPOCH},
+    time::Duration,
 };
 
-use serde::{Deserialize, Serialize};
+use ralph::protocol::{is_supported_protocol, RealtimePayload, PROTOCOL_VERSION};
 use tungstenite::{accept, Message as WsMessage};
 
-#[derive(Serialize, Deserialize)]
-#[serde(tag = "type", rename_all = "snake_case")]
-enum RealtimePayload {
-    Message {
-        author: String,
-        body: String,
-        sent_at: String,
-        channel_id: i64,
-        client_id: Option<String>,
-        #[serde(default, skip_serializing_if = "Vec::is_empty")]
-        attachments: Vec<RealtimeAttachment>,
-    },
-    Auth {
-        token: String,
-        user: String,
-    },
-    Ack {
-        kind: String,
-        detail: String,
-    },
-    Presence {
-        user: String,
-        status: String,
-    },
+fn format_timestamp_utc() -> String {
+    chrono::Utc::now().to_rfc3339_opts(chrono::SecondsFormat::Secs, true)
 }
 
-#[derive(Serialize, Deserialize)]
-struct RealtimeAttachment {
-    file_path: String,
-    file_name: String,
-    file_size: i64,
-    kind: String,
+struct Subscriber {
+    sender: mpsc::Sender<String>,
+    /// Set once the connection authenticates, so we know whom to mark offline.
+    user: Arc<Mutex<Option<String>>>,
 }
 
-fn format_timestamp_utc() -> String {
-    let now = SystemTime::now()
-        .duration_since(UNIX_EPOCH)
-        .unwrap_or_default()
-        .as_secs();
-    let hours = (now / 3600) % 24;
-    let minutes = (now / 60) % 60;
-    format!("{:02}:{:02}", hours, minutes)
-}
+type Subscribers = Arc<Mutex<Vec<Subscriber>>>;
 
-fn broadcast_text(
-    subscribers: &Arc<Mutex<Vec<mpsc::Sender<String>>>>,
-    text: &str,
-) {
+fn broadcast_text(subscribers: &Subscribers, text: &str) {
+    let mut departed = Vec::new();
     if let Ok(mut list) = subscribers.lock() {
-        let mut to_remove = Vec::new();
-        for (idx, sender) in list.iter().enumerate() {
-            if sender.send(text.to_string()).is_err() {
-                to_remove.push(idx);
+        list.retain(|subscriber| {
+            if subscriber.sender.send(text.to_string()).is_ok() {
+                return true;
             }
-        }
-        for idx in to_remove.into_iter().rev() {
-            list.remove(idx);
-        }
+            departed.extend(take_user(&subscriber.user));
+            false
+        });
+    }
+    for user in departed {
+        broadcast_presence(subscribers, user, "offline");
+    }
+}
+
+/// Clears the connection's user so only the first of the read loop or a failed
+/// broadcast announces it as offline.
+fn take_user(slot: &Arc<Mutex<Option<String>>>) -> Option<String> {
+    slot.lock().ok().and_then(|mut user| user.take())
+}
+
+fn broadcast_presence(subscribers: &Subscribers, user: String, status: &str) {
+    let presence = RealtimePayload::Presence {
+        user,
+        status: status.to_string(),
+    };
+    if let Ok(payload) = serde_json::to_string(&presence) {
+        broadcast_text(subscribers, &payload);
     }
 }
 
@@ -79,7 +62,7 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
     let listener = TcpListener::bind("127.0.0.1:9001")?;
     println!("mock server listening on ws://127.0.0.1:9001");
 
-    let subscribers: Arc<Mutex<Vec<mpsc::Sender<String>>>> = Arc::new(Mutex::new(Vec::new()));
+    let subscribers: Subscribers = Arc::new(Mutex::new(Vec::new()));
 
     for stream in listener.incoming() {
         let stream = stream?;
@@ -87,7 +70,11 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
         let _ = socket.get_mut().set_nonblocking(true);
 
         let (tx, rx) = mpsc::channel::<String>();
-        subscribers.lock().expect("subscribers").push(tx);
+        let user_slot = Arc::new(Mutex::new(None));
+        subscribers.lock().expect("subscribers").push(Subscriber {
+            sender: tx,
+            user: Arc::clone(&user_slot),
+        });
         let subscribers = Arc::clone(&subscribers);
 
         let welcome = RealtimePayload::Message {
@@ -97,62 +84,102 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
             channel_id: 1,
             client_id: None,
             attachments: Vec::new(),
+            seq: None,
         };
         send_payload(&mut socket, &welcome);
 
-        thread::spawn(move || loop {
-            match socket.read() {
-                Ok(msg) => {
-                    if let WsMessage::Text(text) = msg {
-                        match serde_json::from_str::<RealtimePayload>(&text) {
-                            Ok(RealtimePayload::Message { author, channel_id, .. }) => {
-                                let ack = RealtimePayload::Ack {
-                                    kind: "message".to_string(),
-                                    detail: format!("stored for {author} in channel {channel_id}"),
-                                };
-                                send_payload(&mut socket, &ack);
-                                broadcast_text(&subscribers, &text);
-                            }
-                            Ok(RealtimePayload::Auth { user, .. }) => {
-                                let ack = RealtimePayload::Ack {
-                                    kind: "auth".to_string(),
-                                    detail: format!("welcome {user}"),
-                                };
-                                send_payload(&mut socket, &ack);
-                                let presence = RealtimePayload::Presence {
-                                    user,
-                                    status: "online".to_string(),
-                                };
-                                if let Ok(payload) = serde_json::to_string(&presence) {
-                                    broadcast_text(&subscribers, &payload);
+        thread::spawn(move || {
+            'connection: loop {
+                match socket.read() {
+                    Ok(msg) => {
+                        if let WsMessage::Text(text) = msg {
+                            match serde_json::from_str::<RealtimePayload>(&text) {
+                                Ok(RealtimePayload::Message {
+                                    author,
+                                    channel_id,
+                                    client_id,
+                                    seq,
+                                    ..
+                                }) => {
+                                    let ack = RealtimePayload::Ack {
+                                        kind: "message".to_string(),
+                                        detail: format!("stored for {author} in channel {channel_id}"),
+                                        client_id,
+                                        channel_id: Some(channel_id),
+                                        seq,
+                                    };
+                                    send_payload(&mut socket, &ack);
+                                    broadcast_text(&subscribers, &text);
+                                }
+                                Ok(RealtimePayload::Auth {
+                                    protocol_version, ..
+                                }) if !is_supported_protocol(protocol_version) => {
+                                    let refusal = RealtimePayload::AuthFailed {
+                                        reason: format!(
+                                            "protocol version {protocol_version} is not \
+                                             supported; this server speaks version \
+                                             {PROTOCOL_VERSION}"
+                                        ),
+                                        protocol_version: PROTOCOL_VERSION,
+                                    };
+                                    send_payload(&mut socket, &refusal);
+                                    let _ = socket.close(None);
+                                    let _ = socket.flush();
+                                    break 'connection;
+                                }
+                                Ok(RealtimePayload::Auth { user, status, .. }) => {
+                                    let ack = RealtimePayload::Ack {
+                                        kind: "auth".to_string(),
+                                        detail: format!("welcome {user}"),
+                                        client_id: None,
+                                        channel_id: None,
+                                        seq: None,
+                                    };
+                                    send_payload(&mut socket, &ack);
+                                    if let Ok(mut slot) = user_slot.lock() {
+                                        *slot = Some(user.clone());
+                                    }
+                                    broadcast_presence(&subscribers, user, &status);
+                                }
+                                Ok(RealtimePayload::Presence { status, .. }) => {
+                                    // Clients only set their own status, e.g. to appear offline.
+                                    let user = user_slot.lock().ok().and_then(|slot| slot.clone());
+                                    if let Some(user) = user {
+                                        broadcast_presence(&subscribers, user, &status);
+                                    }
+                                }
+                                Ok(RealtimePayload::Ack { .. })
+                                | Ok(RealtimePayload::AuthFailed { .. }) => {}
+                                Err(_) => {
+                                    broadcast_text(&subscribers, &text);
                                 }
-                            }
-                            Ok(RealtimePayload::Ack { .. } | RealtimePayload::Presence { .. }) => {}
-                            Err(_) => {
-                                broadcast_text(&subscribers, &text);
                             }
                         }
                     }
-                }
-                Err(err) => {
-                    let io_blocked = matches!(
-                        err,
-                        tungstenite::Error::Io(ref io_err)
-                            if io_err.kind() == std::io::ErrorKind::WouldBlock
-                    );
-                    if !io_blocked {
-                        break;
+                    Err(err) => {
+                        let io_blocked = matches!(
+                            err,
+                            tungstenite::Error::Io(ref io_err)
+                                if io_err.kind() == std::io::ErrorKind::WouldBlock
+                        );
+                        if !io_blocked {
+                            break;
+                        }
                     }
                 }
-            }
 
-            while let Ok(payload) = rx.try_recv() {
-                if socket.send(WsMessage::Text(payload)).is_err() {
-                    return;
+                while let Ok(payload) = rx.try_recv() {
+                    if socket.send(WsMessage::Text(payload)).is_err() {
+                        break 'connection;
+                    }
                 }
+
+                thread::sleep(Duration::from_millis(8));
             }
 
-            thread::sleep(Duration::from_millis(8));
+            if let Some(user) = take_user(&user_slot) {
+                broadcast_presence(&subscribers, user, "offline");
+            }
         });
     }
 
@@ -0,0 +1,1941 @@
+//! SQLite persistence: the schema and its migrations, demo seeding, and every query
+//! the app runs. Functions take a plain `Connection` (or `&mut` when they need a
+//! transaction), so they work the same against a file or an in-memory database.
+
+use std::collections::{HashMap, HashSet};
+
+use rusqlite::{params, params_from_iter, Connection, OptionalExtension};
+
+use crate::model::{
+    compare_message_order, Attachment, Channel, ChannelKind, ChannelState, Message,
+    MessageReaction, PendingAttachment, PresenceChange, SavedMessage, User,
+};
+
+/// Messages loaded when a channel opens.
+pub const MESSAGE_FETCH_LIMIT: i64 = 20;
+/// Search matches loaded per page.
+pub const SEARCH_PAGE_SIZE: i64 = 200;
+
+/// Version of the schema `ensure_schema` builds, recorded in SQLite's `user_version`.
+/// Bump it with any migration that builds without it couldn't safely write around.
+///
+/// 1. Versioning starts.
+/// 2. The `users` table.
+/// 3. `channel_state.read_only`.
+/// 4. The `presence_history` table.
+/// 5. `channel_state.retention_days`.
+pub const SCHEMA_VERSION: i64 = 5;
+
+/// What `checkpoint_wal` did.
+pub enum WalCheckpoint {
+    /// The database isn't in WAL mode, so every commit already lands in the file.
+    NotWal,
+    /// Another connection was reading or writing, so part of the log is still pending.
+    Busy,
+    /// The log was folded into the database file and truncated.
+    Done,
+}
+
+/// Folds the write-ahead log back into the main database file so it can be copied on
+/// its own, as before a backup.
+pub fn checkpoint_wal(conn: &Connection) -> Result<WalCheckpoint, rusqlite::Error> {
+    let mode: String = conn.query_row("PRAGMA journal_mode", [], |row| row.get(0))?;
+    if !mode.eq_ignore_ascii_case("wal") {
+        return Ok(WalCheckpoint::NotWal);
+    }
+    let busy: i64 = conn.query_row("PRAGMA wal_checkpoint(TRUNCATE)", [], |row| row.get(0))?;
+    Ok(if busy == 0 {
+        WalCheckpoint::Done
+    } else {
+        WalCheckpoint::Busy
+    })
+}
+
+/// The schema version recorded in the file; 0 for databases from before versioning.
+pub fn schema_version(conn: &Connection) -> Result<i64, rusqlite::Error> {
+    conn.query_row("PRAGMA user_version", [], |row| row.get(0))
+}
+
+/// Creates and migrates the schema. Callers check `schema_version` first: a file
+/// from a newer build must not be touched.
+pub fn ensure_schema(conn: &Connection) -> Result<(), rusqlite::Error> {
+    let stored_version = schema_version(conn)?;
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS channels (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            name TEXT NOT NULL,
+            kind TEXT NOT NULL
+        )",
+        [],
+    )?;
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS messages (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            author TEXT NOT NULL,
+            body TEXT NOT NULL,
+            sent_at TEXT NOT NULL,
+            channel_id INTEGER NOT NULL,
+            edited_at TEXT,
+            FOREIGN KEY(channel_id) REFERENCES channels(id)
+        )",
+        [],
+    )?;
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS attachments (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            message_id INTEGER NOT NULL,
+            file_path TEXT NOT NULL,
+            file_name TEXT NOT NULL,
+            file_size INTEGER NOT NULL,
+            kind TEXT NOT NULL,
+            FOREIGN KEY(message_id) REFERENCES messages(id)
+        )",
+        [],
+    )?;
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS saved_messages (
+            message_id INTEGER PRIMARY KEY,
+            saved_at TEXT NOT NULL,
+            FOREIGN KEY(message_id) REFERENCES messages(id)
+        )",
+        [],
+    )?;
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS pinned_messages (
+            message_id INTEGER PRIMARY KEY,
+            pinned_at TEXT NOT NULL,
+            pinned_by TEXT NOT NULL,
+            FOREIGN KEY(message_id) REFERENCES messages(id)
+        )",
+        [],
+    )?;
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS message_reactions (
+            message_id INTEGER NOT NULL,
+            emoji TEXT NOT NULL,
+            author TEXT NOT NULL,
+            reacted_at TEXT NOT NULL,
+            PRIMARY KEY (message_id, emoji, author),
+            FOREIGN KEY(message_id) REFERENCES messages(id)
+        )",
+        [],
+    )?;
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS channel_reads (
+            channel_id INTEGER PRIMARY KEY,
+            last_read_message_id INTEGER NOT NULL,
+            FOREIGN KEY(channel_id) REFERENCES channels(id)
+        )",
+        [],
+    )?;
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS settings (
+            key TEXT PRIMARY KEY,
+            value TEXT NOT NULL
+        )",
+        [],
+    )?;
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS message_drafts (
+            channel_id INTEGER PRIMARY KEY,
+            body TEXT NOT NULL,
+            updated_at TEXT NOT NULL,
+            FOREIGN KEY(channel_id) REFERENCES channels(id)
+        )",
+        [],
+    )?;
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS channel_members (
+            channel_id INTEGER NOT NULL,
+            username TEXT NOT NULL,
+            joined INTEGER NOT NULL,
+            updated_at TEXT NOT NULL,
+            PRIMARY KEY (channel_id, username),
+            FOREIGN KEY(channel_id) REFERENCES channels(id)
+        )",
+        [],
+    )?;
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS channel_state (
+            channel_id INTEGER PRIMARY KEY,
+            composer_placeholder TEXT,
+            FOREIGN KEY(channel_id) REFERENCES channels(id)
+        )",
+        [],
+    )?;
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS users (
+            username TEXT PRIMARY KEY COLLATE NOCASE,
+            display_name TEXT,
+            color TEXT,
+            avatar_path TEXT
+        )",
+        [],
+    )?;
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS presence_history (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            username TEXT NOT NULL,
+            status TEXT NOT NULL,
+            changed_at TEXT NOT NULL
+        )",
+        [],
+    )?;
+    add_column_if_missing(conn, "messages", "channel_id", "INTEGER NOT NULL DEFAULT 1")?;
+    add_column_if_missing(conn, "attachments", "file_hash", "TEXT")?;
+    add_column_if_missing(conn, "channels", "topic", "TEXT NOT NULL DEFAULT ''")?;
+    add_column_if_missing(conn, "messages", "edited_at", "TEXT")?;
+    add_column_if_missing(conn, "channels", "archived", "INTEGER NOT NULL DEFAULT 0")?;
+    add_column_if_missing(conn, "channel_state", "announcement", "TEXT")?;
+    add_column_if_missing(conn, "channel_state", "announcement_set_at", "TEXT")?;
+    add_column_if_missing(conn, "channel_state", "announcement_collapsed_at", "TEXT")?;
+    add_column_if_missing(conn, "channel_state", "confirm_send", "INTEGER NOT NULL DEFAULT 0")?;
+    add_column_if_missing(conn, "channel_state", "read_only", "INTEGER NOT NULL DEFAULT 0")?;
+    add_column_if_missing(conn, "channel_state", "retention_days", "INTEGER")?;
+    add_column_if_missing(conn, "messages", "client_id", "TEXT")?;
+    add_column_if_missing(conn, "messages", "client_seq", "INTEGER")?;
+    conn.execute(
+        "CREATE INDEX IF NOT EXISTS messages_by_client ON messages (client_id, client_seq)
+        WHERE client_id IS NOT NULL",
+        [],
+    )?;
+    // Attachment-only messages used to be stored with a stand-in "Attachment" body.
+    // That was gone before versioning started, so only unversioned files can hold
+    // placeholders; in anything newer, "Attachment" is what someone actually typed.
+    if stored_version == 0 {
+        conn.execute(
+            "UPDATE messages SET body = ''
+            WHERE body = 'Attachment'
+              AND id IN (SELECT message_id FROM attachments)",
+            [],
+        )?;
+    }
+    // Everyone who wrote a message before the users table existed gets a row.
+    conn.execute(
+        "INSERT OR IGNORE INTO users (username)
+        SELECT DISTINCT author FROM messages WHERE NOT EXISTS (SELECT 1 FROM users)",
+        [],
+    )?;
+    if stored_version < SCHEMA_VERSION {
+        conn.pragma_update(None, "user_version", SCHEMA_VERSION)?;
+    }
+    Ok(())
+}
+
+fn add_column_if_missing(
+    conn: &Connection,
+    table: &str,
+    column: &str,
+    definition: &str,
+) -> Result<(), rusqlite::Error> {
+    let mut stmt = conn.prepare(&format!("PRAGMA table_info({table})"))?;
+    let columns = stmt.query_map([], |row| row.get::<_, String>(1))?;
+    for existing in columns {
+        if existing? == column {
+            return Ok(());
+        }
+    }
+    conn.execute(
+        &format!("ALTER TABLE {table} ADD COLUMN {column} {definition}"),
+        [],
+    )?;
+    Ok(())
+}
+
+pub fn seed_channels_if_empty(conn: &mut Connection) -> Result<(), rusqlite::Error> {
+    let count: i64 = conn.query_row("SELECT COUNT(*) FROM channels", [], |row| row.get(0))?;
+    if count == 0 {
+        let tx = conn.transaction()?;
+        for (id, name, kind) in seed_channels() {
+            tx.execute(
+                "INSERT INTO channels (id, name, kind) VALUES (?1, ?2, ?3)",
+                params![id, name, kind.as_str()],
+            )?;
+        }
+        tx.commit()?;
+    }
+    Ok(())
+}
+
+pub fn seed_messages_if_empty(conn: &mut Connection) -> Result<(), rusqlite::Error> {
+    let count: i64 = conn.query_row("SELECT COUNT(*) FROM messages", [], |row| row.get(0))?;
+    if count == 0 {
+        let seed = seed_messages();
+        let tx = conn.transaction()?;
+        for message in seed {
+            tx.execute(
+                "INSERT INTO messages (author, body, sent_at, channel_id) VALUES (?1, ?2, ?3, ?4)",
+                params![message.author, message.body, message.sent_at, message.channel_id],
+            )?;
+        }
+        tx.commit()?;
+    }
+    Ok(())
+}
+
+pub fn seed_saved_messages_if_empty(conn: &mut Connection) -> Result<(), rusqlite::Error> {
+    let count: i64 =
+        conn.query_row("SELECT COUNT(*) FROM saved_messages", [], |row| row.get(0))?;
+    if count == 0 {
+        conn.execute(
+            "INSERT OR IGNORE INTO saved_messages (message_id, saved_at)
+            SELECT id, '09:00' FROM messages ORDER BY id ASC LIMIT 2",
+            [],
+        )?;
+    }
+    Ok(())
+}
+
+pub fn seed_pinned_messages_if_empty(conn: &mut Connection) -> Result<(), rusqlite::Error> {
+    let count: i64 =
+        conn.query_row("SELECT COUNT(*) FROM pinned_messages", [], |row| row.get(0))?;
+    if count == 0 {
+        conn.execute(
+            "INSERT OR IGNORE INTO pinned_messages (message_id, pinned_at, pinned_by)
+            SELECT id, '09:12', 'Ava' FROM messages ORDER BY id ASC LIMIT 1",
+            [],
+        )?;
+    }
+    Ok(())
+}
+
+pub fn seed_reactions_if_empty(conn: &mut Connection) -> Result<(), rusqlite::Error> {
+    let count: i64 =
+        conn.query_row("SELECT COUNT(*) FROM message_reactions", [], |row| row.get(0))?;
+    if count == 0 {
+        let tx = conn.transaction()?;
+        tx.execute(
+            "INSERT OR IGNORE INTO message_reactions (message_id, emoji, author, reacted_at)
+            SELECT id, '👍', 'Ava', '09:05' FROM messages ORDER BY id ASC LIMIT 1",
+            [],
+        )?;
+        tx.execute(
+            "INSERT OR IGNORE INTO message_reactions (message_id, emoji, author, reacted_at)
+            SELECT id, '🎉', 'You' COLLATE NOCASE, '09:06' FROM messages ORDER BY id ASC LIMIT 1",
+            [],
+        )?;
+        tx.execute(
+            "INSERT OR IGNORE INTO message_reactions (message_id, emoji, author, reacted_at)
+            SELECT id, '❤️', 'Noah', '09:08' FROM messages ORDER BY id ASC LIMIT 1 OFFSET 1",
+            [],
+        )?;
+        tx.commit()?;
+    }
+    Ok(())
+}
+
+pub fn insert_channel(
+    conn: &Connection,
+    name: &str,
+    kind: ChannelKind,
+) -> Result<i64, rusqlite::Error> {
+    conn.execute(
+        "INSERT INTO channels (name, kind) VALUES (?1, ?2)",
+        params![name, kind.as_str()],
+    )?;
+    Ok(conn.last_insert_rowid())
+}
+
+/// Finds the direct message channel with `username` (case-insensitively), creating
+/// it when there isn't one. An archived DM is brought back to the sidebar.
+pub fn ensure_dm_channel(
+    conn: &Connection,
+    username: &str,
+) -> Result<Channel, rusqlite::Error> {
+    let existing = conn
+        .query_row(
+            "SELECT id, name, topic FROM channels
+            WHERE kind = ?1 AND name = ?2 COLLATE NOCASE
+            ORDER BY id ASC LIMIT 1",
+            params![ChannelKind::DirectMessage.as_str(), username],
+            |row| Ok((row.get::<_, i64>(0)?, row.get::<_, String>(1)?, row.get(2)?)),
+        )
+        .optional()?;
+    if let Some((id, name, topic)) = existing {
+        set_channel_archived(conn, id, false)?;
+        return Ok(Channel {
+            id,
+            name,
+            kind: ChannelKind::DirectMessage,
+            topic,
+            archived: false,
+        });
+    }
+    let id = insert_channel(conn, username, ChannelKind::DirectMessage)?;
+    Ok(Channel {
+        id,
+        name: username.to_string(),
+        kind: ChannelKind::DirectMessage,
+        topic: String::new(),
+        archived: false,
+    })
+}
+
+pub fn update_channel_topic(
+    conn: &Connection,
+    channel_id: i64,
+    topic: &str,
+) -> Result<(), rusqlite::Error> {
+    conn.execute(
+        "UPDATE channels SET topic = ?2 WHERE id = ?1",
+        params![channel_id, topic],
+    )?;
+    Ok(())
+}
+
+pub fn set_channel_archived(
+    conn: &Connection,
+    channel_id: i64,
+    archived: bool,
+) -> Result<(), rusqlite::Error> {
+    conn.execute(
+        "UPDATE channels SET archived = ?2 WHERE id = ?1",
+        params![channel_id, archived],
+    )?;
+    Ok(())
+}
+
+/// Tables keyed by message id. Foreign keys aren't enforced on our connections, so
+/// deleting messages has to clear these by hand.
+const MESSAGE_CHILD_TABLES: [&str; 4] = [
+    "attachments",
+    "saved_messages",
+    "pinned_messages",
+    "message_reactions",
+];
+
+/// Deletes the messages picked by `selector` (a query returning message ids) along
+/// with their attachments, saves, pins, and reactions. Returns the messages removed.
+fn delete_messages_where(
+    tx: &rusqlite::Transaction,
+    selector: &str,
+    args: &[&dyn rusqlite::ToSql],
+) -> Result<usize, rusqlite::Error> {
+    for table in MESSAGE_CHILD_TABLES {
+        tx.execute(
+            &format!("DELETE FROM {table} WHERE message_id IN ({selector})"),
+            args,
+        )?;
+    }
+    tx.execute(&format!("DELETE FROM messages WHERE id IN ({selector})"), args)
+}
+
+/// Removes a channel and everything that hangs off it in one transaction, so a
+/// failure part-way leaves the channel intact rather than its messages orphaned.
+pub fn delete_channel(
+    conn: &mut Connection,
+    channel_id: i64,
+) -> Result<(), rusqlite::Error> {
+    let tx = conn.transaction()?;
+    delete_messages_where(
+        &tx,
+        "SELECT id FROM messages WHERE channel_id = ?1",
+        &[&channel_id],
+    )?;
+    for table in ["channel_reads", "message_drafts", "channel_state", "channel_members"] {
+        tx.execute(
+            &format!("DELETE FROM {table} WHERE channel_id = ?1"),
+            params![channel_id],
+        )?;
+    }
+    tx.execute("DELETE FROM channels WHERE id = ?1", params![channel_id])?;
+    tx.commit()
+}
+
+/// Deletes the given messages with everything hanging off them in one transaction.
+pub fn delete_messages_by_id(
+    conn: &mut Connection,
+    message_ids: &[i64],
+) -> Result<(), rusqlite::Error> {
+    if message_ids.is_empty() {
+        return Ok(());
+    }
+    let placeholders = vec!["?"; message_ids.len()].join(", ");
+    let args: Vec<&dyn rusqlite::ToSql> =
+        message_ids.iter().map(|id| id as &dyn rusqlite::ToSql).collect();
+    let tx = conn.transaction()?;
+    delete_messages_where(
+        &tx,
+        &format!("SELECT id FROM messages WHERE id IN ({placeholders})"),
+        &args,
+    )?;
+    tx.commit()
+}
+
+/// Deletes every message in a channel, with everything hanging off them, and resets
+/// its read position, all in one transaction. Returns the removed message ids and
+/// attachment paths so the caller can update its state and clean up files.
+pub fn clear_channel_messages(
+    conn: &mut Connection,
+    channel_id: i64,
+) -> Result<(Vec<i64>, Vec<String>), rusqlite::Error> {
+    let tx = conn.transaction()?;
+    let message_ids = {
+        let mut stmt = tx.prepare("SELECT id FROM messages WHERE channel_id = ?1")?;
+        let rows = stmt.query_map(params![channel_id], |row| row.get(0))?;
+        rows.collect::<Result<Vec<i64>, _>>()?
+    };
+    let attachment_paths = {
+        let mut stmt = tx.prepare(
+            "SELECT DISTINCT a.file_path
+            FROM attachments a
+            JOIN messages m ON m.id = a.message_id
+            WHERE m.channel_id = ?1",
+        )?;
+        let rows = stmt.query_map(params![channel_id], |row| row.get(0))?;
+        rows.collect::<Result<Vec<String>, _>>()?
+    };
+    delete_messages_where(
+        &tx,
+        "SELECT id FROM messages WHERE channel_id = ?1",
+        &[&channel_id],
+    )?;
+    tx.execute("DELETE FROM channel_reads WHERE channel_id = ?1", params![channel_id])?;
+    tx.commit()?;
+    Ok((message_ids, attachment_paths))
+}
+
+const ORPHANED_MESSAGES: &str =
+    "SELECT id FROM messages WHERE channel_id NOT IN (SELECT id FROM channels)";
+
+pub fn count_orphaned_messages(conn: &Connection) -> Result<i64, rusqlite::Error> {
+    conn.query_row(
+        &format!("SELECT COUNT(*) FROM ({ORPHANED_MESSAGES})"),
+        [],
+        |row| row.get(0),
+    )
+}
+
+pub fn delete_orphaned_messages(conn: &mut Connection) -> Result<usize, rusqlite::Error> {
+    let tx = conn.transaction()?;
+    let removed = delete_messages_where(&tx, ORPHANED_MESSAGES, &[])?;
+    tx.commit()?;
+    Ok(removed)
+}
+
+/// Messages in channels with a retention policy that are older than it allows. Only
+/// full timestamps count; a bare "09:15" would otherwise read as a day in 2000.
+const EXPIRED_MESSAGES: &str = "SELECT m.id
+    FROM messages m
+    JOIN channel_state s ON s.channel_id = m.channel_id
+    WHERE s.retention_days IS NOT NULL
+      AND m.sent_at GLOB '[0-9][0-9][0-9][0-9]-[0-9][0-9]-[0-9][0-9]*'
+      AND julianday(m.sent_at) < julianday('now', '-' || s.retention_days || ' days')
+    ORDER BY m.id
+    LIMIT ?1";
+
+/// Deletes up to `batch` messages that have outlived their channel's retention
+/// policy, with everything hanging off them, in one short transaction. Returns the
+/// removed message ids and attachment paths; fewer than `batch` ids means the sweep
+/// is done.
+pub fn enforce_retention(
+    conn: &mut Connection,
+    batch: usize,
+) -> Result<(Vec<i64>, Vec<String>), rusqlite::Error> {
+    let tx = conn.transaction()?;
+    let message_ids = {
+        let mut stmt = tx.prepare(EXPIRED_MESSAGES)?;
+        let rows = stmt.query_map(params![batch as i64], |row| row.get(0))?;
+        rows.collect::<Result<Vec<i64>, _>>()?
+    };
+    if message_ids.is_empty() {
+        return Ok((Vec::new(), Vec::new()));
+    }
+    let placeholders = vec!["?"; message_ids.len()].join(", ");
+    let args: Vec<&dyn rusqlite::ToSql> =
+        message_ids.iter().map(|id| id as &dyn rusqlite::ToSql).collect();
+    let attachment_paths = {
+        let mut stmt = tx.prepare(&format!(
+            "SELECT DISTINCT file_path FROM attachments WHERE message_id IN ({placeholders})"
+        ))?;
+        let rows = stmt.query_map(args.as_slice(), |row| row.get(0))?;
+        rows.collect::<Result<Vec<String>, _>>()?
+    };
+    delete_messages_where(
+        &tx,
+        &format!("SELECT id FROM messages WHERE id IN ({placeholders})"),
+        &args,
+    )?;
+    tx.commit()?;
+    Ok((message_ids, attachment_paths))
+}
+
+pub fn load_channel_state(
+    conn: &Connection,
+) -> Result<HashMap<i64, ChannelState>, rusqlite::Error> {
+    let mut stmt = conn.prepare(
+        "SELECT channel_id, composer_placeholder, announcement, announcement_set_at,
+            announcement_collapsed_at, confirm_send, read_only, retention_days
+        FROM channel_state",
+    )?;
+    let rows = stmt.query_map([], |row| {
+        Ok((
+            row.get::<_, i64>(0)?,
+            ChannelState {
+                composer_placeholder: row.get(1)?,
+                announcement: row.get(2)?,
+                announcement_set_at: row.get(3)?,
+                announcement_collapsed_at: row.get(4)?,
+                confirm_send: row.get(5)?,
+                read_only: row.get(6)?,
+                retention_days: row.get(7)?,
+            },
+        ))
+    })?;
+    let mut state = HashMap::new();
+    for row in rows {
+        let (channel_id, channel_state) = row?;
+        state.insert(channel_id, channel_state);
+    }
+    Ok(state)
+}
+
+pub fn save_composer_placeholder(
+    conn: &Connection,
+    channel_id: i64,
+    placeholder: Option<&str>,
+) -> Result<(), rusqlite::Error> {
+    conn.execute(
+        "INSERT INTO channel_state (channel_id, composer_placeholder) VALUES (?1, ?2)
+        ON CONFLICT(channel_id) DO UPDATE SET composer_placeholder = excluded.composer_placeholder",
+        params![channel_id, placeholder],
+    )?;
+    Ok(())
+}
+
+pub fn save_confirm_send(
+    conn: &Connection,
+    channel_id: i64,
+    confirm: bool,
+) -> Result<(), rusqlite::Error> {
+    conn.execute(
+        "INSERT INTO channel_state (channel_id, confirm_send) VALUES (?1, ?2)
+        ON CONFLICT(channel_id) DO UPDATE SET confirm_send = excluded.confirm_send",
+        params![channel_id, confirm],
+    )?;
+    Ok(())
+}
+
+pub fn save_channel_retention(
+    conn: &Connection,
+    channel_id: i64,
+    retention_days: Option<u32>,
+) -> Result<(), rusqlite::Error> {
+    conn.execute(
+        "INSERT INTO channel_state (channel_id, retention_days) VALUES (?1, ?2)
+        ON CONFLICT(channel_id) DO UPDATE SET retention_days = excluded.retention_days",
+        params![channel_id, retention_days],
+    )?;
+    Ok(())
+}
+
+pub fn save_channel_read_only(
+    conn: &Connection,
+    channel_id: i64,
+    read_only: bool,
+) -> Result<(), rusqlite::Error> {
+    conn.execute(
+        "INSERT INTO channel_state (channel_id, read_only) VALUES (?1, ?2)
+        ON CONFLICT(channel_id) DO UPDATE SET read_only = excluded.read_only",
+        params![channel_id, read_only],
+    )?;
+    Ok(())
+}
+
+pub fn set_channel_announcement(
+    conn: &Connection,
+    channel_id: i64,
+    text: &str,
+    set_at: &str,
+) -> Result<(), rusqlite::Error> {
+    conn.execute(
+        "INSERT INTO channel_state (channel_id, announcement, announcement_set_at)
+        VALUES (?1, ?2, ?3)
+        ON CONFLICT(channel_id) DO UPDATE SET announcement = excluded.announcement,
+            announcement_set_at = excluded.announcement_set_at",
+        params![channel_id, text, set_at],
+    )?;
+    Ok(())
+}
+
+pub fn clear_channel_announcement(
+    conn: &Connection,
+    channel_id: i64,
+) -> Result<(), rusqlite::Error> {
+    conn.execute(
+        "UPDATE channel_state
+        SET announcement = NULL, announcement_set_at = NULL, announcement_collapsed_at = NULL
+        WHERE channel_id = ?1",
+        params![channel_id],
+    )?;
+    Ok(())
+}
+
+/// Records the banner as collapsed for the announcement set at `set_at`, or expanded
+/// again when `None`.
+pub fn save_announcement_collapsed(
+    conn: &Connection,
+    channel_id: i64,
+    set_at: Option<&str>,
+) -> Result<(), rusqlite::Error> {
+    conn.execute(
+        "UPDATE channel_state SET announcement_collapsed_at = ?2 WHERE channel_id = ?1",
+        params![channel_id, set_at],
+    )?;
+    Ok(())
+}
+
+pub fn load_settings(conn: &Connection) -> Result<HashMap<String, String>, rusqlite::Error> {
+    let mut stmt = conn.prepare("SELECT key, value FROM settings")?;
+    let rows = stmt.query_map([], |row| Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?)))?;
+    let mut settings = HashMap::new();
+    for row in rows {
+        let (key, value) = row?;
+        settings.insert(key, value);
+    }
+    Ok(settings)
+}
+
+pub fn save_setting(
+    conn: &Connection,
+    key: &str,
+    value: &str,
+) -> Result<(), rusqlite::Error> {
+    conn.execute(
+        "INSERT INTO settings (key, value) VALUES (?1, ?2)
+        ON CONFLICT(key) DO UPDATE SET value = excluded.value",
+        params![key, value],
+    )?;
+    Ok(())
+}
+
+pub fn save_onboarding(
+    conn: &mut Connection,
+    username: &str,
+    server_url: &str,
+    channel_name: &str,
+) -> Result<i64, rusqlite::Error> {
+    let tx = conn.transaction()?;
+    save_setting(&tx, "username", username)?;
+    save_setting(&tx, "server_url", server_url)?;
+    let channel_id = insert_channel(&tx, channel_name, ChannelKind::Channel)?;
+    tx.commit()?;
+    Ok(channel_id)
+}
+
+/// Adds `user`, or fills in the fields it sets on an existing row; fields left
+/// `None` keep their stored value, so recording a bare username is harmless.
+pub fn upsert_user(conn: &Connection, user: &User) -> Result<(), rusqlite::Error> {
+    conn.execute(
+        "INSERT INTO users (username, display_name, color, avatar_path)
+        VALUES (?1, ?2, ?3, ?4)
+        ON CONFLICT(username) DO UPDATE SET
+            display_name = COALESCE(excluded.display_name, display_name),
+            color = COALESCE(excluded.color, color),
+            avatar_path = COALESCE(excluded.avatar_path, avatar_path)",
+        params![user.username, user.display_name, user.color, user.avatar_path],
+    )?;
+    Ok(())
+}
+
+pub fn load_users(conn: &Connection) -> Result<Vec<User>, rusqlite::Error> {
+    let mut stmt =
+        conn.prepare("SELECT username, display_name, color, avatar_path FROM users")?;
+    let rows = stmt.query_map([], |row| {
+        Ok(User {
+            username: row.get(0)?,
+            display_name: row.get(1)?,
+            color: row.get(2)?,
+            avatar_path: row.get(3)?,
+        })
+    })?;
+    rows.collect()
+}
+
+/// Records that `username` went to `status`, dropping the oldest entries beyond `keep`.
+pub fn record_presence_change(
+    conn: &Connection,
+    username: &str,
+    status: &str,
+    changed_at: &str,
+    keep: i64,
+) -> Result<(), rusqlite::Error> {
+    conn.execute(
+        "INSERT INTO presence_history (username, status, changed_at) VALUES (?1, ?2, ?3)",
+        params![username, status, changed_at],
+    )?;
+    conn.execute(
+        "DELETE FROM presence_history
+        WHERE id <= (SELECT id FROM presence_history ORDER BY id DESC LIMIT 1 OFFSET ?1)",
+        params![keep],
+    )?;
+    Ok(())
+}
+
+/// The newest presence changes, newest first, for usernames containing `name` and,
+/// when given, only changes to `status`.
+pub fn load_presence_history(
+    conn: &Connection,
+    name: &str,
+    status: Option<&str>,
+    limit: i64,
+) -> Result<Vec<PresenceChange>, rusqlite::Error> {
+    let mut stmt = conn.prepare(
+        "SELECT username, status, changed_at
+        FROM presence_history
+        WHERE instr(lower(username), lower(?1)) > 0 AND (?2 IS NULL OR status = ?2)
+        ORDER BY id DESC
+        LIMIT ?3",
+    )?;
+    let rows = stmt.query_map(params![name, status, limit], |row| {
+        Ok(PresenceChange {
+            username: row.get(0)?,
+            status: row.get(1)?,
+            changed_at: row.get(2)?,
+        })
+    })?;
+    rows.collect()
+}
+
+pub fn load_channels(conn: &Connection) -> Result<Vec<Channel>, rusqlite::Error> {
+    let mut stmt =
+        conn.prepare("SELECT id, name, kind, topic, archived FROM channels ORDER BY id ASC")?;
+    let rows = stmt.query_map([], |row| {
+        Ok(Channel {
+            id: row.get(0)?,
+            name: row.get(1)?,
+            kind: ChannelKind::parse(&row.get::<_, String>(2)?),
+            topic: row.get(3)?,
+            archived: row.get(4)?,
+        })
+    })?;
+
+    let mut channels = Vec::new();
+    for channel in rows {
+        channels.push(channel?);
+    }
+    Ok(channels)
+}
+
+pub fn load_messages(
+    conn: &Connection,
+    channel_id: i64,
+) -> Result<Vec<Message>, rusqlite::Error> {
+    load_messages_with_limit(conn, channel_id, MESSAGE_FETCH_LIMIT)
+}
+
+pub fn load_messages_with_limit(
+    conn: &Connection,
+    channel_id: i64,
+    limit: i64,
+) -> Result<Vec<Message>, rusqlite::Error> {
+    let mut stmt = conn.prepare(
+        "SELECT id, author, body, sent_at, channel_id, edited_at
+        FROM messages
+        WHERE channel_id = ?1
+        ORDER BY id DESC
+        LIMIT ?2",
+    )?;
+    let rows = stmt.query_map(params![channel_id, limit], |row| {
+        Ok(Message {
+            id: row.get(0)?,
+            author: row.get(1)?,
+            body: row.get(2)?,
+            sent_at: row.get(3)?,
+            channel_id: row.get(4)?,
+            edited_at: row.get(5)?,
+        })
+    })?;
+
+    let mut messages = Vec::new();
+    for message in rows {
+        messages.push(message?);
+    }
+    // The window is the newest rows by id; within it, display order is by send time.
+    messages.sort_by(compare_message_order);
+    Ok(messages)
+}
+
+/// The `limit` messages just before `before_id` in a channel, for paging history in
+/// above the loaded window.
+pub fn load_older_messages(
+    conn: &Connection,
+    channel_id: i64,
+    before_id: i64,
+    limit: i64,
+) -> Result<Vec<Message>, rusqlite::Error> {
+    load_message_page(
+        conn,
+        "SELECT id, author, body, sent_at, channel_id, edited_at
+        FROM messages
+        WHERE channel_id = ?1 AND id < ?2
+        ORDER BY id DESC
+        LIMIT ?3",
+        params![channel_id, before_id, limit],
+    )
+}
+
+/// The `limit` messages just after `after_id` in a channel, for paging back down
+/// once the newest rows were dropped from the loaded window.
+pub fn load_newer_messages(
+    conn: &Connection,
+    channel_id: i64,
+    after_id: i64,
+    limit: i64,
+) -> Result<Vec<Message>, rusqlite::Error> {
+    load_message_page(
+        conn,
+        "SELECT id, author, body, sent_at, channel_id, edited_at
+        FROM messages
+        WHERE channel_id = ?1 AND id > ?2
+        ORDER BY id ASC
+        LIMIT ?3",
+        params![channel_id, after_id, limit],
+    )
+}
+
+fn load_message_page(
+    conn: &Connection,
+    query: &str,
+    params: impl rusqlite::Params,
+) -> Result<Vec<Message>, rusqlite::Error> {
+    let mut stmt = conn.prepare(query)?;
+    let rows = stmt.query_map(params, |row| {
+        Ok(Message {
+            id: row.get(0)?,
+            author: row.get(1)?,
+            body: row.get(2)?,
+            sent_at: row.get(3)?,
+            channel_id: row.get(4)?,
+            edited_at: row.get(5)?,
+        })
+    })?;
+    let mut messages = Vec::new();
+    for message in rows {
+        messages.push(message?);
+    }
+    messages.sort_by(compare_message_order);
+    Ok(messages)
+}
+
+pub fn load_reactions_for_message_ids(
+    conn: &Connection,
+    message_ids: &[i64],
+) -> Result<HashMap<i64, Vec<MessageReaction>>, rusqlite::Error> {
+    if message_ids.is_empty() {
+        return Ok(HashMap::new());
+    }
+    let placeholders = vec!["?"; message_ids.len()].join(", ");
+    let query = format!(
+        "SELECT message_id, emoji, author
+        FROM message_reactions
+        WHERE message_id IN ({placeholders})
+        ORDER BY reacted_at, rowid"
+    );
+    let mut stmt = conn.prepare(&query)?;
+    let rows = stmt.query_map(params_from_iter(message_ids), |row| {
+        Ok(MessageReaction {
+            message_id: row.get(0)?,
+            emoji: row.get(1)?,
+            author: row.get(2)?,
+        })
+    })?;
+    let mut reactions: HashMap<i64, Vec<MessageReaction>> = HashMap::new();
+    for reaction in rows {
+        let reaction = reaction?;
+        reactions
+            .entry(reaction.message_id)
+            .or_default()
+            .push(reaction);
+    }
+    Ok(reactions)
+}
+
+pub fn insert_message(conn: &Connection, message: &Message) -> Result<i64, rusqlite::Error> {
+    conn.execute(
+        "INSERT INTO messages (author, body, sent_at, channel_id) VALUES (?1, ?2, ?3, ?4)",
+        params![message.author, message.body, message.sent_at, message.channel_id],
+    )?;
+    Ok(conn.last_insert_rowid())
+}
+
+pub fn add_reaction(
+    conn: &Connection,
+    message_id: i64,
+    emoji: &str,
+    author: &str,
+    reacted_at: &str,
+) -> Result<(), rusqlite::Error> {
+    conn.execute(
+        "INSERT OR IGNORE INTO message_reactions (message_id, emoji, author, reacted_at)
+        VALUES (?1, ?2, ?3, ?4)",
+        params![message_id, emoji, author, reacted_at],
+    )?;
+    Ok(())
+}
+
+pub fn remove_reaction(
+    conn: &Connection,
+    message_id: i64,
+    emoji: &str,
+    author: &str,
+) -> Result<(), rusqlite::Error> {
+    conn.execute(
+        "DELETE FROM message_reactions
+        WHERE message_id = ?1 AND emoji = ?2 AND author = ?3 COLLATE NOCASE",
+        params![message_id, emoji, author],
+    )?;
+    Ok(())
+}
+
+pub fn load_channel_members(
+    conn: &Connection,
+    channels: &[Channel],
+) -> Result<HashMap<i64, HashSet<String>>, rusqlite::Error> {
+    let mut members: HashMap<i64, HashSet<String>> = HashMap::new();
+    let mut stmt = conn.prepare(
+        "SELECT messages.channel_id, messages.author
+        FROM messages
+        JOIN channels ON channels.id = messages.channel_id",
+    )?;
+    let rows = stmt.query_map([], |row| Ok((row.get::<_, i64>(0)?, row.get::<_, String>(1)?)))?;
+    for row in rows {
+        let (channel_id, author) = row?;
+        members.entry(channel_id).or_default().insert(author);
+    }
+    // Explicit joins add lurkers who never posted; explicit leaves override having posted.
+    let mut stmt = conn.prepare("SELECT channel_id, username, joined FROM channel_members")?;
+    let rows = stmt.query_map([], |row| {
+        Ok((
+            row.get::<_, i64>(0)?,
+            row.get::<_, String>(1)?,
+            row.get::<_, bool>(2)?,
+        ))
+    })?;
+    for row in rows {
+        let (channel_id, username, joined) = row?;
+        let channel_members = members.entry(channel_id).or_default();
+        if joined {
+            channel_members.insert(username);
+        } else {
+            channel_members.remove(&username);
+        }
+    }
+    for channel in channels {
+        if channel.kind == ChannelKind::DirectMessage {
+            members
+                .entry(channel.id)
+                .or_default()
+                .insert(channel.name.clone());
+        }
+    }
+    Ok(members)
+}
+
+pub fn set_channel_membership(
+    conn: &Connection,
+    channel_id: i64,
+    username: &str,
+    joined: bool,
+    updated_at: &str,
+) -> Result<(), rusqlite::Error> {
+    conn.execute(
+        "INSERT INTO channel_members (channel_id, username, joined, updated_at)
+        VALUES (?1, ?2, ?3, ?4)
+        ON CONFLICT(channel_id, username)
+        DO UPDATE SET joined = excluded.joined, updated_at = excluded.updated_at",
+        params![channel_id, username, joined, updated_at],
+    )?;
+    Ok(())
+}
+
+fn escape_like(input: &str) -> String {
+    input
+        .replace('\\', "\\\\")
+        .replace('%', "\\%")
+        .replace('_', "\\_")
+}
+
+/// Returns up to `SEARCH_PAGE_SIZE` matches, newest first. Pass the id of the last
+/// result already shown as `before_id` to fetch the next page.
+pub fn search_messages(
+    conn: &Connection,
+    query: &str,
+    channel_id: Option<i64>,
+    before_id: Option<i64>,
+) -> Result<Vec<Message>, rusqlite::Error> {
+    let escaped = escape_like(query);
+    let pattern = format!("%{}%", escaped);
+    let mut stmt = conn.prepare(
+        "SELECT id, author, body, sent_at, channel_id, edited_at
+        FROM messages
+        WHERE (?1 IS NULL OR channel_id = ?1)
+          AND (author LIKE ?2 ESCAPE '\\' OR body LIKE ?2 ESCAPE '\\')
+          AND (?3 IS NULL OR id < ?3)
+        ORDER BY id DESC
+        LIMIT ?4",
+    )?;
+    let rows = stmt.query_map(
+        params![channel_id, pattern, before_id, SEARCH_PAGE_SIZE],
+        |row| {
+            Ok(Message {
+                id: row.get(0)?,
+                author: row.get(1)?,
+                body: row.get(2)?,
+                sent_at: row.get(3)?,
+                channel_id: row.get(4)?,
+                edited_at: row.get(5)?,
+            })
+        },
+    )?;
+    let mut messages = Vec::new();
+    for message in rows {
+        messages.push(message?);
+    }
+    Ok(messages)
+}
+
+pub fn count_search_matches(
+    conn: &Connection,
+    query: &str,
+    channel_id: Option<i64>,
+) -> Result<i64, rusqlite::Error> {
+    let escaped = escape_like(query);
+    let pattern = format!("%{}%", escaped);
+    conn.query_row(
+        "SELECT COUNT(*)
+        FROM messages
+        WHERE (?1 IS NULL OR channel_id = ?1)
+          AND (author LIKE ?2 ESCAPE '\\' OR body LIKE ?2 ESCAPE '\\')",
+        params![channel_id, pattern],
+        |row| row.get(0),
+    )
+}
+
+pub fn insert_attachments(
+    conn: &mut Connection,
+    message_id: i64,
+    attachments: &[PendingAttachment],
+) -> Result<(), rusqlite::Error> {
+    let tx = conn.transaction()?;
+    insert_attachment_rows(&tx, message_id, attachments)?;
+    tx.commit()?;
+    Ok(())
+}
+
+/// Inserts `message` and its attachments in one transaction, so a failure leaves
+/// neither behind. Returns the new message id.
+pub fn insert_message_with_attachments(
+    conn: &mut Connection,
+    message: &Message,
+    attachments: &[PendingAttachment],
+) -> Result<i64, rusqlite::Error> {
+    let tx = conn.transaction()?;
+    let message_id = insert_message(&tx, message)?;
+    insert_attachment_rows(&tx, message_id, attachments)?;
+    tx.commit()?;
+    Ok(message_id)
+}
+
+fn insert_attachment_rows(
+    conn: &Connection,
+    message_id: i64,
+    attachments: &[PendingAttachment],
+) -> Result<(), rusqlite::Error> {
+    for attachment in attachments {
+        conn.execute(
+            "INSERT INTO attachments (message_id, file_path, file_name, file_size, kind, file_hash)
+            VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+            params![
+                message_id,
+                attachment.file_path,
+                attachment.file_name,
+                attachment.file_size,
+                attachment.kind,
+                attachment.file_hash
+            ],
+        )?;
+    }
+    Ok(())
+}
+
+/// Stores a message along with the realtime `client_id` and `seq` it was broadcast
+/// under, so a copy of the same broadcast arriving later can be recognized.
+pub fn insert_message_from_client(
+    conn: &mut Connection,
+    message: &Message,
+    attachments: &[PendingAttachment],
+    client_id: &str,
+    seq: u64,
+) -> Result<i64, rusqlite::Error> {
+    let tx = conn.transaction()?;
+    tx.execute(
+        "INSERT INTO messages (author, body, sent_at, channel_id, client_id, client_seq)
+        VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+        params![
+            message.author,
+            message.body,
+            message.sent_at,
+            message.channel_id,
+            client_id,
+            seq as i64
+        ],
+    )?;
+    let message_id = tx.last_insert_rowid();
+    insert_attachment_rows(&tx, message_id, attachments)?;
+    tx.commit()?;
+    Ok(message_id)
+}
+
+/// The id of the message already stored from broadcast `client_id`/`seq` in
+/// `channel_id`, as when `ralph send` wrote it here before broadcasting it.
+pub fn find_message_from_client(
+    conn: &Connection,
+    client_id: &str,
+    channel_id: i64,
+    seq: u64,
+) -> Result<Option<i64>, rusqlite::Error> {
+    conn.query_row(
+        "SELECT id FROM messages WHERE client_id = ?1 AND client_seq = ?2 AND channel_id = ?3",
+        params![client_id, seq as i64, channel_id],
+        |row| row.get(0),
+    )
+    .optional()
+}
+
+pub fn load_attachments_for_message_ids(
+    conn: &Connection,
+    message_ids: &[i64],
+) -> Result<HashMap<i64, Vec<Attachment>>, rusqlite::Error> {
+    if message_ids.is_empty() {
+        return Ok(HashMap::new());
+    }
+    let placeholders = vec!["?"; message_ids.len()].join(",");
+    let query = format!(
+        "SELECT message_id, file_path, file_name, file_size, kind, file_hash
+        FROM attachments
+        WHERE message_id IN ({placeholders})
+        ORDER BY id ASC"
+    );
+    let mut stmt = conn.prepare(&query)?;
+    let rows = stmt.query_map(params_from_iter(message_ids.iter().copied()), |row| {
+        Ok(Attachment {
+            message_id: row.get(0)?,
+            file_path: row.get(1)?,
+            file_name: row.get(2)?,
+            file_size: row.get(3)?,
+            kind: row.get(4)?,
+            file_hash: row.get(5)?,
+        })
+    })?;
+    let mut map: HashMap<i64, Vec<Attachment>> = HashMap::new();
+    for attachment in rows {
+        let attachment = attachment?;
+        map.entry(attachment.message_id)
+            .or_default()
+            .push(attachment);
+    }
+    Ok(map)
+}
+
+/// One page of a channel's image and video attachments, newest first, starting below
+/// attachment row `before_id` when given.
+pub fn load_gallery_page(
+    conn: &Connection,
+    channel_id: i64,
+    before_id: Option<i64>,
+    limit: i64,
+) -> Result<Vec<(i64, Attachment)>, rusqlite::Error> {
+    let mut stmt = conn.prepare(
+        "SELECT a.id, a.message_id, a.file_path, a.file_name, a.file_size, a.kind, a.file_hash
+        FROM attachments a
+        JOIN messages m ON m.id = a.message_id
+        WHERE m.channel_id = ?1
+          AND a.kind IN ('image', 'video')
+          AND (?2 IS NULL OR a.id < ?2)
+        ORDER BY a.id DESC
+        LIMIT ?3",
+    )?;
+    let rows = stmt.query_map(params![channel_id, before_id, limit], |row| {
+        Ok((
+            row.get(0)?,
+            Attachment {
+                message_id: row.get(1)?,
+                file_path: row.get(2)?,
+                file_name: row.get(3)?,
+                file_size: row.get(4)?,
+                kind: row.get(5)?,
+                file_hash: row.get(6)?,
+            },
+        ))
+    })?;
+    rows.collect()
+}
+
+pub fn backfill_attachment_hash(
+    conn: &Connection,
+    file_path: &str,
+    file_hash: &str,
+) -> Result<(), rusqlite::Error> {
+    conn.execute(
+        "UPDATE attachments SET file_hash = ?2 WHERE file_path = ?1 AND file_hash IS NULL",
+        params![file_path, file_hash],
+    )?;
+    Ok(())
+}
+
+pub fn load_attachment_paths(conn: &Connection) -> Result<Vec<String>, rusqlite::Error> {
+    let mut stmt = conn.prepare("SELECT DISTINCT file_path FROM attachments")?;
+    let rows = stmt.query_map([], |row| row.get::<_, String>(0))?;
+    let mut paths = Vec::new();
+    for row in rows {
+        paths.push(row?);
+    }
+    Ok(paths)
+}
+
+pub fn load_saved_message_ids(conn: &Connection) -> Result<HashSet<i64>, rusqlite::Error> {
+    let mut stmt = conn.prepare("SELECT message_id FROM saved_messages")?;
+    let rows = stmt.query_map([], |row| row.get::<_, i64>(0))?;
+    let mut saved = HashSet::new();
+    for row in rows {
+        saved.insert(row?);
+    }
+    Ok(saved)
+}
+
+/// Every saved message across channels with when it was saved, newest save first.
+pub fn load_saved_messages(conn: &Connection) -> Result<Vec<SavedMessage>, rusqlite::Error> {
+    let mut stmt = conn.prepare(
+        "SELECT m.id, m.author, m.body, m.sent_at, m.channel_id, m.edited_at, s.saved_at
+        FROM saved_messages s
+        JOIN messages m ON m.id = s.message_id
+        ORDER BY s.saved_at DESC, s.message_id DESC",
+    )?;
+    let rows = stmt.query_map([], |row| {
+        Ok(SavedMessage {
+            message: Message {
+                id: row.get(0)?,
+                author: row.get(1)?,
+                body: row.get(2)?,
+                sent_at: row.get(3)?,
+                channel_id: row.get(4)?,
+                edited_at: row.get(5)?,
+            },
+            saved_at: row.get(6)?,
+        })
+    })?;
+    rows.collect()
+}
+
+pub fn load_pinned_message_ids(conn: &Connection) -> Result<HashSet<i64>, rusqlite::Error> {
+    let mut stmt = conn.prepare("SELECT message_id FROM pinned_messages")?;
+    let rows = stmt.query_map([], |row| row.get::<_, i64>(0))?;
+    let mut pinned = HashSet::new();
+    for row in rows {
+        pinned.insert(row?);
+    }
+    Ok(pinned)
+}
+
+pub fn load_last_read(conn: &Connection) -> Result<HashMap<i64, i64>, rusqlite::Error> {
+    let mut stmt = conn.prepare("SELECT channel_id, last_read_message_id FROM channel_reads")?;
+    let rows = stmt.query_map([], |row| Ok((row.get::<_, i64>(0)?, row.get::<_, i64>(1)?)))?;
+    let mut last_read = HashMap::new();
+    for row in rows {
+        let (channel_id, message_id) = row?;
+        last_read.insert(channel_id, message_id);
+    }
+    Ok(last_read)
+}
+
+/// The newest message id in each channel that has messages.
+pub fn load_latest_message_ids(
+    conn: &Connection,
+) -> Result<HashMap<i64, i64>, rusqlite::Error> {
+    let mut stmt = conn.prepare("SELECT channel_id, MAX(id) FROM messages GROUP BY channel_id")?;
+    let rows = stmt.query_map([], |row| Ok((row.get::<_, i64>(0)?, row.get::<_, i64>(1)?)))?;
+    rows.collect()
+}
+
+pub fn save_last_read(
+    conn: &Connection,
+    channel_id: i64,
+    message_id: i64,
+) -> Result<(), rusqlite::Error> {
+    conn.execute(
+        "INSERT INTO channel_reads (channel_id, last_read_message_id)
+        VALUES (?1, ?2)
+        ON CONFLICT(channel_id)
+        DO UPDATE SET last_read_message_id = excluded.last_read_message_id",
+        params![channel_id, message_id],
+    )?;
+    Ok(())
+}
+
+pub fn count_unread_messages(
+    conn: &Connection,
+    channel_id: i64,
+    last_read_message_id: i64,
+) -> Result<i64, rusqlite::Error> {
+    conn.query_row(
+        "SELECT COUNT(*) FROM messages WHERE channel_id = ?1 AND id > ?2",
+        params![channel_id, last_read_message_id],
+        |row| row.get(0),
+    )
+}
+
+/// Like `count_unread_messages`, minus what `username` wrote: posting to a channel
+/// you aren't viewing doesn't leave it with unread messages of your own.
+pub fn count_unread_from_others(
+    conn: &Connection,
+    channel_id: i64,
+    last_read_message_id: i64,
+    username: &str,
+) -> Result<i64, rusqlite::Error> {
+    conn.query_row(
+        "SELECT COUNT(*) FROM messages
+         WHERE channel_id = ?1 AND id > ?2 AND author <> ?3 COLLATE NOCASE",
+        params![channel_id, last_read_message_id, username],
+        |row| row.get(0),
+    )
+}
+
+pub fn load_drafts(conn: &Connection) -> Result<HashMap<i64, String>, rusqlite::Error> {
+    let mut stmt = conn.prepare("SELECT channel_id, body FROM message_drafts")?;
+    let rows = stmt.query_map([], |row| Ok((row.get::<_, i64>(0)?, row.get::<_, String>(1)?)))?;
+    let mut drafts = HashMap::new();
+    for row in rows {
+        let (channel_id, body) = row?;
+        drafts.insert(channel_id, body);
+    }
+    Ok(drafts)
+}
+
+pub fn save_draft(
+    conn: &Connection,
+    channel_id: i64,
+    body: &str,
+    updated_at: &str,
+) -> Result<(), rusqlite::Error> {
+    conn.execute(
+        "INSERT INTO message_drafts (channel_id, body, updated_at)
+        VALUES (?1, ?2, ?3)
+        ON CONFLICT(channel_id)
+        DO UPDATE SET body = excluded.body, updated_at = excluded.updated_at",
+        params![channel_id, body, updated_at],
+    )?;
+    Ok(())
+}
+
+pub fn delete_draft(conn: &Connection, channel_id: i64) -> Result<(), rusqlite::Error> {
+    conn.execute(
+        "DELETE FROM message_drafts WHERE channel_id = ?1",
+        params![channel_id],
+    )?;
+    Ok(())
+}
+
+pub fn save_message(
+    conn: &Connection,
+    message_id: i64,
+    saved_at: &str,
+) -> Result<(), rusqlite::Error> {
+    conn.execute(
+        "INSERT OR IGNORE INTO saved_messages (message_id, saved_at) VALUES (?1, ?2)",
+        params![message_id, saved_at],
+    )?;
+    Ok(())
+}
+
+pub fn remove_saved_message(
+    conn: &Connection,
+    message_id: i64,
+) -> Result<(), rusqlite::Error> {
+    conn.execute(
+        "DELETE FROM saved_messages WHERE message_id = ?1",
+        params![message_id],
+    )?;
+    Ok(())
+}
+
+pub fn pin_message(
+    conn: &Connection,
+    message_id: i64,
+    pinned_at: &str,
+    pinned_by: &str,
+) -> Result<(), rusqlite::Error> {
+    conn.execute(
+        "INSERT OR IGNORE INTO pinned_messages (message_id, pinned_at, pinned_by)
+        VALUES (?1, ?2, ?3)",
+        params![message_id, pinned_at, pinned_by],
+    )?;
+    Ok(())
+}
+
+pub fn remove_pinned_message(
+    conn: &Connection,
+    message_id: i64,
+) -> Result<(), rusqlite::Error> {
+    conn.execute(
+        "DELETE FROM pinned_messages WHERE message_id = ?1",
+        params![message_id],
+    )?;
+    Ok(())
+}
+
+/// Demo channels: id, name, kind.
+pub fn seed_channels() -> Vec<(i64, &'static str, ChannelKind)> {
+    vec![
+        (1, "general", ChannelKind::Channel),
+        (2, "product", ChannelKind::Channel),
+        (3, "mara", ChannelKind::DirectMessage),
+        (4, "devin", ChannelKind::DirectMessage),
+    ]
+}
+
+pub fn seed_messages() -> Vec<Message> {
+    vec![
+        Message {
+            id: 0,
+            author: "mara".to_string(),
+            body: "Shipping the new hotkey flow now.".to_string(),
+            sent_at: "09:12".to_string(),
+            channel_id: 1,
+            edited_at: None,
+        },
+        Message {
+            id: 0,
+            author: "devin".to_string(),
+            body: "Latency on local echo is <100ms.".to_string(),
+            sent_at: "09:13".to_string(),
+            channel_id: 1,
+            edited_at: None,
+        },
+        Message {
+            id: 0,
+            author: "sasha".to_string(),
+            body: "Message search index warmed on startup.".to_string(),
+            sent_at: "09:15".to_string(),
+            channel_id: 1,
+            edited_at: None,
+        },
+        Message {
+            id: 0,
+            author: "you".to_string(),
+            body: "Feels fast. Let's keep it lean.".to_string(),
+            sent_at: "09:18".to_string(),
+            channel_id: 1,
+            edited_at: None,
+        },
+        Message {
+            id: 0,
+            author: "mara".to_string(),
+            body: "Next: attachments + previews.".to_string(),
+            sent_at: "09:21".to_string(),
+            channel_id: 2,
+            edited_at: None,
+        },
+        Message {
+            id: 0,
+            author: "devin".to_string(),
+            body: "Profiling idle CPU now.".to_string(),
+            sent_at: "09:24".to_string(),
+            channel_id: 2,
+            edited_at: None,
+        },
+        Message {
+            id: 0,
+            author: "mara".to_string(),
+            body: "Can you sanity-check the build flags?".to_string(),
+            sent_at: "09:26".to_string(),
+            channel_id: 3,
+            edited_at: None,
+        },
+        Message {
+            id: 0,
+            author: "devin".to_string(),
+            body: "Want me to share flamegraph results?".to_string(),
+            sent_at: "09:28".to_string(),
+            channel_id: 4,
+            edited_at: None,
+        },
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_db() -> Connection {
+        let conn = Connection::open_in_memory().unwrap();
+        ensure_schema(&conn).unwrap();
+        conn
+    }
+
+    #[test]
+    fn schema_records_its_version() {
+        let conn = test_db();
+        assert_eq!(schema_version(&conn).unwrap(), SCHEMA_VERSION);
+    }
+
+    fn message(channel_id: i64, author: &str, body: &str, sent_at: &str) -> Message {
+        Message {
+            id: 0,
+            author: author.to_string(),
+            body: body.to_string(),
+            sent_at: sent_at.to_string(),
+            channel_id,
+            edited_at: None,
+        }
+    }
+
+    fn attachment(file_name: &str, file_size: i64) -> PendingAttachment {
+        PendingAttachment {
+            file_path: format!("/tmp/{file_name}"),
+            file_name: file_name.to_string(),
+            file_size,
+            kind: "file".to_string(),
+            file_hash: None,
+        }
+    }
+
+    #[test]
+    fn migrates_a_database_from_before_the_added_columns() {
+        let conn = Connection::open_in_memory().unwrap();
+        conn.execute_batch(
+            "CREATE TABLE channels (id INTEGER PRIMARY KEY, name TEXT NOT NULL, kind TEXT NOT NULL);
+            CREATE TABLE messages (
+                id INTEGER PRIMARY KEY, author TEXT NOT NULL, body TEXT NOT NULL,
+                sent_at TEXT NOT NULL
+            );
+            INSERT INTO channels (id, name, kind) VALUES (1, 'general', 'channel');
+            INSERT INTO messages (author, body, sent_at) VALUES ('mara', 'hello', '09:12');",
+        )
+        .unwrap();
+
+        ensure_schema(&conn).unwrap();
+
+        assert_eq!(schema_version(&conn).unwrap(), SCHEMA_VERSION);
+        let channels = load_channels(&conn).unwrap();
+        assert_eq!(channels.len(), 1);
+        assert_eq!(channels[0].topic, "");
+        assert!(!channels[0].archived);
+        let messages = load_messages(&conn, 1).unwrap();
+        assert_eq!(messages.len(), 1);
+        assert_eq!(messages[0].body, "hello");
+        assert_eq!(messages[0].edited_at, None);
+        let users = load_users(&conn).unwrap();
+        assert_eq!(users.len(), 1);
+        assert_eq!(users[0].username, "mara");
+        // Running it again on a current schema changes nothing.
+        ensure_schema(&conn).unwrap();
+        assert_eq!(load_messages(&conn, 1).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn messages_round_trip_in_display_order() {
+        let conn = test_db();
+        let channel_id = insert_channel(&conn, "general", ChannelKind::Channel).unwrap();
+        let other = insert_channel(&conn, "random", ChannelKind::Channel).unwrap();
+        for (author, body, sent_at) in [
+            ("mara", "second", "2024-05-01T12:00:05Z"),
+            ("devin", "first", "2024-05-01T12:00:00Z"),
+            ("mara", "third", "2024-05-01T12:00:09Z"),
+        ] {
+            insert_message(&conn, &message(channel_id, author, body, sent_at)).unwrap();
+        }
+        insert_message(&conn, &message(other, "sasha", "elsewhere", "2024-05-01T12:00:01Z"))
+            .unwrap();
+
+        let bodies: Vec<String> = load_messages(&conn, channel_id)
+            .unwrap()
+            .into_iter()
+            .map(|message| message.body)
+            .collect();
+        assert_eq!(bodies, ["first", "second", "third"]);
+        // A limit keeps the newest rows by id, still shown in send order.
+        let newest = load_messages_with_limit(&conn, channel_id, 2).unwrap();
+        let bodies: Vec<&str> = newest.iter().map(|message| message.body.as_str()).collect();
+        assert_eq!(bodies, ["first", "third"]);
+    }
+
+    #[test]
+    fn search_treats_like_wildcards_literally() {
+        let conn = test_db();
+        let channel_id = insert_channel(&conn, "general", ChannelKind::Channel).unwrap();
+        for body in ["100% done", "100 percent", "snake_case", "snakeXcase", r"C:\temp"] {
+            insert_message(&conn, &message(channel_id, "mara", body, "2024-05-01T12:00:00Z"))
+                .unwrap();
+        }
+        let bodies = |query: &str| -> Vec<String> {
+            search_messages(&conn, query, Some(channel_id), None)
+                .unwrap()
+                .into_iter()
+                .map(|message| message.body)
+                .collect()
+        };
+
+        assert_eq!(bodies("100%"), ["100% done"]);
+        assert_eq!(bodies("snake_case"), ["snake_case"]);
+        assert_eq!(bodies(r"C:\"), [r"C:\temp"]);
+        assert_eq!(count_search_matches(&conn, "100%", Some(channel_id)).unwrap(), 1);
+        assert_eq!(count_search_matches(&conn, "100", None).unwrap(), 2);
+    }
+
+    #[test]
+    fn attachments_load_with_their_messages() {
+        let mut conn = test_db();
+        let channel_id = insert_channel(&conn, "general", ChannelKind::Channel).unwrap();
+        let with_files = insert_message_with_attachments(
+            &mut conn,
+            &message(channel_id, "mara", "", "2024-05-01T12:00:00Z"),
+            &[attachment("a.txt", 3), attachment("b.png", 5)],
+        )
+        .unwrap();
+        let plain = insert_message(
+            &conn,
+            &message(channel_id, "devin", "no files", "2024-05-01T12:00:01Z"),
+        )
+        .unwrap();
+
+        let attachments = load_attachments_for_message_ids(&conn, &[with_files, plain]).unwrap();
+        assert!(!attachments.contains_key(&plain));
+        let names: Vec<&str> = attachments[&with_files]
+            .iter()
+            .map(|attachment| attachment.file_name.as_str())
+            .collect();
+        assert_eq!(names, ["a.txt", "b.png"]);
+        assert!(attachments[&with_files]
+            .iter()
+            .all(|attachment| attachment.message_id == with_files));
+        let mut paths = load_attachment_paths(&conn).unwrap();
+        paths.sort();
+        assert_eq!(paths, ["/tmp/a.txt", "/tmp/b.png"]);
+    }
+
+    #[test]
+    fn placeholder_bodies_are_cleared_only_in_unversioned_files() {
+        let body_of = |conn: &Connection, id: i64| -> String {
+            conn.query_row("SELECT body FROM messages WHERE id = ?1", [id], |row| row.get(0))
+                .unwrap()
+        };
+        let seed = |conn: &mut Connection| {
+            let channel_id = insert_channel(conn, "general", ChannelKind::Channel).unwrap();
+            let placeholder = insert_message_with_attachments(
+                conn,
+                &message(channel_id, "mara", "Attachment", "2024-05-01T12:00:00Z"),
+                &[attachment("a.txt", 3)],
+            )
+            .unwrap();
+            let typed = insert_message(
+                conn,
+                &message(channel_id, "devin", "Attachment", "2024-05-01T12:00:01Z"),
+            )
+            .unwrap();
+            (placeholder, typed)
+        };
+
+        let mut old = test_db();
+        let (placeholder, typed) = seed(&mut old);
+        old.pragma_update(None, "user_version", 0).unwrap();
+        ensure_schema(&old).unwrap();
+        assert_eq!(body_of(&old, placeholder), "");
+        assert_eq!(body_of(&old, typed), "Attachment");
+
+        let mut current = test_db();
+        let (with_files, _) = seed(&mut current);
+        ensure_schema(&current).unwrap();
+        assert_eq!(body_of(&current, with_files), "Attachment");
+    }
+
+    /// Rows in each of `MESSAGE_CHILD_TABLES` that still point at `message_id`.
+    fn child_rows(conn: &Connection, message_id: i64) -> i64 {
+        MESSAGE_CHILD_TABLES
+            .iter()
+            .map(|table| {
+                conn.query_row(
+                    &format!("SELECT COUNT(*) FROM {table} WHERE message_id = ?1"),
+                    [message_id],
+                    |row| row.get::<_, i64>(0),
+                )
+                .unwrap()
+            })
+            .sum()
+    }
+
+    /// Gives `message_id` a save, a pin, and a reaction.
+    fn decorate(conn: &Connection, message_id: i64) {
+        save_message(conn, message_id, "2024-05-01T12:00:00Z").unwrap();
+        pin_message(conn, message_id, "2024-05-01T12:00:00Z", "mara").unwrap();
+        add_reaction(conn, message_id, "👍", "devin", "2024-05-01T12:00:00Z").unwrap();
+    }
+
+    #[test]
+    fn retention_sweeps_expired_messages_in_batches() {
+        let mut conn = test_db();
+        let kept_channel = insert_channel(&conn, "general", ChannelKind::Channel).unwrap();
+        let swept_channel = insert_channel(&conn, "ops", ChannelKind::Channel).unwrap();
+        save_channel_retention(&conn, swept_channel, Some(30)).unwrap();
+        let now = chrono::Utc::now().format("%Y-%m-%dT%H:%M:%SZ").to_string();
+
+        let mut expired = Vec::new();
+        for sent_at in ["2020-01-01T09:00:00Z", "2020-01-02T09:00:00Z", "2020-01-03T09:00:00Z"] {
+            let message_id = insert_message_with_attachments(
+                &mut conn,
+                &message(swept_channel, "mara", "old", sent_at),
+                &[attachment(&format!("{sent_at}.txt"), 3)],
+            )
+            .unwrap();
+            decorate(&conn, message_id);
+            expired.push(message_id);
+        }
+        let recent = insert_message(&conn, &message(swept_channel, "mara", "new", &now)).unwrap();
+        let bare = insert_message(&conn, &message(swept_channel, "mara", "legacy", "09:15"))
+            .unwrap();
+        let unlimited =
+            insert_message(&conn, &message(kept_channel, "mara", "old", "2020-01-01T09:00:00Z"))
+                .unwrap();
+        decorate(&conn, recent);
+
+        let mut removed = Vec::new();
+        let mut paths = Vec::new();
+        let mut batches = 0;
+        loop {
+            let (message_ids, attachment_paths) = enforce_retention(&mut conn, 2).unwrap();
+            batches += 1;
+            let done = message_ids.len() < 2;
+            removed.extend(message_ids);
+            paths.extend(attachment_paths);
+            if done {
+                break;
+            }
+        }
+
+        assert_eq!(batches, 2);
+        assert_eq!(removed, expired);
+        assert_eq!(paths.len(), 3);
+        for message_id in &expired {
+            assert_eq!(child_rows(&conn, *message_id), 0);
+        }
+        let left: Vec<i64> = load_messages(&conn, swept_channel)
+            .unwrap()
+            .into_iter()
+            .map(|message| message.id)
+            .collect();
+        assert_eq!(left.len(), 2);
+        assert!(left.contains(&recent) && left.contains(&bare));
+        assert_eq!(child_rows(&conn, recent), 3);
+        assert_eq!(load_messages(&conn, kept_channel).unwrap()[0].id, unlimited);
+        assert_eq!(enforce_retention(&mut conn, 2).unwrap(), (Vec::new(), Vec::new()));
+    }
+
+    #[test]
+    fn deleting_a_channel_leaves_nothing_referencing_it() {
+        let mut conn = test_db();
+        let doomed = insert_channel(&conn, "ops", ChannelKind::Channel).unwrap();
+        let other = insert_channel(&conn, "general", ChannelKind::Channel).unwrap();
+        let message_id = insert_message_with_attachments(
+            &mut conn,
+            &message(doomed, "mara", "runbook", "2024-05-01T12:00:00Z"),
+            &[attachment("runbook.pdf", 10)],
+        )
+        .unwrap();
+        decorate(&conn, message_id);
+        save_last_read(&conn, doomed, message_id).unwrap();
+        save_draft(&conn, doomed, "half a thought", "2024-05-01T12:01:00Z").unwrap();
+        save_confirm_send(&conn, doomed, true).unwrap();
+        set_channel_membership(&conn, doomed, "mara", true, "2024-05-01T12:00:00Z").unwrap();
+        let survivor =
+            insert_message(&conn, &message(other, "devin", "hi", "2024-05-01T12:00:00Z")).unwrap();
+        decorate(&conn, survivor);
+
+        delete_channel(&mut conn, doomed).unwrap();
+
+        assert!(load_channels(&conn).unwrap().iter().all(|channel| channel.id != doomed));
+        assert!(load_messages(&conn, doomed).unwrap().is_empty());
+        assert_eq!(child_rows(&conn, message_id), 0);
+        for table in ["channel_reads", "message_drafts", "channel_state", "channel_members"] {
+            let rows: i64 = conn
+                .query_row(
+                    &format!("SELECT COUNT(*) FROM {table} WHERE channel_id = ?1"),
+                    [doomed],
+                    |row| row.get(0),
+                )
+                .unwrap();
+            assert_eq!(rows, 0, "{table} still references the channel");
+        }
+        assert_eq!(count_orphaned_messages(&conn).unwrap(), 0);
+        assert_eq!(child_rows(&conn, survivor), 3);
+
+        // Rows left by a delete from before it ran in one transaction are swept up.
+        conn.pragma_update(None, "foreign_keys", false).unwrap();
+        let orphan =
+            insert_message(&conn, &message(999, "sasha", "lost", "2024-05-01T12:00:00Z")).unwrap();
+        decorate(&conn, orphan);
+        conn.pragma_update(None, "foreign_keys", true).unwrap();
+        assert_eq!(count_orphaned_messages(&conn).unwrap(), 1);
+        assert_eq!(delete_orphaned_messages(&mut conn).unwrap(), 1);
+        assert_eq!(count_orphaned_messages(&conn).unwrap(), 0);
+        assert_eq!(child_rows(&conn, orphan), 0);
+        assert_eq!(child_rows(&conn, survivor), 3);
+    }
+
+    #[test]
+    fn broadcast_messages_are_found_by_their_client_id_and_seq() {
+        let mut conn = test_db();
+        let channel_id = insert_channel(&conn, "general", ChannelKind::Channel).unwrap();
+        let other = insert_channel(&conn, "random", ChannelKind::Channel).unwrap();
+        let stored = insert_message_from_client(
+            &mut conn,
+            &message(channel_id, "mara", "build is green", "2024-05-01T12:00:00Z"),
+            &[attachment("report.txt", 4)],
+            "mara-send-1",
+            0,
+        )
+        .unwrap();
+
+        let found = find_message_from_client(&conn, "mara-send-1", channel_id, 0).unwrap();
+        assert_eq!(found, Some(stored));
+        assert_eq!(find_message_from_client(&conn, "mara-send-1", channel_id, 1).unwrap(), None);
+        assert_eq!(find_message_from_client(&conn, "mara-send-1", other, 0).unwrap(), None);
+        assert_eq!(find_message_from_client(&conn, "mara-send-2", channel_id, 0).unwrap(), None);
+        let attachments = load_attachments_for_message_ids(&conn, &[stored]).unwrap();
+        assert_eq!(attachments[&stored].len(), 1);
+    }
+
+    #[test]
+    fn users_round_trip_and_keep_unset_fields() {
+        let conn = test_db();
+        upsert_user(
+            &conn,
+            &User {
+                username: "mara".to_string(),
+                display_name: Some("Mara K".to_string()),
+                color: Some("#aa3366".to_string()),
+                avatar_path: None,
+            },
+        )
+        .unwrap();
+        // A later sighting without a profile leaves the stored one alone, and the
+        // username matches without regard to case.
+        upsert_user(
+            &conn,
+            &User {
+                username: "MARA".to_string(),
+                display_name: None,
+                color: None,
+                avatar_path: Some("avatars/mara.png".to_string()),
+            },
+        )
+        .unwrap();
+
+        let users = load_users(&conn).unwrap();
+        assert_eq!(users.len(), 1);
+        let user = &users[0];
+        assert_eq!(user.username, "mara");
+        assert_eq!(user.display_name.as_deref(), Some("Mara K"));
+        assert_eq!(user.color.as_deref(), Some("#aa3366"));
+        assert_eq!(user.avatar_path.as_deref(), Some("avatars/mara.png"));
+    }
+}
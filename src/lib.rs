@@ -0,0 +1,5 @@
+//! Pieces shared by the desktop client and the mock server.
+
+pub mod db;
+pub mod model;
+pub mod protocol;
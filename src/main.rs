@@ -1,21 +1,57 @@
+mod send;
+
 use std::{
+    borrow::Cow,
     collections::{HashMap, HashSet, VecDeque},
     env,
     fs,
-    path::Path,
+    io::{Read, Write},
+    path::{Path, PathBuf},
     process::Command,
     sync::mpsc,
-    sync::Arc,
+    sync::{Arc, Condvar, Mutex, OnceLock, PoisonError},
     thread,
     time::{Duration, Instant, SystemTime, UNIX_EPOCH},
 };
 
+use chrono::{DateTime, FixedOffset, Local, SecondsFormat, Utc};
 use egui_wgpu::{Renderer, ScreenDescriptor};
 use egui_winit::State as EguiWinitState;
-use image::{imageops::FilterType, GenericImageView, ImageReader};
-use rusqlite::{params, params_from_iter, Connection};
-use serde::{Deserialize, Serialize};
-use tungstenite::{connect, Message as WsMessage};
+use image::{
+    imageops::FilterType, metadata::Orientation, DynamicImage, GenericImageView, ImageDecoder,
+    ImageError, ImageReader,
+};
+use ralph::db::{
+    add_reaction, backfill_attachment_hash, checkpoint_wal, clear_channel_announcement,
+    clear_channel_messages, count_orphaned_messages, count_search_matches, count_unread_from_others,
+    count_unread_messages, delete_channel, delete_draft, delete_messages_by_id,
+    delete_orphaned_messages, enforce_retention, ensure_dm_channel, ensure_schema,
+    find_message_from_client, insert_channel, insert_message_from_client,
+    insert_message_with_attachments,
+    load_attachment_paths, load_attachments_for_message_ids, load_channel_members,
+    load_channel_state, load_channels, load_drafts, load_gallery_page, load_last_read,
+    load_latest_message_ids, load_messages, load_messages_with_limit, load_newer_messages,
+    load_older_messages, load_pinned_message_ids, load_presence_history,
+    load_reactions_for_message_ids, load_saved_message_ids, load_saved_messages, load_settings,
+    load_users, pin_message, record_presence_change, remove_pinned_message, remove_reaction,
+    remove_saved_message, save_announcement_collapsed, save_channel_read_only,
+    save_channel_retention, save_composer_placeholder, save_confirm_send, save_draft,
+    save_last_read, save_message, save_onboarding, save_setting, schema_version, search_messages,
+    seed_channels, seed_channels_if_empty, seed_messages, seed_messages_if_empty,
+    seed_pinned_messages_if_empty, seed_reactions_if_empty, seed_saved_messages_if_empty,
+    set_channel_announcement,
+    set_channel_archived, set_channel_membership, update_channel_topic, upsert_user, WalCheckpoint,
+    SCHEMA_VERSION,
+};
+use ralph::model::{
+    compare_message_order, parse_timestamp, Attachment, Channel, ChannelKind, ChannelState,
+    Message, MessageReaction, PendingAttachment, PresenceChange, SavedMessage, User,
+};
+use ralph::protocol::{RealtimeAttachment, RealtimePayload, PROTOCOL_VERSION};
+use rusqlite::{Connection, OpenFlags};
+use tungstenite::{
+    client::connect_with_config, protocol::WebSocketConfig, Message as WsMessage,
+};
 use url::Url;
 use wgpu::{CompositeAlphaMode, PresentMode, SurfaceError, TextureUsages};
 use winit::{
@@ -25,58 +61,93 @@ use winit::{
     window::{Window, WindowBuilder},
 };
 
-#[derive(Debug, Clone, Copy)]
-enum UserEvent {
-    Wake,
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum LogLevel {
+    Off,
+    Error,
+    Warn,
+    Info,
+    Debug,
 }
 
-#[derive(Clone)]
-struct Message {
-    id: i64,
-    author: String,
-    body: String,
-    sent_at: String,
-    channel_id: i64,
-}
+impl LogLevel {
+    fn parse(value: &str) -> Option<Self> {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "off" => Some(LogLevel::Off),
+            "error" => Some(LogLevel::Error),
+            "warn" => Some(LogLevel::Warn),
+            "info" => Some(LogLevel::Info),
+            "debug" | "trace" => Some(LogLevel::Debug),
+            _ => None,
+        }
+    }
 
-#[derive(Clone)]
-struct MessageReaction {
-    message_id: i64,
-    emoji: String,
-    author: String,
-    reacted_at: String,
+    fn label(self) -> &'static str {
+        match self {
+            LogLevel::Off => "off",
+            LogLevel::Error => "error",
+            LogLevel::Warn => "warn",
+            LogLevel::Info => "info",
+            LogLevel::Debug => "debug",
+        }
+    }
 }
 
-#[derive(Clone, Copy, PartialEq, Eq)]
-enum ChannelKind {
-    Channel,
-    DirectMessage,
+static LOG_LEVEL: OnceLock<LogLevel> = OnceLock::new();
+
+/// Reads the max level from `RUST_LOG` once. Accepts a bare level (`warn`) or
+/// `env_logger`-style directives (`ralph=debug,wgpu=warn`); defaults to info.
+fn log_enabled(level: LogLevel) -> bool {
+    let max = *LOG_LEVEL.get_or_init(|| {
+        let spec = env::var("RUST_LOG").unwrap_or_default();
+        spec.split(',')
+            .filter_map(|directive| match directive.split_once('=') {
+                Some((target, level)) if target.trim() == "ralph" => LogLevel::parse(level),
+                Some(_) => None,
+                None => LogLevel::parse(directive),
+            })
+            .next_back()
+            .unwrap_or(LogLevel::Info)
+    });
+    level != LogLevel::Off && level <= max
 }
 
-impl ChannelKind {
-    fn as_str(self) -> &'static str {
-        match self {
-            ChannelKind::Channel => "channel",
-            ChannelKind::DirectMessage => "dm",
+macro_rules! log_at {
+    ($level:expr, $($arg:tt)*) => {
+        if log_enabled($level) {
+            eprintln!("ralph: [{}] {}", $level.label(), format_args!($($arg)*));
         }
-    }
+    };
+}
 
-    fn from_str(value: &str) -> Self {
-        match value {
-            "channel" => ChannelKind::Channel,
-            "dm" => ChannelKind::DirectMessage,
-            _ => ChannelKind::Channel,
-        }
-    }
+macro_rules! log_error {
+    ($($arg:tt)*) => { log_at!(LogLevel::Error, $($arg)*) };
 }
 
-#[derive(Clone)]
-struct Channel {
-    id: i64,
-    name: String,
-    kind: ChannelKind,
+macro_rules! log_warn {
+    ($($arg:tt)*) => { log_at!(LogLevel::Warn, $($arg)*) };
+}
+
+macro_rules! log_info {
+    ($($arg:tt)*) => { log_at!(LogLevel::Info, $($arg)*) };
+}
+
+#[derive(Debug, Clone, Copy)]
+enum UserEvent {
+    Wake,
+}
+
+/// Running totals for a retention sweep, reported once it finishes. The sweep itself
+/// runs on a worker thread that posts each batch to `receiver`.
+struct RetentionSweep {
+    removed: usize,
+    attachment_paths: Vec<String>,
+    receiver: mpsc::Receiver<Result<RetentionBatch, String>>,
 }
 
+/// Message ids and attachment paths removed by one `enforce_retention` batch.
+type RetentionBatch = (Vec<i64>, Vec<String>);
+
 struct ComposerMeta {
     placeholder: String,
     typing_stub: String,
@@ -108,7 +179,11 @@ enum RealtimeCommand {
         sent_at: String,
         channel_id: i64,
         attachments: Vec<RealtimeAttachment>,
+        seq: u64,
     },
+    /// The status others should see; applies to every later connect too. Broadcasts
+    /// are coalesced so a flapping status doesn't flood the server.
+    SetPresence(PresenceStatus),
 }
 
 struct RealtimeEvent {
@@ -117,6 +192,249 @@ struct RealtimeEvent {
     error: Option<String>,
     inbound: Option<IncomingMessage>,
     presence: Option<PresenceUpdate>,
+    /// One of our own messages the server just acknowledged.
+    acked: Option<AckedMessage>,
+    metrics: ConnectionMetrics,
+}
+
+/// A server ack for one of our messages, as precise as the server made it.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum AckedMessage {
+    /// The server echoed the channel and `seq` of the message it stored.
+    Confirmed(i64, u64),
+    /// A server that doesn't echo them acked a message; it stores in order, so it is
+    /// the oldest one still in flight.
+    Unconfirmed,
+}
+
+/// Traffic counters kept by the realtime worker. They carry across dropped
+/// connections and reconnects, and start over when the user disconnects.
+#[derive(Clone, Copy, Default)]
+struct ConnectionMetrics {
+    messages_sent: u64,
+    messages_received: u64,
+    reconnects: u64,
+    bytes_sent: u64,
+    bytes_received: u64,
+}
+
+impl ConnectionMetrics {
+    fn summary(&self) -> Option<String> {
+        if self.bytes_sent == 0 && self.bytes_received == 0 && self.reconnects == 0 {
+            return None;
+        }
+        Some(format!(
+            "Sent {} ({}) • Received {} ({}) • Reconnects {}",
+            self.messages_sent,
+            format_bytes(self.bytes_sent as i64),
+            self.messages_received,
+            format_bytes(self.bytes_received as i64),
+            self.reconnects
+        ))
+    }
+}
+
+/// How long a dropped connection keeps showing as Connected before the indicator
+/// changes, to ride out reconnect blips.
+const STATUS_DEBOUNCE: Duration = Duration::from_millis(1500);
+
+/// How long a sent message waits for the server's ack before it is resent, and
+/// after the resend, before it is given up on.
+const ACK_TIMEOUT: Duration = Duration::from_secs(10);
+/// How many recent `(client_id, channel_id, seq)` triples are kept to drop repeats.
+const SEEN_SEQUENCE_LIMIT: usize = 4096;
+
+/// Shortest gap between two presence broadcasts; changes inside it wait, and only
+/// the latest is sent.
+const PRESENCE_BROADCAST_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Expired messages deleted per transaction while a retention sweep runs, keeping
+/// each write lock short.
+const RETENTION_BATCH: usize = 200;
+/// How often channels with a retention policy are swept after the startup sweep.
+const RETENTION_SWEEP_INTERVAL: Duration = Duration::from_secs(60 * 60);
+/// Policies offered in channel settings.
+const RETENTION_CHOICES: [(Option<u32>, &str); 6] = [
+    (None, "Forever"),
+    (Some(1), "1 day"),
+    (Some(7), "7 days"),
+    (Some(30), "30 days"),
+    (Some(90), "90 days"),
+    (Some(365), "1 year"),
+];
+
+/// Size at which the realtime event log is rotated to `.1`.
+const REALTIME_LOG_MAX_BYTES: u64 = 512 * 1024;
+/// Rotated realtime event logs kept next to the live one.
+const REALTIME_LOG_KEEP: usize = 3;
+
+/// The realtime event log for a workspace database: `ralph.db` logs to
+/// `ralph.realtime.log` beside it.
+fn realtime_log_path(db_path: &Path) -> PathBuf {
+    db_path.with_extension("realtime.log")
+}
+
+/// Advisory lock marking a workspace database as in use by one running app: a
+/// `ralph.lock` file beside `ralph.db` holding the owner's process id. Dropping it
+/// removes the file.
+struct InstanceLock {
+    /// `None` when the lock file couldn't be written; the app then runs unlocked.
+    path: Option<PathBuf>,
+}
+
+impl InstanceLock {
+    /// Takes the lock for `db_path`, or returns the id of the live process holding
+    /// it. A lock left by a process that has exited is taken over.
+    fn acquire(db_path: &Path) -> Result<Self, u32> {
+        let path = db_path.with_extension("lock");
+        for _ in 0..2 {
+            match fs::OpenOptions::new().write(true).create_new(true).open(&path) {
+                Ok(mut file) => {
+                    let _ = write!(file, "{}", std::process::id());
+                    return Ok(Self { path: Some(path) });
+                }
+                Err(err) if err.kind() == std::io::ErrorKind::AlreadyExists => {
+                    let holder = fs::read_to_string(&path)
+                        .ok()
+                        .and_then(|pid| pid.trim().parse::<u32>().ok());
+                    match holder {
+                        Some(pid) if pid != std::process::id() && process_is_running(pid) => {
+                            return Err(pid);
+                        }
+                        _ => {
+                            log_warn!("removing stale lock {}", path.display());
+                            let _ = fs::remove_file(&path);
+                        }
+                    }
+                }
+                Err(err) => {
+                    // A read-only directory can't hold a lock; don't block startup on it.
+                    log_warn!("could not create lock {}: {err}", path.display());
+                    return Ok(Self { path: None });
+                }
+            }
+        }
+        log_warn!("could not take lock {}", path.display());
+        Ok(Self { path: None })
+    }
+}
+
+impl Drop for InstanceLock {
+    fn drop(&mut self) {
+        if let Some(path) = &self.path {
+            let _ = fs::remove_file(path);
+        }
+    }
+}
+
+fn process_is_running(pid: u32) -> bool {
+    if cfg!(target_os = "linux") {
+        return Path::new(&format!("/proc/{pid}")).exists();
+    }
+    if cfg!(target_os = "windows") {
+        return Command::new("tasklist")
+            .args(["/FI", &format!("PID eq {pid}"), "/NH"])
+            .output()
+            .is_ok_and(|output| String::from_utf8_lossy(&output.stdout).contains(&pid.to_string()));
+    }
+    Command::new("kill")
+        .args(["-0", &pid.to_string()])
+        .output()
+        .is_ok_and(|output| output.status.success())
+}
+
+/// Appends connection events to a size-capped file so intermittent trouble can be
+/// traced after a restart. A failed write is logged once and the file is dropped.
+struct RealtimeEventLog {
+    path: PathBuf,
+    file: Option<fs::File>,
+    written: u64,
+}
+
+impl RealtimeEventLog {
+    fn open(path: PathBuf) -> Self {
+        let written = fs::metadata(&path).map(|meta| meta.len()).unwrap_or(0);
+        let file = match fs::OpenOptions::new().create(true).append(true).open(&path) {
+            Ok(file) => Some(file),
+            Err(err) => {
+                log_warn!("realtime log open error for {}: {err}", path.display());
+                None
+            }
+        };
+        Self {
+            path,
+            file,
+            written,
+        }
+    }
+
+    fn record(&mut self, event: &RealtimeEvent) {
+        let Some(file) = self.file.as_mut() else {
+            return;
+        };
+        let mut line = format!("{} [{}]", format_timestamp_utc(), event.status.label());
+        if let Some(message) = &event.message {
+            line.push(' ');
+            line.push_str(message);
+        }
+        if let Some(error) = &event.error {
+            line.push_str(" error: ");
+            line.push_str(error);
+        }
+        line.push('\n');
+        if let Err(err) = file.write_all(line.as_bytes()) {
+            log_warn!("realtime log write error for {}: {err}", self.path.display());
+            self.file = None;
+            return;
+        }
+        self.written += line.len() as u64;
+        if self.written >= REALTIME_LOG_MAX_BYTES {
+            self.rotate();
+        }
+    }
+
+    /// Shifts `log.1` to `log.2` and so on, dropping the oldest, then starts a fresh file.
+    fn rotate(&mut self) {
+        self.file = None;
+        for index in (1..REALTIME_LOG_KEEP).rev() {
+            let _ = fs::rename(self.rotated_path(index), self.rotated_path(index + 1));
+        }
+        if let Err(err) = fs::rename(&self.path, self.rotated_path(1)) {
+            log_warn!("realtime log rotate error for {}: {err}", self.path.display());
+        }
+        *self = Self::open(std::mem::take(&mut self.path));
+    }
+
+    fn rotated_path(&self, index: usize) -> PathBuf {
+        let mut name = self.path.clone().into_os_string();
+        name.push(format!(".{index}"));
+        PathBuf::from(name)
+    }
+}
+
+/// Where the worker delivers events: always the UI channel, and the event log file
+/// when one is configured. Chat traffic (inbound messages and presence) is not logged.
+/// Every delivery wakes the event loop, so events never wait for the next input.
+struct RealtimeEventSink {
+    tx: mpsc::Sender<RealtimeEvent>,
+    log: Option<RealtimeEventLog>,
+    wake: Option<EventLoopProxy<UserEvent>>,
+}
+
+impl RealtimeEventSink {
+    /// Returns false once the UI has dropped its receiver.
+    fn send(&mut self, event: RealtimeEvent) -> bool {
+        if let Some(log) = self.log.as_mut() {
+            if event.inbound.is_none() && event.presence.is_none() {
+                log.record(&event);
+            }
+        }
+        let delivered = self.tx.send(event).is_ok();
+        if let Some(wake) = self.wake.as_ref().filter(|_| delivered) {
+            let _ = wake.send_event(UserEvent::Wake);
+        }
+        delivered
+    }
 }
 
 struct RealtimeClient {
@@ -124,11 +442,62 @@ struct RealtimeClient {
     last_message: Option<String>,
     last_error: Option<String>,
     target_url: String,
+    identity: Identity,
     cmd_tx: Option<mpsc::Sender<RealtimeCommand>>,
     evt_rx: Option<mpsc::Receiver<RealtimeEvent>>,
     incoming: Vec<IncomingMessage>,
     incoming_presence: Vec<PresenceUpdate>,
-    event_proxy: EventLoopProxy<UserEvent>,
+    outbox: VecDeque<(Message, Vec<RealtimeAttachment>, u64)>,
+    /// Sent messages the server hasn't acked yet, by channel and `seq`.
+    awaiting_ack: HashMap<(i64, u64), AwaitingAck>,
+    /// Local ids of messages that went unacked even after a resend.
+    failed_sends: Vec<i64>,
+    /// Identifies this client's sends on the wire, so receivers can order them by `seq`.
+    client_id: String,
+    /// Next send sequence number per channel, assigned when a message is sent or queued.
+    next_seq: HashMap<i64, u64>,
+    ever_connected: bool,
+    /// When the connection last dropped out of Connected on its own; cleared by an
+    /// explicit disconnect. Drives the debounce in `displayed_status`.
+    left_connected_at: Option<Instant>,
+    metrics: ConnectionMetrics,
+    /// File the worker mirrors its events into, when `RALPH_REALTIME_LOG` is set.
+    event_log: Option<PathBuf>,
+    /// Woken on every worker event; `None` leaves the caller to poll on its own.
+    event_proxy: Option<EventLoopProxy<UserEvent>>,
+    invisible: bool,
+    /// Set once an ack echoes which message it confirms. Servers that don't echo are
+    /// never waited on, since their acks can't be matched to a message.
+    acks_confirmed: bool,
+    /// Recent `(client_id, channel_id, seq)` seen, our own sends included, so a resent
+    /// message is only shown once; oldest first, capped at `SEEN_SEQUENCE_LIMIT`.
+    seen_sequences: VecDeque<(String, i64, u64)>,
+    /// The `seq` each given-up message went out with, reused by its retry.
+    failed_seqs: HashMap<i64, u64>,
+    dispatch_count: u64,
+}
+
+/// A message on the wire whose ack hasn't arrived. The socket accepting a send
+/// doesn't mean the server stored it, so each one gets a deadline.
+struct AwaitingAck {
+    message: Message,
+    attachments: Vec<RealtimeAttachment>,
+    deadline: Instant,
+    resent: bool,
+    /// Counts up with each dispatch, so acks that don't say which message they are for
+    /// can be matched in send order.
+    dispatched: u64,
+}
+
+/// What `RealtimeClient::send_message` did with a message, known at call time.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum SendOutcome {
+    /// Handed to the connected worker; delivery now waits on the server's ack.
+    Dispatched,
+    /// Held while the connection is being made or re-made; goes out once it is up.
+    Queued,
+    /// Held with no connection under way; goes out after the next connect.
+    NotConnected,
 }
 
 #[derive(Clone)]
@@ -137,86 +506,56 @@ struct PresenceUpdate {
     status: String,
 }
 
-#[derive(Clone, Serialize, Deserialize)]
-struct RealtimeAttachment {
-    file_path: String,
-    file_name: String,
-    file_size: i64,
-    kind: String,
-}
-
 struct IncomingMessage {
     message: Message,
     attachments: Vec<RealtimeAttachment>,
+    client_id: Option<String>,
+    seq: Option<u64>,
 }
 
-#[derive(Serialize, Deserialize)]
-#[serde(tag = "type", rename_all = "snake_case")]
-enum RealtimePayload {
-    Message {
-        author: String,
-        body: String,
-        sent_at: String,
-        channel_id: i64,
-        client_id: Option<String>,
-        #[serde(default, skip_serializing_if = "Vec::is_empty")]
-        attachments: Vec<RealtimeAttachment>,
-    },
-    Auth {
-        token: String,
-        user: String,
-    },
-    Ack {
-        kind: String,
-        detail: String,
-    },
-    Presence {
-        user: String,
-        status: String,
-    },
+fn encode_realtime_message(
+    message: &Message,
+    attachments: Vec<RealtimeAttachment>,
+    client_id: &str,
+    seq: u64,
+) -> Result<String, serde_json::Error> {
+    serde_json::to_string(&RealtimePayload::Message {
+        author: message.author.clone(),
+        body: message.body.clone(),
+        sent_at: message.sent_at.clone(),
+        channel_id: message.channel_id,
+        client_id: Some(client_id.to_string()),
+        attachments,
+        seq: Some(seq),
+    })
 }
 
-impl RealtimePayload {
-    fn from_message(message: &Message, attachments: Vec<RealtimeAttachment>) -> Self {
-        Self::Message {
-            author: message.author.clone(),
-            body: message.body.clone(),
-            sent_at: message.sent_at.clone(),
-            channel_id: message.channel_id,
-            client_id: None,
-            attachments,
+/// Puts each sender's messages back in `seq` order within a channel. Messages only
+/// trade places with others from the same `client_id` and channel; everything
+/// without a sequence keeps its position.
+fn order_by_sequence(messages: Vec<IncomingMessage>) -> Vec<IncomingMessage> {
+    let mut groups: HashMap<(String, i64), Vec<usize>> = HashMap::new();
+    for (index, incoming) in messages.iter().enumerate() {
+        if let (Some(client_id), Some(_)) = (incoming.client_id.as_ref(), incoming.seq) {
+            groups
+                .entry((client_id.clone(), incoming.message.channel_id))
+                .or_default()
+                .push(index);
         }
     }
-
-    fn into_message(self) -> Option<IncomingMessage> {
-        match self {
-            RealtimePayload::Message {
-                author,
-                body,
-                sent_at,
-                channel_id,
-                client_id: _,
-                attachments,
-            } => Some(IncomingMessage {
-                message: Message {
-                    id: 0,
-                    author,
-                    body,
-                    sent_at,
-                    channel_id,
-                },
-                attachments,
-            }),
-            _ => None,
+    let mut source: Vec<usize> = (0..messages.len()).collect();
+    for slots in groups.into_values() {
+        let mut order = slots.clone();
+        order.sort_by_key(|&index| messages[index].seq);
+        for (slot, index) in slots.into_iter().zip(order) {
+            source[slot] = index;
         }
     }
-}
-
-fn encode_realtime_message(
-    message: &Message,
-    attachments: Vec<RealtimeAttachment>,
-) -> Result<String, serde_json::Error> {
-    serde_json::to_string(&RealtimePayload::from_message(message, attachments))
+    let mut messages: Vec<Option<IncomingMessage>> = messages.into_iter().map(Some).collect();
+    source
+        .into_iter()
+        .filter_map(|index| messages[index].take())
+        .collect()
 }
 
 fn parse_legacy_message(text: &str) -> Option<IncomingMessage> {
@@ -232,28 +571,66 @@ fn parse_legacy_message(text: &str) -> Option<IncomingMessage> {
             body: body.to_string(),
             sent_at: sent_at.to_string(),
             channel_id,
+            edited_at: None,
         },
         attachments: Vec::new(),
+        client_id: None,
+        seq: None,
     })
 }
 
 enum RealtimeInbound {
     Message(IncomingMessage),
     Presence { user: String, status: String },
+    /// An ack of `kind` ("auth", "message"); `confirms` names the sender's
+    /// `client_id`, channel and `seq` when the server echoed them.
+    Ack {
+        kind: String,
+        signal: String,
+        confirms: Option<(String, i64, u64)>,
+    },
+    /// The server turned down the handshake; the connection is closing.
+    AuthFailed(String),
     Signal(String),
 }
 
 fn decode_realtime_inbound(text: &str) -> Result<RealtimeInbound, String> {
     match serde_json::from_str::<RealtimePayload>(text) {
         Ok(payload) => match payload {
-            RealtimePayload::Message { .. } => Ok(RealtimeInbound::Message(
-                payload
-                    .into_message()
-                    .ok_or_else(|| "unexpected payload".to_string())?,
-            )),
-            RealtimePayload::Ack { kind, detail } => {
-                Ok(RealtimeInbound::Signal(format!("Ack: {kind} ({detail})")))
-            }
+            RealtimePayload::Message {
+                author,
+                body,
+                sent_at,
+                channel_id,
+                client_id,
+                attachments,
+                seq,
+            } => Ok(RealtimeInbound::Message(IncomingMessage {
+                message: Message {
+                    id: 0,
+                    author,
+                    body,
+                    sent_at,
+                    channel_id,
+                    edited_at: None,
+                },
+                attachments,
+                client_id,
+                seq,
+            })),
+            RealtimePayload::Ack {
+                kind,
+                detail,
+                client_id,
+                channel_id,
+                seq,
+            } => Ok(RealtimeInbound::Ack {
+                signal: format!("Ack: {kind} ({detail})"),
+                kind,
+                confirms: client_id.zip(channel_id).zip(seq).map(|((id, channel), seq)| {
+                    (id, channel, seq)
+                }),
+            }),
             RealtimePayload::Presence { user, status } => Ok(RealtimeInbound::Presence {
                 user,
                 status,
@@ -261,6 +638,17 @@ fn decode_realtime_inbound(text: &str) -> Result<RealtimeInbound, String> {
             RealtimePayload::Auth { user, .. } => Ok(RealtimeInbound::Signal(format!(
                 "Auth received for {user}"
             ))),
+            RealtimePayload::AuthFailed {
+                reason,
+                protocol_version,
+            } => Ok(RealtimeInbound::AuthFailed(if protocol_version == PROTOCOL_VERSION {
+                format!("Server refused the connection: {reason}")
+            } else {
+                format!(
+                    "Incompatible server version: it speaks protocol {protocol_version}, \
+                     this app speaks {PROTOCOL_VERSION} ({reason})"
+                )
+            })),
         },
         Err(err) => parse_legacy_message(text)
             .map(RealtimeInbound::Message)
@@ -269,17 +657,43 @@ fn decode_realtime_inbound(text: &str) -> Result<RealtimeInbound, String> {
 }
 
 impl RealtimeClient {
-    fn new(target_url: String, event_proxy: EventLoopProxy<UserEvent>) -> Self {
+    fn new(
+        target_url: String,
+        identity: Identity,
+        event_log: Option<PathBuf>,
+        event_proxy: Option<EventLoopProxy<UserEvent>>,
+        invisible: bool,
+    ) -> Self {
+        let started = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_nanos();
+        let client_id = format!("{}-{started:x}-{:x}", identity.username, std::process::id());
         Self {
             status: RealtimeStatus::Disconnected,
             last_message: None,
             last_error: None,
             target_url,
+            identity,
             cmd_tx: None,
             evt_rx: None,
             incoming: Vec::new(),
             incoming_presence: Vec::new(),
+            outbox: VecDeque::new(),
+            awaiting_ack: HashMap::new(),
+            failed_sends: Vec::new(),
+            client_id,
+            next_seq: HashMap::new(),
+            ever_connected: false,
+            left_connected_at: None,
+            metrics: ConnectionMetrics::default(),
+            event_log,
             event_proxy,
+            invisible,
+            acks_confirmed: false,
+            seen_sequences: VecDeque::new(),
+            failed_seqs: HashMap::new(),
+            dispatch_count: 0,
         }
     }
 
@@ -289,7 +703,19 @@ impl RealtimeClient {
         }
         let (cmd_tx, cmd_rx) = mpsc::channel();
         let (evt_tx, evt_rx) = mpsc::channel();
-        spawn_realtime_worker(cmd_rx, evt_tx, self.target_url.clone(), self.event_proxy.clone());
+        let evt_tx = RealtimeEventSink {
+            tx: evt_tx,
+            log: self.event_log.clone().map(RealtimeEventLog::open),
+            wake: self.event_proxy.clone(),
+        };
+        spawn_realtime_worker(
+            cmd_rx,
+            evt_tx,
+            self.target_url.clone(),
+            self.identity.clone(),
+            self.client_id.clone(),
+            self.invisible,
+        );
         self.cmd_tx = Some(cmd_tx);
         self.evt_rx = Some(evt_rx);
     }
@@ -301,7 +727,17 @@ impl RealtimeClient {
         }
     }
 
+    /// Starts or stops appearing offline, telling the server right away when connected.
+    fn set_invisible(&mut self, invisible: bool) {
+        self.invisible = invisible;
+        if let Some(cmd_tx) = self.cmd_tx.as_ref() {
+            let status = if invisible { PresenceStatus::Offline } else { PresenceStatus::Online };
+            let _ = cmd_tx.send(RealtimeCommand::SetPresence(status));
+        }
+    }
+
     fn disconnect(&mut self) {
+        self.left_connected_at = None;
         if let Some(cmd_tx) = self.cmd_tx.as_ref() {
             let _ = cmd_tx.send(RealtimeCommand::Disconnect);
         } else {
@@ -310,73 +746,322 @@ impl RealtimeClient {
         }
     }
 
-    fn send_message(&self, message: &Message, attachments: Vec<RealtimeAttachment>) {
-        if let Some(cmd_tx) = self.cmd_tx.as_ref() {
-            let _ = cmd_tx.send(RealtimeCommand::SendMessage {
+    fn send_message(
+        &mut self,
+        message: &Message,
+        attachments: Vec<RealtimeAttachment>,
+    ) -> SendOutcome {
+        let seq = match self.failed_seqs.remove(&message.id) {
+            Some(seq) => seq,
+            None => {
+                let next = self.next_seq.entry(message.channel_id).or_insert(0);
+                *next += 1;
+                *next - 1
+            }
+        };
+        if self.status == RealtimeStatus::Connected
+            && self.dispatch_message(message, attachments.clone(), seq)
+        {
+            return SendOutcome::Dispatched;
+        }
+        self.outbox.push_back((message.clone(), attachments, seq));
+        if self.status == RealtimeStatus::Disconnected && self.left_connected_at.is_none() {
+            SendOutcome::NotConnected
+        } else {
+            SendOutcome::Queued
+        }
+    }
+
+    /// Whether `message_id` is held in the outbox waiting for a connection.
+    fn is_queued(&self, message_id: i64) -> bool {
+        self.outbox.iter().any(|(message, _, _)| message.id == message_id)
+    }
+
+    /// Hands a message to the worker; false when there is no worker to take it.
+    fn dispatch_message(
+        &mut self,
+        message: &Message,
+        attachments: Vec<RealtimeAttachment>,
+        seq: u64,
+    ) -> bool {
+        let Some(cmd_tx) = self.cmd_tx.as_ref() else {
+            return false;
+        };
+        let sent = cmd_tx
+            .send(RealtimeCommand::SendMessage {
                 author: message.author.clone(),
                 body: message.body.clone(),
                 sent_at: message.sent_at.clone(),
                 channel_id: message.channel_id,
-                attachments,
-            });
+                attachments: attachments.clone(),
+                seq,
+            })
+            .is_ok();
+        if sent {
+            self.note_sequence(self.client_id.clone(), message.channel_id, seq);
+            self.dispatch_count += 1;
+            self.awaiting_ack.insert(
+                (message.channel_id, seq),
+                AwaitingAck {
+                    message: message.clone(),
+                    attachments,
+                    deadline: Instant::now() + ACK_TIMEOUT,
+                    resent: false,
+                    dispatched: self.dispatch_count,
+                },
+            );
         }
+        sent
     }
 
-    fn poll(&mut self) {
-        if let Some(evt_rx) = self.evt_rx.as_ref() {
-            while let Ok(event) = evt_rx.try_recv() {
-                self.status = event.status;
-                self.last_message = event.message;
-                self.last_error = event.error;
-                if let Some(message) = event.inbound {
-                    self.incoming.push(message);
+    /// Records a message's sequence triple; false when it was already seen, as with a
+    /// resend of something that arrived the first time.
+    fn note_sequence(&mut self, client_id: String, channel_id: i64, seq: u64) -> bool {
+        let key = (client_id, channel_id, seq);
+        if self.seen_sequences.contains(&key) {
+            return false;
+        }
+        if self.seen_sequences.len() >= SEEN_SEQUENCE_LIMIT {
+            self.seen_sequences.pop_front();
+        }
+        self.seen_sequences.push_back(key);
+        true
+    }
+
+    /// Clears the message an ack is for. The first echoed ack shows the server can be
+    /// held to deadlines, so those start running from then on.
+    fn apply_ack(&mut self, acked: AckedMessage) {
+        match acked {
+            AckedMessage::Confirmed(channel_id, seq) => {
+                self.awaiting_ack.remove(&(channel_id, seq));
+                if !self.acks_confirmed {
+                    self.acks_confirmed = true;
+                    let deadline = Instant::now() + ACK_TIMEOUT;
+                    for awaiting in self.awaiting_ack.values_mut() {
+                        awaiting.deadline = deadline;
+                    }
                 }
-                if let Some(presence) = event.presence {
-                    self.incoming_presence.push(presence);
+            }
+            AckedMessage::Unconfirmed => {
+                let oldest = self
+                    .awaiting_ack
+                    .iter()
+                    .min_by_key(|(_, awaiting)| awaiting.dispatched)
+                    .map(|(key, _)| *key);
+                if let Some(key) = oldest {
+                    self.awaiting_ack.remove(&key);
                 }
             }
         }
     }
 
-    fn take_incoming(&mut self) -> Vec<IncomingMessage> {
-        self.incoming.drain(..).collect()
+    /// Resends each message whose ack is overdue, once, and gives up on those that
+    /// were already resent. Deadlines only run out while connected, and only against a
+    /// server that echoes its acks; a message sent just before a drop is resent after
+    /// the reconnect instead.
+    fn reap_overdue_acks(&mut self) {
+        if !self.acks_confirmed {
+            return;
+        }
+        let now = Instant::now();
+        let mut overdue: Vec<(i64, u64)> = self
+            .awaiting_ack
+            .iter()
+            .filter(|(_, awaiting)| awaiting.deadline <= now)
+            .map(|(key, _)| *key)
+            .collect();
+        overdue.sort_unstable();
+        for key in overdue {
+            let Some(awaiting) = self.awaiting_ack.remove(&key) else {
+                continue;
+            };
+            if awaiting.resent {
+                log_warn!(
+                    "no ack for message {} in channel {} after a resend",
+                    key.1,
+                    key.0
+                );
+                self.failed_sends.push(awaiting.message.id);
+                self.failed_seqs.insert(awaiting.message.id, key.1);
+                continue;
+            }
+            self.dispatch_message(&awaiting.message, awaiting.attachments, key.1);
+            if let Some(resent) = self.awaiting_ack.get_mut(&key) {
+                resent.resent = true;
+            }
+        }
     }
 
-    fn take_presence(&mut self) -> Vec<PresenceUpdate> {
-        self.incoming_presence.drain(..).collect()
+    /// Time until the next ack deadline, so the UI wakes up to act on it.
+    fn ack_due_in(&self) -> Option<Duration> {
+        if self.status != RealtimeStatus::Connected || !self.acks_confirmed {
+            return None;
+        }
+        let deadline = self.awaiting_ack.values().map(|awaiting| awaiting.deadline).min()?;
+        Some(deadline.saturating_duration_since(Instant::now()))
     }
-}
 
-fn spawn_realtime_worker(
-    cmd_rx: mpsc::Receiver<RealtimeCommand>,
-    evt_tx: mpsc::Sender<RealtimeEvent>,
-    target_url: String,
-    event_proxy: EventLoopProxy<UserEvent>,
-) {
-    thread::spawn(move || {
-        let mut connected = false;
-        let mut socket: Option<
-            tungstenite::WebSocket<tungstenite::stream::MaybeTlsStream<std::net::TcpStream>>,
-        > = None;
-        loop {
-            match cmd_rx.recv_timeout(Duration::from_millis(16)) {
-                Ok(command) => match command {
-                    RealtimeCommand::Connect => {
-                        if connected {
-                            continue;
-                        }
-                        let _ = evt_tx.send(RealtimeEvent {
-                            status: RealtimeStatus::Connecting,
-                            message: Some(format!("Dialing {target_url}")),
+    /// Local ids of messages given up on since the last call.
+    fn take_failed_sends(&mut self) -> Vec<i64> {
+        self.failed_sends.drain(..).collect()
+    }
+
+    /// Local ids of every message still waiting on an ack, which stops waiting for them.
+    fn take_unacked(&mut self) -> Vec<i64> {
+        self.awaiting_ack
+            .drain()
+            .map(|(_, awaiting)| awaiting.message.id)
+            .collect()
+    }
+
+    fn flush_outbox(&mut self) {
+        while let Some((message, attachments, seq)) = self.outbox.pop_front() {
+            self.dispatch_message(&message, attachments, seq);
+        }
+    }
+
+    fn poll(&mut self) {
+        let events: Vec<RealtimeEvent> = match self.evt_rx.as_ref() {
+            Some(evt_rx) => evt_rx.try_iter().collect(),
+            None => Vec::new(),
+        };
+        for event in events {
+            if self.status == RealtimeStatus::Connected
+                && event.status != RealtimeStatus::Connected
+                && event.error.is_some()
+            {
+                self.left_connected_at = Some(Instant::now());
+            }
+            self.status = event.status;
+            self.last_message = event.message;
+            self.last_error = event.error;
+            self.metrics = event.metrics;
+            if event.status == RealtimeStatus::Connected {
+                self.ever_connected = true;
+            }
+            if let Some(message) = event.inbound {
+                let repeat = match (message.client_id.clone(), message.seq) {
+                    (Some(client_id), Some(seq)) => {
+                        !self.note_sequence(client_id, message.message.channel_id, seq)
+                    }
+                    _ => false,
+                };
+                if !repeat {
+                    self.incoming.push(message);
+                }
+            }
+            if let Some(presence) = event.presence {
+                // A burst of updates for one user collapses to the latest.
+                self.incoming_presence.retain(|queued| queued.user != presence.user);
+                self.incoming_presence.push(presence);
+            }
+            if let Some(acked) = event.acked {
+                self.apply_ack(acked);
+            }
+        }
+        if self.status == RealtimeStatus::Connected && !self.outbox.is_empty() {
+            self.flush_outbox();
+        }
+        if self.status == RealtimeStatus::Connected {
+            self.reap_overdue_acks();
+        }
+    }
+
+    /// The status the indicator shows. A connection that drops on its own keeps
+    /// showing Connected for `STATUS_DEBOUNCE`, so a reconnect that succeeds right
+    /// away doesn't flash Disconnected.
+    fn displayed_status(&self) -> RealtimeStatus {
+        match self.status_settles_in() {
+            Some(_) => RealtimeStatus::Connected,
+            None => self.status,
+        }
+    }
+
+    /// Time left before a dropped connection is shown as such, while that is pending.
+    fn status_settles_in(&self) -> Option<Duration> {
+        if self.status == RealtimeStatus::Connected {
+            return None;
+        }
+        let elapsed = self.left_connected_at?.elapsed();
+        STATUS_DEBOUNCE.checked_sub(elapsed).filter(|left| !left.is_zero())
+    }
+
+    fn offline_notice(&self) -> Option<String> {
+        let notice = match (self.status, self.ever_connected) {
+            (RealtimeStatus::Connected, _) => return None,
+            (RealtimeStatus::Disconnected, false) => {
+                "You're offline — messages will send once you connect."
+            }
+            (RealtimeStatus::Disconnected, true) => {
+                "Connection lost — messages will send when reconnected."
+            }
+            (RealtimeStatus::Connecting, false) => {
+                "Connecting — messages will send once the handshake completes."
+            }
+            (RealtimeStatus::Connecting, true) => {
+                "Reconnecting — messages will send when reconnected."
+            }
+        };
+        match self.outbox.len() {
+            0 => Some(notice.to_string()),
+            1 => Some(format!("{notice} 1 message queued.")),
+            queued => Some(format!("{notice} {queued} messages queued.")),
+        }
+    }
+
+    /// Inbound messages since the last call, with each sender's rapid-fire sends
+    /// restored to the order they were written in.
+    fn take_incoming(&mut self) -> Vec<IncomingMessage> {
+        order_by_sequence(self.incoming.drain(..).collect())
+    }
+
+    fn take_presence(&mut self) -> Vec<PresenceUpdate> {
+        self.incoming_presence.drain(..).collect()
+    }
+}
+
+fn spawn_realtime_worker(
+    cmd_rx: mpsc::Receiver<RealtimeCommand>,
+    mut evt_tx: RealtimeEventSink,
+    target_url: String,
+    identity: Identity,
+    client_id: String,
+    invisible: bool,
+) {
+    thread::spawn(move || {
+        let mut connected = false;
+        let mut presence =
+            if invisible { PresenceStatus::Offline } else { PresenceStatus::Online };
+        // What this connection last told the server, and when.
+        let mut presence_sent: Option<PresenceStatus> = None;
+        let mut presence_sent_at: Option<Instant> = None;
+        let mut metrics = ConnectionMetrics::default();
+        let mut has_connected = false;
+        // Connecting until the server acks the auth; nothing is sent on our behalf before.
+        let mut open_status = RealtimeStatus::Connecting;
+        let mut socket: Option<
+            tungstenite::WebSocket<tungstenite::stream::MaybeTlsStream<std::net::TcpStream>>,
+        > = None;
+        loop {
+            match cmd_rx.recv_timeout(Duration::from_millis(16)) {
+                Ok(command) => match command {
+                    RealtimeCommand::Connect => {
+                        if connected {
+                            continue;
+                        }
+                        let _ = evt_tx.send(RealtimeEvent {
+                            status: RealtimeStatus::Connecting,
+                            message: Some(format!("Dialing {target_url}")),
                             error: None,
                             inbound: None,
                             presence: None,
+                            acked: None,
+                            metrics,
                         });
-                        let _ = event_proxy.send_event(UserEvent::Wake);
                         match Url::parse(&target_url)
                             .map_err(|err| err.to_string())
                             .and_then(|url| {
-                                connect(url)
+                                connect_with_config(url, Some(realtime_socket_config()), 3)
                                     .map(|(socket, _response)| socket)
                                     .map_err(|err| err.to_string())
                             }) {
@@ -387,14 +1072,22 @@ fn spawn_realtime_worker(
                                     let _ = stream.set_nonblocking(true);
                                 }
                                 connected = true;
+                                open_status = RealtimeStatus::Connecting;
+                                if has_connected {
+                                    metrics.reconnects += 1;
+                                }
+                                has_connected = true;
                                 socket = Some(ws);
                                 if let Some(ws) = socket.as_mut() {
                                     let auth = RealtimePayload::Auth {
-                                        token: "local-dev".to_string(),
-                                        user: "you".to_string(),
+                                        token: identity.token.clone(),
+                                        user: identity.username.clone(),
+                                        protocol_version: PROTOCOL_VERSION,
+                                        status: presence.label().to_string(),
                                     };
                                     match serde_json::to_string(&auth) {
                                         Ok(payload) => {
+                                            metrics.bytes_sent += payload.len() as u64;
                                             if let Err(err) = ws.send(WsMessage::Text(payload)) {
                                                 connected = false;
                                                 socket = None;
@@ -404,31 +1097,38 @@ fn spawn_realtime_worker(
                                                     error: Some(err.to_string()),
                                                     inbound: None,
                                                     presence: None,
+                                                    acked: None,
+                                                    metrics,
                                                 });
-                                                let _ = event_proxy.send_event(UserEvent::Wake);
                                                 continue;
                                             }
                                         }
                                         Err(err) => {
                                             let _ = evt_tx.send(RealtimeEvent {
-                                                status: RealtimeStatus::Connected,
+                                                status: open_status,
                                                 message: None,
                                                 error: Some(err.to_string()),
                                                 inbound: None,
                                                 presence: None,
+                                                acked: None,
+                                                metrics,
                                             });
-                                            let _ = event_proxy.send_event(UserEvent::Wake);
                                         }
                                     }
+                                    // The server announces the status sent with `Auth`,
+                                    // so there is nothing to take back afterwards.
+                                    presence_sent = Some(presence);
+                                    presence_sent_at = None;
                                 }
                                 let _ = evt_tx.send(RealtimeEvent {
-                                    status: RealtimeStatus::Connected,
-                                    message: Some("Handshake complete".to_string()),
+                                    status: RealtimeStatus::Connecting,
+                                    message: Some("Authenticating".to_string()),
                                     error: None,
                                     inbound: None,
                                     presence: None,
+                                    acked: None,
+                                    metrics,
                                 });
-                                let _ = event_proxy.send_event(UserEvent::Wake);
                             }
                             Err(err) => {
                                 connected = false;
@@ -439,8 +1139,9 @@ fn spawn_realtime_worker(
                                     error: Some(err),
                                     inbound: None,
                                     presence: None,
+                                    acked: None,
+                                    metrics,
                                 });
-                                let _ = event_proxy.send_event(UserEvent::Wake);
                             }
                         }
                     }
@@ -449,21 +1150,27 @@ fn spawn_realtime_worker(
                             let _ = ws.close(None);
                         }
                         connected = false;
+                        metrics = ConnectionMetrics::default();
+                        has_connected = false;
+                        presence_sent = None;
                         let _ = evt_tx.send(RealtimeEvent {
                             status: RealtimeStatus::Disconnected,
                             message: Some("Closed socket".to_string()),
                             error: None,
                             inbound: None,
                             presence: None,
+                            acked: None,
+                            metrics,
                         });
-                        let _ = event_proxy.send_event(UserEvent::Wake);
                     }
+                    RealtimeCommand::SetPresence(status) => presence = status,
                     RealtimeCommand::SendMessage {
                         author,
                         body,
                         sent_at,
                         channel_id,
                         attachments,
+                        seq,
                     } => {
                         if let Some(ws) = socket.as_mut() {
                             let message = Message {
@@ -472,9 +1179,11 @@ fn spawn_realtime_worker(
                                 body,
                                 sent_at,
                                 channel_id,
+                                edited_at: None,
                             };
-                            match encode_realtime_message(&message, attachments) {
+                            match encode_realtime_message(&message, attachments, &client_id, seq) {
                                 Ok(payload) => {
+                                    let payload_len = payload.len() as u64;
                                     if let Err(err) = ws.send(WsMessage::Text(payload)) {
                                         connected = false;
                                         socket = None;
@@ -484,19 +1193,33 @@ fn spawn_realtime_worker(
                                             error: Some(err.to_string()),
                                             inbound: None,
                                             presence: None,
+                                            acked: None,
+                                            metrics,
+                                        });
+                                    } else {
+                                        metrics.messages_sent += 1;
+                                        metrics.bytes_sent += payload_len;
+                                        let _ = evt_tx.send(RealtimeEvent {
+                                            status: open_status,
+                                            message: Some("Message sent".to_string()),
+                                            error: None,
+                                            inbound: None,
+                                            presence: None,
+                                            acked: None,
+                                            metrics,
                                         });
-                                        let _ = event_proxy.send_event(UserEvent::Wake);
                                     }
                                 }
                                 Err(err) => {
                                     let _ = evt_tx.send(RealtimeEvent {
-                                        status: RealtimeStatus::Connected,
+                                        status: open_status,
                                         message: None,
                                         error: Some(err.to_string()),
                                         inbound: None,
                                         presence: None,
+                                        acked: None,
+                                        metrics,
                                     });
-                                    let _ = event_proxy.send_event(UserEvent::Wake);
                                 }
                             }
                         }
@@ -506,51 +1229,125 @@ fn spawn_realtime_worker(
                 Err(mpsc::RecvTimeoutError::Timeout) => {}
             }
 
+            // A status that flips back before the interval is up is never sent at all.
+            let broadcast_due = presence_sent != Some(presence)
+                && presence_sent_at
+                    .is_none_or(|sent_at| sent_at.elapsed() >= PRESENCE_BROADCAST_INTERVAL);
+            let authenticated = open_status == RealtimeStatus::Connected;
+            if let Some(ws) = socket.as_mut().filter(|_| broadcast_due && authenticated) {
+                let status = presence.label();
+                let sent = send_presence(ws, &identity.username, status, &mut metrics);
+                presence_sent = Some(presence);
+                presence_sent_at = Some(Instant::now());
+                let _ = evt_tx.send(RealtimeEvent {
+                    status: open_status,
+                    message: sent.is_ok().then(|| format!("Now appearing {status}")),
+                    error: sent.err(),
+                    inbound: None,
+                    presence: None,
+                    acked: None,
+                    metrics,
+                });
+            }
+
             if connected {
                 if let Some(ws) = socket.as_mut() {
                     match ws.read() {
                         Ok(msg) => {
                             if let WsMessage::Text(text) = msg {
+                                metrics.bytes_received += text.len() as u64;
                                 match decode_realtime_inbound(&text) {
                                     Ok(RealtimeInbound::Message(message)) => {
+                                        metrics.messages_received += 1;
                                         let _ = evt_tx.send(RealtimeEvent {
-                                            status: RealtimeStatus::Connected,
+                                            status: open_status,
                                             message: Some("Message received".to_string()),
                                             error: None,
                                             inbound: Some(message),
                                             presence: None,
+                                            acked: None,
+                                            metrics,
                                         });
-                                        let _ = event_proxy.send_event(UserEvent::Wake);
                                     }
                                     Ok(RealtimeInbound::Presence { user, status }) => {
                                         let _ = evt_tx.send(RealtimeEvent {
-                                            status: RealtimeStatus::Connected,
+                                            status: open_status,
                                             message: Some(format!("Presence: {user} is {status}")),
                                             error: None,
                                             inbound: None,
                                             presence: Some(PresenceUpdate { user, status }),
+                                            acked: None,
+                                            metrics,
+                                        });
+                                    }
+                                    Ok(RealtimeInbound::Ack {
+                                        kind,
+                                        signal,
+                                        confirms,
+                                    }) => {
+                                        let acked = match confirms {
+                                            Some((id, channel_id, seq)) if id == client_id => {
+                                                Some(AckedMessage::Confirmed(channel_id, seq))
+                                            }
+                                            Some(_) => None,
+                                            None if kind == "message" => {
+                                                Some(AckedMessage::Unconfirmed)
+                                            }
+                                            None => None,
+                                        };
+                                        let signal = if kind == "auth" {
+                                            open_status = RealtimeStatus::Connected;
+                                            "Handshake complete".to_string()
+                                        } else {
+                                            signal
+                                        };
+                                        let _ = evt_tx.send(RealtimeEvent {
+                                            status: open_status,
+                                            message: Some(signal),
+                                            error: None,
+                                            inbound: None,
+                                            presence: None,
+                                            acked,
+                                            metrics,
+                                        });
+                                    }
+                                    Ok(RealtimeInbound::AuthFailed(error)) => {
+                                        log_warn!("realtime auth refused: {error}");
+                                        if let Some(mut ws) = socket.take() {
+                                            let _ = ws.close(None);
+                                        }
+                                        connected = false;
+                                        let _ = evt_tx.send(RealtimeEvent {
+                                            status: RealtimeStatus::Disconnected,
+                                            message: None,
+                                            error: Some(error),
+                                            inbound: None,
+                                            presence: None,
+                                            acked: None,
+                                            metrics,
                                         });
-                                        let _ = event_proxy.send_event(UserEvent::Wake);
                                     }
                                     Ok(RealtimeInbound::Signal(signal)) => {
                                         let _ = evt_tx.send(RealtimeEvent {
-                                            status: RealtimeStatus::Connected,
+                                            status: open_status,
                                             message: Some(signal),
                                             error: None,
                                             inbound: None,
                                             presence: None,
+                                            acked: None,
+                                            metrics,
                                         });
-                                        let _ = event_proxy.send_event(UserEvent::Wake);
                                     }
                                     Err(err) => {
                                         let _ = evt_tx.send(RealtimeEvent {
-                                            status: RealtimeStatus::Connected,
+                                            status: open_status,
                                             message: None,
                                             error: Some(err),
                                             inbound: None,
                                             presence: None,
+                                            acked: None,
+                                            metrics,
                                         });
-                                        let _ = event_proxy.send_event(UserEvent::Wake);
                                     }
                                 }
                             }
@@ -562,16 +1359,29 @@ fn spawn_realtime_worker(
                                     if io_err.kind() == std::io::ErrorKind::WouldBlock
                             );
                             if !io_blocked {
+                                // An oversized frame is refused before its payload is
+                                // buffered, which leaves the stream mid-frame, so the
+                                // only way on is to drop the connection.
+                                let error = if let tungstenite::Error::Capacity(capacity) = &err {
+                                    log_warn!("realtime frame rejected: {capacity}");
+                                    format!(
+                                        "Server sent a frame over {}; disconnected",
+                                        format_bytes(REALTIME_MAX_FRAME_BYTES as i64)
+                                    )
+                                } else {
+                                    err.to_string()
+                                };
                                 connected = false;
                                 socket = None;
                                 let _ = evt_tx.send(RealtimeEvent {
                                     status: RealtimeStatus::Disconnected,
                                     message: None,
-                                    error: Some(err.to_string()),
+                                    error: Some(error),
                                     inbound: None,
                                     presence: None,
+                                    acked: None,
+                                    metrics,
                                 });
-                                let _ = event_proxy.send_event(UserEvent::Wake);
                             }
                         }
                     }
@@ -581,2797 +1391,10555 @@ fn spawn_realtime_worker(
     });
 }
 
-fn seed_channels() -> Vec<(i64, &'static str, ChannelKind)> {
-    vec![
-        (1, "general", ChannelKind::Channel),
-        (2, "product", ChannelKind::Channel),
-        (3, "mara", ChannelKind::DirectMessage),
-        (4, "devin", ChannelKind::DirectMessage),
-    ]
+/// Tells the server which status to show others for `user`.
+fn send_presence(
+    ws: &mut tungstenite::WebSocket<tungstenite::stream::MaybeTlsStream<std::net::TcpStream>>,
+    user: &str,
+    status: &str,
+    metrics: &mut ConnectionMetrics,
+) -> Result<(), String> {
+    let payload = serde_json::to_string(&RealtimePayload::Presence {
+        user: user.to_string(),
+        status: status.to_string(),
+    })
+    .map_err(|err| err.to_string())?;
+    metrics.bytes_sent += payload.len() as u64;
+    ws.send(WsMessage::Text(payload)).map_err(|err| err.to_string())
 }
 
-fn seed_messages() -> Vec<Message> {
-    vec![
-        Message {
-            id: 0,
-            author: "mara".to_string(),
-            body: "Shipping the new hotkey flow now.".to_string(),
-            sent_at: "09:12".to_string(),
-            channel_id: 1,
-        },
-        Message {
-            id: 0,
-            author: "devin".to_string(),
-            body: "Latency on local echo is <100ms.".to_string(),
-            sent_at: "09:13".to_string(),
-            channel_id: 1,
-        },
-        Message {
-            id: 0,
-            author: "sasha".to_string(),
-            body: "Message search index warmed on startup.".to_string(),
-            sent_at: "09:15".to_string(),
-            channel_id: 1,
-        },
-        Message {
-            id: 0,
-            author: "you".to_string(),
-            body: "Feels fast. Let's keep it lean.".to_string(),
-            sent_at: "09:18".to_string(),
-            channel_id: 1,
-        },
-        Message {
-            id: 0,
-            author: "mara".to_string(),
-            body: "Next: attachments + previews.".to_string(),
-            sent_at: "09:21".to_string(),
-            channel_id: 2,
-        },
-        Message {
-            id: 0,
-            author: "devin".to_string(),
-            body: "Profiling idle CPU now.".to_string(),
-            sent_at: "09:24".to_string(),
-            channel_id: 2,
-        },
-        Message {
-            id: 0,
-            author: "mara".to_string(),
-            body: "Can you sanity-check the build flags?".to_string(),
-            sent_at: "09:26".to_string(),
-            channel_id: 3,
-        },
-        Message {
-            id: 0,
-            author: "devin".to_string(),
-            body: "Want me to share flamegraph results?".to_string(),
-            sent_at: "09:28".to_string(),
-            channel_id: 4,
-        },
-    ]
+/// Caps what a single inbound frame or message may buffer; tungstenite's own defaults
+/// allow tens of megabytes.
+fn realtime_socket_config() -> WebSocketConfig {
+    WebSocketConfig {
+        max_message_size: Some(REALTIME_MAX_FRAME_BYTES),
+        max_frame_size: Some(REALTIME_MAX_FRAME_BYTES),
+        ..Default::default()
+    }
 }
 
-const MESSAGE_FETCH_LIMIT: i64 = 20;
+const UNREAD_FETCH_LIMIT: i64 = 500;
+/// Messages loaded per step when scrolling past either end of the loaded window.
+const HISTORY_PAGE_SIZE: i64 = 50;
+/// Most messages kept in memory for the open channel; the floor keeps a full
+/// unread jump loaded.
+const DEFAULT_MAX_LOADED_MESSAGES: usize = 2000;
+const MIN_MAX_LOADED_MESSAGES: usize = UNREAD_FETCH_LIMIT as usize;
 const THUMBNAIL_CACHE_LIMIT: usize = 24;
 const THUMBNAIL_ERROR_LIMIT: usize = 24;
+/// Image sizes remembered for reserving preview space; the oldest reads go first.
+const IMAGE_DIMENSIONS_LIMIT: usize = 512;
+const THUMBNAIL_MAX_DIMENSION: u32 = 240;
+/// Threads decoding thumbnails; more requests than this wait in `ThumbnailQueue`.
+const THUMBNAIL_WORKERS: usize = 3;
+const GALLERY_PAGE_SIZE: i64 = 60;
+const GALLERY_TILE_SIZE: f32 = 120.0;
+/// Longest edge, in points, an image is decoded at for the lightbox.
+const LIGHTBOX_MAX_DIMENSION: u32 = 1600;
+/// Edge of the tile a pending image attachment is previewed in above the composer.
+const PENDING_THUMBNAIL_SIZE: f32 = 64.0;
+const THUMBNAIL_RECHECK_INTERVAL: Duration = Duration::from_secs(2);
+/// Failed previews after which an "image" attachment is shown as a plain file.
+const THUMBNAIL_DOWNGRADE_FAILURES: u32 = 3;
+/// Presence changes kept in the database; older ones are dropped as new ones land.
+const PRESENCE_HISTORY_KEEP: i64 = 2000;
+const PRESENCE_HISTORY_VIEW_LIMIT: i64 = 500;
+/// Changes shown per person in the presence history view.
+const PRESENCE_HISTORY_PER_USER: usize = 6;
+const FRAME_STATS_WINDOW: usize = 120;
+const DEFAULT_COLLAPSE_LINES: usize = 12;
+/// Rows within this many points of the viewport are laid out in full, not skipped.
+const ROW_RENDER_MARGIN: f32 = 200.0;
+const DEFAULT_MAX_ATTACHMENTS: usize = 10;
+const DEFAULT_MAX_ATTACHMENT_MB: i64 = 100;
+/// Longest author name shown in a message header before it is cut with "…".
+const AUTHOR_NAME_MAX_CHARS: usize = 32;
+/// Longest channel or DM name shown in the sidebar before it is cut with "…".
+const SIDEBAR_NAME_MAX_CHARS: usize = 22;
+const USERNAME_MAX_CHARS: usize = 64;
+const DEFAULT_GROUP_MINUTES: i64 = 5;
+const GROUP_MINUTES_RANGE: std::ops::RangeInclusive<i64> = 1..=60;
+const CHANNEL_TOPIC_MAX_CHARS: usize = 250;
+const COMPOSER_PLACEHOLDER_MAX_CHARS: usize = 80;
+const ANNOUNCEMENT_MAX_CHARS: usize = 500;
+/// Share of the message column left empty beside own messages in bubble style.
+const OWN_BUBBLE_INDENT: f32 = 0.25;
+const EDITED_MARKER_GRACE_SECS: i64 = 5;
+const DEFAULT_SERVER_URL: &str = "ws://127.0.0.1:9001";
+const DEFAULT_DB_PATH: &str = "ralph.db";
+const REALTIME_MAX_FRAME_BYTES: usize = 1024 * 1024;
+const TEXT_PREVIEW_CACHE_LIMIT: usize = 24;
+const TEXT_PREVIEW_MAX_BYTES: u64 = 16 * 1024;
+const TEXT_PREVIEW_COLLAPSED_LINES: usize = 6;
+const UI_SCALE_STEP: f32 = 0.1;
+const UI_SCALE_MIN: f32 = 0.5;
+const UI_SCALE_MAX: f32 = 3.0;
+/// How close together identical consecutive messages must be to collapse as repeats.
+const REPEAT_WINDOW: Duration = Duration::from_secs(5);
 const IDLE_REPAINT_DELAY: Duration = Duration::from_secs(1);
 const BACKGROUND_REPAINT_DELAY: Duration = Duration::from_secs(5);
 const REACTION_EMOJIS: [&str; 3] = ["👍", "🎉", "❤️"];
-const CURRENT_USER: &str = "You";
+const RECENT_EMOJI_LIMIT: usize = 8;
+/// Emoji offered by the picker, with the shortcode used to search for them and to
+/// type them as `:shortcode:` in the composer.
+const EMOJI_SET: &[(&str, &str)] = &[
+    ("👍", "thumbsup"),
+    ("👎", "thumbsdown"),
+    ("👏", "clap"),
+    ("🙌", "raised_hands"),
+    ("🙏", "pray"),
+    ("👋", "wave"),
+    ("👀", "eyes"),
+    ("💪", "muscle"),
+    ("🤝", "handshake"),
+    ("✌️", "v"),
+    ("👌", "ok_hand"),
+    ("🤞", "crossed_fingers"),
+    ("😀", "grinning"),
+    ("😄", "smile"),
+    ("😂", "joy"),
+    ("🤣", "rofl"),
+    ("😅", "sweat_smile"),
+    ("😊", "blush"),
+    ("😉", "wink"),
+    ("😍", "heart_eyes"),
+    ("😘", "kiss"),
+    ("😎", "sunglasses"),
+    ("🤔", "thinking"),
+    ("🤨", "raised_eyebrow"),
+    ("😐", "neutral_face"),
+    ("🙄", "eye_roll"),
+    ("😬", "grimacing"),
+    ("😴", "sleeping"),
+    ("😢", "cry"),
+    ("😭", "sob"),
+    ("😡", "rage"),
+    ("😱", "scream"),
+    ("🤯", "exploding_head"),
+    ("🥳", "partying_face"),
+    ("🤗", "hugs"),
+    ("🙃", "upside_down"),
+    ("😇", "innocent"),
+    ("🤓", "nerd"),
+    ("😷", "mask"),
+    ("🤷", "shrug"),
+    ("🤦", "facepalm"),
+    ("❤️", "heart"),
+    ("💔", "broken_heart"),
+    ("💯", "100"),
+    ("🔥", "fire"),
+    ("✨", "sparkles"),
+    ("⭐", "star"),
+    ("🎉", "tada"),
+    ("🎊", "confetti_ball"),
+    ("🎂", "birthday"),
+    ("🎁", "gift"),
+    ("🏆", "trophy"),
+    ("🚀", "rocket"),
+    ("💡", "bulb"),
+    ("📌", "pushpin"),
+    ("📎", "paperclip"),
+    ("📝", "memo"),
+    ("📅", "calendar"),
+    ("⏰", "alarm_clock"),
+    ("✅", "white_check_mark"),
+    ("☑️", "ballot_box_with_check"),
+    ("❌", "x"),
+    ("⚠️", "warning"),
+    ("❓", "question"),
+    ("❗", "exclamation"),
+    ("➕", "heavy_plus_sign"),
+    ("🐛", "bug"),
+    ("🔧", "wrench"),
+    ("🔒", "lock"),
+    ("💬", "speech_balloon"),
+    ("☕", "coffee"),
+    ("🍕", "pizza"),
+    ("🍻", "beers"),
+    ("🌮", "taco"),
+    ("🐶", "dog"),
+    ("🐱", "cat"),
+    ("🦀", "crab"),
+    ("🌈", "rainbow"),
+    ("☀️", "sunny"),
+    ("🌧️", "rain_cloud"),
+    ("🎯", "dart"),
+    ("🎵", "musical_note"),
+];
+#[derive(Clone)]
+struct Identity {
+    /// Name sent on the wire as the message author and used for mentions.
+    username: String,
+    /// Name shown in place of `username` on your own messages.
+    display_name: String,
+    /// Credential presented in the realtime auth handshake.
+    token: String,
+}
 
-fn format_timestamp_utc() -> String {
-    let now = SystemTime::now()
-        .duration_since(UNIX_EPOCH)
-        .unwrap_or_default()
-        .as_secs();
-    let hours = (now / 3600) % 24;
-    let minutes = (now / 60) % 60;
-    format!("{:02}:{:02}", hours, minutes)
-}
-
-fn ensure_schema(conn: &Connection) -> Result<(), rusqlite::Error> {
-    conn.execute(
-        "CREATE TABLE IF NOT EXISTS channels (
-            id INTEGER PRIMARY KEY AUTOINCREMENT,
-            name TEXT NOT NULL,
-            kind TEXT NOT NULL
-        )",
-        [],
-    )?;
-    conn.execute(
-        "CREATE TABLE IF NOT EXISTS messages (
-            id INTEGER PRIMARY KEY AUTOINCREMENT,
-            author TEXT NOT NULL,
-            body TEXT NOT NULL,
-            sent_at TEXT NOT NULL,
-            channel_id INTEGER NOT NULL,
-            FOREIGN KEY(channel_id) REFERENCES channels(id)
-        )",
-        [],
-    )?;
-    conn.execute(
-        "CREATE TABLE IF NOT EXISTS attachments (
-            id INTEGER PRIMARY KEY AUTOINCREMENT,
-            message_id INTEGER NOT NULL,
-            file_path TEXT NOT NULL,
-            file_name TEXT NOT NULL,
-            file_size INTEGER NOT NULL,
-            kind TEXT NOT NULL,
-            FOREIGN KEY(message_id) REFERENCES messages(id)
-        )",
-        [],
-    )?;
-    conn.execute(
-        "CREATE TABLE IF NOT EXISTS saved_messages (
-            message_id INTEGER PRIMARY KEY,
-            saved_at TEXT NOT NULL,
-            FOREIGN KEY(message_id) REFERENCES messages(id)
-        )",
-        [],
-    )?;
-    conn.execute(
-        "CREATE TABLE IF NOT EXISTS pinned_messages (
-            message_id INTEGER PRIMARY KEY,
-            pinned_at TEXT NOT NULL,
-            pinned_by TEXT NOT NULL,
-            FOREIGN KEY(message_id) REFERENCES messages(id)
-        )",
-        [],
-    )?;
-    conn.execute(
-        "CREATE TABLE IF NOT EXISTS message_reactions (
-            message_id INTEGER NOT NULL,
-            emoji TEXT NOT NULL,
-            author TEXT NOT NULL,
-            reacted_at TEXT NOT NULL,
-            PRIMARY KEY (message_id, emoji, author),
-            FOREIGN KEY(message_id) REFERENCES messages(id)
-        )",
-        [],
-    )?;
-    conn.execute(
-        "CREATE TABLE IF NOT EXISTS message_drafts (
-            channel_id INTEGER PRIMARY KEY,
-            body TEXT NOT NULL,
-            updated_at TEXT NOT NULL,
-            FOREIGN KEY(channel_id) REFERENCES channels(id)
-        )",
-        [],
-    )?;
-    let mut stmt = conn.prepare("PRAGMA table_info(messages)")?;
-    let columns = stmt.query_map([], |row| row.get::<_, String>(1))?;
-    let mut has_channel = false;
-    for column in columns {
-        if column? == "channel_id" {
-            has_channel = true;
-            break;
+/// A named database the user can switch to at runtime, optionally pinned to its own
+/// server. The first entry is always the default `ralph.db` workspace.
+#[derive(Clone)]
+struct Workspace {
+    name: String,
+    db_path: PathBuf,
+    server_url: Option<String>,
+}
+
+/// How messages written by the configured user stand out from everyone else's.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum OwnMessageStyle {
+    Plain,
+    /// A faint full-width background behind the message.
+    Tint,
+    /// A rounded background, inset from the left so the message sits on the right.
+    Bubble,
+}
+
+impl OwnMessageStyle {
+    fn from_env() -> Self {
+        match env_string("RALPH_OWN_MESSAGES").as_deref() {
+            None | Some("tint") => Self::Tint,
+            Some("bubble") => Self::Bubble,
+            Some("plain") => Self::Plain,
+            Some(other) => {
+                log_warn!("unknown RALPH_OWN_MESSAGES value {other:?}; using tint");
+                Self::Tint
+            }
         }
     }
-    if !has_channel {
-        conn.execute(
-            "ALTER TABLE messages ADD COLUMN channel_id INTEGER NOT NULL DEFAULT 1",
-            [],
-        )?;
-    }
-    Ok(())
-}
-
-fn seed_channels_if_empty(conn: &mut Connection) -> Result<(), rusqlite::Error> {
-    let count: i64 = conn.query_row("SELECT COUNT(*) FROM channels", [], |row| row.get(0))?;
-    if count == 0 {
-        let tx = conn.transaction()?;
-        for (id, name, kind) in seed_channels() {
-            tx.execute(
-                "INSERT INTO channels (id, name, kind) VALUES (?1, ?2, ?3)",
-                params![id, name, kind.as_str()],
-            )?;
-        }
-        tx.commit()?;
-    }
-    Ok(())
-}
-
-fn seed_messages_if_empty(conn: &mut Connection) -> Result<(), rusqlite::Error> {
-    let count: i64 = conn.query_row("SELECT COUNT(*) FROM messages", [], |row| row.get(0))?;
-    if count == 0 {
-        let seed = seed_messages();
-        let tx = conn.transaction()?;
-        for message in seed {
-            tx.execute(
-                "INSERT INTO messages (author, body, sent_at, channel_id) VALUES (?1, ?2, ?3, ?4)",
-                params![message.author, message.body, message.sent_at, message.channel_id],
-            )?;
-        }
-        tx.commit()?;
-    }
-    Ok(())
-}
-
-fn seed_saved_messages_if_empty(conn: &mut Connection) -> Result<(), rusqlite::Error> {
-    let count: i64 =
-        conn.query_row("SELECT COUNT(*) FROM saved_messages", [], |row| row.get(0))?;
-    if count == 0 {
-        conn.execute(
-            "INSERT OR IGNORE INTO saved_messages (message_id, saved_at)
-            SELECT id, '09:00' FROM messages ORDER BY id ASC LIMIT 2",
-            [],
-        )?;
-    }
-    Ok(())
-}
-
-fn seed_pinned_messages_if_empty(conn: &mut Connection) -> Result<(), rusqlite::Error> {
-    let count: i64 =
-        conn.query_row("SELECT COUNT(*) FROM pinned_messages", [], |row| row.get(0))?;
-    if count == 0 {
-        conn.execute(
-            "INSERT OR IGNORE INTO pinned_messages (message_id, pinned_at, pinned_by)
-            SELECT id, '09:12', 'Ava' FROM messages ORDER BY id ASC LIMIT 1",
-            [],
-        )?;
-    }
-    Ok(())
-}
-
-fn seed_reactions_if_empty(conn: &mut Connection) -> Result<(), rusqlite::Error> {
-    let count: i64 =
-        conn.query_row("SELECT COUNT(*) FROM message_reactions", [], |row| row.get(0))?;
-    if count == 0 {
-        let tx = conn.transaction()?;
-        tx.execute(
-            "INSERT OR IGNORE INTO message_reactions (message_id, emoji, author, reacted_at)
-            SELECT id, '👍', 'Ava', '09:05' FROM messages ORDER BY id ASC LIMIT 1",
-            [],
-        )?;
-        tx.execute(
-            "INSERT OR IGNORE INTO message_reactions (message_id, emoji, author, reacted_at)
-            SELECT id, '🎉', 'You' COLLATE NOCASE, '09:06' FROM messages ORDER BY id ASC LIMIT 1",
-            [],
-        )?;
-        tx.execute(
-            "INSERT OR IGNORE INTO message_reactions (message_id, emoji, author, reacted_at)
-            SELECT id, '❤️', 'Noah', '09:08' FROM messages ORDER BY id ASC LIMIT 1 OFFSET 1",
-            [],
-        )?;
-        tx.commit()?;
-    }
-    Ok(())
-}
-
-fn load_channels(conn: &Connection) -> Result<Vec<Channel>, rusqlite::Error> {
-    let mut stmt = conn.prepare("SELECT id, name, kind FROM channels ORDER BY id ASC")?;
-    let rows = stmt.query_map([], |row| {
-        Ok(Channel {
-            id: row.get(0)?,
-            name: row.get(1)?,
-            kind: ChannelKind::from_str(&row.get::<_, String>(2)?),
-        })
-    })?;
-
-    let mut channels = Vec::new();
-    for channel in rows {
-        channels.push(channel?);
-    }
-    Ok(channels)
-}
-
-fn load_messages(conn: &Connection, channel_id: i64) -> Result<Vec<Message>, rusqlite::Error> {
-    let mut stmt = conn.prepare(
-        "SELECT id, author, body, sent_at, channel_id
-        FROM messages
-        WHERE channel_id = ?1
-        ORDER BY id DESC
-        LIMIT ?2",
-    )?;
-    let rows = stmt.query_map(params![channel_id, MESSAGE_FETCH_LIMIT], |row| {
-        Ok(Message {
-            id: row.get(0)?,
-            author: row.get(1)?,
-            body: row.get(2)?,
-            sent_at: row.get(3)?,
-            channel_id: row.get(4)?,
-        })
-    })?;
+}
 
-    let mut messages = Vec::new();
-    for message in rows {
-        messages.push(message?);
-    }
-    messages.reverse();
-    Ok(messages)
+/// How presence is shown next to people. Every style carries the status in shape or
+/// words, so it never depends on color alone.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum PresenceStyle {
+    /// Only the status shape, for the most compact sidebar.
+    Shape,
+    /// Only the status word.
+    Label,
+    Both,
 }
 
-fn load_reactions_for_message_ids(
-    conn: &Connection,
-    message_ids: &[i64],
-) -> Result<HashMap<i64, Vec<MessageReaction>>, rusqlite::Error> {
-    if message_ids.is_empty() {
-        return Ok(HashMap::new());
-    }
-    let placeholders = vec!["?"; message_ids.len()].join(", ");
-    let query = format!(
-        "SELECT message_id, emoji, author, reacted_at
-        FROM message_reactions
-        WHERE message_id IN ({placeholders})"
-    );
-    let mut stmt = conn.prepare(&query)?;
-    let rows = stmt.query_map(params_from_iter(message_ids), |row| {
-        Ok(MessageReaction {
-            message_id: row.get(0)?,
-            emoji: row.get(1)?,
-            author: row.get(2)?,
-            reacted_at: row.get(3)?,
-        })
-    })?;
-    let mut reactions: HashMap<i64, Vec<MessageReaction>> = HashMap::new();
-    for reaction in rows {
-        let reaction = reaction?;
-        reactions
-            .entry(reaction.message_id)
-            .or_default()
-            .push(reaction);
+impl PresenceStyle {
+    fn from_env() -> Self {
+        match env_string("RALPH_PRESENCE_STYLE").as_deref() {
+            None | Some("both") => Self::Both,
+            Some("shape") => Self::Shape,
+            Some("label") => Self::Label,
+            Some(other) => {
+                log_warn!("unknown RALPH_PRESENCE_STYLE value {other:?}; using both");
+                Self::Both
+            }
+        }
     }
-    Ok(reactions)
 }
 
-fn insert_message(conn: &Connection, message: &Message) -> Result<i64, rusqlite::Error> {
-    conn.execute(
-        "INSERT INTO messages (author, body, sent_at, channel_id) VALUES (?1, ?2, ?3, ?4)",
-        params![message.author, message.body, message.sent_at, message.channel_id],
-    )?;
-    Ok(conn.last_insert_rowid())
+/// Caps on what one message can carry, checked as files are added and again at send.
+struct AttachmentLimits {
+    max_count: usize,
+    max_total_bytes: i64,
 }
 
-fn add_reaction(
-    conn: &Connection,
-    message_id: i64,
-    emoji: &str,
-    author: &str,
-    reacted_at: &str,
-) -> Result<(), rusqlite::Error> {
-    conn.execute(
-        "INSERT OR IGNORE INTO message_reactions (message_id, emoji, author, reacted_at)
-        VALUES (?1, ?2, ?3, ?4)",
-        params![message_id, emoji, author, reacted_at],
-    )?;
-    Ok(())
-}
-
-fn remove_reaction(
-    conn: &Connection,
-    message_id: i64,
-    emoji: &str,
-    author: &str,
-) -> Result<(), rusqlite::Error> {
-    conn.execute(
-        "DELETE FROM message_reactions
-        WHERE message_id = ?1 AND emoji = ?2 AND author = ?3 COLLATE NOCASE",
-        params![message_id, emoji, author],
-    )?;
-    Ok(())
-}
-
-fn load_channel_members(
-    conn: &Connection,
-    channels: &[Channel],
-) -> Result<HashMap<i64, HashSet<String>>, rusqlite::Error> {
-    let mut members: HashMap<i64, HashSet<String>> = HashMap::new();
-    let mut stmt = conn.prepare("SELECT channel_id, author FROM messages")?;
-    let rows = stmt.query_map([], |row| Ok((row.get::<_, i64>(0)?, row.get::<_, String>(1)?)))?;
-    for row in rows {
-        let (channel_id, author) = row?;
-        members.entry(channel_id).or_default().insert(author);
+impl AttachmentLimits {
+    fn from_env() -> Self {
+        let read = |key: &str, default: i64| match env_string(key) {
+            None => default,
+            Some(value) => match value.parse::<i64>() {
+                Ok(limit) if limit > 0 => limit,
+                _ => {
+                    log_warn!("ignoring {key}={value}: expected a positive number");
+                    default
+                }
+            },
+        };
+        Self {
+            max_count: read("RALPH_MAX_ATTACHMENTS", DEFAULT_MAX_ATTACHMENTS as i64) as usize,
+            max_total_bytes: read("RALPH_MAX_ATTACHMENT_MB", DEFAULT_MAX_ATTACHMENT_MB)
+                .saturating_mul(1024 * 1024),
+        }
     }
-    for channel in channels {
-        if channel.kind == ChannelKind::DirectMessage {
-            members
-                .entry(channel.id)
-                .or_default()
-                .insert(channel.name.clone());
+
+    /// Whether `pending` fits, with `extra_bytes` more in one more file when `adding`.
+    fn check(
+        &self,
+        pending: &[PendingAttachment],
+        adding: bool,
+        extra_bytes: i64,
+    ) -> Result<(), String> {
+        let count = pending.len() + usize::from(adding);
+        if count > self.max_count {
+            return Err(format!("Maximum {} attachments per message.", self.max_count));
+        }
+        let total = pending
+            .iter()
+            .map(|attachment| attachment.file_size)
+            .sum::<i64>()
+            + extra_bytes;
+        if total > self.max_total_bytes {
+            return Err(format!(
+                "Attachments can total at most {} per message.",
+                format_bytes(self.max_total_bytes)
+            ));
         }
+        Ok(())
     }
-    Ok(members)
-}
 
-fn build_composer_meta(channels: &[Channel]) -> HashMap<i64, ComposerMeta> {
-    let mut meta = HashMap::new();
-    for channel in channels {
-        let (placeholder, typing_stub) = match channel.kind {
-            ChannelKind::Channel => (
-                format!("Message #{}", channel.name),
-                "No one is typing.".to_string(),
-            ),
-            ChannelKind::DirectMessage => (
-                format!("Message @{}", channel.name),
-                format!("{} is typing...", channel.name),
-            ),
-        };
-        meta.insert(
-            channel.id,
-            ComposerMeta {
-                placeholder,
-                typing_stub,
-            },
-        );
+    /// Checks adding the file at `path` before it is hashed or copied anywhere.
+    fn admit(&self, pending: &[PendingAttachment], path: &Path) -> Result<(), String> {
+        let size = fs::metadata(path).map_or(0, |metadata| metadata.len() as i64);
+        self.check(pending, true, size)
     }
-    meta
 }
 
-#[derive(Clone, Copy, PartialEq, Eq)]
-enum PresenceStatus {
-    Online,
-    Away,
-    Offline,
-    Unknown,
+struct AppConfig {
+    attachment_store: Option<PathBuf>,
+    verify_attachments: bool,
+    server_url: String,
+    demo_mode: bool,
+    /// Delete messages left behind by a missing channel when the database is opened.
+    clean_orphans: bool,
+    /// Mirror realtime connection events into a rotating file beside the database.
+    realtime_log: bool,
+    attachment_kinds: HashMap<String, String>,
+    attachment_limits: AttachmentLimits,
+    collapse_lines: usize,
+    /// Cap on the open channel's loaded messages, from `RALPH_MAX_LOADED_MESSAGES`.
+    max_loaded_messages: usize,
+    /// Consecutive messages from one author within this many minutes share a header.
+    group_minutes: i64,
+    clock: ClockSettings,
+    own_message_style: OwnMessageStyle,
+    presence_style: PresenceStyle,
+    /// From `RALPH_REDUCE_MOTION`; `None` follows the OS accessibility setting.
+    reduce_motion: Option<bool>,
+    ui_scale: f32,
+    auto_scroll: AutoScroll,
+    thumbnail_size: ThumbnailSize,
+    /// Appear offline to everyone else while still receiving messages.
+    invisible: bool,
+    /// Whether `RALPH_INVISIBLE` is set, which fixes `invisible` for the session.
+    invisible_from_env: bool,
+    /// Record people's presence changes for the history view; off unless asked for.
+    presence_history: bool,
+    /// Words besides your username that highlight a message, from `RALPH_HIGHLIGHT_WORDS`.
+    highlight_words: Vec<String>,
+    identity: Identity,
+    workspaces: Vec<Workspace>,
 }
 
-impl PresenceStatus {
-    fn from_str(value: &str) -> Self {
-        match value.to_ascii_lowercase().as_str() {
-            "online" => PresenceStatus::Online,
-            "away" => PresenceStatus::Away,
-            "offline" => PresenceStatus::Offline,
-            _ => PresenceStatus::Unknown,
+impl AppConfig {
+    fn from_env() -> Self {
+        let attachment_store = env_string("RALPH_ATTACHMENT_STORE").map(PathBuf::from);
+        let verify_attachments = env_string("RALPH_VERIFY_ATTACHMENTS")
+            .map(|value| value != "0" && !value.eq_ignore_ascii_case("false"))
+            .unwrap_or(false);
+        let username = env_string("RALPH_USERNAME").unwrap_or_else(|| "you".to_string());
+        let display_name = env_string("RALPH_DISPLAY_NAME").unwrap_or_else(|| username.clone());
+        let token = env_string("RALPH_TOKEN").unwrap_or_else(|| "local-dev".to_string());
+        let server_url =
+            env_string("RALPH_SERVER_URL").unwrap_or_else(|| DEFAULT_SERVER_URL.to_string());
+        Self {
+            attachment_store,
+            verify_attachments,
+            server_url,
+            demo_mode: false,
+            clean_orphans: env_string("RALPH_CLEAN_ORPHANS")
+                .is_some_and(|value| value != "0" && !value.eq_ignore_ascii_case("false")),
+            realtime_log: env_string("RALPH_REALTIME_LOG")
+                .is_some_and(|value| value != "0" && !value.eq_ignore_ascii_case("false")),
+            invisible: env_string("RALPH_INVISIBLE")
+                .is_some_and(|value| value != "0" && !value.eq_ignore_ascii_case("false")),
+            invisible_from_env: env_string("RALPH_INVISIBLE").is_some(),
+            presence_history: env_string("RALPH_PRESENCE_HISTORY")
+                .is_some_and(|value| value != "0" && !value.eq_ignore_ascii_case("false")),
+            attachment_kinds: env_string("RALPH_ATTACHMENT_KINDS")
+                .map(|value| parse_attachment_kinds(&value))
+                .unwrap_or_default(),
+            attachment_limits: AttachmentLimits::from_env(),
+            collapse_lines: env_string("RALPH_COLLAPSE_LINES")
+                .and_then(|value| value.parse().ok())
+                .unwrap_or(DEFAULT_COLLAPSE_LINES),
+            max_loaded_messages: env_string("RALPH_MAX_LOADED_MESSAGES")
+                .map(|value| parse_max_loaded_messages(&value))
+                .unwrap_or(DEFAULT_MAX_LOADED_MESSAGES),
+            group_minutes: env_string("RALPH_GROUP_MINUTES")
+                .map(|value| parse_group_minutes(&value))
+                .unwrap_or(DEFAULT_GROUP_MINUTES),
+            clock: ClockSettings::from_env(),
+            own_message_style: OwnMessageStyle::from_env(),
+            presence_style: PresenceStyle::from_env(),
+            reduce_motion: env_string("RALPH_REDUCE_MOTION")
+                .map(|value| value != "0" && !value.eq_ignore_ascii_case("false")),
+            ui_scale: env_string("RALPH_UI_SCALE")
+                .and_then(|value| value.parse().ok())
+                .map(clamp_ui_scale)
+                .unwrap_or(1.0),
+            auto_scroll: env_string("RALPH_AUTO_SCROLL")
+                .and_then(|value| {
+                    let mode = AutoScroll::parse(&value);
+                    if mode.is_none() {
+                        log_warn!(
+                            "ignoring RALPH_AUTO_SCROLL={value}: expected always, bottom, or never"
+                        );
+                    }
+                    mode
+                })
+                .unwrap_or(AutoScroll::AtBottom),
+            thumbnail_size: ThumbnailSize::Medium,
+            highlight_words: env_string("RALPH_HIGHLIGHT_WORDS")
+                .map(|value| parse_highlight_words(&value))
+                .unwrap_or_default(),
+            identity: Identity {
+                username,
+                display_name,
+                token,
+            },
+            workspaces: parse_workspaces(env_string("RALPH_WORKSPACES").as_deref()),
         }
     }
 
-    fn label(self) -> &'static str {
-        match self {
-            PresenceStatus::Online => "online",
-            PresenceStatus::Away => "away",
-            PresenceStatus::Offline => "offline",
-            PresenceStatus::Unknown => "unknown",
+    /// Fills in values saved during onboarding. Environment variables still win, so
+    /// only keys whose variable is unset are taken. Returns whether anything changed.
+    fn apply_saved_settings(&mut self, settings: &HashMap<String, String>) -> bool {
+        let mut changed = false;
+        if let Some(username) = settings.get("username") {
+            if env_string("RALPH_USERNAME").is_none() && *username != self.identity.username {
+                if env_string("RALPH_DISPLAY_NAME").is_none() {
+                    self.identity.display_name = username.clone();
+                }
+                self.identity.username = username.clone();
+                changed = true;
+            }
+        }
+        if let Some(server_url) = settings.get("server_url") {
+            if env_string("RALPH_SERVER_URL").is_none() && *server_url != self.server_url {
+                self.server_url = server_url.clone();
+                changed = true;
+            }
         }
+        changed
     }
+}
 
-    fn color(self) -> egui::Color32 {
-        match self {
-            PresenceStatus::Online => egui::Color32::from_rgb(120, 210, 120),
-            PresenceStatus::Away => egui::Color32::from_rgb(220, 180, 80),
-            PresenceStatus::Offline => egui::Color32::from_rgb(130, 140, 160),
-            PresenceStatus::Unknown => egui::Color32::from_rgb(120, 130, 150),
+fn parse_group_minutes(value: &str) -> i64 {
+    match value.parse() {
+        Ok(minutes) if GROUP_MINUTES_RANGE.contains(&minutes) => minutes,
+        _ => {
+            log_warn!(
+                "ignoring RALPH_GROUP_MINUTES={value}: expected 1 to 60; \
+                 using {DEFAULT_GROUP_MINUTES}"
+            );
+            DEFAULT_GROUP_MINUTES
         }
     }
 }
 
-struct PresenceState {
-    status: PresenceStatus,
-    last_seen: Instant,
+fn parse_max_loaded_messages(value: &str) -> usize {
+    match value.parse::<usize>() {
+        Ok(count) if count >= MIN_MAX_LOADED_MESSAGES => count,
+        Ok(_) => {
+            log_warn!(
+                "RALPH_MAX_LOADED_MESSAGES={value} is below the minimum; \
+                 using {MIN_MAX_LOADED_MESSAGES}"
+            );
+            MIN_MAX_LOADED_MESSAGES
+        }
+        Err(_) => {
+            log_warn!(
+                "ignoring RALPH_MAX_LOADED_MESSAGES={value}: expected a message count; \
+                 using {DEFAULT_MAX_LOADED_MESSAGES}"
+            );
+            DEFAULT_MAX_LOADED_MESSAGES
+        }
+    }
 }
 
-struct SearchRequest {
-    query: String,
-    channel_only: bool,
+/// Splits a comma-separated highlight word list, dropping blanks and repeats.
+fn parse_highlight_words(value: &str) -> Vec<String> {
+    let mut words: Vec<String> = Vec::new();
+    for word in value.split(',').map(str::trim).filter(|word| !word.is_empty()) {
+        if !words.iter().any(|known| known.to_lowercase() == word.to_lowercase()) {
+            words.push(word.to_string());
+        }
+    }
+    words
 }
 
-#[derive(Clone)]
-struct Attachment {
-    message_id: i64,
-    file_path: String,
-    file_name: String,
-    file_size: i64,
-    kind: String,
+/// Reads the OS "reduce motion" accessibility setting where it can be queried
+/// without extra dependencies; anything unreadable counts as no preference.
+fn system_prefers_reduced_motion() -> bool {
+    let read = |program: &str, args: &[&str]| {
+        Command::new(program)
+            .args(args)
+            .output()
+            .ok()
+            .filter(|output| output.status.success())
+            .map(|output| String::from_utf8_lossy(&output.stdout).trim().to_string())
+    };
+    if cfg!(target_os = "macos") {
+        read("defaults", &["read", "com.apple.universalaccess", "reduceMotion"])
+            .is_some_and(|value| value == "1")
+    } else if cfg!(target_os = "windows") {
+        // MinAnimate is "0" when "Show animations in Windows" is off.
+        read(
+            "reg",
+            &["query", r"HKCU\Control Panel\Desktop\WindowMetrics", "/v", "MinAnimate"],
+        )
+        .is_some_and(|value| value.trim_end().ends_with('0'))
+    } else {
+        read("gsettings", &["get", "org.gnome.desktop.interface", "enable-animations"])
+            .is_some_and(|value| value == "false")
+    }
 }
 
-#[derive(Clone)]
-struct PendingAttachment {
-    file_path: String,
-    file_name: String,
-    file_size: i64,
-    kind: String,
+fn env_string(key: &str) -> Option<String> {
+    env::var(key)
+        .ok()
+        .map(|value| value.trim().to_string())
+        .filter(|value| !value.is_empty())
 }
 
-struct ThumbnailResult {
-    path: String,
-    image: Option<egui::ColorImage>,
-    error: Option<String>,
+impl Identity {
+    fn is_self(&self, author: &str) -> bool {
+        author.eq_ignore_ascii_case(&self.username)
+    }
 }
 
-struct DeferredLoadResult {
-    channel_id: i64,
-    channels: Vec<Channel>,
-    messages: Vec<Message>,
-    attachments: HashMap<i64, Vec<Attachment>>,
-    channel_members: HashMap<i64, HashSet<String>>,
-    saved_messages: HashSet<i64>,
-    pinned_messages: HashSet<i64>,
-    message_reactions: HashMap<i64, Vec<MessageReaction>>,
-    drafts: HashMap<i64, String>,
-    db_ready: bool,
+/// Name colors for users without one of their own, picked by a hash of the username
+/// so each person keeps theirs across messages, sessions, and machines.
+const USER_COLORS: [(u8, u8, u8); 8] = [
+    (130, 190, 250),
+    (240, 160, 120),
+    (150, 210, 150),
+    (220, 160, 220),
+    (240, 200, 110),
+    (120, 210, 200),
+    (250, 140, 160),
+    (180, 170, 250),
+];
+
+/// Known users by lowercased username. Everything that shows an author goes through
+/// here, so a display name or color shows the same wherever the person appears.
+#[derive(Default)]
+struct Roster {
+    users: HashMap<String, User>,
 }
 
-struct DeferredLoadPlan {
-    channel_id: i64,
-    channels: Vec<Channel>,
-}
+impl Roster {
+    fn get(&self, username: &str) -> Option<&User> {
+        self.users.get(&username.to_ascii_lowercase())
+    }
 
-struct App {
-    window: Arc<Window>,
-    surface: wgpu::Surface<'static>,
-    device: wgpu::Device,
-    queue: wgpu::Queue,
-    config: wgpu::SurfaceConfiguration,
-    egui_state: EguiWinitState,
-    egui_ctx: egui::Context,
-    egui_renderer: Renderer,
-    boot_started: Instant,
-    next_repaint_at: Instant,
-    needs_repaint: bool,
-    window_focused: bool,
-    window_occluded: bool,
-    first_frame_logged: bool,
-    exit_after_first_frame: bool,
-    exit_requested: bool,
-    started_at: Instant,
-    db: Connection,
-    db_is_fallback: bool,
-    channels: Vec<Channel>,
-    messages: Vec<Message>,
-    selected_channel_id: i64,
-    composer_drafts: HashMap<i64, String>,
-    composer_focus_requested: bool,
-    composer_meta: HashMap<i64, ComposerMeta>,
-    typing_state: HashMap<i64, Instant>,
-    realtime: RealtimeClient,
-    channel_members: HashMap<i64, HashSet<String>>,
-    presence_state: HashMap<String, PresenceState>,
-    search_query: String,
-    search_last_query: String,
-    search_results: Vec<Message>,
-    search_channel_only: bool,
-    search_last_channel_only: bool,
-    messages_loaded: bool,
-    saved_messages: HashSet<i64>,
-    pinned_messages: HashSet<i64>,
-    show_saved_only: bool,
-    show_pinned_only: bool,
-    message_attachments: HashMap<i64, Vec<Attachment>>,
-    message_reactions: HashMap<i64, Vec<MessageReaction>>,
-    attachment_path_drafts: HashMap<i64, String>,
-    pending_attachments: HashMap<i64, Vec<PendingAttachment>>,
-    attachment_error: Option<String>,
-    attachment_action_error: Option<String>,
-    saved_action_error: Option<String>,
-    pinned_action_error: Option<String>,
-    reaction_action_error: Option<String>,
-    attachment_thumbnails: HashMap<String, egui::TextureHandle>,
-    attachment_thumbnail_errors: HashMap<String, String>,
-    thumbnail_cache_order: VecDeque<String>,
-    thumbnail_error_order: VecDeque<String>,
-    thumbnail_sender: mpsc::Sender<ThumbnailResult>,
-    thumbnail_receiver: mpsc::Receiver<ThumbnailResult>,
-    thumbnail_in_flight: HashSet<String>,
-    deferred_load_receiver: Option<mpsc::Receiver<DeferredLoadResult>>,
-    deferred_load_plan: Option<DeferredLoadPlan>,
-    event_proxy: EventLoopProxy<UserEvent>,
-}
+    /// The name to show for `author`: your configured display name for yourself,
+    /// otherwise the stored display name, falling back to the username.
+    fn label<'a>(&'a self, identity: &'a Identity, author: &'a str) -> &'a str {
+        if identity.is_self(author) {
+            return &identity.display_name;
+        }
+        self.get(author)
+            .and_then(|user| user.display_name.as_deref())
+            .filter(|name| !name.trim().is_empty())
+            .unwrap_or(author)
+    }
 
-impl App {
-    fn new(
-        event_loop: &EventLoop<UserEvent>,
-        event_proxy: EventLoopProxy<UserEvent>,
-        boot_started: Instant,
-        exit_after_first_frame: bool,
-    ) -> Self {
-        let window = Arc::new(
-            WindowBuilder::new()
-                .with_title("Ralph")
-                .with_inner_size(PhysicalSize::new(1100, 720))
-                .build(event_loop)
-                .expect("window"),
-        );
+    fn color(&self, author: &str) -> egui::Color32 {
+        if let Some(color) = self
+            .get(author)
+            .and_then(|user| user.color.as_deref())
+            .and_then(parse_hex_color)
+        {
+            return color;
+        }
+        let hash = author
+            .to_ascii_lowercase()
+            .bytes()
+            .fold(0x811c_9dc5_u32, |hash, byte| (hash ^ u32::from(byte)).wrapping_mul(0x0100_0193));
+        let (r, g, b) = USER_COLORS[hash as usize % USER_COLORS.len()];
+        egui::Color32::from_rgb(r, g, b)
+    }
 
-        let instance = wgpu::Instance::new(wgpu::InstanceDescriptor {
-            backends: wgpu::Backends::METAL,
-            ..Default::default()
-        });
-        let surface = instance
-            .create_surface(window.clone())
-            .expect("create surface");
-        let adapter = pollster::block_on(instance.request_adapter(&wgpu::RequestAdapterOptions {
-            power_preference: wgpu::PowerPreference::LowPower,
-            compatible_surface: Some(&surface),
-            force_fallback_adapter: false,
-        }))
-        .expect("adapter");
-        let (device, queue) = pollster::block_on(adapter.request_device(
-            &wgpu::DeviceDescriptor {
-                label: Some("ralph-device"),
-                required_features: wgpu::Features::empty(),
-                required_limits: wgpu::Limits::default(),
+    /// Adds `username` if it's new; returns whether it was.
+    fn observe(&mut self, username: &str) -> bool {
+        let key = username.to_ascii_lowercase();
+        if self.users.contains_key(&key) {
+            return false;
+        }
+        self.users.insert(
+            key,
+            User {
+                username: username.to_string(),
+                display_name: None,
+                color: None,
+                avatar_path: None,
             },
-            None,
-        ))
-        .expect("device");
+        );
+        true
+    }
+}
 
-        let size = window.inner_size();
-        let surface_caps = surface.get_capabilities(&adapter);
-        let surface_format = surface_caps
-            .formats
-            .iter()
-            .copied()
-            .find(|format| format.is_srgb())
-            .unwrap_or(surface_caps.formats[0]);
-        let config = wgpu::SurfaceConfiguration {
-            usage: TextureUsages::RENDER_ATTACHMENT,
-            format: surface_format,
-            width: size.width.max(1),
-            height: size.height.max(1),
-            present_mode: PresentMode::Fifo,
-            desired_maximum_frame_latency: 2,
-            alpha_mode: surface_caps
-                .alpha_modes
-                .iter()
-                .copied()
-                .find(|mode| *mode == CompositeAlphaMode::Opaque)
-                .unwrap_or(surface_caps.alpha_modes[0]),
-            view_formats: vec![],
-        };
-        surface.configure(&device, &config);
+/// Parses `#rrggbb` (the `#` is optional).
+fn parse_hex_color(value: &str) -> Option<egui::Color32> {
+    let hex = value.trim().trim_start_matches('#');
+    if hex.len() != 6 || !hex.is_ascii() {
+        return None;
+    }
+    let channel = |range: std::ops::Range<usize>| u8::from_str_radix(&hex[range], 16).ok();
+    Some(egui::Color32::from_rgb(channel(0..2)?, channel(2..4)?, channel(4..6)?))
+}
 
-        let egui_ctx = egui::Context::default();
-        let egui_state = EguiWinitState::new(
-            egui_ctx.clone(),
-            egui::ViewportId::ROOT,
-            window.as_ref(),
-            None,
-            None,
-        );
-        let egui_renderer = Renderer::new(&device, surface_format, None, 1);
+fn format_timestamp_utc() -> String {
+    Utc::now().to_rfc3339_opts(SecondsFormat::Secs, true)
+}
 
-        let db = Connection::open_in_memory().expect("memory db");
-        let channels: Vec<Channel> = seed_channels()
-            .into_iter()
-            .map(|(id, name, kind)| Channel {
-                id,
-                name: name.to_string(),
-                kind,
-            })
-            .collect();
-        let selected_channel_id = channels.first().map(|channel| channel.id).unwrap_or(1);
-        let composer_meta = build_composer_meta(&channels);
-        let messages = Vec::new();
-        let deferred_load_plan = DeferredLoadPlan {
-            channel_id: selected_channel_id,
-            channels: channels.clone(),
-        };
-        let mut presence_state = HashMap::new();
-        presence_state.insert(
-            "you".to_string(),
-            PresenceState {
-                status: PresenceStatus::Online,
-                last_seen: Instant::now(),
-            },
-        );
+/// Whether the message list follows new messages to the bottom.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum AutoScroll {
+    Always,
+    /// Follow only while already scrolled to the bottom.
+    AtBottom,
+    Never,
+}
 
-        let (thumbnail_sender, thumbnail_receiver) = mpsc::channel();
+impl AutoScroll {
+    const ALL: [AutoScroll; 3] = [AutoScroll::Always, AutoScroll::AtBottom, AutoScroll::Never];
 
-        Self {
-            window,
-            surface,
-            device,
-            queue,
-            config,
-            egui_state,
-            egui_ctx,
-            egui_renderer,
-            boot_started,
-            next_repaint_at: Instant::now(),
-            needs_repaint: true,
-            window_focused: true,
-            window_occluded: false,
-            first_frame_logged: false,
-            exit_after_first_frame,
-            exit_requested: false,
-            started_at: Instant::now(),
-            db,
-            db_is_fallback: true,
-            channels,
-            messages,
-            selected_channel_id,
-            composer_drafts: HashMap::new(),
-            composer_focus_requested: true,
-            composer_meta,
-            typing_state: HashMap::new(),
-            realtime: RealtimeClient::new(
-                "ws://127.0.0.1:9001".to_string(),
-                event_proxy.clone(),
-            ),
-            channel_members: HashMap::new(),
-            presence_state,
-            search_query: String::new(),
-            search_last_query: String::new(),
-            search_results: Vec::new(),
-            search_channel_only: true,
-            search_last_channel_only: true,
-            messages_loaded: false,
-            saved_messages: HashSet::new(),
-            pinned_messages: HashSet::new(),
-            show_saved_only: false,
-            show_pinned_only: false,
-            message_attachments: HashMap::new(),
-            message_reactions: HashMap::new(),
-            attachment_path_drafts: HashMap::new(),
-            pending_attachments: HashMap::new(),
-            attachment_error: None,
-            attachment_action_error: None,
-            saved_action_error: None,
-            pinned_action_error: None,
-            reaction_action_error: None,
-            attachment_thumbnails: HashMap::new(),
-            attachment_thumbnail_errors: HashMap::new(),
-            thumbnail_cache_order: VecDeque::new(),
-            thumbnail_error_order: VecDeque::new(),
-            thumbnail_sender,
-            thumbnail_receiver,
-            thumbnail_in_flight: HashSet::new(),
-            deferred_load_receiver: None,
-            deferred_load_plan: Some(deferred_load_plan),
-            event_proxy,
+    fn parse(value: &str) -> Option<Self> {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "always" => Some(AutoScroll::Always),
+            "bottom" | "at-bottom" => Some(AutoScroll::AtBottom),
+            "never" => Some(AutoScroll::Never),
+            _ => None,
         }
     }
 
-    fn resize(&mut self, size: PhysicalSize<u32>) {
-        if size.width == 0 || size.height == 0 {
-            return;
+    /// The value stored in settings and accepted by `RALPH_AUTO_SCROLL`.
+    fn key(self) -> &'static str {
+        match self {
+            AutoScroll::Always => "always",
+            AutoScroll::AtBottom => "bottom",
+            AutoScroll::Never => "never",
         }
-        self.config.width = size.width;
-        self.config.height = size.height;
-        self.surface.configure(&self.device, &self.config);
     }
 
-    fn render(&mut self) {
-        if !self.first_frame_logged {
-            self.first_frame_logged = true;
-            let elapsed_ms = self.boot_started.elapsed().as_secs_f64() * 1000.0;
-            println!("ralph: first_frame_ms={elapsed_ms:.2}");
-            if self.exit_after_first_frame {
-                self.exit_requested = true;
-            }
+    fn label(self) -> &'static str {
+        match self {
+            AutoScroll::Always => "Always",
+            AutoScroll::AtBottom => "Only when at bottom",
+            AutoScroll::Never => "Never",
         }
-        self.realtime.poll();
-        let mut state_dirty = false;
-        let incoming = self.realtime.take_incoming();
-        if !incoming.is_empty() {
-            state_dirty = true;
+    }
+}
+
+/// How large inline image previews are shown in the message list.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum ThumbnailSize {
+    Small,
+    Medium,
+    Large,
+}
+
+impl ThumbnailSize {
+    const ALL: [ThumbnailSize; 3] =
+        [ThumbnailSize::Small, ThumbnailSize::Medium, ThumbnailSize::Large];
+
+    fn parse(value: &str) -> Option<Self> {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "small" => Some(ThumbnailSize::Small),
+            "medium" => Some(ThumbnailSize::Medium),
+            "large" => Some(ThumbnailSize::Large),
+            _ => None,
         }
-        let presence_updates = self.realtime.take_presence();
-        if !presence_updates.is_empty() {
-            state_dirty = true;
-            for update in presence_updates {
-                self.presence_state.insert(
-                    update.user,
-                    PresenceState {
-                        status: PresenceStatus::from_str(&update.status),
-                        last_seen: Instant::now(),
-                    },
-                );
-            }
+    }
+
+    /// The value stored in settings.
+    fn key(self) -> &'static str {
+        match self {
+            ThumbnailSize::Small => "small",
+            ThumbnailSize::Medium => "medium",
+            ThumbnailSize::Large => "large",
         }
-        if self.drain_thumbnail_results() {
+    }
+
+    fn label(self) -> &'static str {
+        match self {
+            ThumbnailSize::Small => "Small",
+            ThumbnailSize::Medium => "Medium",
+            ThumbnailSize::Large => "Large",
+        }
+    }
+
+    /// Largest box, in points, a preview is shown in; narrower windows shrink it.
+    fn bounds(self) -> egui::Vec2 {
+        match self {
+            ThumbnailSize::Small => egui::Vec2::new(160.0, 120.0),
+            ThumbnailSize::Medium => egui::Vec2::new(220.0, 160.0),
+            ThumbnailSize::Large => egui::Vec2::new(400.0, 300.0),
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum ClockStyle {
+    TwentyFourHour,
+    TwelveHour,
+}
+
+#[derive(Clone, Copy)]
+struct ClockSettings {
+    style: ClockStyle,
+    /// Fixed offset from `RALPH_TIMEZONE`; `None` follows the system local zone.
+    offset: Option<FixedOffset>,
+}
+
+impl ClockSettings {
+    fn from_env() -> Self {
+        let style = match env_string("RALPH_TIME_FORMAT").as_deref() {
+            Some("12h") | Some("12") => ClockStyle::TwelveHour,
+            _ => ClockStyle::TwentyFourHour,
+        };
+        let offset = env_string("RALPH_TIMEZONE").and_then(|value| {
+            let offset = parse_utc_offset(&value);
+            if offset.is_none() && !value.eq_ignore_ascii_case("local") {
+                log_warn!("ignoring RALPH_TIMEZONE={value}: expected local, UTC, or +HH:MM");
+            }
+            offset
+        });
+        Self { style, offset }
+    }
+
+    fn localize(&self, instant: DateTime<Utc>) -> DateTime<FixedOffset> {
+        match self.offset {
+            Some(offset) => instant.with_timezone(&offset),
+            None => instant.with_timezone(&Local).fixed_offset(),
+        }
+    }
+}
+
+fn parse_utc_offset(value: &str) -> Option<FixedOffset> {
+    let value = value.trim();
+    if value.eq_ignore_ascii_case("utc") || value == "Z" {
+        return FixedOffset::east_opt(0);
+    }
+    let (sign, rest) = match value.as_bytes().first()? {
+        b'+' => (1, &value[1..]),
+        b'-' => (-1, &value[1..]),
+        _ => return None,
+    };
+    // Only ASCII digits from here on, so the byte-based split below can't land
+    // inside a character.
+    if !rest.bytes().all(|byte| byte.is_ascii_digit() || byte == b':') {
+        return None;
+    }
+    let (hours, minutes) = match rest.split_once(':') {
+        Some((hours, minutes)) => (hours, minutes),
+        None if rest.len() == 4 => rest.split_at(2),
+        None => (rest, "0"),
+    };
+    let hours: i32 = hours.parse().ok()?;
+    let minutes: i32 = minutes.parse().ok()?;
+    if hours > 14 || minutes > 59 {
+        return None;
+    }
+    FixedOffset::east_opt(sign * (hours * 3600 + minutes * 60))
+}
+
+/// A message as one line of transcript, e.g. `[09:12] mara: Shipping now.` The
+/// channel is named in front when given, for transcripts that span channels.
+fn format_message_for_clipboard(
+    message: &Message,
+    channel: Option<&Channel>,
+    identity: &Identity,
+    roster: &Roster,
+    clock: &ClockSettings,
+) -> String {
+    let prefix = match channel.map(|channel| (channel.kind, &channel.name)) {
+        Some((ChannelKind::Channel, name)) => format!("#{name} "),
+        Some((ChannelKind::DirectMessage, name)) => format!("@{name} "),
+        None => String::new(),
+    };
+    format!(
+        "{prefix}[{}] {}: {}",
+        format_clock(&message.sent_at, clock),
+        roster.label(identity, &message.author),
+        message.body
+    )
+}
+
+/// `message` as a Markdown blockquote led by its author, e.g.
+/// `> mara: Shipping now.`, ending in a newline so a reply can follow it.
+fn format_message_as_quote(message: &Message) -> String {
+    let mut quote = String::new();
+    for (index, line) in message.body.lines().enumerate() {
+        let line = if index == 0 {
+            format!("{}: {line}", message.author)
+        } else {
+            line.to_string()
+        };
+        quote.push_str(format!("> {line}").trim_end());
+        quote.push('\n');
+    }
+    if quote.is_empty() {
+        quote = format!("> {}:\n", message.author);
+    }
+    quote
+}
+
+/// The body of a forwarded copy of `message`, naming who wrote it and where.
+fn format_forwarded_body(message: &Message, channel: Option<&Channel>) -> String {
+    let source = match channel.map(|channel| (channel.kind, &channel.name)) {
+        Some((ChannelKind::Channel, name)) => format!(" in #{name}"),
+        Some((ChannelKind::DirectMessage, name)) => format!(" in @{name}"),
+        None => String::new(),
+    };
+    format!("Forwarded from {}{source}:\n{}", message.author, message.body)
+}
+
+/// Renders a stored timestamp as a wall-clock time. Legacy bare `HH:MM` values (and
+/// anything else that isn't RFC 3339) are shown verbatim.
+fn format_clock(ts: &str, settings: &ClockSettings) -> String {
+    let Some(instant) = parse_timestamp(ts) else {
+        return ts.to_string();
+    };
+    let local = settings.localize(instant);
+    match settings.style {
+        ClockStyle::TwentyFourHour => local.format("%H:%M").to_string(),
+        ClockStyle::TwelveHour => local.format("%-I:%M %p").to_string(),
+    }
+}
+
+/// How long ago something happened, coarsely: "just now", "5m ago", "3h ago", "2d ago".
+fn format_relative_duration(elapsed: Duration) -> String {
+    let secs = elapsed.as_secs();
+    match secs {
+        0..=59 => "just now".to_string(),
+        60..=3599 => format!("{}m ago", secs / 60),
+        3600..=86_399 => format!("{}h ago", secs / 3600),
+        _ => format!("{}d ago", secs / 86_400),
+    }
+}
+
+/// Longer form for hover text: the date, the clock time, and the offset it is shown in.
+fn format_timestamp_detail(ts: &str, settings: &ClockSettings) -> String {
+    let Some(instant) = parse_timestamp(ts) else {
+        return ts.to_string();
+    };
+    let local = settings.localize(instant);
+    format!(
+        "{} {} (UTC{})",
+        local.format("%a %b %-d, %Y"),
+        format_clock(ts, settings),
+        local.format("%:z")
+    )
+}
+
+/// Whether `message` continues `previous`'s group: same author and channel, sent
+/// no more than `window_minutes` later. Unparseable timestamps never group.
+fn continues_group(previous: &Message, message: &Message, window_minutes: i64) -> bool {
+    if previous.channel_id != message.channel_id
+        || !previous.author.eq_ignore_ascii_case(&message.author)
+    {
+        return false;
+    }
+    match (parse_timestamp(&previous.sent_at), parse_timestamp(&message.sent_at)) {
+        (Some(previous), Some(sent)) => {
+            let gap = sent.signed_duration_since(previous);
+            gap >= chrono::Duration::zero() && gap <= chrono::Duration::minutes(window_minutes)
+        }
+        _ => false,
+    }
+}
+
+/// Whether `edited_at` is far enough from `sent_at` to be worth flagging. Saves
+/// within the grace window (e.g. a store that stamps both on insert) don't count.
+fn is_meaningful_edit(sent_at: &str, edited_at: &str) -> bool {
+    match (parse_timestamp(sent_at), parse_timestamp(edited_at)) {
+        (Some(sent), Some(edited)) => {
+            edited.signed_duration_since(sent).num_seconds() >= EDITED_MARKER_GRACE_SECS
+        }
+        _ => edited_at.trim() != sent_at.trim(),
+    }
+}
+
+/// Inserts `message` after every message that sorts at or before it, so a late
+/// arrival with an older timestamp lands in place instead of at the end. Returns
+/// its index.
+fn insert_message_sorted(messages: &mut Vec<Message>, message: Message) -> usize {
+    let index = messages.partition_point(|existing| {
+        compare_message_order(existing, &message) != std::cmp::Ordering::Greater
+    });
+    messages.insert(index, message);
+    index
+}
+
+fn default_composer_placeholder(channel: &Channel) -> String {
+    match channel.kind {
+        ChannelKind::Channel => format!("Message #{}", channel.name),
+        ChannelKind::DirectMessage => format!("Message @{}", channel.name),
+    }
+}
+
+fn build_composer_meta(
+    channels: &[Channel],
+    channel_state: &HashMap<i64, ChannelState>,
+) -> HashMap<i64, ComposerMeta> {
+    let mut meta = HashMap::new();
+    for channel in channels {
+        let placeholder = channel_state
+            .get(&channel.id)
+            .and_then(|state| state.composer_placeholder.clone())
+            .unwrap_or_else(|| default_composer_placeholder(channel));
+        let typing_stub = match channel.kind {
+            ChannelKind::Channel => "No one is typing.".to_string(),
+            ChannelKind::DirectMessage => format!("{} is typing...", channel.name),
+        };
+        meta.insert(
+            channel.id,
+            ComposerMeta {
+                placeholder,
+                typing_stub,
+            },
+        );
+    }
+    meta
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum PresenceStatus {
+    Online,
+    Away,
+    Offline,
+    Unknown,
+}
+
+impl PresenceStatus {
+    fn from_str(value: &str) -> Self {
+        match value.to_ascii_lowercase().as_str() {
+            "online" => PresenceStatus::Online,
+            "away" => PresenceStatus::Away,
+            "offline" => PresenceStatus::Offline,
+            _ => PresenceStatus::Unknown,
+        }
+    }
+
+    fn label(self) -> &'static str {
+        match self {
+            PresenceStatus::Online => "online",
+            PresenceStatus::Away => "away",
+            PresenceStatus::Offline => "offline",
+            PresenceStatus::Unknown => "unknown",
+        }
+    }
+
+    fn color(self) -> egui::Color32 {
+        match self {
+            PresenceStatus::Online => egui::Color32::from_rgb(120, 210, 120),
+            PresenceStatus::Away => egui::Color32::from_rgb(220, 180, 80),
+            PresenceStatus::Offline => egui::Color32::from_rgb(130, 140, 160),
+            PresenceStatus::Unknown => egui::Color32::from_rgb(120, 130, 150),
+        }
+    }
+
+    /// Draws the status shape: a filled circle when online, a diamond when away, a
+    /// ring when offline, and a dash when unknown.
+    fn paint_shape(self, painter: &egui::Painter, center: egui::Pos2, radius: f32) {
+        let color = self.color();
+        match self {
+            PresenceStatus::Online => painter.circle_filled(center, radius, color),
+            PresenceStatus::Away => painter.add(egui::Shape::convex_polygon(
+                vec![
+                    center - egui::vec2(0.0, radius),
+                    center + egui::vec2(radius, 0.0),
+                    center + egui::vec2(0.0, radius),
+                    center - egui::vec2(radius, 0.0),
+                ],
+                color,
+                egui::Stroke::NONE,
+            )),
+            PresenceStatus::Offline => {
+                painter.circle_stroke(center, radius - 0.75, egui::Stroke::new(1.5, color))
+            }
+            PresenceStatus::Unknown => painter.hline(
+                center.x - radius..=center.x + radius,
+                center.y,
+                egui::Stroke::new(1.5, color),
+            ),
+        };
+    }
+
+    fn sort_rank(self) -> u8 {
+        match self {
+            PresenceStatus::Online => 0,
+            PresenceStatus::Away => 1,
+            PresenceStatus::Offline | PresenceStatus::Unknown => 2,
+        }
+    }
+}
+
+struct PresenceState {
+    status: PresenceStatus,
+    last_seen: Instant,
+}
+
+/// The presence history view, shown in place of the message list.
+#[derive(Default)]
+struct PresenceHistoryView {
+    name_filter: String,
+    status_filter: Option<PresenceStatus>,
+    entries: Vec<PresenceChange>,
+}
+
+struct SearchRequest {
+    query: String,
+    channel_only: bool,
+}
+
+struct FindState {
+    query: String,
+    current: usize,
+    focus_requested: bool,
+}
+
+/// The open command palette: the typed filter and the highlighted row.
+#[derive(Default)]
+struct CommandPalette {
+    query: String,
+    selected: usize,
+}
+
+enum PaletteChoice {
+    Run(ShortcutAction),
+    Dismiss,
+}
+
+/// An open "Clear channel history" prompt. The clear only goes ahead once the
+/// channel's name has been typed back.
+struct HistoryClearConfirm {
+    channel_id: i64,
+    typed_name: String,
+    delete_files: bool,
+}
+
+/// A file from the path box that matches one already attached, held ready (hashed and
+/// stored) until the user adds it anyway or cancels.
+struct DuplicateAttachment {
+    channel_id: i64,
+    attachment: PendingAttachment,
+    reason: String,
+}
+
+/// Where a file being attached came from, which decides what happens once it has
+/// been read.
+enum IngestSource {
+    /// The composer's path box; checked for duplicates before it is added.
+    PathBox,
+    /// A pasted file path, whose text leaves the draft once the file is attached.
+    Paste(String),
+    /// A region capture in the temp dir, removed once copied into the store.
+    Screenshot,
+}
+
+/// A file hashed, and copied into the store when one is set, off the UI thread.
+struct IngestResult {
+    channel_id: i64,
+    path: PathBuf,
+    source: IngestSource,
+    attachment: Result<PendingAttachment, String>,
+}
+
+/// A composer paste that was nothing but the path of an existing file, offered as
+/// an attachment instead of text.
+struct PastedPath {
+    channel_id: i64,
+    /// The pasted text, removed from the draft if the file is attached.
+    text: String,
+    path: PathBuf,
+}
+
+/// Which program an attachment Open request launches.
+enum OpenWith {
+    Default,
+    /// Passed to `open_attachment_with_args` as the application override.
+    App(String),
+    /// The platform's own "Open with" chooser.
+    Chooser,
+}
+
+/// An attachment hashed off the UI thread before it is opened.
+struct VerifyResult {
+    attachment: Attachment,
+    open_with: OpenWith,
+    hash: Result<String, String>,
+}
+
+/// An attachment that no longer matches its recorded hash, opened only if the user
+/// goes ahead anyway.
+struct OpenConfirm {
+    attachment: Attachment,
+    open_with: OpenWith,
+    warning: String,
+}
+
+/// What a thumbnail worker reports: an image's header size as soon as it is read,
+/// so its preview space can be reserved, then the decoded thumbnail.
+enum ThumbnailUpdate {
+    Dimensions(String, Option<(u32, u32)>),
+    Done(ThumbnailResult),
+}
+
+struct ThumbnailResult {
+    path: String,
+    image: Option<egui::ColorImage>,
+    error: Option<String>,
+    mtime: Option<SystemTime>,
+}
+
+/// A thumbnail waiting for a worker. The render loop re-requests it every frame its
+/// image is laid out, refreshing `visible`; a job not requested in a frame is dropped.
+struct ThumbnailJob {
+    path: String,
+    max_dimension: u32,
+    /// Whether the image is inside the viewport, rather than laid out but clipped.
+    visible: bool,
+    requested_frame: u64,
+}
+
+/// Thumbnail jobs shared with the worker threads, which take visible images first.
+#[derive(Default)]
+struct ThumbnailQueue {
+    jobs: Mutex<Vec<ThumbnailJob>>,
+    ready: Condvar,
+}
+
+impl ThumbnailQueue {
+    fn jobs(&self) -> std::sync::MutexGuard<'_, Vec<ThumbnailJob>> {
+        self.jobs.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Blocks until there is a job, then takes the oldest visible one, or the oldest.
+    fn take(&self) -> ThumbnailJob {
+        let mut jobs = self.jobs();
+        loop {
+            let next = jobs
+                .iter()
+                .position(|job| job.visible)
+                .or((!jobs.is_empty()).then_some(0));
+            if let Some(index) = next {
+                return jobs.remove(index);
+            }
+            jobs = self.ready.wait(jobs).unwrap_or_else(PoisonError::into_inner);
+        }
+    }
+}
+
+fn spawn_thumbnail_workers(
+    queue: &Arc<ThumbnailQueue>,
+    sender: &mpsc::Sender<ThumbnailUpdate>,
+    event_proxy: &EventLoopProxy<UserEvent>,
+) {
+    for _ in 0..THUMBNAIL_WORKERS {
+        let queue = Arc::clone(queue);
+        let sender = sender.clone();
+        let event_proxy = event_proxy.clone();
+        thread::spawn(move || loop {
+            let ThumbnailJob {
+                path,
+                max_dimension,
+                ..
+            } = queue.take();
+            let dimensions = read_image_dimensions(&path);
+            if sender.send(ThumbnailUpdate::Dimensions(path.clone(), dimensions)).is_err() {
+                break;
+            }
+            let _ = event_proxy.send_event(UserEvent::Wake);
+            let mtime = file_mtime(&path);
+            let result = match load_attachment_thumbnail_image(&path, max_dimension) {
+                Ok(image) => ThumbnailResult {
+                    path,
+                    image: Some(image),
+                    error: None,
+                    mtime,
+                },
+                Err(error) => ThumbnailResult {
+                    path,
+                    image: None,
+                    error: Some(error),
+                    mtime,
+                },
+            };
+            if sender.send(ThumbnailUpdate::Done(result)).is_err() {
+                break;
+            }
+            let _ = event_proxy.send_event(UserEvent::Wake);
+        });
+    }
+}
+
+/// The image and video attachments of one channel, newest first, loaded a page at a
+/// time. Each item carries its attachment row id, which pages are keyed on.
+struct Gallery {
+    channel_id: i64,
+    items: Vec<(i64, Attachment)>,
+    has_more: bool,
+}
+
+/// An image shown full-size over the app, decoded off the UI thread.
+struct Lightbox {
+    attachment: Attachment,
+    texture: Option<egui::TextureHandle>,
+    error: Option<String>,
+}
+
+/// A finished region capture: the saved image, or `None` when it was cancelled.
+struct ScreenshotResult {
+    channel_id: i64,
+    capture: Result<Option<PathBuf>, String>,
+}
+
+struct LightboxResult {
+    path: String,
+    image: Result<egui::ColorImage, String>,
+}
+
+enum LightboxAction {
+    Close,
+    Open,
+    JumpToMessage,
+}
+
+struct ThumbnailError {
+    message: String,
+    /// Source mtime at the failed attempt; a different mtime later triggers a retry.
+    mtime: Option<SystemTime>,
+    checked_at: Instant,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum ShortcutAction {
+    ToggleShortcutHelp,
+    CloseOverlay,
+    ToggleFrameStats,
+    OpenFind,
+    ZoomIn,
+    ZoomOut,
+    ZoomReset,
+    OpenCommandPalette,
+    Connect,
+    Disconnect,
+    MarkAllRead,
+    ToggleGallery,
+    TogglePresenceHistory,
+    ToggleSavedView,
+    FlushDatabase,
+}
+
+enum ShortcutKeys {
+    /// Dispatched by `App::handle_shortcuts`. `while_typing` shortcuts also fire when a
+    /// text field has focus.
+    Global {
+        shortcut: egui::KeyboardShortcut,
+        action: ShortcutAction,
+        while_typing: bool,
+    },
+    /// Handled by the focused widget itself; listed only so the help sheet covers it.
+    InField(&'static str),
+    /// No keybinding; run from the command palette only and left off the help sheet.
+    Unbound(ShortcutAction),
+}
+
+impl ShortcutKeys {
+    /// The app action behind these keys, for entries the command palette can run.
+    fn action(&self) -> Option<ShortcutAction> {
+        match self {
+            ShortcutKeys::Global { action, .. } | ShortcutKeys::Unbound(action) => Some(*action),
+            ShortcutKeys::InField(_) => None,
+        }
+    }
+}
+
+struct Shortcut {
+    category: &'static str,
+    description: &'static str,
+    keys: ShortcutKeys,
+}
+
+/// Every keybinding and app action. The help overlay and the command palette are
+/// both rendered from this list, so an action added here is documented and
+/// searchable automatically.
+const SHORTCUTS: &[Shortcut] = &[
+    Shortcut {
+        category: "General",
+        description: "Show keyboard shortcuts",
+        keys: ShortcutKeys::Global {
+            shortcut: egui::KeyboardShortcut::new(egui::Modifiers::NONE, egui::Key::Questionmark),
+            action: ShortcutAction::ToggleShortcutHelp,
+            while_typing: false,
+        },
+    },
+    Shortcut {
+        category: "General",
+        description: "Show keyboard shortcuts",
+        keys: ShortcutKeys::Global {
+            shortcut: egui::KeyboardShortcut::new(egui::Modifiers::COMMAND, egui::Key::Slash),
+            action: ShortcutAction::ToggleShortcutHelp,
+            while_typing: true,
+        },
+    },
+    Shortcut {
+        category: "General",
+        description: "Open command palette",
+        keys: ShortcutKeys::Global {
+            shortcut: egui::KeyboardShortcut::new(
+                egui::Modifiers::COMMAND.plus(egui::Modifiers::SHIFT),
+                egui::Key::P,
+            ),
+            action: ShortcutAction::OpenCommandPalette,
+            while_typing: true,
+        },
+    },
+    Shortcut {
+        category: "General",
+        description: "Close overlay",
+        keys: ShortcutKeys::Global {
+            shortcut: egui::KeyboardShortcut::new(egui::Modifiers::NONE, egui::Key::Escape),
+            action: ShortcutAction::CloseOverlay,
+            while_typing: true,
+        },
+    },
+    Shortcut {
+        category: "View",
+        description: "Make text larger",
+        keys: ShortcutKeys::Global {
+            shortcut: egui::KeyboardShortcut::new(egui::Modifiers::COMMAND, egui::Key::Plus),
+            action: ShortcutAction::ZoomIn,
+            while_typing: true,
+        },
+    },
+    Shortcut {
+        category: "View",
+        description: "Make text larger",
+        keys: ShortcutKeys::Global {
+            shortcut: egui::KeyboardShortcut::new(egui::Modifiers::COMMAND, egui::Key::Equals),
+            action: ShortcutAction::ZoomIn,
+            while_typing: true,
+        },
+    },
+    Shortcut {
+        category: "View",
+        description: "Make text smaller",
+        keys: ShortcutKeys::Global {
+            shortcut: egui::KeyboardShortcut::new(egui::Modifiers::COMMAND, egui::Key::Minus),
+            action: ShortcutAction::ZoomOut,
+            while_typing: true,
+        },
+    },
+    Shortcut {
+        category: "View",
+        description: "Reset text size",
+        keys: ShortcutKeys::Global {
+            shortcut: egui::KeyboardShortcut::new(egui::Modifiers::COMMAND, egui::Key::Num0),
+            action: ShortcutAction::ZoomReset,
+            while_typing: true,
+        },
+    },
+    Shortcut {
+        category: "Find in view",
+        description: "Find text in the loaded messages",
+        keys: ShortcutKeys::Global {
+            shortcut: egui::KeyboardShortcut::new(egui::Modifiers::COMMAND, egui::Key::F),
+            action: ShortcutAction::OpenFind,
+            while_typing: true,
+        },
+    },
+    Shortcut {
+        category: "Find in view",
+        description: "Next match",
+        keys: ShortcutKeys::InField("Enter"),
+    },
+    Shortcut {
+        category: "Find in view",
+        description: "Previous match",
+        keys: ShortcutKeys::InField("Shift+Enter"),
+    },
+    Shortcut {
+        category: "Composer",
+        description: "Send message",
+        keys: ShortcutKeys::InField("Enter"),
+    },
+    Shortcut {
+        category: "Search",
+        description: "Run search",
+        keys: ShortcutKeys::InField("Enter"),
+    },
+    Shortcut {
+        category: "Channel",
+        description: "Save topic while editing",
+        keys: ShortcutKeys::InField("Enter"),
+    },
+    Shortcut {
+        category: "Channel",
+        description: "Show or hide the media gallery",
+        keys: ShortcutKeys::Unbound(ShortcutAction::ToggleGallery),
+    },
+    Shortcut {
+        category: "General",
+        description: "Show or hide presence history",
+        keys: ShortcutKeys::Unbound(ShortcutAction::TogglePresenceHistory),
+    },
+    Shortcut {
+        category: "General",
+        description: "Show or hide saved messages from every channel",
+        keys: ShortcutKeys::Unbound(ShortcutAction::ToggleSavedView),
+    },
+    Shortcut {
+        category: "Channel",
+        description: "Mark all channels read",
+        keys: ShortcutKeys::Unbound(ShortcutAction::MarkAllRead),
+    },
+    Shortcut {
+        category: "General",
+        description: "Flush the database to disk, as before a backup",
+        keys: ShortcutKeys::Unbound(ShortcutAction::FlushDatabase),
+    },
+    Shortcut {
+        category: "Connection",
+        description: "Connect to the realtime server",
+        keys: ShortcutKeys::Unbound(ShortcutAction::Connect),
+    },
+    Shortcut {
+        category: "Connection",
+        description: "Disconnect from the realtime server",
+        keys: ShortcutKeys::Unbound(ShortcutAction::Disconnect),
+    },
+    Shortcut {
+        category: "Debug",
+        description: "Toggle frame timing overlay",
+        keys: ShortcutKeys::Global {
+            shortcut: egui::KeyboardShortcut::new(egui::Modifiers::NONE, egui::Key::F3),
+            action: ShortcutAction::ToggleFrameStats,
+            while_typing: true,
+        },
+    },
+];
+
+/// Rolling frame timing for the F3 overlay. Frame time is CPU time spent in `render`;
+/// the interval between presented frames gives the effective repaint rate.
+#[derive(Default)]
+struct FrameStats {
+    frame_times: VecDeque<Duration>,
+    intervals: VecDeque<Duration>,
+    last_frame_at: Option<Instant>,
+    messages_rendered: usize,
+    thumbnails_rendered: usize,
+}
+
+impl FrameStats {
+    fn record(&mut self, started: Instant) {
+        let now = Instant::now();
+        self.frame_times.push_back(now - started);
+        if let Some(last) = self.last_frame_at {
+            self.intervals.push_back(now - last);
+        }
+        self.last_frame_at = Some(now);
+        while self.frame_times.len() > FRAME_STATS_WINDOW {
+            self.frame_times.pop_front();
+        }
+        while self.intervals.len() > FRAME_STATS_WINDOW {
+            self.intervals.pop_front();
+        }
+    }
+
+    fn average_ms(samples: &VecDeque<Duration>) -> f64 {
+        if samples.is_empty() {
+            return 0.0;
+        }
+        let total: Duration = samples.iter().sum();
+        total.as_secs_f64() * 1000.0 / samples.len() as f64
+    }
+
+    fn frame_ms(&self) -> f64 {
+        Self::average_ms(&self.frame_times)
+    }
+
+    fn fps(&self) -> f64 {
+        let interval_ms = Self::average_ms(&self.intervals);
+        if interval_ms > 0.0 {
+            1000.0 / interval_ms
+        } else {
+            0.0
+        }
+    }
+}
+
+struct TextPreviewResult {
+    path: String,
+    text: Option<String>,
+}
+
+struct DeferredLoadResult {
+    channel_id: i64,
+    channels: Vec<Channel>,
+    messages: Vec<Message>,
+    attachments: HashMap<i64, Vec<Attachment>>,
+    channel_members: HashMap<i64, HashSet<String>>,
+    saved_messages: HashSet<i64>,
+    pinned_messages: HashSet<i64>,
+    message_reactions: HashMap<i64, Vec<MessageReaction>>,
+    drafts: HashMap<i64, String>,
+    last_read: HashMap<i64, i64>,
+    settings: HashMap<String, String>,
+    channel_state: HashMap<i64, ChannelState>,
+    users: Vec<User>,
+    first_run: bool,
+    db_ready: bool,
+    /// Schema version of a database written by a newer build, which is opened read-only.
+    newer_schema: Option<i64>,
+    /// The OS reduce-motion setting, when the plan asked for it to be read.
+    system_reduce_motion: Option<bool>,
+}
+
+struct DeferredLoadPlan {
+    channel_id: i64,
+    channels: Vec<Channel>,
+    demo_mode: bool,
+    clean_orphans: bool,
+    db_path: PathBuf,
+    /// Query the OS reduce-motion setting; skipped when `RALPH_REDUCE_MOTION` decides.
+    detect_reduce_motion: bool,
+    /// Another running app holds the database, so load without migrating or seeding.
+    read_only: bool,
+}
+
+struct OnboardingForm {
+    username: String,
+    server_url: String,
+    channel_name: String,
+    error: Option<String>,
+}
+
+struct App {
+    window: Arc<Window>,
+    surface: wgpu::Surface<'static>,
+    device: wgpu::Device,
+    queue: wgpu::Queue,
+    config: wgpu::SurfaceConfiguration,
+    egui_state: EguiWinitState,
+    egui_ctx: egui::Context,
+    egui_renderer: Renderer,
+    boot_started: Instant,
+    next_repaint_at: Instant,
+    needs_repaint: bool,
+    window_focused: bool,
+    window_occluded: bool,
+    /// The title last given to the window, which carries the unread count.
+    window_title: String,
+    /// Set when messages arrive or read positions move, so the count is redone.
+    unread_title_dirty: bool,
+    first_frame_logged: bool,
+    /// `RALPH_STARTUP_BENCH`: report startup milestones once the database is loaded,
+    /// then exit.
+    startup_bench: bool,
+    first_frame_ms: f64,
+    exit_requested: bool,
+    started_at: Instant,
+    app_config: AppConfig,
+    /// Index into `app_config.workspaces` of the database currently open.
+    active_workspace: usize,
+    db: Connection,
+    db_is_fallback: bool,
+    /// Set when the database came from a newer build; it is then open read-only.
+    db_newer_schema: Option<i64>,
+    /// Held while this app owns the workspace database.
+    instance_lock: Option<InstanceLock>,
+    /// Process id of another running app that holds the workspace database, until
+    /// you choose to open it read-only or exit.
+    instance_conflict: Option<u32>,
+    /// Opened read-only because another running app holds the database.
+    read_only_instance: bool,
+    channels: Vec<Channel>,
+    messages: Vec<Message>,
+    selected_channel_id: i64,
+    composer_drafts: HashMap<i64, String>,
+    /// Last measured height of each message row, keyed by message id and whether it
+    /// was shown as a search result, for skipping rows outside the viewport.
+    row_heights: HashMap<(i64, bool), f32>,
+    /// Width the `row_heights` were measured at.
+    row_heights_width: f32,
+    /// Channel whose send is held for a "you're posting in #name" confirmation.
+    send_confirm: Option<i64>,
+    /// Channels that got a mention or highlight word while you were elsewhere.
+    highlighted_channels: HashSet<i64>,
+    composer_focus_requested: bool,
+    composer_meta: HashMap<i64, ComposerMeta>,
+    typing_state: HashMap<i64, Instant>,
+    realtime: RealtimeClient,
+    channel_members: HashMap<i64, HashSet<String>>,
+    presence_state: HashMap<String, PresenceState>,
+    presence_counts: HashMap<i64, (usize, usize)>,
+    presence_counts_dirty: bool,
+    sidebar_filter: String,
+    search_query: String,
+    search_last_query: String,
+    search_results: Vec<Message>,
+    search_total: i64,
+    search_channel_only: bool,
+    search_last_channel_only: bool,
+    messages_loaded: bool,
+    saved_messages: HashSet<i64>,
+    pinned_messages: HashSet<i64>,
+    /// Own messages the server never acked, even after a resend; they offer a retry.
+    failed_sends: HashSet<i64>,
+    /// How each of your messages still in the realtime outbox was held back.
+    held_sends: HashMap<i64, SendOutcome>,
+    /// `failed_sends` of the workspaces switched away from, restored on switching back.
+    stashed_failed_sends: HashMap<usize, HashSet<i64>>,
+    roster: Roster,
+    show_saved_only: bool,
+    show_pinned_only: bool,
+    collapse_repeats: bool,
+    sort_dms_by_presence: bool,
+    onboarding: Option<OnboardingForm>,
+    /// Name typed into the "create your first channel" prompt shown when the
+    /// database has no channels and onboarding is not running.
+    first_channel_name: String,
+    first_channel_error: Option<String>,
+    /// Username typed into the sidebar's new direct message field, while it is open.
+    dm_draft: Option<String>,
+    dm_error: Option<String>,
+    topic_draft: Option<String>,
+    channel_state: HashMap<i64, ChannelState>,
+    placeholder_draft: Option<String>,
+    announcement_draft: Option<String>,
+    channel_delete_confirm: Option<i64>,
+    history_clear_confirm: Option<HistoryClearConfirm>,
+    expanded_messages: HashSet<i64>,
+    show_frame_stats: bool,
+    frame_stats: FrameStats,
+    show_shortcut_help: bool,
+    command_palette: Option<CommandPalette>,
+    find: Option<FindState>,
+    message_attachments: HashMap<i64, Vec<Attachment>>,
+    message_reactions: HashMap<i64, Vec<MessageReaction>>,
+    attachment_path_drafts: HashMap<i64, String>,
+    pending_attachments: HashMap<i64, Vec<PendingAttachment>>,
+    last_read: HashMap<i64, i64>,
+    unread_marker: Option<i64>,
+    /// Channel and id of the newest message shown last frame, to spot new arrivals.
+    newest_rendered: Option<(i64, i64)>,
+    scroll_to_message: Option<i64>,
+    /// Channel and oldest loaded id once paging above it found nothing older.
+    older_messages_exhausted: Option<(i64, i64)>,
+    /// Channel and newest loaded id after newer rows were dropped to stay under
+    /// `max_loaded_messages`; they page back in from SQLite at the bottom.
+    newer_messages_trimmed: Option<(i64, i64)>,
+    /// Row to hold in place while a history page loads around it.
+    history_anchor: Option<(i64, egui::Align)>,
+    attachment_error: Option<String>,
+    /// Why the composer's last send in a channel failed; its text stays in the draft.
+    send_error: Option<(i64, String)>,
+    attachment_action_error: Option<String>,
+    attachment_action_notice: Option<String>,
+    attachment_duplicate: Option<DuplicateAttachment>,
+    ingest_sender: mpsc::Sender<IngestResult>,
+    ingest_receiver: mpsc::Receiver<IngestResult>,
+    /// Files still being hashed or copied for the composer.
+    ingests_in_flight: usize,
+    pasted_path: Option<PastedPath>,
+    /// Most recently used emoji first, saved as the `recent_emoji` setting.
+    recent_emoji: Vec<String>,
+    attachments_expanded: bool,
+    saved_action_error: Option<String>,
+    pinned_action_error: Option<String>,
+    reaction_action_error: Option<String>,
+    maintenance_status: Option<String>,
+    attachment_thumbnails: HashMap<String, egui::TextureHandle>,
+    attachment_thumbnail_errors: HashMap<String, ThumbnailError>,
+    /// Display dimensions read from image headers by the thumbnail workers, so a
+    /// preview's space is reserved before it decodes. `None` when the header couldn't
+    /// be read.
+    image_dimensions: HashMap<String, Option<(u32, u32)>>,
+    image_dimensions_order: VecDeque<String>,
+    /// Failed preview attempts per path, kept past eviction from the error cache.
+    thumbnail_failures: HashMap<String, u32>,
+    /// Image attachments that kept failing to preview and are now shown as files.
+    downgraded_images: HashSet<String>,
+    thumbnail_cache_order: VecDeque<String>,
+    thumbnail_error_order: VecDeque<String>,
+    thumbnail_receiver: mpsc::Receiver<ThumbnailUpdate>,
+    thumbnail_queue: Arc<ThumbnailQueue>,
+    /// Counts rendered frames, to tell which queued thumbnails are still requested.
+    thumbnail_frame: u64,
+    thumbnail_in_flight: HashSet<String>,
+    /// Messages picked for a transcript, forwarding, or deletion. A shift-click or
+    /// Shift+Up/Down spans from `selection_anchor` (the last plain pick) to
+    /// `selection_focus`, replacing the previous span from the same anchor.
+    selected_messages: HashSet<i64>,
+    selection_anchor: Option<i64>,
+    selection_focus: Option<i64>,
+    selection_delete_confirm: bool,
+    gallery: Option<Gallery>,
+    presence_history: Option<PresenceHistoryView>,
+    /// Saved messages from every channel, listed in place of the message list.
+    saved_view: Option<Vec<SavedMessage>>,
+    lightbox: Option<Lightbox>,
+    /// Skip animations: set from `RALPH_REDUCE_MOTION` or the OS preference.
+    reduce_motion: bool,
+    lightbox_sender: mpsc::Sender<LightboxResult>,
+    lightbox_receiver: mpsc::Receiver<LightboxResult>,
+    /// Set while a region capture is open; at most one runs at a time.
+    screenshot_receiver: Option<mpsc::Receiver<ScreenshotResult>>,
+    /// Where a "Save as…" copy landed, or `None` if the dialog was cancelled.
+    save_copy_receiver: Option<mpsc::Receiver<Result<Option<PathBuf>, String>>>,
+    /// Set while "Flush database" runs; carries its summary or error.
+    flush_receiver: Option<mpsc::Receiver<Result<String, String>>>,
+    flush_result: Option<Result<String, String>>,
+    /// Progress of the retention sweep in flight.
+    retention_sweep: Option<RetentionSweep>,
+    retention_swept_at: Option<Instant>,
+    text_previews: HashMap<String, Option<String>>,
+    text_preview_order: VecDeque<String>,
+    text_preview_sender: mpsc::Sender<TextPreviewResult>,
+    text_preview_receiver: mpsc::Receiver<TextPreviewResult>,
+    text_preview_in_flight: HashSet<String>,
+    expanded_text_previews: HashSet<String>,
+    /// Applications registered for each attachment's type, looked up when its
+    /// "Open with" menu is first opened (Linux only).
+    open_with_apps: HashMap<String, Vec<DesktopApp>>,
+    /// Set while one attachment's applications are being looked up.
+    open_with_apps_receiver: Option<mpsc::Receiver<(String, Vec<DesktopApp>)>>,
+    /// Set while the platform's application chooser is open.
+    chooser_receiver: Option<mpsc::Receiver<Result<(), String>>>,
+    /// Set while an attachment is checked against its hash before opening.
+    verify_receiver: Option<mpsc::Receiver<VerifyResult>>,
+    open_confirm: Option<OpenConfirm>,
+    deferred_load_receiver: Option<mpsc::Receiver<DeferredLoadResult>>,
+    deferred_load_plan: Option<DeferredLoadPlan>,
+    event_proxy: EventLoopProxy<UserEvent>,
+}
+
+impl App {
+    fn new(
+        event_loop: &EventLoop<UserEvent>,
+        event_proxy: EventLoopProxy<UserEvent>,
+        boot_started: Instant,
+        startup_bench: bool,
+        app_config: AppConfig,
+    ) -> Self {
+        let window = Arc::new(
+            WindowBuilder::new()
+                .with_title(window_title(0))
+                .with_inner_size(PhysicalSize::new(1100, 720))
+                .build(event_loop)
+                .expect("window"),
+        );
+
+        let instance = wgpu::Instance::new(wgpu::InstanceDescriptor {
+            backends: wgpu::Backends::METAL,
+            ..Default::default()
+        });
+        let surface = instance
+            .create_surface(window.clone())
+            .expect("create surface");
+        let adapter = pollster::block_on(instance.request_adapter(&wgpu::RequestAdapterOptions {
+            power_preference: wgpu::PowerPreference::LowPower,
+            compatible_surface: Some(&surface),
+            force_fallback_adapter: false,
+        }))
+        .expect("adapter");
+        let (device, queue) = pollster::block_on(adapter.request_device(
+            &wgpu::DeviceDescriptor {
+                label: Some("ralph-device"),
+                required_features: wgpu::Features::empty(),
+                required_limits: wgpu::Limits::default(),
+            },
+            None,
+        ))
+        .expect("device");
+
+        let size = window.inner_size();
+        let surface_caps = surface.get_capabilities(&adapter);
+        let surface_format = surface_caps
+            .formats
+            .iter()
+            .copied()
+            .find(|format| format.is_srgb())
+            .unwrap_or(surface_caps.formats[0]);
+        let config = wgpu::SurfaceConfiguration {
+            usage: TextureUsages::RENDER_ATTACHMENT,
+            format: surface_format,
+            width: size.width.max(1),
+            height: size.height.max(1),
+            present_mode: PresentMode::Fifo,
+            desired_maximum_frame_latency: 2,
+            alpha_mode: surface_caps
+                .alpha_modes
+                .iter()
+                .copied()
+                .find(|mode| *mode == CompositeAlphaMode::Opaque)
+                .unwrap_or(surface_caps.alpha_modes[0]),
+            view_formats: vec![],
+        };
+        surface.configure(&device, &config);
+
+        let egui_ctx = egui::Context::default();
+        // Zoom shortcuts go through the shortcut registry so the scale can be saved.
+        egui_ctx.options_mut(|options| options.zoom_with_keyboard = false);
+        let reduce_motion = app_config.reduce_motion.unwrap_or(false);
+        if reduce_motion {
+            egui_ctx.style_mut(|style| style.animation_time = 0.0);
+        }
+        let egui_state = EguiWinitState::new(
+            egui_ctx.clone(),
+            egui::ViewportId::ROOT,
+            window.as_ref(),
+            None,
+            None,
+        );
+        let egui_renderer = Renderer::new(&device, surface_format, None, 1);
+
+        let db = Connection::open_in_memory().expect("memory db");
+        let seeded = if app_config.demo_mode {
+            seed_channels()
+        } else {
+            Vec::new()
+        };
+        let channels: Vec<Channel> = seeded
+            .into_iter()
+            .map(|(id, name, kind)| Channel {
+                id,
+                name: name.to_string(),
+                kind,
+                topic: String::new(),
+                archived: false,
+            })
+            .collect();
+        let selected_channel_id = channels.first().map(|channel| channel.id).unwrap_or(1);
+        let composer_meta = build_composer_meta(&channels, &HashMap::new());
+        let messages = Vec::new();
+        let (instance_lock, instance_conflict) =
+            match InstanceLock::acquire(&app_config.workspaces[0].db_path) {
+                Ok(lock) => (Some(lock), None),
+                Err(pid) => (None, Some(pid)),
+            };
+        let deferred_load_plan = DeferredLoadPlan {
+            channel_id: selected_channel_id,
+            channels: channels.clone(),
+            demo_mode: app_config.demo_mode,
+            clean_orphans: app_config.clean_orphans,
+            db_path: app_config.workspaces[0].db_path.clone(),
+            detect_reduce_motion: app_config.reduce_motion.is_none(),
+            read_only: false,
+        };
+        let mut presence_state = HashMap::new();
+        presence_state.insert(
+            app_config.identity.username.clone(),
+            PresenceState {
+                status: PresenceStatus::Online,
+                last_seen: Instant::now(),
+            },
+        );
+
+        let (thumbnail_sender, thumbnail_receiver) = mpsc::channel();
+        let thumbnail_queue = Arc::new(ThumbnailQueue::default());
+        spawn_thumbnail_workers(&thumbnail_queue, &thumbnail_sender, &event_proxy);
+        let (lightbox_sender, lightbox_receiver) = mpsc::channel();
+        let (ingest_sender, ingest_receiver) = mpsc::channel();
+        let (text_preview_sender, text_preview_receiver) = mpsc::channel();
+        let realtime = RealtimeClient::new(
+            app_config.server_url.clone(),
+            app_config.identity.clone(),
+            app_config
+                .realtime_log
+                .then(|| realtime_log_path(&app_config.workspaces[0].db_path)),
+            Some(event_proxy.clone()),
+            app_config.invisible,
+        );
+
+        Self {
+            window,
+            surface,
+            device,
+            queue,
+            config,
+            egui_state,
+            egui_ctx,
+            egui_renderer,
+            boot_started,
+            next_repaint_at: Instant::now(),
+            needs_repaint: true,
+            window_focused: true,
+            window_occluded: false,
+            window_title: window_title(0),
+            unread_title_dirty: true,
+            first_frame_logged: false,
+            startup_bench,
+            first_frame_ms: 0.0,
+            exit_requested: false,
+            started_at: Instant::now(),
+            app_config,
+            active_workspace: 0,
+            db,
+            db_is_fallback: true,
+            db_newer_schema: None,
+            instance_lock,
+            instance_conflict,
+            read_only_instance: false,
+            channels,
+            messages,
+            selected_channel_id,
+            composer_drafts: HashMap::new(),
+            row_heights: HashMap::new(),
+            row_heights_width: 0.0,
+            send_confirm: None,
+            highlighted_channels: HashSet::new(),
+            composer_focus_requested: true,
+            composer_meta,
+            typing_state: HashMap::new(),
+            realtime,
+            channel_members: HashMap::new(),
+            presence_state,
+            presence_counts: HashMap::new(),
+            presence_counts_dirty: true,
+            sidebar_filter: String::new(),
+            search_query: String::new(),
+            search_last_query: String::new(),
+            search_results: Vec::new(),
+            search_total: 0,
+            search_channel_only: true,
+            search_last_channel_only: true,
+            messages_loaded: false,
+            saved_messages: HashSet::new(),
+            pinned_messages: HashSet::new(),
+            failed_sends: HashSet::new(),
+            held_sends: HashMap::new(),
+            stashed_failed_sends: HashMap::new(),
+            roster: Roster::default(),
+            show_saved_only: false,
+            show_pinned_only: false,
+            collapse_repeats: true,
+            sort_dms_by_presence: true,
+            onboarding: None,
+            first_channel_name: String::new(),
+            first_channel_error: None,
+            dm_draft: None,
+            dm_error: None,
+            topic_draft: None,
+            channel_state: HashMap::new(),
+            placeholder_draft: None,
+            announcement_draft: None,
+            channel_delete_confirm: None,
+            history_clear_confirm: None,
+            expanded_messages: HashSet::new(),
+            show_frame_stats: false,
+            frame_stats: FrameStats::default(),
+            show_shortcut_help: false,
+            command_palette: None,
+            find: None,
+            message_attachments: HashMap::new(),
+            message_reactions: HashMap::new(),
+            attachment_path_drafts: HashMap::new(),
+            pending_attachments: HashMap::new(),
+            last_read: HashMap::new(),
+            unread_marker: None,
+            newest_rendered: None,
+            scroll_to_message: None,
+            older_messages_exhausted: None,
+            newer_messages_trimmed: None,
+            history_anchor: None,
+            attachment_error: None,
+            send_error: None,
+            attachment_action_error: None,
+            attachment_action_notice: None,
+            attachment_duplicate: None,
+            ingest_sender,
+            ingest_receiver,
+            ingests_in_flight: 0,
+            pasted_path: None,
+            recent_emoji: Vec::new(),
+            attachments_expanded: false,
+            saved_action_error: None,
+            pinned_action_error: None,
+            reaction_action_error: None,
+            maintenance_status: None,
+            attachment_thumbnails: HashMap::new(),
+            attachment_thumbnail_errors: HashMap::new(),
+            image_dimensions: HashMap::new(),
+            image_dimensions_order: VecDeque::new(),
+            thumbnail_failures: HashMap::new(),
+            downgraded_images: HashSet::new(),
+            thumbnail_cache_order: VecDeque::new(),
+            thumbnail_error_order: VecDeque::new(),
+            thumbnail_receiver,
+            thumbnail_queue,
+            thumbnail_frame: 0,
+            thumbnail_in_flight: HashSet::new(),
+            selected_messages: HashSet::new(),
+            selection_anchor: None,
+            selection_focus: None,
+            selection_delete_confirm: false,
+            gallery: None,
+            presence_history: None,
+            saved_view: None,
+            lightbox: None,
+            reduce_motion,
+            lightbox_sender,
+            lightbox_receiver,
+            screenshot_receiver: None,
+            save_copy_receiver: None,
+            flush_receiver: None,
+            flush_result: None,
+            retention_sweep: None,
+            retention_swept_at: None,
+            text_previews: HashMap::new(),
+            text_preview_order: VecDeque::new(),
+            text_preview_sender,
+            text_preview_receiver,
+            text_preview_in_flight: HashSet::new(),
+            expanded_text_previews: HashSet::new(),
+            open_with_apps: HashMap::new(),
+            open_with_apps_receiver: None,
+            chooser_receiver: None,
+            verify_receiver: None,
+            open_confirm: None,
+            deferred_load_receiver: None,
+            deferred_load_plan: Some(deferred_load_plan),
+            event_proxy,
+        }
+    }
+
+    fn resize(&mut self, size: PhysicalSize<u32>) {
+        if size.width == 0 || size.height == 0 {
+            return;
+        }
+        self.config.width = size.width;
+        self.config.height = size.height;
+        self.surface.configure(&self.device, &self.config);
+    }
+
+    fn render(&mut self) {
+        let render_started = Instant::now();
+        if !self.first_frame_logged {
+            self.first_frame_logged = true;
+            self.first_frame_ms = self.boot_started.elapsed().as_secs_f64() * 1000.0;
+            log_info!("first_frame_ms={:.2}", self.first_frame_ms);
+        }
+        self.realtime.poll();
+        let mut state_dirty = false;
+        let incoming = self.realtime.take_incoming();
+        if !incoming.is_empty() {
+            state_dirty = true;
+        }
+        if !self.held_sends.is_empty() {
+            let before = self.held_sends.len();
+            let realtime = &self.realtime;
+            self.held_sends.retain(|message_id, _| realtime.is_queued(*message_id));
+            if self.held_sends.len() != before {
+                state_dirty = true;
+            }
+        }
+        let failed_sends = self.realtime.take_failed_sends();
+        if !failed_sends.is_empty() {
+            state_dirty = true;
+            self.failed_sends.extend(failed_sends);
+        }
+        let presence_updates = self.realtime.take_presence();
+        if !presence_updates.is_empty() {
+            state_dirty = true;
+            for update in presence_updates {
+                self.note_user(&update.user);
+                let status = PresenceStatus::from_str(&update.status);
+                let previous = self.presence_state.get(&update.user).map(|state| state.status);
+                if previous != Some(status) {
+                    self.record_presence_change(&update.user, status);
+                }
+                self.presence_state.insert(
+                    update.user,
+                    PresenceState {
+                        status,
+                        last_seen: Instant::now(),
+                    },
+                );
+            }
+            self.presence_counts_dirty = true;
+            if self.presence_history.is_some() {
+                self.reload_presence_history();
+            }
+        }
+        if self.drain_thumbnail_results() {
+            state_dirty = true;
+        }
+        if self.drain_lightbox_results() {
+            state_dirty = true;
+        }
+        if self.drain_screenshot_result() {
+            state_dirty = true;
+        }
+        if self.drain_ingest_results() {
+            state_dirty = true;
+        }
+        if self.drain_save_copy_result() {
+            state_dirty = true;
+        }
+        if self.drain_open_with_results() {
+            state_dirty = true;
+        }
+        if self.drain_verify_result() {
+            state_dirty = true;
+        }
+        if self.drain_flush_result() {
+            state_dirty = true;
+        }
+        if self.drain_retention_sweep() {
+            state_dirty = true;
+        }
+        if self.drain_text_preview_results() {
+            state_dirty = true;
+        }
+        if self.apply_deferred_loads() {
             state_dirty = true;
         }
-        if self.apply_deferred_loads() {
-            state_dirty = true;
+        if self.startup_bench && self.messages_loaded && !self.exit_requested {
+            self.report_startup_bench();
+            self.exit_requested = true;
+        }
+        if self.presence_counts_dirty {
+            self.refresh_presence_counts();
+        }
+        let raw_input = self.egui_state.take_egui_input(self.window.as_ref());
+        let has_input_events = !raw_input.events.is_empty();
+        let mut pending_send: Option<String> = None;
+        let mut pending_attachments_send = Vec::new();
+        let mut pending_thumbnail_requests: Vec<String> = Vec::new();
+        let mut channel_switch: Option<i64> = None;
+        let mut dm_open: Option<String> = None;
+        let mut channel_archive: Option<(i64, bool)> = None;
+        let mut channel_delete: Option<i64> = None;
+        let mut history_clear: Option<(i64, bool)> = None;
+        let mut gallery_toggle = false;
+        let mut selection_forward: Option<(i64, Vec<Message>)> = None;
+        let mut selection_delete: Option<Vec<i64>> = None;
+        let mut auto_scroll_change: Option<AutoScroll> = None;
+        let mut thumbnail_size_change: Option<ThumbnailSize> = None;
+        let mut invisible_toggle: Option<bool> = None;
+        let mut screenshot_request = false;
+        let mut ingest_request: Option<(PathBuf, IngestSource)> = None;
+        let mut open_confirm_choice: Option<bool> = None;
+        let mut lightbox_action: Option<LightboxAction> = None;
+        let mut instance_choice: Option<bool> = None;
+        let mut presence_history_reload = false;
+        let mut presence_history_close = false;
+        let mut membership_toggle: Option<(i64, bool)> = None;
+        let mut search_request: Option<SearchRequest> = None;
+        let mut search_load_more = false;
+        let mut history_page: Option<bool> = None;
+        let mut search_clear = false;
+        let mut realtime_connect = false;
+        let mut realtime_disconnect = false;
+        let mut saved_toggle: Option<i64> = None;
+        let mut saved_view_toggle = false;
+        let mut saved_jump: Option<(i64, i64)> = None;
+        let mut pinned_toggle: Option<i64> = None;
+        let mut resend_request: Option<Message> = None;
+        let mut reaction_toggle: Option<(i64, String, bool)> = None;
+        let mut store_cleanup = false;
+        let mut onboarding_submit = false;
+        let mut first_channel_submit = false;
+        let mut workspace_switch: Option<usize> = None;
+        let mut topic_save: Option<String> = None;
+        let mut placeholder_save: Option<String> = None;
+        let mut announcement_save: Option<String> = None;
+        let mut announcement_collapse: Option<bool> = None;
+        let mut confirm_send_toggle: Option<bool> = None;
+        let mut read_only_toggle: Option<bool> = None;
+        let mut retention_change: Option<Option<u32>> = None;
+        let egui_ctx = self.egui_ctx.clone();
+        let full_output = egui_ctx.run(raw_input, |ctx| {
+            self.handle_shortcuts(ctx);
+            egui::SidePanel::left("channel_list")
+                .resizable(false)
+                .default_width(220.0)
+                .show(ctx, |ui| {
+                    ui.heading("Ralph");
+                    if self.app_config.workspaces.len() > 1 {
+                        ui.add_space(4.0);
+                        let active_name = format!("Workspace: {}", self.active_workspace().name);
+                        let switchable = self.can_switch_workspace();
+                        let held = !self.realtime.outbox.is_empty();
+                        let switcher = ui.add_enabled_ui(switchable, |ui| {
+                            egui::ComboBox::from_id_source("workspace_switcher")
+                                .selected_text(active_name)
+                                .width(200.0)
+                                .show_ui(ui, |menu| {
+                                    for (index, workspace) in
+                                        self.app_config.workspaces.iter().enumerate()
+                                    {
+                                        let active = index == self.active_workspace;
+                                        if menu
+                                            .selectable_label(active, &workspace.name)
+                                            .on_hover_text(workspace.db_path.display().to_string())
+                                            .clicked()
+                                        {
+                                            workspace_switch = Some(index);
+                                        }
+                                    }
+                                });
+                        });
+                        if held {
+                            switcher.response.on_disabled_hover_text(
+                                "Queued messages are waiting to send in this workspace; \
+                                 reconnect to send them before switching.",
+                            );
+                        }
+                    }
+                    ui.add_space(10.0);
+                    ui.add(
+                        egui::TextEdit::singleline(&mut self.sidebar_filter)
+                            .hint_text("Filter channels")
+                            .desired_width(f32::INFINITY),
+                    );
+                    ui.add_space(6.0);
+                    ui.label("Channels");
+                    let channel_matches = self.filtered_channels(ChannelKind::Channel);
+                    let mut dm_matches = self.filtered_channels(ChannelKind::DirectMessage);
+                    if self.sort_dms_by_presence {
+                        // Stable sort keeps name (or filter rank) order inside each bucket.
+                        if self.sidebar_filter.trim().is_empty() {
+                            dm_matches
+                                .sort_by_key(|(channel, _)| channel.name.to_ascii_lowercase());
+                        }
+                        dm_matches.sort_by_key(|(channel, _)| {
+                            self.presence_for_user(&channel.name).sort_rank()
+                        });
+                    }
+                    if channel_matches.is_empty() && dm_matches.is_empty() {
+                        let empty_label = if self.channels.is_empty() {
+                            "No channels yet."
+                        } else {
+                            "No matching channels."
+                        };
+                        ui.label(
+                            egui::RichText::new(empty_label)
+                                .small()
+                                .color(egui::Color32::from_rgb(120, 130, 150)),
+                        );
+                    }
+                    for (channel, matched) in channel_matches {
+                        ui.horizontal(|row| {
+                            let shown = ellipsize(&channel.name, SIDEBAR_NAME_MAX_CHARS);
+                            let response = row.selectable_label(
+                                self.selected_channel_id == channel.id,
+                                highlight_matches(row.style(), "# ", &shown, &matched, None),
+                            );
+                            if hover_full_text(response, &shown, &channel.name).clicked() {
+                                channel_switch = Some(channel.id);
+                            }
+                            if self.highlighted_channels.contains(&channel.id) {
+                                highlight_marker(row);
+                            }
+                            let (online, total) = self.channel_presence_counts(channel.id);
+                            let summary = if total == 0 {
+                                "no members".to_string()
+                            } else {
+                                format!("{online}/{total} online")
+                            };
+                            row.label(
+                                egui::RichText::new(summary)
+                                    .small()
+                                    .color(egui::Color32::from_rgb(120, 130, 150)),
+                            );
+                        });
+                    }
+                    ui.add_space(8.0);
+                    let mut sort_dms_by_presence = self.sort_dms_by_presence;
+                    let mut dm_toggle = false;
+                    ui.horizontal(|row| {
+                        row.label("Direct Messages");
+                        row.checkbox(&mut sort_dms_by_presence, "Online first")
+                            .on_hover_text("Sort available people to the top");
+                        if row
+                            .small_button("+")
+                            .on_hover_text("New direct message")
+                            .clicked()
+                        {
+                            dm_toggle = true;
+                        }
+                    });
+                    for (channel, matched) in dm_matches {
+                        ui.horizontal(|row| {
+                            let name = self.roster.label(&self.app_config.identity, &channel.name);
+                            let shown = ellipsize(name, SIDEBAR_NAME_MAX_CHARS);
+                            // The filter matches the handle, so only mark it when shown as-is.
+                            let matched = if name == channel.name { &matched[..] } else { &[] };
+                            let response = row.selectable_label(
+                                self.selected_channel_id == channel.id,
+                                highlight_matches(
+                                    row.style(),
+                                    "@",
+                                    &shown,
+                                    matched,
+                                    Some(self.roster.color(&channel.name)),
+                                ),
+                            );
+                            if hover_full_text(response, &shown, name).clicked() {
+                                channel_switch = Some(channel.id);
+                            }
+                            if self.highlighted_channels.contains(&channel.id) {
+                                highlight_marker(row);
+                            }
+                            let status = self.presence_for_user(&channel.name);
+                            presence_indicator(
+                                row,
+                                status,
+                                self.app_config.presence_style,
+                                &channel.name,
+                            );
+                        });
+                    }
+                    self.sort_dms_by_presence = sort_dms_by_presence;
+                    if dm_toggle {
+                        self.dm_draft = match self.dm_draft {
+                            Some(_) => None,
+                            None => Some(String::new()),
+                        };
+                        self.dm_error = None;
+                    }
+                    if let Some(draft) = self.dm_draft.as_mut() {
+                        ui.horizontal(|row| {
+                            let response = row.add(
+                                egui::TextEdit::singleline(draft)
+                                    .hint_text("Username")
+                                    .desired_width(140.0),
+                            );
+                            let submitted = response.lost_focus()
+                                && row.input(|input| input.key_pressed(egui::Key::Enter));
+                            if row.button("Open").clicked() || submitted {
+                                dm_open = Some(draft.clone());
+                            }
+                        });
+                        if let Some(error) = &self.dm_error {
+                            ui.label(
+                                egui::RichText::new(error)
+                                    .small()
+                                    .color(egui::Color32::from_rgb(220, 120, 120)),
+                            );
+                        }
+                    }
+                    let archived: Vec<&Channel> =
+                        self.channels.iter().filter(|channel| channel.archived).collect();
+                    if !archived.is_empty() {
+                        ui.add_space(8.0);
+                        egui::CollapsingHeader::new(format!(
+                            "Archived channels ({})",
+                            archived.len()
+                        ))
+                        .id_source("archived_channels")
+                        .show(ui, |section| {
+                            for channel in archived {
+                                section.horizontal(|row| {
+                                    let shown = ellipsize(&channel.name, SIDEBAR_NAME_MAX_CHARS);
+                                    let response = row.selectable_label(
+                                        self.selected_channel_id == channel.id,
+                                        format!("#{shown}"),
+                                    );
+                                    if hover_full_text(response, &shown, &channel.name).clicked() {
+                                        channel_switch = Some(channel.id);
+                                    }
+                                    if row.small_button("Unarchive").clicked() {
+                                        channel_archive = Some((channel.id, false));
+                                    }
+                                });
+                            }
+                        });
+                    }
+                    if let Some(store) = &self.app_config.attachment_store {
+                        ui.add_space(8.0);
+                        ui.separator();
+                        ui.label("Maintenance");
+                        ui.label(
+                            egui::RichText::new(format!("Store: {}", store.display()))
+                                .small()
+                                .color(egui::Color32::from_rgb(120, 130, 150)),
+                        );
+                        // A file being copied in has no row yet and would look orphaned.
+                        if ui
+                            .add_enabled(
+                                self.messages_loaded && self.ingests_in_flight == 0,
+                                egui::Button::new("Clean up orphaned attachments"),
+                            )
+                            .clicked()
+                        {
+                            store_cleanup = true;
+                        }
+                        if let Some(status) = &self.maintenance_status {
+                            ui.label(
+                                egui::RichText::new(status)
+                                    .small()
+                                    .color(egui::Color32::from_rgb(140, 150, 170)),
+                            );
+                        }
+                    }
+                });
+            let show_composer = self.onboarding.is_none() && !self.channels.is_empty();
+            egui::TopBottomPanel::bottom("composer").show_animated(ctx, show_composer, |ui| {
+                ui.add_space(4.0);
+                if let Some(error) = &self.attachment_action_error {
+                    ui.label(
+                        egui::RichText::new(error)
+                            .small()
+                            .color(egui::Color32::from_rgb(220, 120, 120)),
+                    );
+                }
+                if let Some(notice) = &self.attachment_action_notice {
+                    ui.label(
+                        egui::RichText::new(notice)
+                            .small()
+                            .color(egui::Color32::from_rgb(120, 130, 150)),
+                    );
+                }
+                if let Some(confirm) = &self.open_confirm {
+                    ui.horizontal_wrapped(|row| {
+                        row.label(
+                            egui::RichText::new(&confirm.warning)
+                                .small()
+                                .color(egui::Color32::from_rgb(220, 180, 80)),
+                        );
+                        if row.small_button("Open anyway").clicked() {
+                            open_confirm_choice = Some(true);
+                        }
+                        if row.small_button("Cancel").clicked() {
+                            open_confirm_choice = Some(false);
+                        }
+                    });
+                }
+                if let Some(error) = &self.saved_action_error {
+                    ui.label(
+                        egui::RichText::new(error)
+                            .small()
+                            .color(egui::Color32::from_rgb(220, 120, 120)),
+                    );
+                }
+                if let Some(error) = &self.pinned_action_error {
+                    ui.label(
+                        egui::RichText::new(error)
+                            .small()
+                            .color(egui::Color32::from_rgb(220, 120, 120)),
+                    );
+                }
+                if let Some(error) = &self.reaction_action_error {
+                    ui.label(
+                        egui::RichText::new(error)
+                            .small()
+                            .color(egui::Color32::from_rgb(220, 120, 120)),
+                    );
+                }
+                match &self.flush_result {
+                    Some(Ok(summary)) => {
+                        ui.label(
+                            egui::RichText::new(summary)
+                                .small()
+                                .color(egui::Color32::from_rgb(120, 130, 150)),
+                        );
+                    }
+                    Some(Err(error)) => {
+                        ui.label(
+                            egui::RichText::new(error)
+                                .small()
+                                .color(egui::Color32::from_rgb(220, 120, 120)),
+                        );
+                    }
+                    None => {}
+                }
+                if let Some(version) = self.db_newer_schema {
+                    egui::Frame::none()
+                        .fill(egui::Color32::from_rgb(58, 30, 30))
+                        .rounding(4.0)
+                        .inner_margin(6.0)
+                        .show(ui, |banner| {
+                            banner.label(
+                                egui::RichText::new(format!(
+                                    "This workspace was last opened by a newer version of Ralph \
+                                     (database schema {version}; this build knows up to \
+                                     {SCHEMA_VERSION}). It is read-only here so nothing gets \
+                                     damaged. Upgrade Ralph to post or make changes."
+                                ))
+                                .small()
+                                .color(egui::Color32::from_rgb(240, 170, 160)),
+                            );
+                        });
+                }
+                if let Some(notice) = self.realtime.offline_notice() {
+                    egui::Frame::none()
+                        .fill(egui::Color32::from_rgb(48, 42, 30))
+                        .rounding(4.0)
+                        .inner_margin(6.0)
+                        .show(ui, |banner| {
+                            banner.label(
+                                egui::RichText::new(notice)
+                                    .small()
+                                    .color(egui::Color32::from_rgb(230, 200, 140)),
+                            );
+                        });
+                }
+                let read_only = self.is_read_only(self.selected_channel_id);
+                if read_only {
+                    ui.label(
+                        egui::RichText::new(format!(
+                            "Posting is turned off in {}. You can still read, search, and react.",
+                            self.channel_label(self.selected_channel_id)
+                        ))
+                        .small()
+                        .color(egui::Color32::from_rgb(120, 130, 150)),
+                    );
+                }
+                if self.read_only_instance {
+                    egui::Frame::none()
+                        .fill(egui::Color32::from_rgb(58, 30, 30))
+                        .rounding(4.0)
+                        .inner_margin(6.0)
+                        .show(ui, |banner| {
+                            banner.label(
+                                egui::RichText::new(
+                                    "Another Ralph window is using this workspace, so this one \
+                                     is read-only. Close the other window and reopen to post.",
+                                )
+                                .small()
+                                .color(egui::Color32::from_rgb(240, 170, 160)),
+                            );
+                        });
+                }
+                let composer_enabled = self.messages_loaded
+                    && self.db_newer_schema.is_none()
+                    && !self.read_only_instance
+                    && !read_only;
+                ui.add_enabled_ui(composer_enabled, |ui| {
+                    let (composer_placeholder, typing_stub) = self
+                        .composer_meta
+                        .get(&self.selected_channel_id)
+                        .map(|meta| (meta.placeholder.as_str(), meta.typing_stub.as_str()))
+                        .unwrap_or(("Send a message", "Typing..."));
+                    // Sends queue while disconnected, so say so where you type.
+                    let composer_placeholder = if !self.messages_loaded {
+                        "Loading messages…".to_string()
+                    } else if self.realtime.status == RealtimeStatus::Connected {
+                        composer_placeholder.to_string()
+                    } else {
+                        format!("{composer_placeholder} (offline — will send on reconnect)")
+                    };
+                    let confirm_required = self
+                        .channel_state
+                        .get(&self.selected_channel_id)
+                        .is_some_and(|state| state.confirm_send);
+                    let channel_label = self.channel_label(self.selected_channel_id);
+                    let draft = self
+                        .composer_drafts
+                        .entry(self.selected_channel_id)
+                        .or_default();
+                    let typing_active =
+                        match self.typing_state.get(&self.selected_channel_id).copied() {
+                            Some(last_edit) if last_edit.elapsed() < Duration::from_secs(3) => true,
+                            Some(_) => {
+                                self.typing_state.remove(&self.selected_channel_id);
+                                false
+                            }
+                            None => false,
+                        };
+                    let typing_label = if typing_active && !draft.trim().is_empty() {
+                        "You are typing..."
+                    } else {
+                        typing_stub
+                    };
+                    ui.label(
+                        egui::RichText::new(typing_label)
+                            .small()
+                            .color(egui::Color32::from_rgb(140, 150, 170)),
+                    );
+                    let attachment_path = self
+                        .attachment_path_drafts
+                        .entry(self.selected_channel_id)
+                        .or_default();
+                    let pending_list = self
+                        .pending_attachments
+                        .entry(self.selected_channel_id)
+                        .or_default();
+                    if self.attachments_expanded {
+                        ui.horizontal(|row| {
+                            row.label("Attach");
+                            row.add(
+                                egui::TextEdit::singleline(attachment_path)
+                                    .hint_text("Path to file")
+                                    .desired_width(320.0),
+                            );
+                            if row
+                                .add_enabled(
+                                    self.screenshot_receiver.is_none(),
+                                    egui::Button::new("Screenshot"),
+                                )
+                                .on_hover_text("Select a screen region to attach")
+                                .clicked()
+                            {
+                                screenshot_request = true;
+                            }
+                            if row.button("Add").clicked() {
+                                let trimmed = attachment_path.trim();
+                                if trimmed.is_empty() {
+                                    self.attachment_error =
+                                        Some("Attachment path is empty.".to_string());
+                                } else {
+                                    ingest_request =
+                                        Some((PathBuf::from(trimmed), IngestSource::PathBox));
+                                }
+                            }
+                            if self.ingests_in_flight > 0 {
+                                row.label(
+                                    egui::RichText::new("Reading file…")
+                                        .small()
+                                        .color(egui::Color32::from_rgb(120, 130, 150)),
+                                );
+                            }
+                        });
+                        let mut duplicate_choice: Option<bool> = None;
+                        if let Some(duplicate) = self
+                            .attachment_duplicate
+                            .as_ref()
+                            .filter(|duplicate| duplicate.channel_id == self.selected_channel_id)
+                        {
+                            ui.horizontal_wrapped(|row| {
+                                row.label(
+                                    egui::RichText::new(&duplicate.reason)
+                                        .small()
+                                        .color(egui::Color32::from_rgb(220, 180, 80)),
+                                );
+                                if row.small_button("Add anyway").clicked() {
+                                    duplicate_choice = Some(true);
+                                }
+                                if row.small_button("Cancel").clicked() {
+                                    duplicate_choice = Some(false);
+                                }
+                            });
+                        }
+                        if let Some(add_anyway) = duplicate_choice {
+                            if let Some(duplicate) = self.attachment_duplicate.take() {
+                                if add_anyway {
+                                    let limits = &self.app_config.attachment_limits;
+                                    let size = duplicate.attachment.file_size;
+                                    match limits.check(pending_list, true, size) {
+                                        Ok(()) => {
+                                            pending_list.push(duplicate.attachment);
+                                            attachment_path.clear();
+                                            self.attachment_error = None;
+                                        }
+                                        Err(err) => {
+                                            self.attachment_error = Some(err);
+                                        }
+                                    }
+                                }
+                            }
+                        }
+                    }
+                    let mut remove_attachment: Option<usize> = None;
+                    // Images show as thumbnails with a remove badge in the corner,
+                    // other files as chips; the strip wraps onto more rows.
+                    if !pending_list.is_empty() {
+                        ui.horizontal_wrapped(|strip| {
+                            for (idx, attachment) in pending_list.iter().enumerate() {
+                                // Shares the timeline's path-keyed cache, so an image that
+                                // is later sent is decoded once.
+                                let path = attachment.file_path.as_str();
+                                let details = format!(
+                                    "{} ({})",
+                                    attachment.file_name,
+                                    format_bytes(attachment.file_size)
+                                );
+                                if attachment.kind != "image"
+                                    || self.downgraded_images.contains(path)
+                                {
+                                    egui::Frame::none()
+                                        .fill(egui::Color32::from_rgb(32, 36, 44))
+                                        .rounding(4.0)
+                                        .inner_margin(egui::Margin::symmetric(6.0, 4.0))
+                                        .show(strip, |chip| {
+                                            chip.horizontal(|chip| {
+                                                chip.label(
+                                                    egui::RichText::new(format!(
+                                                        "{} {}",
+                                                        attachment_kind_icon(&attachment.kind),
+                                                        ellipsize(&attachment.file_name, 24)
+                                                    ))
+                                                    .small()
+                                                    .color(egui::Color32::from_rgb(190, 200, 215)),
+                                                )
+                                                .on_hover_text(&details);
+                                                if chip
+                                                    .small_button("×")
+                                                    .on_hover_text("Remove")
+                                                    .clicked()
+                                                {
+                                                    remove_attachment = Some(idx);
+                                                }
+                                            });
+                                        });
+                                    continue;
+                                }
+                                let (rect, tile) = strip.allocate_exact_size(
+                                    egui::Vec2::splat(PENDING_THUMBNAIL_SIZE),
+                                    egui::Sense::hover(),
+                                );
+                                tile.on_hover_text(&details);
+                                strip.painter().rect_filled(
+                                    rect,
+                                    4.0,
+                                    egui::Color32::from_rgb(32, 36, 44),
+                                );
+                                if let Some(texture) = self.attachment_thumbnails.get(path) {
+                                    pending_thumbnail_requests.push(path.to_string());
+                                    let [width, height] = texture.size();
+                                    let size = thumbnail_display_size(
+                                        Some((width as u32, height as u32)),
+                                        rect.size(),
+                                    );
+                                    egui::Image::from_texture(
+                                        egui::load::SizedTexture::from_handle(texture),
+                                    )
+                                    .paint_at(
+                                        strip,
+                                        egui::Rect::from_center_size(rect.center(), size),
+                                    );
+                                } else {
+                                    if !self.attachment_thumbnail_errors.contains_key(path) {
+                                        pending_thumbnail_requests.push(path.to_string());
+                                    }
+                                    strip.painter().text(
+                                        rect.center(),
+                                        egui::Align2::CENTER_CENTER,
+                                        attachment_kind_icon(&attachment.kind),
+                                        egui::TextStyle::Heading.resolve(strip.style()),
+                                        egui::Color32::from_rgb(130, 140, 160),
+                                    );
+                                }
+                                let badge = egui::Rect::from_min_size(
+                                    rect.right_top() + egui::vec2(-18.0, 2.0),
+                                    egui::Vec2::splat(16.0),
+                                );
+                                let remove = strip
+                                    .interact(
+                                        badge,
+                                        strip.id().with(("pending-remove", idx)),
+                                        egui::Sense::click(),
+                                    )
+                                    .on_hover_text("Remove");
+                                let badge_fill = if remove.hovered() {
+                                    egui::Color32::from_rgb(220, 120, 120)
+                                } else {
+                                    egui::Color32::from_black_alpha(180)
+                                };
+                                strip.painter().circle_filled(badge.center(), 8.0, badge_fill);
+                                strip.painter().text(
+                                    badge.center(),
+                                    egui::Align2::CENTER_CENTER,
+                                    "×",
+                                    egui::TextStyle::Small.resolve(strip.style()),
+                                    egui::Color32::WHITE,
+                                );
+                                if remove.clicked() {
+                                    remove_attachment = Some(idx);
+                                }
+                            }
+                        });
+                    }
+                    if let Some(idx) = remove_attachment {
+                        if idx < pending_list.len() {
+                            pending_list.remove(idx);
+                        }
+                    }
+                    if let Some(error) = &self.attachment_error {
+                        ui.label(
+                            egui::RichText::new(error)
+                                .small()
+                                .color(egui::Color32::from_rgb(220, 120, 120)),
+                        );
+                    }
+                    if let Some((_, error)) = self
+                        .send_error
+                        .as_ref()
+                        .filter(|(channel_id, _)| *channel_id == self.selected_channel_id)
+                    {
+                        ui.label(
+                            egui::RichText::new(error)
+                                .small()
+                                .color(egui::Color32::from_rgb(220, 120, 120)),
+                        );
+                    }
+                    let mut confirm_accepted = false;
+                    if self.send_confirm == Some(self.selected_channel_id) {
+                        egui::Frame::none()
+                            .fill(egui::Color32::from_rgba_unmultiplied(220, 180, 80, 30))
+                            .rounding(4.0)
+                            .inner_margin(6.0)
+                            .show(ui, |frame| {
+                                frame.horizontal(|row| {
+                                    row.label(
+                                        egui::RichText::new(format!(
+                                            "You're posting in {channel_label}."
+                                        ))
+                                        .color(egui::Color32::from_rgb(220, 180, 80)),
+                                    );
+                                    if row.small_button("Send").clicked() {
+                                        confirm_accepted = true;
+                                    }
+                                    if row.small_button("Cancel").clicked() {
+                                        self.send_confirm = None;
+                                        self.composer_focus_requested = true;
+                                    }
+                                    row.label(
+                                        egui::RichText::new("or press Enter again")
+                                            .small()
+                                            .color(egui::Color32::from_rgb(120, 130, 150)),
+                                    );
+                                });
+                            });
+                    }
+                    let mut toggle_attachments = false;
+                    let mut recent_emoji_used: Option<String> = None;
+                    ui.horizontal(|row| {
+                        let paperclip = if pending_list.is_empty() {
+                            "📎".to_string()
+                        } else {
+                            format!("📎 {}", pending_list.len())
+                        };
+                        if row
+                            .selectable_label(self.attachments_expanded, paperclip)
+                            .on_hover_text(if self.attachments_expanded {
+                                "Hide attachment controls"
+                            } else {
+                                "Attach files"
+                            })
+                            .clicked()
+                        {
+                            toggle_attachments = true;
+                        }
+                        let mut picked_emoji = None;
+                        row.menu_button("☺", |menu| {
+                            picked_emoji = emoji_picker(menu, &self.recent_emoji);
+                            if picked_emoji.is_some() {
+                                menu.close_menu();
+                            }
+                        })
+                        .response
+                        .on_hover_text("Insert emoji");
+                        let composer = row.add(
+                            egui::TextEdit::singleline(draft)
+                                .hint_text(composer_placeholder)
+                                .desired_width(f32::INFINITY),
+                        );
+                        let mut draft_changed = composer.changed();
+                        if let Some(emoji) = picked_emoji {
+                            draft.push_str(&emoji);
+                            recent_emoji_used = Some(emoji);
+                            self.composer_focus_requested = true;
+                            draft_changed = true;
+                        }
+                        // A disabled composer can still take focus; keep the request
+                        // for when it can be typed into.
+                        if self.composer_focus_requested && composer_enabled {
+                            composer.request_focus();
+                            self.composer_focus_requested = false;
+                        }
+                        let send_clicked = row.button("Send").clicked();
+                        // Enter that confirms an IME composition arrives alongside the
+                        // composition's events; it finishes the text, it doesn't send.
+                        let send_enter = composer.has_focus()
+                            && row.input(|input| {
+                                input.key_pressed(egui::Key::Enter)
+                                    && !has_ime_composition_event(&input.events)
+                            });
+                        let mut send_now = send_clicked || send_enter || confirm_accepted;
+                        if send_clicked || confirm_accepted {
+                            self.composer_focus_requested = true;
+                        }
+                        let has_content = !draft.trim().is_empty() || !pending_list.is_empty();
+                        if send_now
+                            && has_content
+                            && confirm_required
+                            && self.send_confirm != Some(self.selected_channel_id)
+                        {
+                            self.send_confirm = Some(self.selected_channel_id);
+                            self.composer_focus_requested = true;
+                            send_now = false;
+                        }
+                        if draft_changed {
+                            if let Some(expanded) = expand_shortcodes(draft) {
+                                *draft = expanded;
+                            }
+                            let pasted = row.input(|input| {
+                                input.events.iter().find_map(|event| match event {
+                                    egui::Event::Paste(text) => Some(text.clone()),
+                                    _ => None,
+                                })
+                            });
+                            if let Some(text) = pasted {
+                                self.pasted_path =
+                                    pasted_file_path(&text).map(|path| PastedPath {
+                                        channel_id: self.selected_channel_id,
+                                        text,
+                                        path,
+                                    });
+                            }
+                            if draft.trim().is_empty() {
+                                self.typing_state.remove(&self.selected_channel_id);
+                                if let Err(err) = delete_draft(&self.db, self.selected_channel_id) {
+                                    log_error!("db draft delete error: {err}");
+                                }
+                            } else {
+                                self.typing_state
+                                    .insert(self.selected_channel_id, Instant::now());
+                                if let Err(err) = save_draft(
+                                    &self.db,
+                                    self.selected_channel_id,
+                                    draft,
+                                    &format_timestamp_utc(),
+                                ) {
+                                    log_error!("db draft save error: {err}");
+                                }
+                            }
+                        }
+                        let within_limits =
+                            self.app_config.attachment_limits.check(pending_list, false, 0);
+                        if send_now && within_limits.is_err() {
+                            self.attachment_error = within_limits.err();
+                            self.attachments_expanded = true;
+                        } else if send_now {
+                            let body = draft.trim().to_string();
+                            if !body.is_empty() || !pending_list.is_empty() {
+                                self.send_confirm = None;
+                                pending_send = Some(body);
+                                pending_attachments_send = pending_list.clone();
+                            }
+                        }
+                    });
+                    let mut paste_choice: Option<bool> = None;
+                    if let Some(pasted) = self
+                        .pasted_path
+                        .as_ref()
+                        .filter(|pasted| pasted.channel_id == self.selected_channel_id)
+                    {
+                        ui.horizontal_wrapped(|row| {
+                            row.label(
+                                egui::RichText::new(format!(
+                                    "Attach {} instead?",
+                                    file_name_from_path(&pasted.path)
+                                ))
+                                .small()
+                                .color(egui::Color32::from_rgb(120, 130, 150)),
+                            );
+                            if row.small_button("Attach").clicked() {
+                                paste_choice = Some(true);
+                            }
+                            if row.small_button("Keep as text").clicked() {
+                                paste_choice = Some(false);
+                            }
+                        });
+                    }
+                    if let Some(attach) = paste_choice {
+                        if let Some(pasted) = self.pasted_path.take().filter(|_| attach) {
+                            ingest_request = Some((pasted.path, IngestSource::Paste(pasted.text)));
+                        }
+                    }
+                    if toggle_attachments {
+                        self.attachments_expanded = !self.attachments_expanded;
+                    }
+                    if let Some(emoji) = recent_emoji_used {
+                        self.note_recent_emoji(&emoji);
+                    }
+                });
+                if !self.messages_loaded {
+                    ui.label(
+                        egui::RichText::new("Composer available once messages finish loading.")
+                            .small()
+                            .color(egui::Color32::from_rgb(140, 150, 170)),
+                    );
+                }
+                ui.add_space(4.0);
+            });
+            egui::CentralPanel::default().show(ctx, |ui| {
+                if let Some(form) = self.onboarding.as_mut() {
+                    ui.heading("Welcome to Ralph");
+                    ui.add_space(4.0);
+                    ui.label(
+                        egui::RichText::new(
+                            "Choose how you appear, where to connect, and name your first channel.",
+                        )
+                        .color(egui::Color32::from_rgb(140, 150, 170)),
+                    );
+                    ui.add_space(10.0);
+                    egui::Grid::new("onboarding")
+                        .num_columns(2)
+                        .spacing([12.0, 8.0])
+                        .show(ui, |grid| {
+                            grid.label("Username");
+                            grid.add(
+                                egui::TextEdit::singleline(&mut form.username)
+                                    .desired_width(260.0),
+                            );
+                            grid.end_row();
+                            grid.label("Server URL");
+                            grid.add(
+                                egui::TextEdit::singleline(&mut form.server_url)
+                                    .hint_text(DEFAULT_SERVER_URL)
+                                    .desired_width(260.0),
+                            );
+                            grid.end_row();
+                            grid.label("First channel");
+                            grid.add(
+                                egui::TextEdit::singleline(&mut form.channel_name)
+                                    .hint_text("general")
+                                    .desired_width(260.0),
+                            );
+                            grid.end_row();
+                        });
+                    ui.add_space(8.0);
+                    if ui.button("Get started").clicked() {
+                        onboarding_submit = true;
+                    }
+                    if let Some(error) = &form.error {
+                        ui.label(
+                            egui::RichText::new(error)
+                                .color(egui::Color32::from_rgb(220, 120, 120)),
+                        );
+                    }
+                    ui.add_space(8.0);
+                    ui.label(
+                        egui::RichText::new("Want sample conversations instead? Start Ralph with --demo.")
+                            .small()
+                            .color(egui::Color32::from_rgb(120, 130, 150)),
+                    );
+                    return;
+                }
+                if self.messages_loaded && self.channels.is_empty() {
+                    ui.heading("No channels yet");
+                    ui.add_space(4.0);
+                    ui.label(
+                        egui::RichText::new("Create a channel to start the conversation.")
+                            .color(egui::Color32::from_rgb(140, 150, 170)),
+                    );
+                    ui.add_space(10.0);
+                    ui.horizontal(|row| {
+                        let response = row.add(
+                            egui::TextEdit::singleline(&mut self.first_channel_name)
+                                .hint_text("general")
+                                .desired_width(260.0),
+                        );
+                        let submitted = response.lost_focus()
+                            && row.input(|input| input.key_pressed(egui::Key::Enter));
+                        if row.button("Create channel").clicked() || submitted {
+                            first_channel_submit = true;
+                        }
+                    });
+                    if let Some(error) = &self.first_channel_error {
+                        ui.label(
+                            egui::RichText::new(error)
+                                .color(egui::Color32::from_rgb(220, 120, 120)),
+                        );
+                    }
+                    return;
+                }
+                let channel_title = self
+                    .channels
+                    .iter()
+                    .find(|channel| channel.id == self.selected_channel_id)
+                    .map(|channel| match channel.kind {
+                        ChannelKind::Channel => format!("#{}", channel.name),
+                        ChannelKind::DirectMessage => format!("DM: {}", channel.name),
+                    })
+                    .unwrap_or_else(|| "Messages".to_string());
+                let gallery_open = self
+                    .gallery
+                    .as_ref()
+                    .is_some_and(|gallery| gallery.channel_id == self.selected_channel_id);
+                ui.horizontal(|row| {
+                    row.heading(format!("Ralph — {}", channel_title));
+                    if row
+                        .selectable_label(gallery_open, "🖼 Gallery")
+                        .on_hover_text("Browse images and videos shared in this channel")
+                        .clicked()
+                    {
+                        gallery_toggle = true;
+                    }
+                    egui::ComboBox::from_id_source("auto_scroll")
+                        .selected_text(format!(
+                            "Auto-scroll: {}",
+                            self.app_config.auto_scroll.label()
+                        ))
+                        .show_ui(row, |menu| {
+                            for mode in AutoScroll::ALL {
+                                let current = self.app_config.auto_scroll == mode;
+                                if menu.selectable_label(current, mode.label()).clicked()
+                                {
+                                    auto_scroll_change = Some(mode);
+                                }
+                            }
+                        })
+                        .response
+                        .on_hover_text("Whether the view follows new messages to the bottom");
+                    egui::ComboBox::from_id_source("thumbnail_size")
+                        .selected_text(format!(
+                            "Previews: {}",
+                            self.app_config.thumbnail_size.label()
+                        ))
+                        .show_ui(row, |menu| {
+                            for size in ThumbnailSize::ALL {
+                                let current = self.app_config.thumbnail_size == size;
+                                if menu.selectable_label(current, size.label()).clicked() {
+                                    thumbnail_size_change = Some(size);
+                                }
+                            }
+                        })
+                        .response
+                        .on_hover_text("How large image previews are shown in messages");
+                });
+                if let Some((text, collapsed)) = self
+                    .channel_state
+                    .get(&self.selected_channel_id)
+                    .and_then(ChannelState::active_announcement)
+                {
+                    egui::Frame::none()
+                        .fill(egui::Color32::from_rgb(30, 40, 58))
+                        .rounding(4.0)
+                        .inner_margin(6.0)
+                        .show(ui, |banner| {
+                            banner.set_width(banner.available_width());
+                            banner.horizontal(|row| {
+                                row.label(egui::RichText::new("📢").small());
+                                if collapsed {
+                                    let first_line = text.lines().next().unwrap_or_default();
+                                    let shown = ellipsize(first_line, 60);
+                                    let response = row.label(
+                                        egui::RichText::new(shown.as_ref())
+                                            .small()
+                                            .color(egui::Color32::from_rgb(120, 130, 150)),
+                                    );
+                                    hover_full_text(response, &shown, text);
+                                    if row.small_button("Show").clicked() {
+                                        announcement_collapse = Some(false);
+                                    }
+                                } else {
+                                    row.label(
+                                        egui::RichText::new(text)
+                                            .color(egui::Color32::from_rgb(190, 200, 215)),
+                                    );
+                                    let hint = "It comes back when the announcement changes";
+                                    if row.small_button("Collapse").on_hover_text(hint).clicked()
+                                    {
+                                        announcement_collapse = Some(true);
+                                    }
+                                }
+                            });
+                        });
+                }
+                if let Some(channel) = self
+                    .channels
+                    .iter()
+                    .find(|channel| channel.id == self.selected_channel_id)
+                {
+                    match channel.kind {
+                        ChannelKind::Channel => {
+                            ui.horizontal(|row| {
+                                row.label(
+                                    egui::RichText::new("Topic:")
+                                        .small()
+                                        .color(egui::Color32::from_rgb(120, 130, 150)),
+                                );
+                                if channel.topic.is_empty() {
+                                    row.label(
+                                        egui::RichText::new("No topic set")
+                                            .small()
+                                            .italics()
+                                            .color(egui::Color32::from_rgb(120, 130, 150)),
+                                    );
+                                } else {
+                                    row.label(
+                                        egui::RichText::new(&channel.topic)
+                                            .color(egui::Color32::from_rgb(190, 200, 215)),
+                                    );
+                                }
+                            });
+                            let joined = self.is_member(channel.id);
+                            ui.horizontal(|row| {
+                                let (_, total) = self.channel_presence_counts(channel.id);
+                                row.label(
+                                    egui::RichText::new(format!(
+                                        "{total} member{}",
+                                        if total == 1 { "" } else { "s" }
+                                    ))
+                                    .small()
+                                    .color(egui::Color32::from_rgb(120, 130, 150)),
+                                );
+                                let (label, hint) = if joined {
+                                    ("Leave", "Stop counting as a member of this channel")
+                                } else {
+                                    ("Join", "Become a member without having to post")
+                                };
+                                if row.small_button(label).on_hover_text(hint).clicked() {
+                                    membership_toggle = Some((channel.id, !joined));
+                                }
+                            });
+                        }
+                        ChannelKind::DirectMessage => {
+                            let status = self.presence_for_user(&channel.name);
+                            ui.horizontal(|row| {
+                                row.label(
+                                    egui::RichText::new("Status:")
+                                        .small()
+                                        .color(egui::Color32::from_rgb(120, 130, 150)),
+                                );
+                                presence_indicator(row, status, PresenceStyle::Both, &channel.name);
+                            });
+                        }
+                    }
+                    let custom_placeholder = self
+                        .channel_state
+                        .get(&channel.id)
+                        .and_then(|state| state.composer_placeholder.clone());
+                    let mut placeholder_start = false;
+                    let custom_announcement = self
+                        .channel_state
+                        .get(&channel.id)
+                        .and_then(|state| state.announcement.clone());
+                    let mut announcement_start = false;
+                    let mut topic_start = false;
+                    egui::CollapsingHeader::new(
+                        egui::RichText::new("Channel settings")
+                            .small()
+                            .color(egui::Color32::from_rgb(120, 130, 150)),
+                    )
+                    .id_source(("channel_settings", channel.id))
+                    .show(ui, |section| {
+                        let default_placeholder = default_composer_placeholder(channel);
+                        section.horizontal(|row| {
+                            row.label(
+                                egui::RichText::new("Composer placeholder:")
+                                    .small()
+                                    .color(egui::Color32::from_rgb(120, 130, 150)),
+                            );
+                            if let Some(draft) = self.placeholder_draft.as_mut() {
+                                let response = row.add(
+                                    egui::TextEdit::singleline(draft)
+                                        .char_limit(COMPOSER_PLACEHOLDER_MAX_CHARS)
+                                        .hint_text(&default_placeholder)
+                                        .desired_width(260.0),
+                                );
+                                let submitted = response.lost_focus()
+                                    && row.input(|input| input.key_pressed(egui::Key::Enter));
+                                if row.small_button("Save").clicked() || submitted {
+                                    placeholder_save = Some(draft.trim().to_string());
+                                }
+                                if row.small_button("Cancel").clicked() {
+                                    self.placeholder_draft = None;
+                                }
+                            } else {
+                                match &custom_placeholder {
+                                    Some(placeholder) => row.label(
+                                        egui::RichText::new(placeholder)
+                                            .color(egui::Color32::from_rgb(190, 200, 215)),
+                                    ),
+                                    None => row.label(
+                                        egui::RichText::new(format!(
+                                            "{default_placeholder} (default)"
+                                        ))
+                                        .small()
+                                        .italics()
+                                        .color(egui::Color32::from_rgb(120, 130, 150)),
+                                    ),
+                                };
+                                if row.small_button("Edit").clicked() {
+                                    placeholder_start = true;
+                                }
+                                if custom_placeholder.is_some()
+                                    && row.small_button("Use default").clicked()
+                                {
+                                    placeholder_save = Some(String::new());
+                                }
+                            }
+                        });
+                        if channel.kind == ChannelKind::Channel {
+                            section.horizontal(|row| {
+                                row.label(
+                                    egui::RichText::new("Topic:")
+                                        .small()
+                                        .color(egui::Color32::from_rgb(120, 130, 150)),
+                                );
+                                if let Some(draft) = self.topic_draft.as_mut() {
+                                    let response = row.add(
+                                        egui::TextEdit::singleline(draft)
+                                            .char_limit(CHANNEL_TOPIC_MAX_CHARS)
+                                            .hint_text("What is this channel about?")
+                                            .desired_width(260.0),
+                                    );
+                                    let submitted = response.lost_focus()
+                                        && row.input(|input| input.key_pressed(egui::Key::Enter));
+                                    if row.small_button("Save").clicked() || submitted {
+                                        topic_save = Some(draft.trim().to_string());
+                                    }
+                                    if row.small_button("Cancel").clicked() {
+                                        self.topic_draft = None;
+                                    }
+                                } else {
+                                    let has_topic = !channel.topic.is_empty();
+                                    let label = if has_topic { "Edit" } else { "Set" };
+                                    if row.small_button(label).clicked() {
+                                        topic_start = true;
+                                    }
+                                }
+                            });
+                        }
+                        let announcement = custom_announcement.as_deref();
+                        section.horizontal(|row| {
+                            row.label(
+                                egui::RichText::new("Announcement:")
+                                    .small()
+                                    .color(egui::Color32::from_rgb(120, 130, 150)),
+                            );
+                            if let Some(draft) = self.announcement_draft.as_mut() {
+                                row.add(
+                                    egui::TextEdit::multiline(draft)
+                                        .char_limit(ANNOUNCEMENT_MAX_CHARS)
+                                        .hint_text("Shown to everyone at the top of the channel")
+                                        .desired_rows(2)
+                                        .desired_width(260.0),
+                                );
+                                if row.small_button("Save").clicked() {
+                                    announcement_save = Some(draft.trim().to_string());
+                                }
+                                if row.small_button("Cancel").clicked() {
+                                    self.announcement_draft = None;
+                                }
+                            } else {
+                                match announcement {
+                                    Some(text) => {
+                                        let shown = ellipsize(text, 40);
+                                        let response = row.label(
+                                            egui::RichText::new(shown.as_ref())
+                                                .color(egui::Color32::from_rgb(190, 200, 215)),
+                                        );
+                                        hover_full_text(response, &shown, text);
+                                    }
+                                    None => {
+                                        row.label(
+                                            egui::RichText::new("None")
+                                                .small()
+                                                .italics()
+                                                .color(egui::Color32::from_rgb(120, 130, 150)),
+                                        );
+                                    }
+                                }
+                                let label = if announcement.is_some() { "Edit" } else { "Set" };
+                                if row.small_button(label).clicked() {
+                                    announcement_start = true;
+                                }
+                                if announcement.is_some() && row.small_button("Clear").clicked() {
+                                    announcement_save = Some(String::new());
+                                }
+                            }
+                        });
+                        let mut confirm_send = self
+                            .channel_state
+                            .get(&channel.id)
+                            .is_some_and(|state| state.confirm_send);
+                        if section
+                            .checkbox(&mut confirm_send, "Confirm before posting here")
+                            .on_hover_text("Name the channel and ask again before each send")
+                            .changed()
+                        {
+                            confirm_send_toggle = Some(confirm_send);
+                        }
+                        let mut read_only = self
+                            .channel_state
+                            .get(&channel.id)
+                            .is_some_and(|state| state.read_only);
+                        if section
+                            .checkbox(&mut read_only, "Read-only")
+                            .on_hover_text("Turn off posting here, as for announcements")
+                            .changed()
+                        {
+                            read_only_toggle = Some(read_only);
+                        }
+                        let retention_days = self
+                            .channel_state
+                            .get(&channel.id)
+                            .and_then(|state| state.retention_days);
+                        section.horizontal(|row| {
+                            row.label("Keep messages");
+                            let current = RETENTION_CHOICES
+                                .iter()
+                                .find(|(days, _)| *days == retention_days)
+                                .map(|(_, label)| label.to_string())
+                                .or_else(|| retention_days.map(|days| format!("{days} days")))
+                                .unwrap_or_default();
+                            egui::ComboBox::from_id_source(("retention", channel.id))
+                                .selected_text(current)
+                                .show_ui(row, |combo| {
+                                    for (days, label) in RETENTION_CHOICES {
+                                        if combo
+                                            .selectable_label(days == retention_days, label)
+                                            .clicked()
+                                            && days != retention_days
+                                        {
+                                            retention_change = Some(days);
+                                        }
+                                    }
+                                })
+                                .response
+                                .on_hover_text(
+                                    "Older messages and their attachments are deleted for good",
+                                );
+                        });
+                        let (archive_label, archive_hint) = if channel.archived {
+                            ("Unarchive channel", "Show it in the sidebar again")
+                        } else {
+                            (
+                                "Archive channel",
+                                "Hide it from the sidebar; messages stay searchable",
+                            )
+                        };
+                        if section
+                            .small_button(archive_label)
+                            .on_hover_text(archive_hint)
+                            .clicked()
+                        {
+                            channel_archive = Some((channel.id, !channel.archived));
+                        }
+                        if self.channel_delete_confirm == Some(channel.id) {
+                            section.horizontal(|row| {
+                                row.label(
+                                    egui::RichText::new(
+                                        "Delete this channel and all of its messages?",
+                                    )
+                                    .small()
+                                    .color(egui::Color32::from_rgb(220, 180, 80)),
+                                );
+                                if row.small_button("Delete").clicked() {
+                                    channel_delete = Some(channel.id);
+                                }
+                                if row.small_button("Cancel").clicked() {
+                                    self.channel_delete_confirm = None;
+                                }
+                            });
+                        } else if section.small_button("Delete channel…").clicked() {
+                            self.channel_delete_confirm = Some(channel.id);
+                        }
+                        let mut cancel_clear = false;
+                        if let Some(confirm) = self
+                            .history_clear_confirm
+                            .as_mut()
+                            .filter(|confirm| confirm.channel_id == channel.id)
+                        {
+                            section.label(
+                                egui::RichText::new(format!(
+                                    "Permanently delete every message in #{}? Type the \
+                                     channel name to confirm.",
+                                    channel.name
+                                ))
+                                .small()
+                                .color(egui::Color32::from_rgb(220, 180, 80)),
+                            );
+                            section.horizontal(|row| {
+                                row.add(
+                                    egui::TextEdit::singleline(&mut confirm.typed_name)
+                                        .hint_text(&channel.name)
+                                        .desired_width(180.0),
+                                );
+                                let matches = confirm.typed_name.trim() == channel.name;
+                                let clear = egui::Button::new("Clear history").small();
+                                if row.add_enabled(matches, clear).clicked()
+                                {
+                                    history_clear = Some((channel.id, confirm.delete_files));
+                                }
+                                if row.small_button("Cancel").clicked() {
+                                    cancel_clear = true;
+                                }
+                            });
+                            if self.app_config.attachment_store.is_some() {
+                                section.checkbox(
+                                    &mut confirm.delete_files,
+                                    "Also delete their files from the attachment store",
+                                );
+                            }
+                        } else if section
+                            .small_button("Clear channel history…")
+                            .on_hover_text("Delete all messages but keep the channel")
+                            .clicked()
+                        {
+                            self.history_clear_confirm = Some(HistoryClearConfirm {
+                                channel_id: channel.id,
+                                typed_name: String::new(),
+                                delete_files: false,
+                            });
+                        }
+                        if cancel_clear {
+                            self.history_clear_confirm = None;
+                        }
+                    });
+                    if placeholder_start {
+                        self.placeholder_draft = Some(custom_placeholder.unwrap_or_default());
+                    }
+                    if announcement_start {
+                        self.announcement_draft = Some(custom_announcement.unwrap_or_default());
+                    }
+                    if topic_start {
+                        self.topic_draft = Some(channel.topic.clone());
+                    }
+                }
+                ui.add_space(4.0);
+                ui.label(format!(
+                    "Session uptime: {:.1}s",
+                    self.started_at.elapsed().as_secs_f32()
+                ));
+                if let Some(settles_in) = self.realtime.status_settles_in() {
+                    ui.ctx().request_repaint_after(settles_in);
+                }
+                if let Some(due_in) = self.realtime.ack_due_in() {
+                    ui.ctx().request_repaint_after(due_in);
+                }
+                let displayed_status = self.realtime.displayed_status();
+                ui.horizontal(|row| {
+                    row.label(format!("Realtime: {}", displayed_status.label()));
+                    if displayed_status == RealtimeStatus::Connecting && !self.reduce_motion {
+                        row.add(egui::Spinner::new().size(12.0));
+                    }
+                    row.label(
+                        egui::RichText::new(&self.realtime.target_url)
+                            .small()
+                            .color(egui::Color32::from_rgb(120, 130, 150)),
+                    );
+                    match displayed_status {
+                        RealtimeStatus::Disconnected => {
+                            if row.button("Connect").clicked() {
+                                realtime_connect = true;
+                            }
+                        }
+                        RealtimeStatus::Connecting => {
+                            row.add_enabled(false, egui::Button::new("Connecting..."));
+                        }
+                        RealtimeStatus::Connected => {
+                            if row.button("Disconnect").clicked() {
+                                realtime_disconnect = true;
+                            }
+                        }
+                    }
+                    let mut invisible = self.app_config.invisible;
+                    let hint = if self.app_config.invisible_from_env {
+                        "Set by RALPH_INVISIBLE; unset it to change this here"
+                    } else {
+                        "Appear offline to others; messages still arrive"
+                    };
+                    if row
+                        .add_enabled(
+                            !self.app_config.invisible_from_env,
+                            egui::Checkbox::new(&mut invisible, "Invisible"),
+                        )
+                        .on_hover_text(hint)
+                        .on_disabled_hover_text(hint)
+                        .changed()
+                    {
+                        invisible_toggle = Some(invisible);
+                    }
+                    if self.app_config.invisible {
+                        row.label(
+                            egui::RichText::new("Hidden")
+                                .small()
+                                .strong()
+                                .color(egui::Color32::from_rgb(220, 180, 80)),
+                        )
+                        .on_hover_text("Others see you as offline");
+                    }
+                    if let Some(message) = &self.realtime.last_message {
+                        row.label(
+                            egui::RichText::new(message)
+                                .small()
+                                .color(egui::Color32::from_rgb(140, 150, 170)),
+                        );
+                    }
+                    // A blip still being ridden out shouldn't flash its error either.
+                    if let Some(error) = self
+                        .realtime
+                        .last_error
+                        .as_ref()
+                        .filter(|_| displayed_status == self.realtime.status)
+                    {
+                        row.label(
+                            egui::RichText::new(error)
+                                .small()
+                                .color(egui::Color32::from_rgb(220, 120, 120)),
+                        );
+                    }
+                });
+                if let Some(summary) = self.realtime.metrics.summary() {
+                    ui.label(
+                        egui::RichText::new(summary)
+                            .small()
+                            .color(egui::Color32::from_rgb(120, 130, 150)),
+                    );
+                }
+                if let Some(details) = self.channel_presence_details() {
+                    ui.label(
+                        egui::RichText::new(details)
+                            .small()
+                            .color(egui::Color32::from_rgb(120, 130, 150)),
+                    );
+                }
+                ui.separator();
+                ui.add_enabled_ui(self.messages_loaded, |ui| {
+                    ui.horizontal(|row| {
+                        row.label("Search");
+                        let search_box = row.add(
+                            egui::TextEdit::singleline(&mut self.search_query)
+                                .hint_text("Search messages")
+                                .desired_width(240.0),
+                        );
+                        let search_enter = search_box.has_focus()
+                            && row.input(|input| input.key_pressed(egui::Key::Enter));
+                        if row.button("Go").clicked() || search_enter {
+                            let trimmed = self.search_query.trim();
+                            if !trimmed.is_empty() {
+                                search_request = Some(SearchRequest {
+                                    query: trimmed.to_string(),
+                                    channel_only: self.search_channel_only,
+                                });
+                            }
+                        }
+                        row.checkbox(&mut self.search_channel_only, "This channel");
+                        if row.button("Clear").clicked() {
+                            search_clear = true;
+                        }
+                    });
+                    if self.search_query.trim().is_empty() {
+                        ui.label(
+                            egui::RichText::new("Search by author or text.")
+                                .small()
+                                .color(egui::Color32::from_rgb(120, 130, 150)),
+                        );
+                    } else if self.search_last_query == self.search_query.trim()
+                        && self.search_last_channel_only == self.search_channel_only
+                    {
+                        ui.label(
+                            egui::RichText::new(if self.search_has_more() {
+                                format!(
+                                    "Results: {} of {}",
+                                    self.search_results.len(),
+                                    self.search_total
+                                )
+                            } else {
+                                format!("Results: {}", self.search_results.len())
+                            })
+                                .small()
+                                .color(egui::Color32::from_rgb(120, 130, 150)),
+                        );
+                    } else {
+                        ui.label(
+                            egui::RichText::new("Press Enter to search.")
+                                .small()
+                                .color(egui::Color32::from_rgb(120, 130, 150)),
+                        );
+                    }
+                });
+                if !self.messages_loaded {
+                    ui.label(
+                        egui::RichText::new("Search available once messages finish loading.")
+                            .small()
+                            .color(egui::Color32::from_rgb(120, 130, 150)),
+                    );
+                }
+                ui.separator();
+                if let Some(entries) = self.saved_view.as_ref() {
+                    ui.horizontal(|row| {
+                        row.label(egui::RichText::new("Saved messages").strong());
+                        row.label(
+                            egui::RichText::new(format!("{} across all channels", entries.len()))
+                                .small()
+                                .color(egui::Color32::from_rgb(120, 130, 150)),
+                        );
+                        if row.small_button("Close").clicked() {
+                            saved_view_toggle = true;
+                        }
+                    });
+                    egui::ScrollArea::vertical()
+                        .id_source("saved_view")
+                        .auto_shrink([false, false])
+                        .show(ui, |ui| {
+                            if entries.is_empty() {
+                                ui.label(
+                                    egui::RichText::new(
+                                        "Nothing saved yet. Use ☆ on a message to save it here.",
+                                    )
+                                    .small()
+                                    .color(egui::Color32::from_rgb(160, 170, 190)),
+                                );
+                            }
+                            for entry in entries {
+                                let message = &entry.message;
+                                ui.horizontal(|row| {
+                                    row.label(
+                                        egui::RichText::new(self.channel_label(message.channel_id))
+                                            .small()
+                                            .color(egui::Color32::from_rgb(120, 130, 150)),
+                                    );
+                                    row.label(
+                                        egui::RichText::new(self.roster.label(
+                                            &self.app_config.identity,
+                                            &message.author,
+                                        ))
+                                        .strong()
+                                        .color(self.roster.color(&message.author)),
+                                    );
+                                    row.label(
+                                        egui::RichText::new(format_clock(
+                                            &message.sent_at,
+                                            &self.app_config.clock,
+                                        ))
+                                        .small()
+                                        .color(egui::Color32::from_rgb(120, 130, 150)),
+                                    )
+                                    .on_hover_text(format!(
+                                        "Saved {}",
+                                        format_clock(&entry.saved_at, &self.app_config.clock)
+                                    ));
+                                    if row.small_button("Jump").clicked() {
+                                        if message.channel_id != self.selected_channel_id {
+                                            channel_switch = Some(message.channel_id);
+                                        }
+                                        saved_jump = Some((message.channel_id, message.id));
+                                    }
+                                    if row.small_button("Unsave").clicked() {
+                                        saved_toggle = Some(message.id);
+                                    }
+                                });
+                                let first_line = message.body.lines().next().unwrap_or_default();
+                                let snippet = if first_line.trim().is_empty() {
+                                    "(attachment)".into()
+                                } else {
+                                    ellipsize(first_line, 160)
+                                };
+                                ui.label(
+                                    egui::RichText::new(snippet.as_ref())
+                                        .color(egui::Color32::from_rgb(190, 200, 215)),
+                                );
+                                ui.add_space(6.0);
+                            }
+                        });
+                    return;
+                }
+                if let Some(view) = self.presence_history.as_mut() {
+                    ui.horizontal(|row| {
+                        row.label(egui::RichText::new("Presence history").strong());
+                        if row
+                            .add(
+                                egui::TextEdit::singleline(&mut view.name_filter)
+                                    .hint_text("Filter by name")
+                                    .desired_width(160.0),
+                            )
+                            .changed()
+                        {
+                            presence_history_reload = true;
+                        }
+                        let status_label =
+                            view.status_filter.map_or("Any status", PresenceStatus::label);
+                        egui::ComboBox::from_id_source("presence_history_status")
+                            .selected_text(status_label)
+                            .show_ui(row, |menu| {
+                                let choices = [
+                                    None,
+                                    Some(PresenceStatus::Online),
+                                    Some(PresenceStatus::Away),
+                                    Some(PresenceStatus::Offline),
+                                ];
+                                for choice in choices {
+                                    let label = choice.map_or("Any status", PresenceStatus::label);
+                                    if menu
+                                        .selectable_value(&mut view.status_filter, choice, label)
+                                        .changed()
+                                    {
+                                        presence_history_reload = true;
+                                    }
+                                }
+                            });
+                        if row.small_button("Close").clicked() {
+                            presence_history_close = true;
+                        }
+                    });
+                    if !self.app_config.presence_history {
+                        ui.label(
+                            egui::RichText::new(
+                                "Presence history is off. Set RALPH_PRESENCE_HISTORY=1 to record \
+                                 when people go online, away, or offline.",
+                            )
+                            .small()
+                            .color(egui::Color32::from_rgb(120, 130, 150)),
+                        );
+                    }
+                    // Newest activity first; each person's changes read oldest to newest.
+                    let mut people: Vec<(&str, Vec<&PresenceChange>)> = Vec::new();
+                    for change in &view.entries {
+                        match people
+                            .iter_mut()
+                            .find(|(username, _)| username.eq_ignore_ascii_case(&change.username))
+                        {
+                            Some((_, changes)) => changes.push(change),
+                            None => people.push((&change.username, vec![change])),
+                        }
+                    }
+                    egui::ScrollArea::vertical()
+                        .id_source("presence_history")
+                        .auto_shrink([false, false])
+                        .show(ui, |ui| {
+                            if people.is_empty() && self.app_config.presence_history {
+                                ui.label(
+                                    egui::RichText::new("No presence changes recorded yet.")
+                                        .small()
+                                        .color(egui::Color32::from_rgb(160, 170, 190)),
+                                );
+                            }
+                            for (username, changes) in people {
+                                let trail: Vec<String> = changes
+                                    .iter()
+                                    .take(PRESENCE_HISTORY_PER_USER)
+                                    .rev()
+                                    .map(|change| {
+                                        format!(
+                                            "{} {}",
+                                            change.status,
+                                            format_clock(&change.changed_at, &self.app_config.clock)
+                                        )
+                                    })
+                                    .collect();
+                                let latest = changes
+                                    .first()
+                                    .and_then(|change| parse_timestamp(&change.changed_at))
+                                    .and_then(|at| (Utc::now() - at).to_std().ok());
+                                ui.horizontal_wrapped(|row| {
+                                    row.label(
+                                        egui::RichText::new(format!(
+                                            "{}:",
+                                            self.roster.label(&self.app_config.identity, username)
+                                        ))
+                                        .strong()
+                                        .color(self.roster.color(username)),
+                                    );
+                                    row.label(trail.join(" → "));
+                                    if let Some(elapsed) = latest {
+                                        row.label(
+                                            egui::RichText::new(format_relative_duration(elapsed))
+                                                .small()
+                                                .color(egui::Color32::from_rgb(120, 130, 150)),
+                                        );
+                                    }
+                                });
+                            }
+                        });
+                    return;
+                }
+                if let Some(gallery) = self
+                    .gallery
+                    .as_ref()
+                    .filter(|gallery| gallery.channel_id == self.selected_channel_id)
+                {
+                    let mut thumbnail_requests: Vec<(String, bool)> = Vec::new();
+                    let mut touched_thumbnails: Vec<String> = Vec::new();
+                    let mut opened: Option<Attachment> = None;
+                    let mut load_more = false;
+                    egui::ScrollArea::vertical()
+                        .id_source(("gallery", gallery.channel_id))
+                        .auto_shrink([false, false])
+                        .show(ui, |ui| {
+                            if gallery.items.is_empty() {
+                                ui.label(
+                                    egui::RichText::new(
+                                        "No images or videos shared in this channel yet.",
+                                    )
+                                    .small()
+                                    .color(egui::Color32::from_rgb(160, 170, 190)),
+                                );
+                            }
+                            let tile = egui::Vec2::splat(GALLERY_TILE_SIZE);
+                            ui.horizontal_wrapped(|row| {
+                                for (_, attachment) in &gallery.items {
+                                    let path = attachment.file_path.as_str();
+                                    let image = attachment.kind == "image"
+                                        && !self.downgraded_images.contains(path);
+                                    let texture = image
+                                        .then(|| self.attachment_thumbnails.get(path))
+                                        .flatten();
+                                    let response = if let Some(texture) = texture {
+                                        touched_thumbnails.push(path.to_string());
+                                        row.add(
+                                            egui::Image::from_texture(
+                                                egui::load::SizedTexture::from_handle(texture),
+                                            )
+                                            .max_size(tile)
+                                            .sense(egui::Sense::click()),
+                                        )
+                                    } else {
+                                        let failed =
+                                            self.attachment_thumbnail_errors.contains_key(path);
+                                        let loading = image && !failed;
+                                        let placeholder = if attachment.kind != "image" {
+                                            "🎬"
+                                        } else if loading {
+                                            "Loading..."
+                                        } else {
+                                            "Preview unavailable"
+                                        };
+                                        let response =
+                                            row.add_sized(tile, egui::Button::new(placeholder));
+                                        if loading {
+                                            let visible = row.clip_rect().intersects(response.rect);
+                                            thumbnail_requests.push((path.to_string(), visible));
+                                        }
+                                        response
+                                    };
+                                    if response.on_hover_text(&attachment.file_name).clicked() {
+                                        opened = Some(attachment.clone());
+                                    }
+                                }
+                            });
+                            if gallery.has_more {
+                                ui.add_space(6.0);
+                                if ui.button("Load more").clicked() {
+                                    load_more = true;
+                                }
+                            }
+                        });
+                    for (path, visible) in dedup_thumbnail_requests(thumbnail_requests) {
+                        self.queue_thumbnail_load(&path, visible);
+                    }
+                    for path in touched_thumbnails {
+                        self.touch_thumbnail_cache(&path);
+                    }
+                    if load_more {
+                        self.load_more_gallery();
+                    }
+                    if let Some(attachment) = opened {
+                        if attachment.kind == "image"
+                            && !self.downgraded_images.contains(&attachment.file_path)
+                        {
+                            self.open_lightbox(attachment);
+                        } else {
+                            self.request_attachment_open(attachment, OpenWith::Default);
+                        }
+                    }
+                    return;
+                }
+                let show_search_results =
+                    !self.search_query.trim().is_empty()
+                        && self.search_last_query == self.search_query.trim()
+                        && self.search_last_channel_only == self.search_channel_only;
+                let show_channel =
+                    show_search_results && !self.search_channel_only;
+                ui.add_enabled_ui(self.messages_loaded, |ui| {
+                    ui.horizontal(|row| {
+                        row.checkbox(&mut self.show_saved_only, "Saved only");
+                        if row
+                            .small_button("All saved")
+                            .on_hover_text("List saved messages from every channel")
+                            .clicked()
+                        {
+                            saved_view_toggle = true;
+                        }
+                        let saved_in_view = self
+                            .messages
+                            .iter()
+                            .filter(|message| self.saved_messages.contains(&message.id))
+                            .count();
+                        row.label(
+                            egui::RichText::new(format!("Saved in view: {saved_in_view}"))
+                                .small()
+                                .color(egui::Color32::from_rgb(120, 130, 150)),
+                        );
+                        row.add_space(10.0);
+                        row.checkbox(&mut self.show_pinned_only, "Pinned only");
+                        let pinned_in_view = self
+                            .messages
+                            .iter()
+                            .filter(|message| self.pinned_messages.contains(&message.id))
+                            .count();
+                        row.label(
+                            egui::RichText::new(format!("Pinned in view: {pinned_in_view}"))
+                                .small()
+                                .color(egui::Color32::from_rgb(120, 130, 150)),
+                        );
+                        row.add_space(10.0);
+                        row.checkbox(&mut self.collapse_repeats, "Collapse repeats")
+                            .on_hover_text(
+                                "Show identical back-to-back messages once with a count",
+                            );
+                        if show_search_results {
+                            row.label(
+                                egui::RichText::new("Saved filter ignored in search.")
+                                    .small()
+                                    .color(egui::Color32::from_rgb(120, 130, 150)),
+                            );
+                            row.label(
+                                egui::RichText::new("Pinned filter ignored in search.")
+                                    .small()
+                                    .color(egui::Color32::from_rgb(120, 130, 150)),
+                            );
+                        }
+                    });
+                });
+                let mut messages: Vec<&Message> = if show_search_results {
+                    self.search_results.iter().collect()
+                } else {
+                    self.messages.iter().collect()
+                };
+                let saved_only_active = !show_search_results && self.show_saved_only;
+                let pinned_only_active = !show_search_results && self.show_pinned_only;
+                if saved_only_active {
+                    messages.retain(|message| self.saved_messages.contains(&message.id));
+                }
+                if pinned_only_active {
+                    messages.retain(|message| self.pinned_messages.contains(&message.id));
+                }
+                if show_search_results && messages.is_empty() {
+                    ui.label(
+                        egui::RichText::new("No matches found.")
+                            .small()
+                            .color(egui::Color32::from_rgb(160, 170, 190)),
+                    );
+                } else if !show_search_results && !self.messages_loaded && self.messages.is_empty() {
+                    ui.label(
+                        egui::RichText::new("Loading messages...")
+                            .small()
+                            .color(egui::Color32::from_rgb(160, 170, 190)),
+                    );
+                } else if (saved_only_active || pinned_only_active) && messages.is_empty() {
+                    let empty_label = if saved_only_active && pinned_only_active {
+                        "No saved and pinned messages in this channel."
+                    } else if saved_only_active {
+                        "No saved messages in this channel."
+                    } else {
+                        "No pinned messages in this channel."
+                    };
+                    ui.label(
+                        egui::RichText::new(empty_label)
+                            .small()
+                            .color(egui::Color32::from_rgb(160, 170, 190)),
+                    );
+                }
+                let mut rows: Vec<(&Message, usize)> = Vec::with_capacity(messages.len());
+                for message in messages {
+                    if self.collapse_repeats
+                        && self.unread_marker != Some(message.id)
+                        && !self.message_attachments.contains_key(&message.id)
+                    {
+                        if let Some((previous, count)) = rows.last_mut() {
+                            if is_repeat_message(previous, message)
+                                && !self.message_attachments.contains_key(&previous.id)
+                            {
+                                *count += 1;
+                                continue;
+                            }
+                        }
+                    }
+                    rows.push((message, 1));
+                }
+                // A row continues the previous group unless the unread marker splits them.
+                let grouped: Vec<bool> = rows
+                    .iter()
+                    .enumerate()
+                    .map(|(index, (message, _))| {
+                        index > 0
+                            && self.unread_marker != Some(message.id)
+                            && continues_group(
+                                rows[index - 1].0,
+                                message,
+                                self.app_config.group_minutes,
+                            )
+                    })
+                    .collect();
+                let find_term = self
+                    .find
+                    .as_ref()
+                    .map(|find| find.query.trim().to_string())
+                    .filter(|term| !term.is_empty());
+                let find_matches: Vec<(i64, usize)> = match find_term.as_deref() {
+                    Some(term) => rows
+                        .iter()
+                        .flat_map(|(message, _)| {
+                            let count = count_find_matches(
+                                &message.body,
+                                &self.app_config.identity.username,
+                                &self.app_config.highlight_words,
+                                term,
+                            );
+                            (0..count).map(move |ordinal| (message.id, ordinal))
+                        })
+                        .collect(),
+                    None => Vec::new(),
+                };
+                let mut find_close = false;
+                if let Some(find) = self.find.as_mut() {
+                    let mut find_step: Option<isize> = None;
+                    ui.horizontal(|row| {
+                        row.label("Find");
+                        let response = row.add(
+                            egui::TextEdit::singleline(&mut find.query)
+                                .hint_text("Find in view")
+                                .desired_width(220.0),
+                        );
+                        if find.focus_requested {
+                            response.request_focus();
+                            find.focus_requested = false;
+                        }
+                        if response.changed() {
+                            find.current = 0;
+                            find_step = Some(0);
+                        }
+                        if response.lost_focus()
+                            && row.input(|input| input.key_pressed(egui::Key::Enter))
+                        {
+                            let backwards = row.input(|input| input.modifiers.shift);
+                            find_step = Some(if backwards { -1 } else { 1 });
+                            response.request_focus();
+                        }
+                        let counter = if find_matches.is_empty() {
+                            "No matches".to_string()
+                        } else {
+                            format!("{}/{}", find.current.min(find_matches.len() - 1) + 1, find_matches.len())
+                        };
+                        row.label(
+                            egui::RichText::new(counter)
+                                .small()
+                                .color(egui::Color32::from_rgb(140, 150, 170)),
+                        );
+                        if row.small_button("▲").on_hover_text("Previous match").clicked() {
+                            find_step = Some(-1);
+                        }
+                        if row.small_button("▼").on_hover_text("Next match").clicked() {
+                            find_step = Some(1);
+                        }
+                        if row.small_button("✕").on_hover_text("Close (Esc)").clicked() {
+                            find_close = true;
+                        }
+                    });
+                    if find_matches.is_empty() {
+                        find.current = 0;
+                    } else {
+                        let total = find_matches.len() as isize;
+                        let current = (find.current as isize).min(total - 1);
+                        if let Some(step) = find_step {
+                            find.current = (current + step).rem_euclid(total) as usize;
+                            let (message_id, _) = find_matches[find.current];
+                            self.scroll_to_message = Some(message_id);
+                            self.expanded_messages.insert(message_id);
+                        } else {
+                            find.current = current as usize;
+                        }
+                    }
+                }
+                let current_find_match = self
+                    .find
+                    .as_ref()
+                    .and_then(|find| find_matches.get(find.current).copied());
+                if find_close {
+                    self.find = None;
+                }
+                let mut thumbnail_requests: Vec<(String, bool)> = Vec::new();
+                let mut touched_thumbnails: Vec<String> = Vec::new();
+                let mut touched_errors: Vec<String> = Vec::new();
+                let preview_bounds = self.app_config.thumbnail_size.bounds();
+                let mut thumbnail_retries: Vec<String> = Vec::new();
+                let mut attachment_action: Option<Result<(), String>> = None;
+                let mut open_request: Option<(Attachment, OpenWith)> = None;
+                let mut open_with_lookup: Option<String> = None;
+                let mut save_copy_request: Option<Attachment> = None;
+                let mut text_preview_requests: Vec<String> = Vec::new();
+                let mut text_preview_toggle: Option<String> = None;
+                let mut long_message_toggle: Option<i64> = None;
+                let mut rendered_messages = 0;
+                let mut rendered_thumbnails = 0;
+                let mut scrolled_to_target = false;
+                let scroll_target = if show_search_results && self.find.is_none() {
+                    None
+                } else {
+                    self.scroll_to_message
+                };
+                let mut copy_text: Option<String> = None;
+                let mut copied_notice: Option<&str> = None;
+                let mut selection_toggle: Option<(i64, bool)> = None;
+                let mut selection_clear = false;
+                let visible_ids: Vec<i64> = rows.iter().map(|(message, _)| message.id).collect();
+                let selecting = !self.selected_messages.is_empty();
+                if selecting {
+                    let selected: Vec<&Message> = rows
+                        .iter()
+                        .map(|(message, _)| *message)
+                        .filter(|message| self.selected_messages.contains(&message.id))
+                        .collect();
+                    ui.horizontal(|row| {
+                        row.label(
+                            egui::RichText::new(format!("{} selected", selected.len()))
+                                .small()
+                                .color(egui::Color32::from_rgb(190, 200, 215)),
+                        );
+                        if row.small_button("Copy transcript").clicked() {
+                            let transcript: Vec<String> = selected
+                                .iter()
+                                .map(|message| {
+                                    let channel = self
+                                        .channels
+                                        .iter()
+                                        .find(|channel| channel.id == message.channel_id)
+                                        .filter(|_| show_channel);
+                                    format_message_for_clipboard(
+                                        message,
+                                        channel,
+                                        &self.app_config.identity,
+                                        &self.roster,
+                                        &self.app_config.clock,
+                                    )
+                                })
+                                .collect();
+                            copy_text = Some(transcript.join("\n"));
+                        }
+                        row.menu_button("Forward to…", |menu| {
+                            let channel_state = &self.channel_state;
+                            let targets = self.channels.iter().filter(|channel| {
+                                !channel.archived
+                                    && !channel_state
+                                        .get(&channel.id)
+                                        .is_some_and(|state| state.read_only)
+                            });
+                            for channel in targets {
+                                let label = match channel.kind {
+                                    ChannelKind::Channel => format!("# {}", channel.name),
+                                    ChannelKind::DirectMessage => format!("@{}", channel.name),
+                                };
+                                if menu.button(label).clicked() {
+                                    selection_forward = Some((
+                                        channel.id,
+                                        selected.iter().map(|message| (*message).clone()).collect(),
+                                    ));
+                                    menu.close_menu();
+                                }
+                            }
+                        });
+                        let own_ids: Vec<i64> = selected
+                            .iter()
+                            .filter(|message| self.app_config.identity.is_self(&message.author))
+                            .map(|message| message.id)
+                            .collect();
+                        if !own_ids.is_empty() {
+                            if self.selection_delete_confirm {
+                                row.label(
+                                    egui::RichText::new(format!(
+                                        "Delete {} of your messages?",
+                                        own_ids.len()
+                                    ))
+                                    .small()
+                                    .color(egui::Color32::from_rgb(220, 180, 80)),
+                                );
+                                if row.small_button("Delete").clicked() {
+                                    selection_delete = Some(own_ids);
+                                }
+                                if row.small_button("Cancel").clicked() {
+                                    self.selection_delete_confirm = false;
+                                }
+                            } else if row
+                                .small_button("Delete mine…")
+                                .on_hover_text("Only your own messages are deleted")
+                                .clicked()
+                            {
+                                self.selection_delete_confirm = true;
+                            }
+                        }
+                        if row.small_button("Clear selection").clicked() {
+                            selection_clear = true;
+                        }
+                        row.label(
+                            egui::RichText::new("Shift-click or Shift+Up/Down to select a range.")
+                                .small()
+                                .color(egui::Color32::from_rgb(120, 130, 150)),
+                        );
+                    });
+                    if !ui.ctx().wants_keyboard_input() {
+                        let step = ui.input_mut(|input| {
+                            let shift = egui::Modifiers::SHIFT;
+                            if input.consume_key(shift, egui::Key::ArrowUp) {
+                                Some(-1)
+                            } else if input.consume_key(shift, egui::Key::ArrowDown) {
+                                Some(1)
+                            } else {
+                                None
+                            }
+                        });
+                        if let Some(step) = step {
+                            let focus = self.selection_focus.or(self.selection_anchor);
+                            let next = focus
+                                .and_then(|focus| visible_ids.iter().position(|id| *id == focus))
+                                .map(|index| {
+                                    index.saturating_add_signed(step).min(visible_ids.len() - 1)
+                                });
+                            if let Some(next) = next {
+                                selection_toggle = Some((visible_ids[next], true));
+                            }
+                        }
+                    }
+                }
+                // New messages at the end of the channel this frame, as opposed to a
+                // channel switch or older history loading in above.
+                let newest = self
+                    .messages
+                    .last()
+                    .map(|message| (self.selected_channel_id, message.id));
+                let appended = !show_search_results
+                    && newest != self.newest_rendered
+                    && newest.map(|(channel_id, _)| channel_id)
+                        == self.newest_rendered.map(|(channel_id, _)| channel_id);
+                if !show_search_results {
+                    self.newest_rendered = newest;
+                }
+                // Your own sends always bring the view down to them.
+                let own_send = appended
+                    && self
+                        .messages
+                        .last()
+                        .is_some_and(|message| self.app_config.identity.is_self(&message.author));
+                let auto_scroll = if own_send {
+                    AutoScroll::Always
+                } else {
+                    self.app_config.auto_scroll
+                };
+                let history_anchor = self.history_anchor.filter(|_| !show_search_results);
+                let mut message_area = egui::ScrollArea::vertical()
+                    .id_source(("messages", self.selected_channel_id, show_search_results))
+                    .auto_shrink([false, false])
+                    .animated(history_anchor.is_none())
+                    .stick_to_bottom(
+                        scroll_target.is_none()
+                            && history_anchor.is_none()
+                            && !(appended && auto_scroll == AutoScroll::Never),
+                    );
+                if appended && auto_scroll == AutoScroll::Always && scroll_target.is_none() {
+                    message_area = message_area.vertical_scroll_offset(f32::MAX);
+                }
+                // Rows are laid out once to learn their height; after that, rows well
+                // outside the viewport are stood in for by blank space of that height.
+                // Heights depend on the wrap width, so a resize re-measures.
+                let mut row_heights = std::mem::take(&mut self.row_heights);
+                let mut row_heights_width = self.row_heights_width;
+                let message_output = message_area
+                    .show(ui, |ui| {
+                        let row_width = ui.available_width();
+                        if (row_heights_width - row_width).abs() > 0.5 {
+                            row_heights.clear();
+                            row_heights_width = row_width;
+                        }
+                        let live_range = ui.clip_rect().expand2(egui::vec2(0.0, ROW_RENDER_MARGIN));
+                        for ((message, repeat_count), grouped) in rows.into_iter().zip(grouped) {
+                            let row_top = ui.cursor().min.y;
+                            let row_key = (message.id, show_search_results);
+                            let anchor = history_anchor
+                                .filter(|(id, _)| *id == message.id)
+                                .map(|(_, align)| align);
+                            let must_render = scroll_target == Some(message.id)
+                                || anchor.is_some()
+                                || current_find_match.is_some_and(|(id, _)| id == message.id);
+                            if let Some(&height) = row_heights.get(&row_key) {
+                                if !must_render
+                                    && !live_range.y_range().intersects(
+                                        egui::Rangef::new(row_top, row_top + height),
+                                    )
+                                {
+                                    ui.add_space(height);
+                                    continue;
+                                }
+                            }
+                            rendered_messages += 1;
+                            if !show_search_results && self.unread_marker == Some(message.id) {
+                                ui.horizontal(|row| {
+                                    row.label(
+                                        egui::RichText::new("New messages")
+                                            .small()
+                                            .strong()
+                                            .color(egui::Color32::from_rgb(230, 120, 110)),
+                                    );
+                                    row.separator();
+                                });
+                            }
+                            if scroll_target == Some(message.id) {
+                                ui.scroll_to_cursor(Some(egui::Align::TOP));
+                                scrolled_to_target = true;
+                            }
+                            if anchor == Some(egui::Align::TOP) {
+                                ui.scroll_to_cursor(anchor);
+                            }
+                            let own_style = if self.app_config.identity.is_self(&message.author) {
+                                self.app_config.own_message_style
+                            } else {
+                                OwnMessageStyle::Plain
+                            };
+                            let background_slot = (own_style != OwnMessageStyle::Plain)
+                                .then(|| ui.painter().add(egui::Shape::Noop));
+                            let selection_slot = self
+                                .selected_messages
+                                .contains(&message.id)
+                                .then(|| ui.painter().add(egui::Shape::Noop));
+                            let mut content_rect = ui.available_rect_before_wrap();
+                            if own_style == OwnMessageStyle::Bubble {
+                                content_rect.min.x += content_rect.width() * OWN_BUBBLE_INDENT;
+                            }
+                            let message_rect = ui
+                                .allocate_ui_at_rect(content_rect, |ui| {
+                                let message_top = ui.cursor().min;
+                                let long_message_preview =
+                                    collapsed_preview(&message.body, self.app_config.collapse_lines);
+                                let long_message_expanded = self.expanded_messages.contains(&message.id);
+                                let mut body_rect = None;
+                                let mentioned = mentions_user(
+                                    &message.body,
+                                    &self.app_config.identity.username,
+                                    &self.app_config.highlight_words,
+                                );
+                                ui.horizontal(|row| {
+                                    if selecting {
+                                        let mut checked =
+                                            self.selected_messages.contains(&message.id);
+                                        if row.checkbox(&mut checked, "").clicked() {
+                                            let extend = row.input(|input| input.modifiers.shift);
+                                            selection_toggle = Some((message.id, extend));
+                                        }
+                                    }
+                                    let clock = egui::RichText::new(format_clock(
+                                        &message.sent_at,
+                                        &self.app_config.clock,
+                                    ));
+                                    let clock = if grouped {
+                                        // The group's first message already shows the author.
+                                        clock.small().color(egui::Color32::from_rgb(110, 120, 140))
+                                    } else {
+                                        let author = self
+                                            .roster
+                                            .label(&self.app_config.identity, &message.author);
+                                        let shown = ellipsize(author, AUTHOR_NAME_MAX_CHARS);
+                                        let response = row.label(
+                                            egui::RichText::new(shown.as_ref())
+                                                .strong()
+                                                .color(self.roster.color(&message.author)),
+                                        );
+                                        hover_full_text(response, &shown, author);
+                                        clock.color(egui::Color32::from_rgb(140, 150, 170))
+                                    };
+                                    row.label(clock)
+                                    .on_hover_text(format_timestamp_detail(
+                                        &message.sent_at,
+                                        &self.app_config.clock,
+                                    ));
+                                    if let Some(edited_at) = message
+                                        .edited_at
+                                        .as_deref()
+                                        .filter(|edited_at| {
+                                            is_meaningful_edit(&message.sent_at, edited_at)
+                                        })
+                                    {
+                                        row.label(
+                                            egui::RichText::new("(edited)")
+                                                .small()
+                                                .color(egui::Color32::from_rgb(120, 130, 150)),
+                                        )
+                                        .on_hover_text(format!(
+                                            "Edited {}",
+                                            format_timestamp_detail(edited_at, &self.app_config.clock)
+                                        ));
+                                    }
+                                    if repeat_count > 1 {
+                                        row.label(
+                                            egui::RichText::new(format!("×{repeat_count}"))
+                                                .small()
+                                                .strong()
+                                                .color(egui::Color32::from_rgb(200, 170, 110)),
+                                        )
+                                        .on_hover_text("Identical consecutive messages collapsed");
+                                    }
+                                    if let Some(outcome) = self.held_sends.get(&message.id) {
+                                        let (label, hint) = match outcome {
+                                            SendOutcome::NotConnected => (
+                                                "Not sent",
+                                                "You're offline; this sends once you connect",
+                                            ),
+                                            _ => {
+                                                ("Queued", "Sends as soon as the connection is up")
+                                            }
+                                        };
+                                        row.label(
+                                            egui::RichText::new(label)
+                                                .small()
+                                                .color(egui::Color32::from_rgb(120, 130, 150)),
+                                        )
+                                        .on_hover_text(hint);
+                                    }
+                                    if self.failed_sends.contains(&message.id) {
+                                        row.label(
+                                            egui::RichText::new("Not delivered")
+                                                .small()
+                                                .color(egui::Color32::from_rgb(220, 120, 120)),
+                                        )
+                                        .on_hover_text("The server never confirmed this message");
+                                        if row.small_button("Retry").clicked() {
+                                            resend_request = Some(message.clone());
+                                        }
+                                    }
+                                    let pinned = self.pinned_messages.contains(&message.id);
+                                    let pin_label = if pinned { "📌" } else { "📍" };
+                                    if row
+                                        .button(pin_label)
+                                        .on_hover_text(if pinned { "Unpin message" } else { "Pin message" })
+                                        .clicked()
+                                    {
+                                        pinned_toggle = Some(message.id);
+                                    }
+                                    let saved = self.saved_messages.contains(&message.id);
+                                    let save_label = if saved { "★" } else { "☆" };
+                                    if row
+                                        .button(save_label)
+                                        .on_hover_text(if saved {
+                                            "Remove from saved"
+                                        } else {
+                                            "Save message"
+                                        })
+                                        .clicked()
+                                    {
+                                        saved_toggle = Some(message.id);
+                                    }
+                                    row.menu_button("⋯", |menu| {
+                                        if menu.button("Copy text").clicked() {
+                                            copy_text = Some(message.body.clone());
+                                            menu.close_menu();
+                                        }
+                                        if menu.button("Copy with author and time").clicked() {
+                                            let channel = self
+                                                .channels
+                                                .iter()
+                                                .find(|channel| channel.id == message.channel_id)
+                                                .filter(|_| show_channel);
+                                            copy_text = Some(format_message_for_clipboard(
+                                                message,
+                                                channel,
+                                                &self.app_config.identity,
+                                                &self.roster,
+                                                &self.app_config.clock,
+                                            ));
+                                            menu.close_menu();
+                                        }
+                                        if menu
+                                            .button("Copy as quote")
+                                            .on_hover_text("For pasting into another channel")
+                                            .clicked()
+                                        {
+                                            copy_text = Some(format_message_as_quote(message));
+                                            menu.close_menu();
+                                        }
+                                        let selected = self.selected_messages.contains(&message.id);
+                                        let select_label = if selected {
+                                            "Deselect"
+                                        } else {
+                                            "Select"
+                                        };
+                                        if menu.button(select_label).clicked() {
+                                            selection_toggle = Some((message.id, false));
+                                            menu.close_menu();
+                                        }
+                                    })
+                                    .response
+                                    .on_hover_text("More actions");
+                                    if show_channel {
+                                        row.label(
+                                            egui::RichText::new(self.channel_label(message.channel_id))
+                                                .small()
+                                                .color(egui::Color32::from_rgb(140, 150, 170)),
+                                        );
+                                    }
+                                    let body = if long_message_expanded {
+                                        message.body.as_str()
+                                    } else {
+                                        long_message_preview.as_deref().unwrap_or(&message.body)
+                                    };
+                                    // Attachment-only messages have no body; the files are the content.
+                                    if !body.trim().is_empty() {
+                                        body_rect = Some(
+                                            row.horizontal_wrapped(|body_ui| {
+                                                let original_spacing = body_ui.spacing().item_spacing;
+                                                body_ui.spacing_mut().item_spacing.x = 0.0;
+                                                let link = render_message_body(
+                                                    body_ui,
+                                                    body,
+                                                    &self.app_config.identity.username,
+                                                    &self.app_config.highlight_words,
+                                                    &self.channels,
+                                                    find_term.as_deref().map(|term| {
+                                                        let current = current_find_match
+                                                            .filter(|(id, _)| *id == message.id)
+                                                            .map(|(_, ordinal)| ordinal);
+                                                        (term, current)
+                                                    }),
+                                                );
+                                                if link.is_some() {
+                                                    channel_switch = link;
+                                                }
+                                                body_ui.spacing_mut().item_spacing = original_spacing;
+                                            })
+                                            .response
+                                            .rect,
+                                        );
+                                    }
+                                });
+                                if long_message_preview.is_some() {
+                                    if let (false, Some(rect)) = (long_message_expanded, body_rect) {
+                                        paint_bottom_fade(ui, rect);
+                                    }
+                                    if ui
+                                        .small_button(if long_message_expanded {
+                                            "Show less"
+                                        } else {
+                                            "Show more"
+                                        })
+                                        .clicked()
+                                    {
+                                        long_message_toggle = Some(message.id);
+                                    }
+                                }
+                                ui.horizontal(|row| {
+                                    row.label(
+                                        egui::RichText::new("Reactions")
+                                            .small()
+                                            .color(egui::Color32::from_rgb(120, 130, 150)),
+                                    );
+                                    let mut reactors: HashMap<String, Vec<&str>> = HashMap::new();
+                                    let mut user_reactions: HashSet<String> = HashSet::new();
+                                    if let Some(reactions) = self.message_reactions.get(&message.id) {
+                                        for reaction in reactions {
+                                            reactors
+                                                .entry(reaction.emoji.clone())
+                                                .or_default()
+                                                .push(&reaction.author);
+                                            if self.app_config.identity.is_self(&reaction.author) {
+                                                user_reactions.insert(reaction.emoji.clone());
+                                            }
+                                        }
+                                    }
+                                    // The quick set first, then anything picked from the full set.
+                                    let mut extra: Vec<&str> = reactors
+                                        .keys()
+                                        .map(String::as_str)
+                                        .filter(|emoji| !REACTION_EMOJIS.contains(emoji))
+                                        .collect();
+                                    extra.sort_unstable();
+                                    for emoji in REACTION_EMOJIS.iter().copied().chain(extra) {
+                                        let authors = reactors.get(emoji).map(Vec::as_slice);
+                                        let count = authors.map_or(0, <[&str]>::len);
+                                        let label = if count > 0 {
+                                            format!("{emoji} {count}")
+                                        } else {
+                                            emoji.to_string()
+                                        };
+                                        let reacted = user_reactions.contains(emoji);
+                                        let text = if reacted {
+                                            egui::RichText::new(label)
+                                                .color(egui::Color32::from_rgb(230, 210, 140))
+                                        } else {
+                                            egui::RichText::new(label)
+                                                .color(egui::Color32::from_rgb(170, 180, 200))
+                                        };
+                                        let action = if reacted {
+                                            "Remove reaction"
+                                        } else {
+                                            "Add reaction"
+                                        };
+                                        let hover = match authors {
+                                            Some(authors) => format!(
+                                                "{} reacted with {emoji}\n{action}",
+                                                format_reactors(
+                                                    authors,
+                                                    &self.app_config.identity,
+                                                    &self.roster,
+                                                )
+                                            ),
+                                            None => action.to_string(),
+                                        };
+                                        if row
+                                            .add(egui::Button::new(text))
+                                            .on_hover_text(hover)
+                                            .clicked()
+                                        {
+                                            reaction_toggle =
+                                                Some((message.id, emoji.to_string(), reacted));
+                                        }
+                                    }
+                                    row.menu_button("＋", |menu| {
+                                        let picked = emoji_picker(menu, &self.recent_emoji);
+                                        if let Some(emoji) = picked {
+                                            let reacted = user_reactions.contains(&emoji);
+                                            reaction_toggle = Some((message.id, emoji, reacted));
+                                            menu.close_menu();
+                                        }
+                                    })
+                                    .response
+                                    .on_hover_text("More reactions");
+                                });
+                                if let Some(attachments) = self.message_attachments.get(&message.id) {
+                                    for attachment in attachments {
+                                        let path = attachment.file_path.as_str();
+                                        let downgraded = self.downgraded_images.contains(path);
+                                        let shown_kind =
+                                            if downgraded { "file" } else { attachment.kind.as_str() };
+                                        if attachment.kind == "image" && !downgraded {
+                                            let thumbnail = if self.attachment_thumbnails.contains_key(path) {
+                                                touched_thumbnails.push(path.to_string());
+                                                self.attachment_thumbnails.get(path)
+                                            } else {
+                                                if self.attachment_thumbnail_errors.contains_key(path) {
+                                                    touched_errors.push(path.to_string());
+                                                }
+                                                None
+                                            };
+                                            let dimensions =
+                                                self.image_dimensions.get(path).copied().flatten();
+                                            let bounds = egui::Vec2::new(
+                                                ui.available_width().min(preview_bounds.x),
+                                                preview_bounds.y,
+                                            );
+                                            if let Some(texture) = thumbnail {
+                                                rendered_thumbnails += 1;
+                                                let sized =
+                                                    egui::load::SizedTexture::from_handle(texture);
+                                                let [width, height] = texture.size();
+                                                let size = thumbnail_display_size(
+                                                    dimensions
+                                                        .or(Some((width as u32, height as u32))),
+                                                    bounds,
+                                                );
+                                                ui.add(
+                                                    egui::Image::from_texture(sized)
+                                                        .fit_to_exact_size(size),
+                                                );
+                                            } else if let Some(err) =
+                                                self.attachment_thumbnail_errors.get(path)
+                                            {
+                                                ui.horizontal(|row| {
+                                                    row.label(
+                                                        egui::RichText::new(format!(
+                                                            "Image preview unavailable: {}",
+                                                            err.message
+                                                        ))
+                                                        .small()
+                                                        .color(egui::Color32::from_rgb(170, 140, 140)),
+                                                    );
+                                                    if row.small_button("Retry preview").clicked() {
+                                                        thumbnail_retries.push(path.to_string());
+                                                    }
+                                                });
+                                            } else {
+                                                // Holds the thumbnail's final size so the
+                                                // list doesn't jump when it arrives.
+                                                let size =
+                                                    thumbnail_display_size(dimensions, bounds);
+                                                let (rect, _) =
+                                                    ui.allocate_exact_size(
+                                                        size,
+                                                        egui::Sense::hover(),
+                                                    );
+                                                let painter = ui.painter();
+                                                painter.rect_filled(
+                                                    rect,
+                                                    4.0,
+                                                    egui::Color32::from_rgb(32, 36, 44),
+                                                );
+                                                painter.text(
+                                                    rect.center(),
+                                                    egui::Align2::CENTER_CENTER,
+                                                    "Loading image preview...",
+                                                    egui::TextStyle::Small.resolve(ui.style()),
+                                                    egui::Color32::from_rgb(130, 140, 160),
+                                                );
+                                                // Rows scrolled past are still laid out, just
+                                                // clipped; they wait behind on-screen images.
+                                                let visible = ui.clip_rect().intersects(rect);
+                                                thumbnail_requests.push((path.to_string(), visible));
+                                            }
+                                        }
+                                        ui.horizontal(|row| {
+                                            row.label(
+                                                egui::RichText::new("[attachment]")
+                                                    .small()
+                                                    .color(egui::Color32::from_rgb(120, 130, 150)),
+                                            );
+                                            row.label(
+                                                egui::RichText::new(&attachment.file_name)
+                                                    .small()
+                                                    .color(egui::Color32::from_rgb(190, 200, 215)),
+                                            )
+                                            .on_hover_text(&attachment.file_path);
+                                            row.label(
+                                                egui::RichText::new(format!(
+                                                    "{} • {}",
+                                                    shown_kind,
+                                                    format_bytes(attachment.file_size)
+                                                ))
+                                                .small()
+                                                .color(egui::Color32::from_rgb(120, 130, 150)),
+                                            );
+                                            if row.button("Open").clicked() {
+                                                open_request =
+                                                    Some((attachment.clone(), OpenWith::Default));
+                                            }
+                                            row.menu_button("Open with…", |menu| {
+                                                if cfg!(any(
+                                                    target_os = "macos",
+                                                    target_os = "windows"
+                                                )) {
+                                                    let choose = menu.add_enabled(
+                                                        self.chooser_receiver.is_none(),
+                                                        egui::Button::new("Choose application…"),
+                                                    );
+                                                    if choose.clicked() {
+                                                        open_request = Some((
+                                                            attachment.clone(),
+                                                            OpenWith::Chooser,
+                                                        ));
+                                                        menu.close_menu();
+                                                    }
+                                                    return;
+                                                }
+                                                let apps = self.open_with_apps.get(&attachment.file_path);
+                                                match apps {
+                                                    None => {
+                                                        open_with_lookup =
+                                                            Some(attachment.file_path.clone());
+                                                        menu.label("Looking up applications...");
+                                                    }
+                                                    Some(apps) if apps.is_empty() => {
+                                                        menu.label("No applications for this type.");
+                                                    }
+                                                    Some(apps) => {
+                                                        for app in apps {
+                                                            if menu.button(&app.name).clicked() {
+                                                                open_request = Some((
+                                                                    attachment.clone(),
+                                                                    OpenWith::App(app.id.clone()),
+                                                                ));
+                                                                menu.close_menu();
+                                                            }
+                                                        }
+                                                    }
+                                                }
+                                            });
+                                            if row.button("Reveal").clicked() {
+                                                attachment_action =
+                                                    Some(reveal_attachment(&attachment.file_path));
+                                            }
+                                            if row
+                                                .button("Copy path")
+                                                .on_hover_text(&attachment.file_path)
+                                                .clicked()
+                                            {
+                                                copy_text = Some(attachment.file_path.clone());
+                                                copied_notice = Some("Copied file path.");
+                                            }
+                                            // Stored copies carry a timestamp prefix, so the
+                                            // name the sender chose is only in the database.
+                                            let in_store = self
+                                                .app_config
+                                                .attachment_store
+                                                .as_deref()
+                                                .is_some_and(|store| {
+                                                    Path::new(&attachment.file_path)
+                                                        .starts_with(store)
+                                                });
+                                            if in_store && row.button("Copy name").clicked() {
+                                                copy_text = Some(attachment.file_name.clone());
+                                                copied_notice = Some("Copied original file name.");
+                                            }
+                                            if row
+                                                .add_enabled(
+                                                    self.save_copy_receiver.is_none(),
+                                                    egui::Button::new("Save as…"),
+                                                )
+                                                .on_hover_text("Save a copy somewhere else")
+                                                .clicked()
+                                            {
+                                                save_copy_request = Some(attachment.clone());
+                                            }
+                                        });
+                                        if is_text_attachment(&attachment.file_name) {
+                                            let path = attachment.file_path.as_str();
+                                            match self.text_previews.get(path) {
+                                                Some(Some(text)) => {
+                                                    let expanded =
+                                                        self.expanded_text_previews.contains(path);
+                                                    let line_count = text.lines().count();
+                                                    let shown = if expanded {
+                                                        text.clone()
+                                                    } else {
+                                                        text.lines()
+                                                            .take(TEXT_PREVIEW_COLLAPSED_LINES)
+                                                            .collect::<Vec<_>>()
+                                                            .join("\n")
+                                                    };
+                                                    egui::Frame::none()
+                                                        .fill(egui::Color32::from_rgb(24, 27, 33))
+                                                        .rounding(4.0)
+                                                        .inner_margin(6.0)
+                                                        .show(ui, |frame| {
+                                                            egui::ScrollArea::vertical()
+                                                                .id_source(("text-preview", message.id, path))
+                                                                .max_height(240.0)
+                                                                .show(frame, |frame| {
+                                                                    frame.label(
+                                                                        egui::RichText::new(shown)
+                                                                            .monospace()
+                                                                            .small(),
+                                                                    );
+                                                                });
+                                                        });
+                                                    if line_count > TEXT_PREVIEW_COLLAPSED_LINES
+                                                        && ui
+                                                            .small_button(if expanded {
+                                                                "Show less"
+                                                            } else {
+                                                                "Show more"
+                                                            })
+                                                            .clicked()
+                                                    {
+                                                        text_preview_toggle = Some(path.to_string());
+                                                    }
+                                                }
+                                                Some(None) => {}
+                                                None => {
+                                                    if !self.text_preview_in_flight.contains(path) {
+                                                        text_preview_requests.push(path.to_string());
+                                                    }
+                                                    ui.label(
+                                                        egui::RichText::new("Loading text preview...")
+                                                            .small()
+                                                            .color(egui::Color32::from_rgb(130, 140, 160)),
+                                                    );
+                                                }
+                                            }
+                                        }
+                                    }
+                                }
+                                if mentioned {
+                                    let bottom = ui.cursor().min.y;
+                                    ui.painter().vline(
+                                        message_top.x - 4.0,
+                                        message_top.y..=bottom,
+                                        egui::Stroke::new(3.0, egui::Color32::from_rgb(230, 190, 90)),
+                                    );
+                                }
+                                })
+                                .response
+                                .rect;
+                            if let Some(slot) = background_slot {
+                                let (rect, rounding, fill) = match own_style {
+                                    OwnMessageStyle::Bubble => (
+                                        message_rect.expand2(egui::vec2(6.0, 3.0)),
+                                        8.0,
+                                        egui::Color32::from_rgba_unmultiplied(90, 130, 200, 40),
+                                    ),
+                                    _ => (
+                                        egui::Rect::from_x_y_ranges(
+                                            ui.max_rect().x_range(),
+                                            message_rect.y_range(),
+                                        )
+                                        .expand2(egui::vec2(0.0, 2.0)),
+                                        2.0,
+                                        egui::Color32::from_rgba_unmultiplied(90, 130, 200, 18),
+                                    ),
+                                };
+                                ui.painter()
+                                    .set(slot, egui::Shape::rect_filled(rect, rounding, fill));
+                            }
+                            if let Some(slot) = selection_slot {
+                                // Reaches into the gap below so a selected run reads as one band.
+                                let rect = egui::Rect::from_x_y_ranges(
+                                    ui.max_rect().x_range(),
+                                    message_rect.min.y - 1.0..=message_rect.max.y + 3.0,
+                                );
+                                ui.painter().set(
+                                    slot,
+                                    egui::Shape::rect_filled(
+                                        rect,
+                                        0.0,
+                                        egui::Color32::from_rgba_unmultiplied(120, 160, 230, 36),
+                                    ),
+                                );
+                            }
+                            ui.add_space(2.0);
+                            if anchor == Some(egui::Align::BOTTOM) {
+                                ui.scroll_to_cursor(anchor);
+                            }
+                            row_heights.insert(row_key, ui.cursor().min.y - row_top);
+                        }
+                        if show_search_results && self.search_has_more() {
+                            ui.add_space(4.0);
+                            if ui
+                                .button(format!(
+                                    "Load more results ({} remaining)",
+                                    self.search_total - self.search_results.len() as i64
+                                ))
+                                .clicked()
+                            {
+                                search_load_more = true;
+                            }
+                        }
+                    });
+                self.row_heights = row_heights;
+                self.row_heights_width = row_heights_width;
+                if scrolled_to_target {
+                    self.scroll_to_message = None;
+                }
+                // Reaching either end of the loaded window pages in more history; the
+                // row at that end stays put while the page lands around it.
+                if history_anchor.is_some() {
+                    self.history_anchor = None;
+                } else if !show_search_results && scroll_target.is_none() {
+                    let offset = message_output.state.offset.y;
+                    let overflow =
+                        message_output.content_size.y - message_output.inner_rect.height();
+                    if offset <= 0.5 && self.has_older_messages() {
+                        history_page = Some(true);
+                    } else if offset >= overflow - 0.5 && self.has_trimmed_newer_messages() {
+                        history_page = Some(false);
+                    }
+                }
+                if let Some(text) = copy_text {
+                    ui.output_mut(|output| output.copied_text = text);
+                }
+                if selection_clear {
+                    self.clear_selection();
+                }
+                if let Some((message_id, extend)) = selection_toggle {
+                    self.selection_delete_confirm = false;
+                    let position = |id: Option<i64>| {
+                        id.and_then(|id| visible_ids.iter().position(|visible| *visible == id))
+                    };
+                    let anchor = position(self.selection_anchor);
+                    let focus = position(self.selection_focus).or(anchor);
+                    let target = position(Some(message_id));
+                    match (extend, anchor, focus, target) {
+                        (true, Some(anchor), Some(focus), Some(target)) => {
+                            for id in &visible_ids[anchor.min(focus)..=anchor.max(focus)] {
+                                self.selected_messages.remove(id);
+                            }
+                            let range = anchor.min(target)..=anchor.max(target);
+                            self.selected_messages.extend(&visible_ids[range]);
+                            self.selection_focus = Some(message_id);
+                        }
+                        _ => {
+                            if !self.selected_messages.remove(&message_id) {
+                                self.selected_messages.insert(message_id);
+                            }
+                            self.selection_anchor = Some(message_id);
+                            self.selection_focus = Some(message_id);
+                        }
+                    }
+                }
+                if let Some((attachment, open_with)) = open_request {
+                    self.request_attachment_open(attachment, open_with);
+                }
+                if let Some(path) = open_with_lookup {
+                    self.start_open_with_lookup(path);
+                }
+                if let Some(result) = attachment_action {
+                    self.attachment_action_error = result.err();
+                    self.attachment_action_notice = None;
+                }
+                if let Some(notice) = copied_notice {
+                    self.attachment_action_error = None;
+                    self.attachment_action_notice = Some(notice.to_string());
+                }
+                if let Some(attachment) = save_copy_request {
+                    self.start_save_copy(&attachment);
+                }
+                for (path, visible) in dedup_thumbnail_requests(thumbnail_requests) {
+                    self.queue_thumbnail_load(&path, visible);
+                }
+                for path in touched_thumbnails {
+                    self.touch_thumbnail_cache(&path);
+                }
+                for path in touched_errors {
+                    self.touch_thumbnail_error(&path);
+                    self.recheck_thumbnail_error(&path);
+                }
+                for path in thumbnail_retries {
+                    self.retry_thumbnail(&path);
+                }
+                for path in text_preview_requests {
+                    self.queue_text_preview_load(&path);
+                }
+                if let Some(message_id) = long_message_toggle {
+                    if !self.expanded_messages.remove(&message_id) {
+                        self.expanded_messages.insert(message_id);
+                    }
+                }
+                if let Some(path) = text_preview_toggle {
+                    if !self.expanded_text_previews.remove(&path) {
+                        self.expanded_text_previews.insert(path);
+                    }
+                }
+                self.frame_stats.messages_rendered = rendered_messages;
+                self.frame_stats.thumbnails_rendered = rendered_thumbnails;
+            });
+            if self.show_shortcut_help && render_shortcut_help(ctx) {
+                self.show_shortcut_help = false;
+            }
+            if let Some(choice) = self
+                .command_palette
+                .as_mut()
+                .and_then(|palette| render_command_palette(ctx, palette))
+            {
+                self.command_palette = None;
+                if let PaletteChoice::Run(action) = choice {
+                    self.run_shortcut_action(action);
+                }
+                ctx.request_repaint();
+            }
+            if let Some(lightbox) = self.lightbox.as_ref() {
+                lightbox_action = render_lightbox(ctx, lightbox);
+            }
+            if let Some(pid) = self.instance_conflict {
+                instance_choice =
+                    render_instance_conflict(ctx, pid, &self.active_workspace().db_path);
+            }
+            if self.show_frame_stats {
+                egui::Area::new(egui::Id::new("frame_stats"))
+                    .anchor(egui::Align2::RIGHT_TOP, egui::vec2(-8.0, 8.0))
+                    .interactable(false)
+                    .show(ctx, |ui| {
+                        egui::Frame::popup(ui.style()).show(ui, |ui| {
+                            let stats = &self.frame_stats;
+                            ui.label(
+                                egui::RichText::new(format!(
+                                    "frame {:.2} ms (avg {})\nfps {:.1}\nmessages {}\nthumbnails {}",
+                                    stats.frame_ms(),
+                                    stats.frame_times.len(),
+                                    stats.fps(),
+                                    stats.messages_rendered,
+                                    stats.thumbnails_rendered
+                                ))
+                                .monospace()
+                                .small(),
+                            );
+                        });
+                    });
+            }
+        });
+        let mut repaint_delay = full_output
+            .viewport_output
+            .get(&egui::ViewportId::ROOT)
+            .map(|output| output.repaint_delay)
+            .unwrap_or(Duration::from_millis(16));
+        let suppress_repaint = self.window_occluded || !self.window_focused;
+        if suppress_repaint {
+            repaint_delay = BACKGROUND_REPAINT_DELAY;
+        } else if !has_input_events && !state_dirty && repaint_delay < IDLE_REPAINT_DELAY {
+            repaint_delay = IDLE_REPAINT_DELAY;
+        }
+        let now = Instant::now();
+        self.next_repaint_at = if repaint_delay == Duration::MAX {
+            now + Duration::from_secs(60 * 60 * 24)
+        } else {
+            now.checked_add(repaint_delay)
+                .unwrap_or_else(|| now + Duration::from_secs(1))
+        };
+        self.needs_repaint = repaint_delay.is_zero();
+        if realtime_connect {
+            self.realtime.connect();
+        }
+        if realtime_disconnect {
+            self.realtime.disconnect();
+        }
+
+        self.egui_state
+            .handle_platform_output(self.window.as_ref(), full_output.platform_output);
+
+        for (id, delta) in &full_output.textures_delta.set {
+            self.egui_renderer
+                .update_texture(&self.device, &self.queue, *id, delta);
+        }
+
+        let screen_descriptor = ScreenDescriptor {
+            size_in_pixels: [self.config.width, self.config.height],
+            pixels_per_point: full_output.pixels_per_point,
+        };
+
+        let clipped_primitives = self
+            .egui_ctx
+            .tessellate(full_output.shapes, full_output.pixels_per_point);
+        let mut encoder =
+            self.device
+                .create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                    label: Some("ralph-encoder"),
+                });
+        self.egui_renderer.update_buffers(
+            &self.device,
+            &self.queue,
+            &mut encoder,
+            &clipped_primitives,
+            &screen_descriptor,
+        );
+
+        // Without a frame only the paint is skipped; what this frame collected below
+        // still applies, and the next frame draws it.
+        let frame = match self.surface.get_current_texture() {
+            Ok(frame) => Some(frame),
+            // Display and scale changes can leave the surface configured for a size
+            // it no longer has, so rebuild it from the window and draw again.
+            Err(err @ (SurfaceError::Lost | SurfaceError::Outdated)) => {
+                log_info!("surface {err:?}; reconfiguring");
+                let size = self.window.inner_size();
+                // A minimized window has no size to configure; wait for it to come back.
+                if size.width > 0 && size.height > 0 {
+                    self.resize(size);
+                    self.window.request_redraw();
+                }
+                None
+            }
+            // The compositor didn't hand back a frame in time; try the next one.
+            Err(SurfaceError::Timeout) => {
+                self.window.request_redraw();
+                None
+            }
+            Err(SurfaceError::OutOfMemory) => {
+                log_error!("surface out of memory");
+                None
+            }
+        };
+        if let Some(frame) = frame {
+            let view = frame
+                .texture
+                .create_view(&wgpu::TextureViewDescriptor::default());
+            {
+                let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                    label: Some("ralph-render-pass"),
+                    color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                        view: &view,
+                        resolve_target: None,
+                        ops: wgpu::Operations {
+                            load: wgpu::LoadOp::Clear(wgpu::Color {
+                                r: 0.06,
+                                g: 0.07,
+                                b: 0.09,
+                                a: 1.0,
+                            }),
+                            store: wgpu::StoreOp::Store,
+                        },
+                    })],
+                    depth_stencil_attachment: None,
+                    timestamp_writes: None,
+                    occlusion_query_set: None,
+                });
+                self.egui_renderer.render(
+                    &mut render_pass,
+                    &clipped_primitives,
+                    &screen_descriptor,
+                );
+            }
+
+            self.queue.submit(Some(encoder.finish()));
+            frame.present();
+            self.frame_stats.record(render_started);
+        }
+
+        for id in &full_output.textures_delta.free {
+            self.egui_renderer.free_texture(id);
+        }
+
+        if let Some((channel_id, archived)) = channel_archive {
+            let saved = self.db_is_fallback || {
+                match set_channel_archived(&self.db, channel_id, archived) {
+                    Ok(()) => true,
+                    Err(err) => {
+                        log_error!("db channel archive error: {err}");
+                        false
+                    }
+                }
+            };
+            if saved {
+                if let Some(channel) = self
+                    .channels
+                    .iter_mut()
+                    .find(|channel| channel.id == channel_id)
+                {
+                    channel.archived = archived;
+                }
+                self.unread_title_dirty = true;
+                if archived && channel_id == self.selected_channel_id && channel_switch.is_none() {
+                    channel_switch = self
+                        .channels
+                        .iter()
+                        .find(|channel| !channel.archived)
+                        .map(|channel| channel.id);
+                }
+            }
+        }
+
+        if gallery_toggle {
+            self.toggle_gallery();
+        }
+
+        if let Some(invisible) = invisible_toggle {
+            self.set_invisible(invisible);
+        }
+        if let Some(mode) = auto_scroll_change {
+            self.set_auto_scroll(mode);
+        }
+        if let Some(size) = thumbnail_size_change {
+            self.set_thumbnail_size(size);
+            if !self.db_is_fallback {
+                if let Err(err) = save_setting(&self.db, "thumbnail_size", size.key()) {
+                    log_error!("db thumbnail size save error: {err}");
+                }
+            }
+        }
+
+        if screenshot_request {
+            self.start_screenshot();
+        }
+        if let Some((path, source)) = ingest_request {
+            self.start_ingest(self.selected_channel_id, path, source);
+        }
+        if let Some(open_anyway) = open_confirm_choice {
+            if let Some(confirm) = self.open_confirm.take().filter(|_| open_anyway) {
+                self.open_attachment_now(&confirm.attachment, confirm.open_with);
+            }
+        }
+
+        if presence_history_close {
+            self.presence_history = None;
+        } else if presence_history_reload {
+            self.reload_presence_history();
+        }
+
+        match instance_choice {
+            Some(true) => {
+                self.instance_conflict = None;
+                self.read_only_instance = true;
+                if let Some(plan) = self.deferred_load_plan.as_mut() {
+                    plan.read_only = true;
+                }
+            }
+            Some(false) => self.exit_requested = true,
+            None => {}
+        }
+
+        if let Some(action) = lightbox_action {
+            if let Some(lightbox) = self.lightbox.take() {
+                match action {
+                    LightboxAction::Close => {}
+                    LightboxAction::Open => {
+                        self.request_attachment_open(lightbox.attachment, OpenWith::Default);
+                    }
+                    LightboxAction::JumpToMessage => {
+                        self.gallery = None;
+                        if !self.jump_to_message(lightbox.attachment.message_id) {
+                            self.attachment_action_error =
+                                Some("That message is no longer in this channel.".to_string());
+                        }
+                    }
+                }
+            }
+        }
+
+        if let Some((channel_id, delete_files)) = history_clear {
+            self.clear_channel_history(channel_id, delete_files);
+        }
+
+        if let Some(channel_id) = channel_delete {
+            self.channel_delete_confirm = None;
+            let deleted = self.db_is_fallback || {
+                match delete_channel(&mut self.db, channel_id) {
+                    Ok(()) => true,
+                    Err(err) => {
+                        log_error!("db channel delete error: {err}");
+                        false
+                    }
+                }
+            };
+            if deleted {
+                self.channels.retain(|channel| channel.id != channel_id);
+                self.channel_state.remove(&channel_id);
+                self.composer_meta = build_composer_meta(&self.channels, &self.channel_state);
+                self.channel_members.remove(&channel_id);
+                self.presence_counts_dirty = true;
+                self.composer_drafts.remove(&channel_id);
+                self.last_read.remove(&channel_id);
+                self.unread_title_dirty = true;
+                self.search_results
+                    .retain(|message| message.channel_id != channel_id);
+                if channel_id == self.selected_channel_id {
+                    channel_switch = self
+                        .channels
+                        .iter()
+                        .find(|channel| !channel.archived)
+                        .or(self.channels.first())
+                        .map(|channel| channel.id);
+                    if channel_switch.is_none() {
+                        self.messages.clear();
+                        self.message_attachments.clear();
+                        self.message_reactions.clear();
+                    }
+                }
+            }
+        }
+
+        if let Some((channel_id, joined)) = membership_toggle {
+            self.set_membership(channel_id, joined);
+        }
+
+        if let Some(input) = dm_open {
+            if let Some(channel_id) = self.open_direct_message(&input) {
+                channel_switch = Some(channel_id);
+            }
+        }
+
+        if let Some(channel_id) = channel_switch {
+            if self.messages_loaded && channel_id != self.selected_channel_id {
+                self.selected_channel_id = channel_id;
+                self.clear_selection();
+                self.send_confirm = None;
+                self.highlighted_channels.remove(&channel_id);
+                self.row_heights.clear();
+                self.topic_draft = None;
+                self.placeholder_draft = None;
+                self.announcement_draft = None;
+                self.messages = match load_messages(&self.db, channel_id) {
+                    Ok(messages) => messages,
+                    Err(err) => {
+                        log_error!("db load error: {err}");
+                        Vec::new()
+                    }
+                };
+                self.prepare_unread_jump(true);
+                self.messages_loaded = true;
+                self.message_attachments = match load_attachments_for_message_ids(
+                    &self.db,
+                    &self.messages.iter().map(|message| message.id).collect::<Vec<_>>(),
+                ) {
+                    Ok(attachments) => attachments,
+                    Err(err) => {
+                        log_error!("db attachments load error: {err}");
+                        HashMap::new()
+                    }
+                };
+                self.message_reactions = match load_reactions_for_message_ids(
+                    &self.db,
+                    &self.messages.iter().map(|message| message.id).collect::<Vec<_>>(),
+                ) {
+                    Ok(reactions) => reactions,
+                    Err(err) => {
+                        log_error!("db reactions load error: {err}");
+                        HashMap::new()
+                    }
+                };
+                self.composer_focus_requested = true;
+                if self.search_channel_only && !self.search_query.trim().is_empty() {
+                    let query = self.search_query.trim().to_string();
+                    self.run_search(query, true);
+                }
+            }
+        }
+
+        // After any channel switch above, so the jump lands in the loaded channel.
+        if let Some((channel_id, message_id)) = saved_jump {
+            if channel_id == self.selected_channel_id {
+                self.saved_view = None;
+                self.gallery = None;
+                if !self.jump_to_message(message_id) {
+                    self.saved_action_error = Some(format!(
+                        "That message is no longer in {}.",
+                        self.channel_label(channel_id)
+                    ));
+                }
+            }
+        }
+        if let Some(message_id) = saved_toggle {
+            self.toggle_saved(message_id);
+        }
+        if saved_view_toggle {
+            self.run_shortcut_action(ShortcutAction::ToggleSavedView);
+        }
+
+        if let Some(message) = resend_request {
+            self.resend_message(&message);
+        }
+
+        if let Some(message_id) = pinned_toggle {
+            if self.pinned_messages.contains(&message_id) {
+                match remove_pinned_message(&self.db, message_id) {
+                    Ok(()) => {
+                        self.pinned_messages.remove(&message_id);
+                        self.pinned_action_error = None;
+                    }
+                    Err(err) => {
+                        self.pinned_action_error =
+                            Some(format!("Could not unpin message: {err}"));
+                    }
+                }
+            } else {
+                let pinned_at = format_timestamp_utc();
+                match pin_message(
+                    &self.db,
+                    message_id,
+                    &pinned_at,
+                    &self.app_config.identity.username,
+                ) {
+                    Ok(()) => {
+                        self.pinned_messages.insert(message_id);
+                        self.pinned_action_error = None;
+                    }
+                    Err(err) => {
+                        self.pinned_action_error = Some(format!("Could not pin message: {err}"));
+                    }
+                }
+            }
+        }
+
+        if let Some((message_id, emoji, reacted)) = reaction_toggle {
+            if reacted {
+                let username = &self.app_config.identity.username;
+                match remove_reaction(&self.db, message_id, &emoji, username) {
+                    Ok(()) => {
+                        if let Some(reactions) = self.message_reactions.get_mut(&message_id) {
+                            reactions.retain(|reaction| {
+                                !(reaction.emoji == emoji
+                                    && reaction.author.eq_ignore_ascii_case(username))
+                            });
+                            if reactions.is_empty() {
+                                self.message_reactions.remove(&message_id);
+                            }
+                        }
+                        self.reaction_action_error = None;
+                    }
+                    Err(err) => {
+                        self.reaction_action_error =
+                            Some(format!("Could not remove reaction: {err}"));
+                    }
+                }
+            } else {
+                let reacted_at = format_timestamp_utc();
+                let username = self.app_config.identity.username.clone();
+                match add_reaction(&self.db, message_id, &emoji, &username, &reacted_at) {
+                    Ok(()) => {
+                        self.note_recent_emoji(&emoji);
+                        self.message_reactions
+                            .entry(message_id)
+                            .or_default()
+                            .push(MessageReaction {
+                                message_id,
+                                emoji,
+                                author: username,
+                            });
+                        self.reaction_action_error = None;
+                    }
+                    Err(err) => {
+                        self.reaction_action_error =
+                            Some(format!("Could not add reaction: {err}"));
+                    }
+                }
+            }
+        }
+
+        if onboarding_submit {
+            self.complete_onboarding();
+        }
+
+        if first_channel_submit {
+            self.create_first_channel();
+        }
+
+        if let Some(index) = workspace_switch {
+            self.switch_workspace(index);
+        }
+
+        if let Some(topic) = topic_save {
+            let channel_id = self.selected_channel_id;
+            let saved = self.db_is_fallback || {
+                match update_channel_topic(&self.db, channel_id, &topic) {
+                    Ok(()) => true,
+                    Err(err) => {
+                        log_error!("db topic save error: {err}");
+                        false
+                    }
+                }
+            };
+            if saved {
+                if let Some(channel) = self
+                    .channels
+                    .iter_mut()
+                    .find(|channel| channel.id == channel_id)
+                {
+                    channel.topic = topic;
+                }
+                self.topic_draft = None;
+            }
+        }
+
+        if let Some(placeholder) = placeholder_save {
+            let channel_id = self.selected_channel_id;
+            let placeholder = (!placeholder.is_empty()).then_some(placeholder);
+            let saved = self.db_is_fallback || {
+                match save_composer_placeholder(&self.db, channel_id, placeholder.as_deref()) {
+                    Ok(()) => true,
+                    Err(err) => {
+                        log_error!("db placeholder save error: {err}");
+                        false
+                    }
+                }
+            };
+            if saved {
+                self.channel_state
+                    .entry(channel_id)
+                    .or_default()
+                    .composer_placeholder = placeholder;
+                self.composer_meta = build_composer_meta(&self.channels, &self.channel_state);
+                self.placeholder_draft = None;
+            }
+        }
+
+        if let Some(text) = announcement_save {
+            self.save_announcement(text);
+        }
+
+        if let Some(confirm) = confirm_send_toggle {
+            self.set_confirm_send(confirm);
+        }
+
+        if let Some(read_only) = read_only_toggle {
+            self.set_read_only(read_only);
+        }
+        if let Some(retention_days) = retention_change {
+            self.set_retention(retention_days);
+        }
+
+        if let Some(collapsed) = announcement_collapse {
+            self.set_announcement_collapsed(collapsed);
+        }
+
+        if store_cleanup {
+            if let Some(store) = self.app_config.attachment_store.as_deref() {
+                let elsewhere = self.other_workspace_attachment_paths();
+                let cleaned = elsewhere.and_then(|elsewhere| {
+                    let mut keep = self.pending_attachment_paths();
+                    keep.extend(elsewhere.iter().map(String::as_str));
+                    cleanup_attachment_store(&self.db, store, &keep)
+                });
+                self.maintenance_status = Some(match cleaned {
+                    Ok((0, _)) => "No orphaned attachments found.".to_string(),
+                    Ok((files, bytes)) => format!(
+                        "Removed {files} orphaned file{} ({} freed).",
+                        if files == 1 { "" } else { "s" },
+                        format_bytes(bytes as i64)
+                    ),
+                    Err(err) => format!("Cleanup failed: {err}"),
+                });
+            }
+        }
+
+        if search_clear {
+            self.search_query.clear();
+            self.search_last_query.clear();
+            self.search_last_channel_only = self.search_channel_only;
+            self.search_results.clear();
+            self.search_total = 0;
+            if self.messages_loaded {
+                self.message_attachments = match load_attachments_for_message_ids(
+                    &self.db,
+                    &self.messages.iter().map(|message| message.id).collect::<Vec<_>>(),
+                ) {
+                    Ok(attachments) => attachments,
+                    Err(err) => {
+                        log_error!("db attachments load error: {err}");
+                        HashMap::new()
+                    }
+                };
+                self.message_reactions = match load_reactions_for_message_ids(
+                    &self.db,
+                    &self.messages.iter().map(|message| message.id).collect::<Vec<_>>(),
+                ) {
+                    Ok(reactions) => reactions,
+                    Err(err) => {
+                        log_error!("db reactions load error: {err}");
+                        HashMap::new()
+                    }
+                };
+            }
+        }
+
+        if let Some(request) = search_request {
+            if self.messages_loaded {
+                self.run_search(request.query, request.channel_only);
+            }
+        }
+
+        if search_load_more && self.messages_loaded {
+            self.load_more_search_results();
+        }
+
+        if let Some(older) = history_page {
+            if self.messages_loaded {
+                self.load_history_page(older);
+            }
+        }
+
+        for path in pending_thumbnail_requests {
+            if self.attachment_thumbnails.contains_key(&path) {
+                self.touch_thumbnail_cache(&path);
+            } else {
+                self.queue_thumbnail_load(&path, true);
+            }
+        }
+        self.prune_thumbnail_queue();
+
+        if let Some(body) = pending_send {
+            if self.messages_loaded {
+                self.send_composer(body, pending_attachments_send);
+            }
+        }
+
+        if let Some((channel_id, messages)) = selection_forward {
+            if self.messages_loaded {
+                self.forward_messages(channel_id, messages);
+            }
+        }
+
+        if let Some(message_ids) = selection_delete {
+            self.delete_own_messages(&message_ids);
+        }
+
+        if !incoming.is_empty() {
+            for incoming_message in incoming {
+                if self.messages_loaded {
+                    let mut inbound = incoming_message.message;
+                    if !self
+                        .channels
+                        .iter()
+                        .any(|channel| channel.id == inbound.channel_id)
+                    {
+                        log_warn!(
+                            "dropping realtime message from {} for unknown channel {}",
+                            inbound.author,
+                            inbound.channel_id
+                        );
+                        continue;
+                    }
+                    let pending = realtime_to_pending_attachments(&incoming_message.attachments);
+                    // `ralph send` stores what it broadcasts in its own database, which
+                    // may be this one; then the row is already here.
+                    let stored = match (incoming_message.client_id.as_deref(), incoming_message.seq)
+                    {
+                        (Some(client_id), Some(seq)) => {
+                            find_message_from_client(&self.db, client_id, inbound.channel_id, seq)
+                                .and_then(|existing| match existing {
+                                    Some(id) => Ok(id),
+                                    None => insert_message_from_client(
+                                        &mut self.db,
+                                        &inbound,
+                                        &pending,
+                                        client_id,
+                                        seq,
+                                    ),
+                                })
+                        }
+                        _ => insert_message_with_attachments(&mut self.db, &inbound, &pending),
+                    };
+                    match stored {
+                        Ok(id) => {
+                            inbound.id = id;
+                            if !pending.is_empty() {
+                                self.message_attachments.insert(
+                                    inbound.id,
+                                    pending
+                                        .into_iter()
+                                        .map(|pending| Attachment {
+                                            message_id: inbound.id,
+                                            file_path: pending.file_path,
+                                            file_name: pending.file_name,
+                                            file_size: pending.file_size,
+                                            kind: pending.kind,
+                                            file_hash: pending.file_hash,
+                                        })
+                                        .collect(),
+                                );
+                            }
+                        }
+                        Err(err) => {
+                            log_error!("db insert error: {err}");
+                        }
+                    }
+                    self.track_member(&inbound);
+                    self.note_user(&inbound.author);
+                    self.unread_title_dirty = true;
+                    if inbound.channel_id != self.selected_channel_id
+                        && !self.app_config.identity.is_self(&inbound.author)
+                        && mentions_user(
+                            &inbound.body,
+                            &self.app_config.identity.username,
+                            &self.app_config.highlight_words,
+                        )
+                    {
+                        self.highlighted_channels.insert(inbound.channel_id);
+                    }
+                    if inbound.channel_id == self.selected_channel_id {
+                        // With newer rows trimmed, this one pages in with them instead.
+                        // A reload may already have picked up a row stored elsewhere.
+                        let loaded = self.messages.iter().any(|message| message.id == inbound.id);
+                        if !loaded && !self.has_trimmed_newer_messages() {
+                            insert_message_sorted(&mut self.messages, inbound);
+                            self.trim_loaded_messages(true);
+                        }
+                        self.mark_selected_channel_read();
+                    }
+                }
+            }
+        }
+
+        self.refresh_window_title();
+        self.maybe_start_deferred_load();
+    }
+}
+
+impl App {
+    /// Prints the startup milestones as `key=value` lines on stdout for the benchmark
+    /// scripts. Peak memory comes from the kernel where it's tracked (Linux);
+    /// elsewhere the resident size at this point stands in for it.
+    fn report_startup_bench(&self) {
+        let db_ready_ms = self.boot_started.elapsed().as_secs_f64() * 1000.0;
+        println!("first_frame_ms={:.2}", self.first_frame_ms);
+        println!("db_ready_ms={db_ready_ms:.2}");
+        match peak_rss_kb() {
+            Some((kb, true)) => println!("peak_rss_kb={kb}"),
+            Some((kb, false)) => println!("rss_kb={kb}"),
+            None => log_warn!("could not read memory usage"),
+        }
+    }
+
+    fn maybe_start_deferred_load(&mut self) {
+        if !self.first_frame_logged {
+            return;
+        }
+        if self.deferred_load_plan.is_none() || self.deferred_load_receiver.is_some() {
+            return;
+        }
+        // Wait for the choice between read-only and exiting.
+        if self.instance_conflict.is_some() {
+            return;
+        }
+        self.start_deferred_load();
+    }
+
+    fn start_deferred_load(&mut self) {
+        let plan = match self.deferred_load_plan.take() {
+            Some(plan) => plan,
+            None => return,
+        };
+        let (deferred_load_sender, deferred_load_receiver) = mpsc::channel();
+        self.deferred_load_receiver = Some(deferred_load_receiver);
+        let event_proxy = self.event_proxy.clone();
+        thread::spawn(move || {
+            let deferred_channel_id = plan.channel_id;
+            let channels_for_load = plan.channels;
+            let demo_messages = |channel_id: i64| -> Vec<Message> {
+                if !plan.demo_mode {
+                    return Vec::new();
+                }
+                seed_messages()
+                    .into_iter()
+                    .filter(|message| message.channel_id == channel_id)
+                    .collect()
+            };
+            let mut db = match Connection::open(&plan.db_path) {
+                Ok(conn) => conn,
+                Err(err) => {
+                    log_error!("db open error (deferred): {err}");
+                    let messages = demo_messages(deferred_channel_id);
+                    let _ = deferred_load_sender.send(DeferredLoadResult {
+                        channel_id: deferred_channel_id,
+                        channels: channels_for_load.clone(),
+                        messages,
+                        attachments: HashMap::new(),
+                        channel_members: HashMap::new(),
+                        saved_messages: HashSet::new(),
+                        pinned_messages: HashSet::new(),
+                        message_reactions: HashMap::new(),
+                        drafts: HashMap::new(),
+                        last_read: HashMap::new(),
+                        settings: HashMap::new(),
+                        channel_state: HashMap::new(),
+                        users: Vec::new(),
+                        first_run: false,
+                        db_ready: false,
+                        newer_schema: None,
+                        system_reduce_motion: plan
+                            .detect_reduce_motion
+                            .then(system_prefers_reduced_motion),
+                    });
+                    let _ = event_proxy.send_event(UserEvent::Wake);
+                    return;
+                }
+            };
+            // A newer build may have added tables or columns this one doesn't know to
+            // keep consistent, so its files are only read, never migrated or seeded.
+            let newer_schema = match schema_version(&db) {
+                Ok(version) if version > SCHEMA_VERSION => {
+                    log_warn!(
+                        "database schema {version} is newer than this build's \
+                        {SCHEMA_VERSION}; opening it read-only"
+                    );
+                    Some(version)
+                }
+                Ok(_) => None,
+                Err(err) => {
+                    log_error!("db schema version error (deferred): {err}");
+                    None
+                }
+            };
+            let writable = newer_schema.is_none() && !plan.read_only;
+            let mut db_ready = true;
+            if writable {
+                if let Err(err) = ensure_schema(&db) {
+                    log_error!("db schema error (deferred): {err}");
+                    db_ready = false;
+                }
+            }
+            if plan.demo_mode && writable {
+                if let Err(err) = seed_channels_if_empty(&mut db) {
+                    log_error!("db seed channels error (deferred): {err}");
+                }
+                if let Err(err) = seed_messages_if_empty(&mut db) {
+                    log_error!("db seed error (deferred): {err}");
+                }
+                if let Err(err) = seed_saved_messages_if_empty(&mut db) {
+                    log_error!("db seed saved error (deferred): {err}");
+                }
+                if let Err(err) = seed_pinned_messages_if_empty(&mut db) {
+                    log_error!("db seed pinned error (deferred): {err}");
+                }
+                if let Err(err) = seed_reactions_if_empty(&mut db) {
+                    log_error!("db seed reactions error (deferred): {err}");
+                }
+            }
+            match count_orphaned_messages(&db) {
+                Ok(0) => {}
+                Ok(_) if plan.clean_orphans && writable => match delete_orphaned_messages(&mut db) {
+                    Ok(removed) => {
+                        log_warn!("removed {removed} messages whose channel no longer exists");
+                    }
+                    Err(err) => log_error!("db orphan cleanup error (deferred): {err}"),
+                },
+                Ok(count) => log_warn!(
+                    "{count} messages belong to channels that no longer exist; \
+                    set RALPH_CLEAN_ORPHANS=1 to remove them"
+                ),
+                Err(err) => log_error!("db integrity check error (deferred): {err}"),
+            }
+            let mut first_run = false;
+            let channels = match load_channels(&db) {
+                Ok(channels) if !channels.is_empty() => channels,
+                Ok(_) => {
+                    first_run = db_ready;
+                    channels_for_load.clone()
+                }
+                Err(err) => {
+                    log_error!("db channels load error (deferred): {err}");
+                    channels_for_load.clone()
+                }
+            };
+            let load_channel_id = channels
+                .iter()
+                .find(|channel| channel.id == deferred_channel_id)
+                .map(|channel| channel.id)
+                .or_else(|| channels.first().map(|channel| channel.id))
+                .unwrap_or(deferred_channel_id);
+            let messages = match load_messages(&db, load_channel_id) {
+                Ok(messages) => messages,
+                Err(err) => {
+                    log_error!("db load error (deferred): {err}");
+                    demo_messages(load_channel_id)
+                }
+            };
+            let message_ids: Vec<i64> = messages.iter().map(|message| message.id).collect();
+            let attachments = match load_attachments_for_message_ids(&db, &message_ids) {
+                Ok(attachments) => attachments,
+                Err(err) => {
+                    log_error!("db attachments load error (deferred): {err}");
+                    HashMap::new()
+                }
+            };
+            let message_reactions = match load_reactions_for_message_ids(&db, &message_ids) {
+                Ok(reactions) => reactions,
+                Err(err) => {
+                    log_error!("db reactions load error (deferred): {err}");
+                    HashMap::new()
+                }
+            };
+            let channel_members = match load_channel_members(&db, &channels) {
+                Ok(members) => members,
+                Err(err) => {
+                    log_error!("db members load error (deferred): {err}");
+                    HashMap::new()
+                }
+            };
+            let saved_messages = match load_saved_message_ids(&db) {
+                Ok(saved) => saved,
+                Err(err) => {
+                    log_error!("db saved load error (deferred): {err}");
+                    HashSet::new()
+                }
+            };
+            let pinned_messages = match load_pinned_message_ids(&db) {
+                Ok(pinned) => pinned,
+                Err(err) => {
+                    log_error!("db pinned load error (deferred): {err}");
+                    HashSet::new()
+                }
+            };
+            let drafts = match load_drafts(&db) {
+                Ok(drafts) => drafts,
+                Err(err) => {
+                    log_error!("db drafts load error (deferred): {err}");
+                    HashMap::new()
+                }
+            };
+            let last_read = match load_last_read(&db) {
+                Ok(last_read) => last_read,
+                Err(err) => {
+                    log_error!("db read state load error (deferred): {err}");
+                    HashMap::new()
+                }
+            };
+            let settings = match load_settings(&db) {
+                Ok(settings) => settings,
+                Err(err) => {
+                    log_error!("db settings load error (deferred): {err}");
+                    HashMap::new()
+                }
+            };
+            let channel_state = match load_channel_state(&db) {
+                Ok(channel_state) => channel_state,
+                Err(err) => {
+                    log_error!("db channel state load error (deferred): {err}");
+                    HashMap::new()
+                }
+            };
+            let users = match load_users(&db) {
+                Ok(users) => users,
+                Err(err) => {
+                    log_error!("db users load error (deferred): {err}");
+                    Vec::new()
+                }
+            };
+            let _ = deferred_load_sender.send(DeferredLoadResult {
+                channel_id: load_channel_id,
+                channels,
+                messages,
+                attachments,
+                channel_members,
+                saved_messages,
+                pinned_messages,
+                message_reactions,
+                drafts,
+                last_read,
+                settings,
+                channel_state,
+                users,
+                first_run,
+                db_ready,
+                newer_schema,
+                system_reduce_motion: plan
+                    .detect_reduce_motion
+                    .then(system_prefers_reduced_motion),
+            });
+            let _ = event_proxy.send_event(UserEvent::Wake);
+        });
+    }
+
+    fn drain_lightbox_results(&mut self) -> bool {
+        let mut changed = false;
+        while let Ok(result) = self.lightbox_receiver.try_recv() {
+            // A result for an image that has since been closed or replaced is dropped.
+            let Some(lightbox) = self
+                .lightbox
+                .as_mut()
+                .filter(|lightbox| lightbox.attachment.file_path == result.path)
+            else {
+                continue;
+            };
+            match result.image {
+                Ok(image) => {
+                    lightbox.texture = Some(self.egui_ctx.load_texture(
+                        format!("lightbox:{}", result.path),
+                        image,
+                        egui::TextureOptions::LINEAR,
+                    ));
+                }
+                Err(error) => lightbox.error = Some(error),
+            }
+            changed = true;
+        }
+        changed
+    }
+
+    /// Attaches a finished screenshot to the composer it was taken from. With an
+    /// attachment store the capture is copied there and its temp file removed.
+    fn drain_screenshot_result(&mut self) -> bool {
+        let Some(result) = self
+            .screenshot_receiver
+            .as_ref()
+            .and_then(|receiver| receiver.try_recv().ok())
+        else {
+            return false;
+        };
+        self.screenshot_receiver = None;
+        match result.capture {
+            Ok(Some(path)) => self.start_ingest(result.channel_id, path, IngestSource::Screenshot),
+            Ok(None) => {}
+            Err(err) => self.attachment_error = Some(err),
+        }
+        true
+    }
+
+    /// Hashes `path`, and copies it into the attachment store when one is set, on a
+    /// worker thread; `drain_ingest_results` adds it to the channel's composer. The
+    /// size limits are checked first, so an oversized file is never read.
+    fn start_ingest(&mut self, channel_id: i64, path: PathBuf, source: IngestSource) {
+        let pending = self.pending_attachments.entry(channel_id).or_default();
+        if let Err(err) = self.app_config.attachment_limits.admit(pending, &path) {
+            self.attachment_error = Some(err);
+            return;
+        }
+        self.attachment_error = None;
+        self.ingests_in_flight += 1;
+        let sender = self.ingest_sender.clone();
+        let event_proxy = self.event_proxy.clone();
+        let store = self.app_config.attachment_store.clone();
+        let kinds = self.app_config.attachment_kinds.clone();
+        thread::spawn(move || {
+            let attachment = ingest_attachment(&path, store.as_deref(), &kinds);
+            let _ = sender.send(IngestResult {
+                channel_id,
+                path,
+                source,
+                attachment,
+            });
+            let _ = event_proxy.send_event(UserEvent::Wake);
+        });
+    }
+
+    fn drain_ingest_results(&mut self) -> bool {
+        let mut changed = false;
+        while let Ok(result) = self.ingest_receiver.try_recv() {
+            self.ingests_in_flight = self.ingests_in_flight.saturating_sub(1);
+            changed = true;
+            let attachment = match result.attachment {
+                Ok(attachment) => attachment,
+                Err(err) => {
+                    self.attachment_error = Some(err);
+                    continue;
+                }
+            };
+            let channel_id = result.channel_id;
+            let pending = self.pending_attachments.entry(channel_id).or_default();
+            if matches!(result.source, IngestSource::PathBox) {
+                let recent = self
+                    .messages
+                    .iter()
+                    .filter(|message| message.channel_id == channel_id)
+                    .filter_map(|message| self.message_attachments.get(&message.id))
+                    .flatten();
+                let path = result.path.to_string_lossy();
+                if let Some(reason) = duplicate_attachment_reason(&path, &attachment, pending, recent)
+                {
+                    self.attachment_duplicate = Some(DuplicateAttachment {
+                        channel_id,
+                        attachment,
+                        reason,
+                    });
+                    self.attachment_error = None;
+                    continue;
+                }
+            }
+            // Other files may have been attached while this one was being read.
+            let size = attachment.file_size;
+            if let Err(err) = self.app_config.attachment_limits.check(pending, true, size) {
+                self.attachment_error = Some(err);
+                continue;
+            }
+            pending.push(attachment);
+            self.attachments_expanded = true;
+            self.attachment_error = None;
+            match result.source {
+                IngestSource::PathBox => {
+                    // Leave the box alone if another path was typed in the meantime.
+                    let typed = self.attachment_path_drafts.entry(channel_id).or_default();
+                    if Path::new(typed.trim()) == result.path {
+                        typed.clear();
+                    }
+                }
+                IngestSource::Paste(text) => {
+                    let draft = self.composer_drafts.entry(channel_id).or_default();
+                    *draft = draft.replacen(&text, "", 1);
+                    let saved = if draft.trim().is_empty() {
+                        delete_draft(&self.db, channel_id)
+                    } else {
+                        save_draft(&self.db, channel_id, draft, &format_timestamp_utc())
+                    };
+                    if let Err(err) = saved {
+                        log_error!("db draft save error: {err}");
+                    }
+                }
+                IngestSource::Screenshot => {
+                    if self.app_config.attachment_store.is_some() {
+                        if let Err(err) = fs::remove_file(&result.path) {
+                            log_warn!(
+                                "screenshot cleanup error ({}): {err}",
+                                result.path.display()
+                            );
+                        }
+                    }
+                }
+            }
+        }
+        changed
+    }
+
+    fn drain_save_copy_result(&mut self) -> bool {
+        let Some(result) = self
+            .save_copy_receiver
+            .as_ref()
+            .and_then(|receiver| receiver.try_recv().ok())
+        else {
+            return false;
+        };
+        self.save_copy_receiver = None;
+        match result {
+            Ok(Some(target)) => {
+                self.attachment_action_error = None;
+                self.attachment_action_notice =
+                    Some(format!("Saved a copy to {}", target.display()));
+            }
+            Ok(None) => {}
+            Err(err) => self.attachment_action_error = Some(err),
+        }
+        true
+    }
+
+    /// Files finished "Open with" lookups and reports how the chooser went.
+    fn drain_open_with_results(&mut self) -> bool {
+        let mut changed = false;
+        if let Some((path, apps)) = self
+            .open_with_apps_receiver
+            .as_ref()
+            .and_then(|receiver| receiver.try_recv().ok())
+        {
+            self.open_with_apps_receiver = None;
+            self.open_with_apps.insert(path, apps);
+            changed = true;
+        }
+        if let Some(result) = self
+            .chooser_receiver
+            .as_ref()
+            .and_then(|receiver| receiver.try_recv().ok())
+        {
+            self.chooser_receiver = None;
+            if let Err(err) = result {
+                self.attachment_action_error = Some(err);
+                self.attachment_action_notice = None;
+            }
+            changed = true;
+        }
+        changed
+    }
+
+    fn drain_flush_result(&mut self) -> bool {
+        let Some(result) = self
+            .flush_receiver
+            .as_ref()
+            .and_then(|receiver| receiver.try_recv().ok())
+        else {
+            return false;
+        };
+        self.flush_receiver = None;
+        self.flush_result = Some(result);
+        true
+    }
+
+    fn drain_thumbnail_results(&mut self) -> bool {
+        let mut changed = false;
+        while let Ok(update) = self.thumbnail_receiver.try_recv() {
+            let result = match update {
+                ThumbnailUpdate::Dimensions(path, dimensions) => {
+                    self.remember_image_dimensions(path, dimensions);
+                    changed = true;
+                    continue;
+                }
+                ThumbnailUpdate::Done(result) => result,
+            };
+            self.thumbnail_in_flight.remove(&result.path);
+            if let Some(error) = result.error {
+                let failures = self.thumbnail_failures.entry(result.path.clone()).or_insert(0);
+                *failures += 1;
+                if *failures >= THUMBNAIL_DOWNGRADE_FAILURES {
+                    self.downgraded_images.insert(result.path.clone());
+                }
+                self.attachment_thumbnail_errors.insert(
+                    result.path.clone(),
+                    ThumbnailError {
+                        message: error,
+                        mtime: result.mtime,
+                        checked_at: Instant::now(),
+                    },
+                );
+                self.touch_thumbnail_error(&result.path);
+                self.enforce_thumbnail_cache_limits();
+                changed = true;
+                continue;
+            }
+            if let Some(image) = result.image {
+                self.thumbnail_failures.remove(&result.path);
+                let texture = self.egui_ctx.load_texture(
+                    format!("attachment:{}", result.path),
+                    image,
+                    egui::TextureOptions::LINEAR,
+                );
+                self.attachment_thumbnails
+                    .insert(result.path.clone(), texture);
+                self.touch_thumbnail_cache(&result.path);
+                self.enforce_thumbnail_cache_limits();
+                changed = true;
+            }
+        }
+        changed
+    }
+
+    /// Opens `attachment`, first checking it against its recorded hash when
+    /// verification is on. The hash runs on a worker thread; a file that no longer
+    /// matches is only opened once the user confirms.
+    fn request_attachment_open(&mut self, attachment: Attachment, open_with: OpenWith) {
+        self.open_confirm = None;
+        if !self.app_config.verify_attachments {
+            self.open_attachment_now(&attachment, open_with);
+            return;
+        }
+        self.attachment_action_error = None;
+        self.attachment_action_notice =
+            Some(format!("Checking {} before opening…", attachment.file_name));
+        // A newer request replaces one still being checked.
+        let (sender, receiver) = mpsc::channel();
+        self.verify_receiver = Some(receiver);
+        let event_proxy = self.event_proxy.clone();
+        thread::spawn(move || {
+            let hash = hash_file(Path::new(&attachment.file_path));
+            let _ = sender.send(VerifyResult {
+                attachment,
+                open_with,
+                hash: hash.map_err(|err| err.to_string()),
+            });
+            let _ = event_proxy.send_event(UserEvent::Wake);
+        });
+    }
+
+    fn open_attachment_now(&mut self, attachment: &Attachment, open_with: OpenWith) {
+        let path = &attachment.file_path;
+        let result = match open_with {
+            OpenWith::Default => open_attachment(path),
+            OpenWith::App(app) => open_attachment_with_args(path, &[], Some(&app)),
+            OpenWith::Chooser => {
+                self.start_open_with_chooser(path);
+                Ok(())
+            }
+        };
+        self.attachment_action_error = result.err();
+        self.attachment_action_notice = None;
+    }
+
+    /// Opens a checked attachment, or holds it for confirmation when it no longer
+    /// matches its recorded hash. Attachments stored before hashes existed get theirs
+    /// recorded here on first open.
+    fn drain_verify_result(&mut self) -> bool {
+        let Some(result) = self
+            .verify_receiver
+            .as_ref()
+            .and_then(|receiver| receiver.try_recv().ok())
+        else {
+            return false;
+        };
+        self.verify_receiver = None;
+        let VerifyResult {
+            attachment,
+            open_with,
+            hash,
+        } = result;
+        let actual = match hash {
+            Ok(actual) => actual,
+            Err(err) => {
+                // Opening reports a missing or unreadable file better than the hash can.
+                log_warn!("attachment hash error ({}): {err}", attachment.file_path);
+                self.open_attachment_now(&attachment, open_with);
+                return true;
+            }
+        };
+        match &attachment.file_hash {
+            Some(expected) if *expected != actual => {
+                self.attachment_action_notice = None;
+                self.open_confirm = Some(OpenConfirm {
+                    warning: format!(
+                        "{} has been modified or truncated since it was attached.",
+                        attachment.file_name
+                    ),
+                    attachment,
+                    open_with,
+                });
+                return true;
+            }
+            Some(_) => {}
+            None => {
+                if !self.db_is_fallback {
+                    if let Err(err) =
+                        backfill_attachment_hash(&self.db, &attachment.file_path, &actual)
+                    {
+                        log_error!("db attachment hash backfill error: {err}");
+                    }
+                }
+                for stored in self.message_attachments.values_mut().flatten() {
+                    if stored.file_path == attachment.file_path && stored.file_hash.is_none() {
+                        stored.file_hash = Some(actual.clone());
+                    }
+                }
+            }
+        }
+        self.open_attachment_now(&attachment, open_with);
+        true
+    }
+
+    fn drain_text_preview_results(&mut self) -> bool {
+        let mut changed = false;
+        while let Ok(result) = self.text_preview_receiver.try_recv() {
+            self.text_preview_in_flight.remove(&result.path);
+            self.text_previews.insert(result.path.clone(), result.text);
+            Self::touch_cache_order(&mut self.text_preview_order, &result.path);
+            while self.text_preview_order.len() > TEXT_PREVIEW_CACHE_LIMIT {
+                if let Some(evicted) = self.text_preview_order.pop_front() {
+                    self.text_previews.remove(&evicted);
+                    self.expanded_text_previews.remove(&evicted);
+                }
+            }
+            changed = true;
+        }
+        changed
+    }
+
+    fn queue_text_preview_load(&mut self, path: &str) {
+        if !self.text_preview_in_flight.insert(path.to_string()) {
+            return;
+        }
+        let sender = self.text_preview_sender.clone();
+        let event_proxy = self.event_proxy.clone();
+        let path = path.to_string();
+        thread::spawn(move || {
+            let text = load_text_preview(&path);
+            let _ = sender.send(TextPreviewResult { path, text });
+            let _ = event_proxy.send_event(UserEvent::Wake);
+        });
+    }
+
+    fn set_invisible(&mut self, invisible: bool) {
+        self.app_config.invisible = invisible;
+        self.realtime.set_invisible(invisible);
+        if !self.db_is_fallback {
+            let value = if invisible { "1" } else { "0" };
+            if let Err(err) = save_setting(&self.db, "invisible", value) {
+                log_error!("db invisible save error: {err}");
+            }
+        }
+    }
+
+    fn set_auto_scroll(&mut self, mode: AutoScroll) {
+        self.app_config.auto_scroll = mode;
+        if !self.db_is_fallback {
+            if let Err(err) = save_setting(&self.db, "auto_scroll", mode.key()) {
+                log_error!("db auto-scroll save error: {err}");
+            }
+        }
+    }
+
+    /// Larger previews need more decoded pixels, so thumbnails are dropped and
+    /// reloaded at the new size.
+    fn set_thumbnail_size(&mut self, size: ThumbnailSize) {
+        if size == self.app_config.thumbnail_size {
+            return;
+        }
+        self.app_config.thumbnail_size = size;
+        self.attachment_thumbnails.clear();
+        self.thumbnail_cache_order.clear();
+    }
+
+    /// Applies and remembers a new text scale. Thumbnails are decoded for the old
+    /// pixel density, so they are dropped and reloaded at the new one.
+    fn set_ui_scale(&mut self, scale: f32) {
+        let scale = clamp_ui_scale(scale);
+        if scale == self.app_config.ui_scale {
+            return;
+        }
+        self.app_config.ui_scale = scale;
+        self.egui_ctx.set_zoom_factor(scale);
+        self.attachment_thumbnails.clear();
+        self.thumbnail_cache_order.clear();
+        if !self.db_is_fallback {
+            if let Err(err) = save_setting(&self.db, "ui_scale", &scale.to_string()) {
+                log_error!("db ui scale save error: {err}");
+            }
+        }
+    }
+
+    fn note_recent_emoji(&mut self, emoji: &str) {
+        push_recent_emoji(&mut self.recent_emoji, emoji);
+        if !self.db_is_fallback {
+            if let Err(err) = save_setting(&self.db, "recent_emoji", &self.recent_emoji.join(" ")) {
+                log_error!("db recent emoji save error: {err}");
+            }
+        }
+    }
+
+    /// Turns egui's transitions (collapsing sections, panel slides, scroll jumps) off
+    /// or back on. Anything that animates frame by frame should check `reduce_motion`.
+    fn set_reduce_motion(&mut self, reduce_motion: bool) {
+        self.reduce_motion = reduce_motion;
+        let animation_time = if reduce_motion {
+            0.0
+        } else {
+            egui::Style::default().animation_time
+        };
+        self.egui_ctx.style_mut(|style| style.animation_time = animation_time);
+    }
+
+    fn retry_thumbnail(&mut self, path: &str) {
+        self.attachment_thumbnail_errors.remove(path);
+        if let Some(pos) = self
+            .thumbnail_error_order
+            .iter()
+            .position(|entry| entry == path)
+        {
+            self.thumbnail_error_order.remove(pos);
+        }
+        self.queue_thumbnail_load(path, true);
+    }
+
+    /// Retries a failed thumbnail when its source file changed since the failed
+    /// attempt. Checks are throttled so visible errors don't stat the file every frame.
+    fn recheck_thumbnail_error(&mut self, path: &str) {
+        let Some(error) = self.attachment_thumbnail_errors.get_mut(path) else {
+            return;
+        };
+        if error.checked_at.elapsed() < THUMBNAIL_RECHECK_INTERVAL {
+            return;
         }
-        let raw_input = self.egui_state.take_egui_input(self.window.as_ref());
-        let has_input_events = !raw_input.events.is_empty();
-        let mut pending_send: Option<String> = None;
-        let mut pending_attachments_send = Vec::new();
-        let mut channel_switch: Option<i64> = None;
-        let mut search_request: Option<SearchRequest> = None;
-        let mut search_clear = false;
-        let mut realtime_connect = false;
-        let mut realtime_disconnect = false;
-        let mut saved_toggle: Option<i64> = None;
-        let mut pinned_toggle: Option<i64> = None;
-        let mut reaction_toggle: Option<(i64, String, bool)> = None;
-        let egui_ctx = self.egui_ctx.clone();
-        let full_output = egui_ctx.run(raw_input, |ctx| {
-            egui::SidePanel::left("channel_list")
-                .resizable(false)
-                .default_width(220.0)
-                .show(ctx, |ui| {
-                    ui.heading("Ralph");
-                    ui.add_space(10.0);
-                    ui.label("Channels");
-                    for channel in self
-                        .channels
-                        .iter()
-                        .filter(|channel| channel.kind == ChannelKind::Channel)
-                    {
-                        ui.horizontal(|row| {
-                            let label = format!("# {}", channel.name);
-                            if row
-                                .selectable_label(self.selected_channel_id == channel.id, label)
-                                .clicked()
-                            {
-                                channel_switch = Some(channel.id);
-                            }
-                            let (online, total) = self.channel_presence_counts(channel.id);
-                            let summary = if total == 0 {
-                                "no members".to_string()
-                            } else {
-                                format!("{online}/{total} online")
-                            };
-                            row.label(
-                                egui::RichText::new(summary)
-                                    .small()
-                                    .color(egui::Color32::from_rgb(120, 130, 150)),
-                            );
-                        });
+        error.checked_at = Instant::now();
+        let mtime = file_mtime(path);
+        if mtime.is_some() && mtime != error.mtime {
+            self.retry_thumbnail(path);
+        }
+    }
+
+    fn touch_thumbnail_cache(&mut self, path: &str) {
+        Self::touch_cache_order(&mut self.thumbnail_cache_order, path);
+    }
+
+    fn touch_thumbnail_error(&mut self, path: &str) {
+        Self::touch_cache_order(&mut self.thumbnail_error_order, path);
+    }
+
+    fn touch_cache_order(order: &mut VecDeque<String>, path: &str) {
+        if let Some(pos) = order.iter().position(|entry| entry == path) {
+            order.remove(pos);
+        }
+        order.push_back(path.to_string());
+    }
+
+    fn remember_image_dimensions(&mut self, path: String, dimensions: Option<(u32, u32)>) {
+        if self.image_dimensions.insert(path.clone(), dimensions).is_none() {
+            self.image_dimensions_order.push_back(path);
+        }
+        while self.image_dimensions_order.len() > IMAGE_DIMENSIONS_LIMIT {
+            if let Some(evicted) = self.image_dimensions_order.pop_front() {
+                self.image_dimensions.remove(&evicted);
+            }
+        }
+    }
+
+    fn enforce_thumbnail_cache_limits(&mut self) {
+        while self.thumbnail_cache_order.len() > THUMBNAIL_CACHE_LIMIT {
+            if let Some(evicted) = self.thumbnail_cache_order.pop_front() {
+                self.attachment_thumbnails.remove(&evicted);
+            }
+        }
+        while self.thumbnail_error_order.len() > THUMBNAIL_ERROR_LIMIT {
+            if let Some(evicted) = self.thumbnail_error_order.pop_front() {
+                self.attachment_thumbnail_errors.remove(&evicted);
+            }
+        }
+    }
+
+    fn apply_deferred_loads(&mut self) -> bool {
+        let result = match self.deferred_load_receiver.as_ref() {
+            Some(receiver) => receiver.try_recv().ok(),
+            None => None,
+        };
+        if let Some(result) = result {
+            let mut changed = false;
+            if result.db_ready && self.db_is_fallback {
+                let path = &self.active_workspace().db_path;
+                let opened = if result.newer_schema.is_some() || self.read_only_instance {
+                    Connection::open_with_flags(path, OpenFlags::SQLITE_OPEN_READ_ONLY)
+                } else {
+                    Connection::open(path)
+                };
+                match opened {
+                    Ok(conn) => {
+                        self.db = conn;
+                        self.db_is_fallback = false;
+                        self.db_newer_schema = result.newer_schema;
+                        changed = true;
                     }
-                    ui.add_space(8.0);
-                    ui.label("Direct Messages");
-                    for channel in self
-                        .channels
-                        .iter()
-                        .filter(|channel| channel.kind == ChannelKind::DirectMessage)
-                    {
-                        ui.horizontal(|row| {
-                            let label = format!("@{}", channel.name);
-                            if row
-                                .selectable_label(self.selected_channel_id == channel.id, label)
-                                .clicked()
-                            {
-                                channel_switch = Some(channel.id);
-                            }
-                            let status = self.presence_for_user(&channel.name);
-                            row.label(
-                                egui::RichText::new("o")
-                                    .color(status.color())
-                                    .small(),
-                            );
-                            row.label(
-                                egui::RichText::new(status.label())
-                                    .small()
-                                    .color(status.color()),
-                            );
-                        });
+                    Err(err) => {
+                        log_error!("db open error (deferred swap): {err}");
                     }
-                });
-            egui::CentralPanel::default().show(ctx, |ui| {
-                let channel_title = self
+                }
+            }
+            let selected_before = self.selected_channel_id;
+            self.channel_state = result.channel_state;
+            for user in result.users {
+                self.roster.users.insert(user.username.to_ascii_lowercase(), user);
+            }
+            if !result.channels.is_empty() {
+                self.channels = result.channels;
+                self.composer_meta = build_composer_meta(&self.channels, &self.channel_state);
+                changed = true;
+                if !self
                     .channels
                     .iter()
-                    .find(|channel| channel.id == self.selected_channel_id)
-                    .map(|channel| match channel.kind {
-                        ChannelKind::Channel => format!("#{}", channel.name),
-                        ChannelKind::DirectMessage => format!("DM: {}", channel.name),
-                    })
-                    .unwrap_or_else(|| "Messages".to_string());
-                ui.heading(format!("Ralph — {}", channel_title));
-                ui.add_space(4.0);
-                ui.label(format!(
-                    "Session uptime: {:.1}s",
-                    self.started_at.elapsed().as_secs_f32()
-                ));
-                ui.horizontal(|row| {
-                    row.label(format!("Realtime: {}", self.realtime.status.label()));
-                    row.label(
-                        egui::RichText::new(&self.realtime.target_url)
-                            .small()
-                            .color(egui::Color32::from_rgb(120, 130, 150)),
-                    );
-                    match self.realtime.status {
-                        RealtimeStatus::Disconnected => {
-                            if row.button("Connect").clicked() {
-                                realtime_connect = true;
-                            }
-                        }
-                        RealtimeStatus::Connecting => {
-                            row.add_enabled(false, egui::Button::new("Connecting..."));
-                        }
-                        RealtimeStatus::Connected => {
-                            if row.button("Disconnect").clicked() {
-                                realtime_disconnect = true;
-                            }
-                        }
-                    }
-                    if let Some(message) = &self.realtime.last_message {
-                        row.label(
-                            egui::RichText::new(message)
-                                .small()
-                                .color(egui::Color32::from_rgb(140, 150, 170)),
-                        );
-                    }
-                    if let Some(error) = &self.realtime.last_error {
-                        row.label(
-                            egui::RichText::new(error)
-                                .small()
-                                .color(egui::Color32::from_rgb(220, 120, 120)),
-                        );
+                    .any(|channel| channel.id == self.selected_channel_id)
+                {
+                    if let Some(channel) = self.channels.first() {
+                        self.selected_channel_id = channel.id;
+                        self.composer_focus_requested = true;
+                        changed = true;
                     }
-                });
-                if let Some(details) = self.channel_presence_details() {
-                    ui.label(
-                        egui::RichText::new(details)
-                            .small()
-                            .color(egui::Color32::from_rgb(120, 130, 150)),
-                    );
                 }
-                ui.separator();
-                ui.add_enabled_ui(self.messages_loaded, |ui| {
-                    ui.horizontal(|row| {
-                        row.label("Search");
-                        let search_box = row.add(
-                            egui::TextEdit::singleline(&mut self.search_query)
-                                .hint_text("Search messages")
-                                .desired_width(240.0),
-                        );
-                        let search_enter = search_box.has_focus()
-                            && row.input(|input| input.key_pressed(egui::Key::Enter));
-                        if row.button("Go").clicked() || search_enter {
-                            let trimmed = self.search_query.trim();
-                            if !trimmed.is_empty() {
-                                search_request = Some(SearchRequest {
-                                    query: trimmed.to_string(),
-                                    channel_only: self.search_channel_only,
-                                });
-                            }
-                        }
-                        row.checkbox(&mut self.search_channel_only, "This channel");
-                        if row.button("Clear").clicked() {
-                            search_clear = true;
-                        }
-                    });
-                    if self.search_query.trim().is_empty() {
-                        ui.label(
-                            egui::RichText::new("Search by author or text.")
-                                .small()
-                                .color(egui::Color32::from_rgb(120, 130, 150)),
-                        );
-                    } else if self.search_last_query == self.search_query.trim()
-                        && self.search_last_channel_only == self.search_channel_only
+            }
+            self.last_read = result.last_read;
+            self.unread_title_dirty = true;
+            let mut settings = result.settings;
+            if self.active_workspace().server_url.is_some() {
+                settings.remove("server_url");
+            }
+            self.recent_emoji = settings
+                .get("recent_emoji")
+                .map(|value| value.split_whitespace().map(str::to_string).collect())
+                .unwrap_or_default();
+            if let Some(reduce_motion) = result.system_reduce_motion {
+                if reduce_motion {
+                    log_info!("system prefers reduced motion; disabling animations");
+                }
+                self.set_reduce_motion(reduce_motion);
+            }
+            let saved_scale = settings.get("ui_scale").and_then(|value| value.parse().ok());
+            if let (Some(scale), None) = (saved_scale, env_string("RALPH_UI_SCALE")) {
+                self.app_config.ui_scale = clamp_ui_scale(scale);
+                changed = true;
+            }
+            let saved_auto_scroll =
+                settings.get("auto_scroll").and_then(|value| AutoScroll::parse(value));
+            if let (Some(mode), None) = (saved_auto_scroll, env_string("RALPH_AUTO_SCROLL")) {
+                self.app_config.auto_scroll = mode;
+            }
+            if let Some(size) =
+                settings.get("thumbnail_size").and_then(|value| ThumbnailSize::parse(value))
+            {
+                self.set_thumbnail_size(size);
+            }
+            let saved_invisible = settings
+                .get("invisible")
+                .filter(|_| !self.app_config.invisible_from_env)
+                .map(|value| value == "1");
+            if let Some(invisible) = saved_invisible {
+                if invisible != self.app_config.invisible {
+                    self.app_config.invisible = invisible;
+                    self.realtime.set_invisible(invisible);
+                    changed = true;
+                }
+            }
+            if self.app_config.apply_saved_settings(&settings) {
+                self.reconfigure_realtime();
+                changed = true;
+            }
+            if result.first_run && !self.app_config.demo_mode {
+                self.onboarding = Some(OnboardingForm {
+                    username: self.app_config.identity.username.clone(),
+                    server_url: self.app_config.server_url.clone(),
+                    channel_name: "general".to_string(),
+                    error: None,
+                });
+                changed = true;
+            }
+            if result.channel_id == self.selected_channel_id {
+                self.messages = result.messages;
+                self.message_attachments = result.attachments;
+                self.message_reactions = result.message_reactions;
+                // The startup page is short; widen it to the first unread message
+                // once the real database is open.
+                self.prepare_unread_jump(!self.db_is_fallback);
+                self.messages_loaded = true;
+                changed = true;
+            } else if self.selected_channel_id != selected_before {
+                self.messages = match load_messages(&self.db, self.selected_channel_id) {
+                    Ok(messages) => messages,
+                    Err(err) => {
+                        log_error!("db load error: {err}");
+                        Vec::new()
+                    }
+                };
+                self.prepare_unread_jump(true);
+                self.messages_loaded = true;
+                self.message_attachments = match load_attachments_for_message_ids(
+                    &self.db,
+                    &self.messages.iter().map(|message| message.id).collect::<Vec<_>>(),
+                ) {
+                    Ok(attachments) => attachments,
+                    Err(err) => {
+                        log_error!("db attachments load error: {err}");
+                        HashMap::new()
+                    }
+                };
+                self.message_reactions = match load_reactions_for_message_ids(
+                    &self.db,
+                    &self.messages.iter().map(|message| message.id).collect::<Vec<_>>(),
+                ) {
+                    Ok(reactions) => reactions,
+                    Err(err) => {
+                        log_error!("db reactions load error: {err}");
+                        HashMap::new()
+                    }
+                };
+                changed = true;
+            }
+            for (channel_id, members) in result.channel_members {
+                self.channel_members
+                    .entry(channel_id)
+                    .or_default()
+                    .extend(members);
+                self.presence_counts_dirty = true;
+                changed = true;
+            }
+            self.saved_messages = result.saved_messages;
+            self.pinned_messages = result.pinned_messages;
+            // Text typed before the load finished wins over the saved draft. What was
+            // typed into a channel the load dropped follows the selection to its
+            // replacement rather than being lost.
+            let writable = !self.db_is_fallback
+                && self.db_newer_schema.is_none()
+                && !self.read_only_instance;
+            let mut drafts = result.drafts;
+            for (channel_id, typed) in std::mem::take(&mut self.composer_drafts) {
+                if typed.trim().is_empty() {
+                    continue;
+                }
+                let channel_id = if channel_id == selected_before {
+                    self.selected_channel_id
+                } else {
+                    channel_id
+                };
+                if writable {
+                    if let Err(err) =
+                        save_draft(&self.db, channel_id, &typed, &format_timestamp_utc())
                     {
-                        ui.label(
-                            egui::RichText::new(format!("Results: {}", self.search_results.len()))
-                                .small()
-                                .color(egui::Color32::from_rgb(120, 130, 150)),
-                        );
-                    } else {
-                        ui.label(
-                            egui::RichText::new("Press Enter to search.")
-                                .small()
-                                .color(egui::Color32::from_rgb(120, 130, 150)),
-                        );
+                        log_error!("db draft save error: {err}");
                     }
-                });
-                if !self.messages_loaded {
-                    ui.label(
-                        egui::RichText::new("Search available once messages finish loading.")
-                            .small()
-                            .color(egui::Color32::from_rgb(120, 130, 150)),
-                    );
                 }
-                ui.separator();
-                let show_search_results =
-                    !self.search_query.trim().is_empty()
-                        && self.search_last_query == self.search_query.trim()
-                        && self.search_last_channel_only == self.search_channel_only;
-                let show_channel =
-                    show_search_results && !self.search_channel_only;
-                ui.add_enabled_ui(self.messages_loaded, |ui| {
-                    ui.horizontal(|row| {
-                        row.checkbox(&mut self.show_saved_only, "Saved only");
-                        let saved_in_view = self
-                            .messages
-                            .iter()
-                            .filter(|message| self.saved_messages.contains(&message.id))
-                            .count();
-                        row.label(
-                            egui::RichText::new(format!("Saved in view: {saved_in_view}"))
-                                .small()
-                                .color(egui::Color32::from_rgb(120, 130, 150)),
-                        );
-                        row.add_space(10.0);
-                        row.checkbox(&mut self.show_pinned_only, "Pinned only");
-                        let pinned_in_view = self
-                            .messages
-                            .iter()
-                            .filter(|message| self.pinned_messages.contains(&message.id))
-                            .count();
-                        row.label(
-                            egui::RichText::new(format!("Pinned in view: {pinned_in_view}"))
-                                .small()
-                                .color(egui::Color32::from_rgb(120, 130, 150)),
-                        );
-                        if show_search_results {
-                            row.label(
-                                egui::RichText::new("Saved filter ignored in search.")
-                                    .small()
-                                    .color(egui::Color32::from_rgb(120, 130, 150)),
-                            );
-                            row.label(
-                                egui::RichText::new("Pinned filter ignored in search.")
-                                    .small()
-                                    .color(egui::Color32::from_rgb(120, 130, 150)),
-                            );
-                        }
-                    });
-                });
-                let mut messages: Vec<&Message> = if show_search_results {
-                    self.search_results.iter().collect()
+                drafts.insert(channel_id, typed);
+            }
+            if !drafts.is_empty() {
+                changed = true;
+            }
+            self.composer_drafts = drafts;
+            if !result.db_ready || self.db_is_fallback {
+                if let Err(err) = ensure_schema(&self.db) {
+                    log_error!("db schema error: {err}");
+                }
+            }
+            self.deferred_load_receiver = None;
+            return changed;
+        }
+        false
+    }
+
+    fn handle_shortcuts(&mut self, ctx: &egui::Context) {
+        if ctx.zoom_factor() != self.app_config.ui_scale {
+            ctx.set_zoom_factor(self.app_config.ui_scale);
+        }
+        let typing = ctx.wants_keyboard_input();
+        for entry in SHORTCUTS {
+            let ShortcutKeys::Global {
+                shortcut,
+                action,
+                while_typing,
+            } = &entry.keys
+            else {
+                continue;
+            };
+            if typing && !while_typing {
+                continue;
+            }
+            // Leave Escape to text fields and popups unless there is an overlay to close.
+            if *action == ShortcutAction::CloseOverlay
+                && !self.show_shortcut_help
+                && self.command_palette.is_none()
+                && self.lightbox.is_none()
+                && self.find.is_none()
+            {
+                continue;
+            }
+            if ctx.input_mut(|input| input.consume_shortcut(shortcut)) {
+                self.run_shortcut_action(*action);
+            }
+        }
+    }
+
+    /// Carries out an action from `SHORTCUTS`, whether its keys were pressed or it was
+    /// picked in the command palette.
+    fn run_shortcut_action(&mut self, action: ShortcutAction) {
+        match action {
+            ShortcutAction::ToggleShortcutHelp => {
+                self.show_shortcut_help = !self.show_shortcut_help;
+            }
+            ShortcutAction::CloseOverlay => {
+                // Close the topmost overlay first.
+                if self.command_palette.is_some() {
+                    self.command_palette = None;
+                } else if self.show_shortcut_help {
+                    self.show_shortcut_help = false;
+                } else if self.lightbox.is_some() {
+                    self.lightbox = None;
                 } else {
-                    self.messages.iter().collect()
+                    self.find = None;
+                }
+            }
+            ShortcutAction::ToggleFrameStats => self.show_frame_stats = !self.show_frame_stats,
+            ShortcutAction::ZoomIn => {
+                self.set_ui_scale(self.app_config.ui_scale + UI_SCALE_STEP);
+            }
+            ShortcutAction::ZoomOut => {
+                self.set_ui_scale(self.app_config.ui_scale - UI_SCALE_STEP);
+            }
+            ShortcutAction::ZoomReset => self.set_ui_scale(1.0),
+            ShortcutAction::OpenFind => match self.find.as_mut() {
+                Some(find) => find.focus_requested = true,
+                None => {
+                    self.find = Some(FindState {
+                        query: String::new(),
+                        current: 0,
+                        focus_requested: true,
+                    });
+                }
+            },
+            ShortcutAction::OpenCommandPalette => {
+                self.command_palette = match self.command_palette {
+                    Some(_) => None,
+                    None => Some(CommandPalette::default()),
                 };
-                let saved_only_active = !show_search_results && self.show_saved_only;
-                let pinned_only_active = !show_search_results && self.show_pinned_only;
-                if saved_only_active {
-                    messages.retain(|message| self.saved_messages.contains(&message.id));
+            }
+            ShortcutAction::Connect => self.realtime.connect(),
+            ShortcutAction::Disconnect => self.realtime.disconnect(),
+            ShortcutAction::MarkAllRead => self.mark_all_channels_read(),
+            ShortcutAction::ToggleGallery => self.toggle_gallery(),
+            ShortcutAction::TogglePresenceHistory => {
+                if self.presence_history.take().is_none() {
+                    self.presence_history = Some(PresenceHistoryView::default());
+                    self.reload_presence_history();
                 }
-                if pinned_only_active {
-                    messages.retain(|message| self.pinned_messages.contains(&message.id));
+            }
+            ShortcutAction::ToggleSavedView => {
+                if self.saved_view.take().is_none() {
+                    self.saved_view = Some(Vec::new());
+                    self.reload_saved_view();
+                }
+            }
+            ShortcutAction::FlushDatabase => self.start_flush(),
+        }
+    }
+
+    fn run_search(&mut self, query: String, channel_only: bool) {
+        let channel_filter = channel_only.then_some(self.selected_channel_id);
+        let results = search_messages(&self.db, &query, channel_filter, None).and_then(|results| {
+            count_search_matches(&self.db, &query, channel_filter).map(|total| (results, total))
+        });
+        match results {
+            Ok((results, total)) => {
+                self.search_last_query = query;
+                self.search_last_channel_only = channel_only;
+                self.search_results = results;
+                self.search_total = total;
+                let ids: Vec<i64> = self.search_results.iter().map(|message| message.id).collect();
+                self.message_attachments = match load_attachments_for_message_ids(&self.db, &ids) {
+                    Ok(attachments) => attachments,
+                    Err(err) => {
+                        log_error!("db attachments load error: {err}");
+                        HashMap::new()
+                    }
+                };
+                self.message_reactions = match load_reactions_for_message_ids(&self.db, &ids) {
+                    Ok(reactions) => reactions,
+                    Err(err) => {
+                        log_error!("db reactions load error: {err}");
+                        HashMap::new()
+                    }
+                };
+            }
+            Err(err) => {
+                log_error!("db search error: {err}");
+                self.search_last_query.clear();
+                self.search_last_channel_only = channel_only;
+                self.search_results.clear();
+                self.search_total = 0;
+            }
+        }
+    }
+
+    fn load_more_search_results(&mut self) {
+        let Some(last) = self.search_results.last() else {
+            return;
+        };
+        let channel_filter = self
+            .search_last_channel_only
+            .then_some(self.selected_channel_id);
+        let page = match search_messages(
+            &self.db,
+            &self.search_last_query,
+            channel_filter,
+            Some(last.id),
+        ) {
+            Ok(page) => page,
+            Err(err) => {
+                log_error!("db search error: {err}");
+                return;
+            }
+        };
+        let ids: Vec<i64> = page.iter().map(|message| message.id).collect();
+        match load_attachments_for_message_ids(&self.db, &ids) {
+            Ok(attachments) => self.message_attachments.extend(attachments),
+            Err(err) => log_error!("db attachments load error: {err}"),
+        }
+        match load_reactions_for_message_ids(&self.db, &ids) {
+            Ok(reactions) => self.message_reactions.extend(reactions),
+            Err(err) => log_error!("db reactions load error: {err}"),
+        }
+        if page.is_empty() {
+            // Matches were deleted since the count ran; stop offering more.
+            self.search_total = self.search_results.len() as i64;
+        }
+        self.search_results.extend(page);
+    }
+
+    /// Refreshes the match total after a delete, which can take matches beyond the
+    /// loaded page with it. The fallback database never saw the delete, so there
+    /// only the `dropped` loaded results come off the total.
+    fn recount_search_matches(&mut self, dropped: usize) {
+        if self.db_is_fallback || self.search_last_query.is_empty() {
+            self.search_total -= dropped as i64;
+            return;
+        }
+        let channel_filter = self
+            .search_last_channel_only
+            .then_some(self.selected_channel_id);
+        match count_search_matches(&self.db, &self.search_last_query, channel_filter) {
+            Ok(total) => self.search_total = total,
+            Err(err) => {
+                log_error!("db search count error: {err}");
+                self.search_total -= dropped as i64;
+            }
+        }
+    }
+
+    fn search_has_more(&self) -> bool {
+        (self.search_results.len() as i64) < self.search_total
+    }
+
+    fn loaded_id_range(&self) -> Option<(i64, i64)> {
+        let ids = self.messages.iter().map(|message| message.id);
+        Some((ids.clone().min()?, ids.max()?))
+    }
+
+    /// Whether SQLite may hold messages older than the loaded window.
+    fn has_older_messages(&self) -> bool {
+        self.loaded_id_range().is_some_and(|(oldest, _)| {
+            self.older_messages_exhausted != Some((self.selected_channel_id, oldest))
+        })
+    }
+
+    /// Whether newer messages were dropped from the loaded window and need paging back in.
+    fn has_trimmed_newer_messages(&self) -> bool {
+        self.loaded_id_range().is_some_and(|(_, newest)| {
+            self.newer_messages_trimmed == Some((self.selected_channel_id, newest))
+        })
+    }
+
+    /// Loads a page of the selected channel's history just above (`older`) or below
+    /// the loaded window, keeping the row at that edge where it is on screen.
+    fn load_history_page(&mut self, older: bool) {
+        let Some((oldest, newest)) = self.loaded_id_range() else {
+            return;
+        };
+        let channel_id = self.selected_channel_id;
+        let page = if older {
+            load_older_messages(&self.db, channel_id, oldest, HISTORY_PAGE_SIZE)
+        } else {
+            load_newer_messages(&self.db, channel_id, newest, HISTORY_PAGE_SIZE)
+        };
+        let page = match page {
+            Ok(page) => page,
+            Err(err) => {
+                log_error!("db history load error: {err}");
+                return;
+            }
+        };
+        let complete = (page.len() as i64) < HISTORY_PAGE_SIZE;
+        let ids: Vec<i64> = page.iter().map(|message| message.id).collect();
+        match load_attachments_for_message_ids(&self.db, &ids) {
+            Ok(attachments) => self.message_attachments.extend(attachments),
+            Err(err) => log_error!("db attachments load error: {err}"),
+        }
+        match load_reactions_for_message_ids(&self.db, &ids) {
+            Ok(reactions) => self.message_reactions.extend(reactions),
+            Err(err) => log_error!("db reactions load error: {err}"),
+        }
+        self.history_anchor = if older {
+            self.messages.first().map(|message| (message.id, egui::Align::TOP))
+        } else {
+            self.messages.last().map(|message| (message.id, egui::Align::BOTTOM))
+        };
+        self.messages.extend(page);
+        self.messages.sort_by(compare_message_order);
+        self.trim_loaded_messages(!older);
+        if let Some((oldest, newest)) = self.loaded_id_range() {
+            if older {
+                if complete {
+                    self.older_messages_exhausted = Some((channel_id, oldest));
+                }
+            } else if complete {
+                self.newer_messages_trimmed = None;
+            } else {
+                self.newer_messages_trimmed = Some((channel_id, newest));
+            }
+        }
+        // Paging down is reading on past an unread jump that couldn't load it all.
+        if !older {
+            self.mark_selected_channel_read();
+        }
+        self.egui_ctx.request_repaint();
+    }
+
+    /// Keeps the loaded window within `max_loaded_messages` by dropping rows from the
+    /// oldest end, or from the newest end when paging up through history. Dropped
+    /// rows stay in SQLite and page back in when scrolled to.
+    fn trim_loaded_messages(&mut self, drop_oldest: bool) {
+        let excess = self
+            .messages
+            .len()
+            .saturating_sub(self.app_config.max_loaded_messages);
+        if excess == 0 {
+            return;
+        }
+        let mut ids: Vec<i64> = self.messages.iter().map(|message| message.id).collect();
+        ids.sort_unstable();
+        let dropped: HashSet<i64> = if drop_oldest {
+            ids[..excess].iter().copied().collect()
+        } else {
+            ids[ids.len() - excess..].iter().copied().collect()
+        };
+        self.messages.retain(|message| !dropped.contains(&message.id));
+        // Search results can show the same rows, so keep what they still use.
+        let in_search: HashSet<i64> =
+            self.search_results.iter().map(|message| message.id).collect();
+        for id in dropped.iter().filter(|id| !in_search.contains(id)) {
+            self.message_attachments.remove(id);
+            self.message_reactions.remove(id);
+        }
+        self.row_heights.retain(|(id, _), _| !dropped.contains(id));
+        if !drop_oldest {
+            if let Some((_, newest)) = self.loaded_id_range() {
+                self.newer_messages_trimmed = Some((self.selected_channel_id, newest));
+            }
+        }
+    }
+
+    /// Replaces the realtime client so the next connect uses the current server URL and
+    /// identity. The old worker exits once its command channel is dropped.
+    fn reconfigure_realtime(&mut self) {
+        self.realtime = RealtimeClient::new(
+            self.app_config.server_url.clone(),
+            self.app_config.identity.clone(),
+            self.app_config
+                .realtime_log
+                .then(|| realtime_log_path(&self.active_workspace().db_path)),
+            Some(self.event_proxy.clone()),
+            self.app_config.invisible,
+        );
+        self.presence_state.insert(
+            self.app_config.identity.username.clone(),
+            PresenceState {
+                status: PresenceStatus::Online,
+                last_seen: Instant::now(),
+            },
+        );
+        self.presence_counts_dirty = true;
+    }
+
+    fn complete_onboarding(&mut self) {
+        let Some(form) = self.onboarding.as_mut() else {
+            return;
+        };
+        let username = form.username.trim().to_string();
+        let server_url = form.server_url.trim().to_string();
+        let channel_name = normalize_channel_name(&form.channel_name);
+        if username.is_empty() || username.contains(char::is_whitespace) {
+            form.error = Some("Pick a username without spaces.".to_string());
+            return;
+        }
+        if !Url::parse(&server_url).is_ok_and(|url| matches!(url.scheme(), "ws" | "wss")) {
+            form.error = Some("Server URL must start with ws:// or wss://.".to_string());
+            return;
+        }
+        if channel_name.is_empty() {
+            form.error = Some("Give your first channel a name.".to_string());
+            return;
+        }
+        let channel_id = match save_onboarding(&mut self.db, &username, &server_url, &channel_name)
+        {
+            Ok(channel_id) => channel_id,
+            Err(err) => {
+                log_error!("db onboarding save error: {err}");
+                form.error = Some(format!("Could not save setup: {err}"));
+                return;
+            }
+        };
+        self.onboarding = None;
+        if env_string("RALPH_DISPLAY_NAME").is_none() {
+            self.app_config.identity.display_name = username.clone();
+        }
+        self.app_config.identity.username = username;
+        self.app_config.server_url = server_url;
+        self.reconfigure_realtime();
+        self.channels = vec![Channel {
+            id: channel_id,
+            name: channel_name,
+            kind: ChannelKind::Channel,
+            topic: String::new(),
+            archived: false,
+        }];
+        self.composer_meta = build_composer_meta(&self.channels, &self.channel_state);
+        self.selected_channel_id = channel_id;
+        self.messages.clear();
+        self.message_attachments.clear();
+        self.message_reactions.clear();
+        self.messages_loaded = true;
+        self.composer_focus_requested = true;
+    }
+
+    fn active_workspace(&self) -> &Workspace {
+        &self.app_config.workspaces[self.active_workspace]
+    }
+
+    /// Loads must have landed, and nothing may be waiting in the outbox: queued messages
+    /// belong to this workspace's server and would be lost with its connection.
+    fn can_switch_workspace(&self) -> bool {
+        self.deferred_load_plan.is_none()
+            && self.deferred_load_receiver.is_none()
+            && self.realtime.outbox.is_empty()
+    }
+
+    /// Drops everything loaded from the current database, reconnects with the
+    /// workspace's identity and server, and queues a fresh deferred load against its
+    /// file. Until that lands the app runs on an in-memory fallback, as at startup.
+    fn switch_workspace(&mut self, index: usize) {
+        if index == self.active_workspace
+            || index >= self.app_config.workspaces.len()
+            || !self.can_switch_workspace()
+        {
+            return;
+        }
+        let db = match Connection::open_in_memory() {
+            Ok(db) => db,
+            Err(err) => {
+                log_error!("db open error (workspace switch): {err}");
+                return;
+            }
+        };
+        let previous = std::mem::replace(&mut self.active_workspace, index);
+        let workspace = self.active_workspace().clone();
+        log_info!("switching to workspace {} ({})", workspace.name, workspace.db_path.display());
+        let env_config = AppConfig::from_env();
+        self.app_config.identity = env_config.identity;
+        self.app_config.server_url = workspace.server_url.unwrap_or(env_config.server_url);
+        self.presence_state.clear();
+        // Sends still waiting on an ack can't get one over the new connection; keep them
+        // as failed, with a Retry, for when this workspace is opened again.
+        let mut failed = std::mem::take(&mut self.failed_sends);
+        failed.extend(self.realtime.take_failed_sends());
+        failed.extend(self.realtime.take_unacked());
+        if !failed.is_empty() {
+            self.stashed_failed_sends.insert(previous, failed);
+        }
+        self.reconfigure_realtime();
+        self.db = db;
+        self.db_is_fallback = true;
+        self.db_newer_schema = None;
+        // Release the old workspace before taking the new one's lock.
+        self.instance_lock = None;
+        match InstanceLock::acquire(&workspace.db_path) {
+            Ok(lock) => {
+                self.instance_lock = Some(lock);
+                self.instance_conflict = None;
+            }
+            Err(pid) => self.instance_conflict = Some(pid),
+        }
+        self.read_only_instance = false;
+        self.retention_sweep = None;
+        self.retention_swept_at = None;
+
+        self.channels.clear();
+        self.composer_meta.clear();
+        self.selected_channel_id = 1;
+        self.messages.clear();
+        self.messages_loaded = false;
+        self.message_attachments.clear();
+        self.message_reactions.clear();
+        self.channel_members.clear();
+        self.presence_counts_dirty = true;
+        self.saved_messages.clear();
+        self.pinned_messages.clear();
+        self.failed_sends = self.stashed_failed_sends.remove(&index).unwrap_or_default();
+        self.held_sends.clear();
+        self.roster = Roster::default();
+        self.composer_drafts.clear();
+        self.typing_state.clear();
+        self.last_read.clear();
+        self.unread_title_dirty = true;
+        self.unread_marker = None;
+        self.scroll_to_message = None;
+        self.search_query.clear();
+        self.search_last_query.clear();
+        self.search_results.clear();
+        self.search_total = 0;
+        self.onboarding = None;
+        self.first_channel_name.clear();
+        self.first_channel_error = None;
+        self.dm_draft = None;
+        self.dm_error = None;
+        self.topic_draft = None;
+        self.placeholder_draft = None;
+        self.announcement_draft = None;
+        self.channel_state.clear();
+        self.find = None;
+        self.expanded_messages.clear();
+        self.attachment_path_drafts.clear();
+        self.pending_attachments.clear();
+        self.attachment_duplicate = None;
+        self.verify_receiver = None;
+        self.open_confirm = None;
+        // Files still being read belong to the old workspace's composers.
+        (self.ingest_sender, self.ingest_receiver) = mpsc::channel();
+        self.ingests_in_flight = 0;
+        self.pasted_path = None;
+        self.recent_emoji.clear();
+        self.gallery = None;
+        self.presence_history = None;
+        self.saved_view = None;
+        self.lightbox = None;
+        self.screenshot_receiver = None;
+        self.clear_selection();
+        self.send_confirm = None;
+        self.highlighted_channels.clear();
+        self.row_heights.clear();
+        self.attachment_error = None;
+        self.send_error = None;
+        self.older_messages_exhausted = None;
+        self.newer_messages_trimmed = None;
+        self.history_anchor = None;
+        self.attachment_action_error = None;
+        self.attachment_action_notice = None;
+        self.saved_action_error = None;
+        self.pinned_action_error = None;
+        self.reaction_action_error = None;
+        self.maintenance_status = None;
+        self.attachment_thumbnails.clear();
+        self.attachment_thumbnail_errors.clear();
+        self.image_dimensions.clear();
+        self.image_dimensions_order.clear();
+        self.thumbnail_failures.clear();
+        self.downgraded_images.clear();
+        self.thumbnail_cache_order.clear();
+        self.thumbnail_error_order.clear();
+        self.text_previews.clear();
+        self.text_preview_order.clear();
+        self.expanded_text_previews.clear();
+
+        self.deferred_load_plan = Some(DeferredLoadPlan {
+            channel_id: self.selected_channel_id,
+            channels: Vec::new(),
+            demo_mode: self.app_config.demo_mode,
+            clean_orphans: self.app_config.clean_orphans,
+            db_path: workspace.db_path,
+            detect_reduce_motion: false,
+            read_only: false,
+        });
+        self.composer_focus_requested = true;
+    }
+
+    fn create_first_channel(&mut self) {
+        let channel_name = normalize_channel_name(&self.first_channel_name);
+        if channel_name.is_empty() {
+            self.first_channel_error = Some("Give the channel a name.".to_string());
+            return;
+        }
+        let channel_id = match insert_channel(&self.db, &channel_name, ChannelKind::Channel) {
+            Ok(channel_id) => channel_id,
+            Err(err) => {
+                log_error!("db channel insert error: {err}");
+                self.first_channel_error = Some(format!("Could not create channel: {err}"));
+                return;
+            }
+        };
+        self.first_channel_name.clear();
+        self.first_channel_error = None;
+        self.channels.push(Channel {
+            id: channel_id,
+            name: channel_name,
+            kind: ChannelKind::Channel,
+            topic: String::new(),
+            archived: false,
+        });
+        self.composer_meta = build_composer_meta(&self.channels, &self.channel_state);
+        self.selected_channel_id = channel_id;
+        self.messages.clear();
+        self.message_attachments.clear();
+        self.message_reactions.clear();
+        self.composer_focus_requested = true;
+    }
+
+    /// Finds or creates the DM with the typed username and returns its id for the
+    /// caller to switch to. Problems are left in `dm_error` next to the field.
+    fn open_direct_message(&mut self, input: &str) -> Option<i64> {
+        let username = match validate_dm_username(input, &self.app_config.identity) {
+            Ok(username) => username,
+            Err(message) => {
+                self.dm_error = Some(message);
+                return None;
+            }
+        };
+        let channel = match ensure_dm_channel(&self.db, &username) {
+            Ok(channel) => channel,
+            Err(err) => {
+                log_error!("db direct message error: {err}");
+                self.dm_error = Some(format!("Could not open direct message: {err}"));
+                return None;
+            }
+        };
+        self.dm_draft = None;
+        self.dm_error = None;
+        let channel_id = channel.id;
+        match self.channels.iter_mut().find(|known| known.id == channel_id) {
+            Some(known) => known.archived = false,
+            None => {
+                self.channels.push(channel);
+                self.composer_meta = build_composer_meta(&self.channels, &self.channel_state);
+            }
+        }
+        Some(channel_id)
+    }
+
+    /// Posts the open channel's composer contents. The draft and its attachments are
+    /// only cleared once the message is stored; if that fails they stay in place with
+    /// an error, so nothing typed is lost.
+    fn send_composer(&mut self, body: String, attachments: Vec<PendingAttachment>) {
+        let channel_id = self.selected_channel_id;
+        if let Err(err) = self.post_message(channel_id, body, attachments) {
+            self.send_error = Some((channel_id, format!("Message not sent: {err}")));
+            return;
+        }
+        self.send_error = None;
+        if let Some(draft) = self.composer_drafts.get_mut(&channel_id) {
+            draft.clear();
+        }
+        if let Some(pending) = self.pending_attachments.get_mut(&channel_id) {
+            pending.clear();
+        }
+        self.pasted_path = None;
+        self.typing_state.remove(&channel_id);
+        self.composer_focus_requested = true;
+        if let Err(err) = delete_draft(&self.db, channel_id) {
+            log_error!("db draft delete error: {err}");
+        }
+    }
+
+    /// Stores a message of yours in `channel_id` together with its attachments, shows
+    /// it if that channel is open, and sends it to the server.
+    fn post_message(
+        &mut self,
+        channel_id: i64,
+        body: String,
+        attachments: Vec<PendingAttachment>,
+    ) -> Result<(), String> {
+        // Checked here as well as in the composer, so no path can post into it.
+        if self.is_read_only(channel_id) {
+            return Err(format!("posting is turned off in {}", self.channel_label(channel_id)));
+        }
+        let mut message = Message {
+            id: 0,
+            author: self.app_config.identity.username.clone(),
+            body,
+            sent_at: format_timestamp_utc(),
+            channel_id,
+            edited_at: None,
+        };
+        match insert_message_with_attachments(&mut self.db, &message, &attachments) {
+            Ok(id) => {
+                message.id = id;
+                let outgoing_attachments = pending_to_realtime_attachments(&attachments);
+                if !attachments.is_empty() {
+                    self.message_attachments
+                        .entry(message.id)
+                        .or_default()
+                        .extend(attachments.into_iter().map(|pending| Attachment {
+                            message_id: message.id,
+                            file_path: pending.file_path,
+                            file_name: pending.file_name,
+                            file_size: pending.file_size,
+                            kind: pending.kind,
+                            file_hash: pending.file_hash,
+                        }));
                 }
-                if show_search_results && messages.is_empty() {
-                    ui.label(
-                        egui::RichText::new("No matches found.")
-                            .small()
-                            .color(egui::Color32::from_rgb(160, 170, 190)),
-                    );
-                } else if !show_search_results && !self.messages_loaded && self.messages.is_empty() {
-                    ui.label(
-                        egui::RichText::new("Loading messages...")
-                            .small()
-                            .color(egui::Color32::from_rgb(160, 170, 190)),
-                    );
-                } else if (saved_only_active || pinned_only_active) && messages.is_empty() {
-                    let empty_label = if saved_only_active && pinned_only_active {
-                        "No saved and pinned messages in this channel."
-                    } else if saved_only_active {
-                        "No saved messages in this channel."
-                    } else {
-                        "No pinned messages in this channel."
-                    };
-                    ui.label(
-                        egui::RichText::new(empty_label)
-                            .small()
-                            .color(egui::Color32::from_rgb(160, 170, 190)),
-                    );
+                if !self.is_member(channel_id) {
+                    self.set_membership(channel_id, true);
                 }
-                let mut thumbnail_requests: Vec<String> = Vec::new();
-                let mut touched_thumbnails: Vec<String> = Vec::new();
-                let mut touched_errors: Vec<String> = Vec::new();
-                for message in messages {
-                    ui.horizontal(|row| {
-                        row.label(
-                            egui::RichText::new(&message.author)
-                                .strong()
-                                .color(egui::Color32::from_rgb(200, 210, 230)),
-                        );
-                        row.label(
-                            egui::RichText::new(&message.sent_at)
-                                .color(egui::Color32::from_rgb(140, 150, 170)),
-                        );
-                        let pinned = self.pinned_messages.contains(&message.id);
-                        let pin_label = if pinned { "📌" } else { "📍" };
-                        if row
-                            .button(pin_label)
-                            .on_hover_text(if pinned { "Unpin message" } else { "Pin message" })
-                            .clicked()
-                        {
-                            pinned_toggle = Some(message.id);
-                        }
-                        let saved = self.saved_messages.contains(&message.id);
-                        let save_label = if saved { "★" } else { "☆" };
-                        if row
-                            .button(save_label)
-                            .on_hover_text(if saved {
-                                "Remove from saved"
-                            } else {
-                                "Save message"
-                            })
-                            .clicked()
-                        {
-                            saved_toggle = Some(message.id);
-                        }
-                        if show_channel {
-                            row.label(
-                                egui::RichText::new(self.channel_label(message.channel_id))
-                                    .small()
-                                    .color(egui::Color32::from_rgb(140, 150, 170)),
-                            );
-                        }
-                        row.horizontal_wrapped(|body_ui| {
-                            let original_spacing = body_ui.spacing().item_spacing;
-                            body_ui.spacing_mut().item_spacing.x = 0.0;
-                            render_message_body(body_ui, &message.body);
-                            body_ui.spacing_mut().item_spacing = original_spacing;
-                        });
-                    });
-                    ui.horizontal(|row| {
-                        row.label(
-                            egui::RichText::new("Reactions")
-                                .small()
-                                .color(egui::Color32::from_rgb(120, 130, 150)),
-                        );
-                        let mut counts: HashMap<String, usize> = HashMap::new();
-                        let mut user_reactions: HashSet<String> = HashSet::new();
-                        if let Some(reactions) = self.message_reactions.get(&message.id) {
-                            for reaction in reactions {
-                                *counts.entry(reaction.emoji.clone()).or_insert(0) += 1;
-                                if reaction.author.eq_ignore_ascii_case("you") {
-                                    user_reactions.insert(reaction.emoji.clone());
-                                }
-                            }
-                        }
-                        for emoji in REACTION_EMOJIS.iter().copied() {
-                            let count = counts.get(emoji).copied().unwrap_or(0);
-                            let label = if count > 0 {
-                                format!("{emoji} {count}")
-                            } else {
-                                emoji.to_string()
-                            };
-                            let reacted = user_reactions.contains(emoji);
-                            let text = if reacted {
-                                egui::RichText::new(label)
-                                    .color(egui::Color32::from_rgb(230, 210, 140))
-                            } else {
-                                egui::RichText::new(label)
-                                    .color(egui::Color32::from_rgb(170, 180, 200))
-                            };
-                            if row
-                                .add(egui::Button::new(text))
-                                .on_hover_text(if reacted {
-                                    "Remove reaction"
-                                } else {
-                                    "Add reaction"
-                                })
-                                .clicked()
-                            {
-                                reaction_toggle =
-                                    Some((message.id, emoji.to_string(), reacted));
-                            }
-                        }
-                    });
-                    if let Some(attachments) = self.message_attachments.get(&message.id) {
-                        for attachment in attachments {
-                            if attachment.kind == "image" {
-                                let path = attachment.file_path.as_str();
-                                let thumbnail = if self.attachment_thumbnails.contains_key(path) {
-                                    touched_thumbnails.push(path.to_string());
-                                    self.attachment_thumbnails.get(path)
-                                } else if self.attachment_thumbnail_errors.contains_key(path) {
-                                    touched_errors.push(path.to_string());
-                                    None
-                                } else if self.thumbnail_in_flight.contains(path) {
-                                    None
-                                } else {
-                                    thumbnail_requests.push(path.to_string());
-                                    None
-                                };
-                                if let Some(texture) = thumbnail {
-                                    let sized =
-                                        egui::load::SizedTexture::from_handle(texture);
-                                    ui.add(
-                                        egui::Image::from_texture(sized)
-                                            .max_size(egui::Vec2::new(220.0, 160.0)),
-                                    );
-                                } else if self.thumbnail_in_flight.contains(path)
-                                    || thumbnail_requests
-                                        .iter()
-                                        .any(|queued| queued == path)
-                                {
-                                    ui.label(
-                                        egui::RichText::new("Loading image preview...")
-                                            .small()
-                                            .color(egui::Color32::from_rgb(130, 140, 160)),
-                                    );
-                                } else if let Some(err) =
-                                    self.attachment_thumbnail_errors.get(path)
-                                {
-                                    ui.label(
-                                        egui::RichText::new(format!(
-                                            "Image preview unavailable: {err}"
-                                        ))
-                                        .small()
-                                        .color(egui::Color32::from_rgb(170, 140, 140)),
-                                    );
-                                }
-                            }
-                            ui.horizontal(|row| {
-                                row.label(
-                                    egui::RichText::new("[attachment]")
-                                        .small()
-                                        .color(egui::Color32::from_rgb(120, 130, 150)),
-                                );
-                                row.label(
-                                    egui::RichText::new(&attachment.file_name)
-                                        .small()
-                                        .color(egui::Color32::from_rgb(190, 200, 215)),
-                                )
-                                .on_hover_text(&attachment.file_path);
-                                row.label(
-                                    egui::RichText::new(format!(
-                                        "{} • {}",
-                                        attachment.kind,
-                                        format_bytes(attachment.file_size)
-                                    ))
-                                    .small()
-                                    .color(egui::Color32::from_rgb(120, 130, 150)),
-                                );
-                                if row.button("Open").clicked() {
-                                    match open_attachment(&attachment.file_path) {
-                                        Ok(()) => self.attachment_action_error = None,
-                                        Err(err) => self.attachment_action_error = Some(err),
-                                    }
-                                }
-                                if row.button("Reveal").clicked() {
-                                    match reveal_attachment(&attachment.file_path) {
-                                        Ok(()) => self.attachment_action_error = None,
-                                        Err(err) => self.attachment_action_error = Some(err),
-                                    }
-                                }
-                            });
-                        }
+                let outcome = self.realtime.send_message(&message, outgoing_attachments);
+                self.note_send_outcome(message.id, outcome);
+                if channel_id == self.selected_channel_id {
+                    // Your own send brings the view down, so page the newest rows back.
+                    if self.has_trimmed_newer_messages() {
+                        self.reload_latest_messages();
                     }
-                    ui.add_space(2.0);
+                    insert_message_sorted(&mut self.messages, message);
+                    self.trim_loaded_messages(true);
+                    self.mark_selected_channel_read();
                 }
-                if !thumbnail_requests.is_empty() {
-                    for path in thumbnail_requests {
-                        self.queue_thumbnail_load(&path);
-                    }
+                Ok(())
+            }
+            Err(err) => {
+                log_error!("db insert error: {err}");
+                Err(err.to_string())
+            }
+        }
+    }
+
+    /// Replaces the loaded window with the selected channel's newest messages.
+    fn reload_latest_messages(&mut self) {
+        self.messages = match load_messages(&self.db, self.selected_channel_id) {
+            Ok(messages) => messages,
+            Err(err) => {
+                log_error!("db load error: {err}");
+                Vec::new()
+            }
+        };
+        let message_ids: Vec<i64> = self.messages.iter().map(|message| message.id).collect();
+        match load_attachments_for_message_ids(&self.db, &message_ids) {
+            Ok(attachments) => self.message_attachments = attachments,
+            Err(err) => log_error!("db attachments load error: {err}"),
+        }
+        match load_reactions_for_message_ids(&self.db, &message_ids) {
+            Ok(reactions) => self.message_reactions = reactions,
+            Err(err) => log_error!("db reactions load error: {err}"),
+        }
+        self.newer_messages_trimmed = None;
+    }
+
+    /// Sends a message that was given up on again, under a fresh `seq`, with its
+    /// attachments.
+    fn resend_message(&mut self, message: &Message) {
+        self.failed_sends.remove(&message.id);
+        let attachments = self
+            .message_attachments
+            .get(&message.id)
+            .map(|attachments| {
+                attachments
+                    .iter()
+                    .map(|attachment| RealtimeAttachment {
+                        file_path: attachment.file_path.clone(),
+                        file_name: attachment.file_name.clone(),
+                        file_size: attachment.file_size,
+                        kind: attachment.kind.clone(),
+                        file_hash: attachment.file_hash.clone(),
+                    })
+                    .collect()
+            })
+            .unwrap_or_default();
+        let outcome = self.realtime.send_message(message, attachments);
+        self.note_send_outcome(message.id, outcome);
+    }
+
+    /// Remembers a send that didn't go straight out, so its row can say so.
+    fn note_send_outcome(&mut self, message_id: i64, outcome: SendOutcome) {
+        if outcome == SendOutcome::Dispatched {
+            self.held_sends.remove(&message_id);
+        } else {
+            self.held_sends.insert(message_id, outcome);
+        }
+    }
+
+    /// Posts a copy of each message, in the order given, to `channel_id`, carrying
+    /// its attachments along, then clears the selection.
+    fn forward_messages(&mut self, channel_id: i64, messages: Vec<Message>) {
+        let message_ids: Vec<i64> = messages.iter().map(|message| message.id).collect();
+        let mut attachments = match load_attachments_for_message_ids(&self.db, &message_ids) {
+            Ok(attachments) => attachments,
+            Err(err) => {
+                log_error!("db attachments load error: {err}");
+                HashMap::new()
+            }
+        };
+        for message in messages {
+            let channel = self
+                .channels
+                .iter()
+                .find(|channel| channel.id == message.channel_id);
+            let body = format_forwarded_body(&message, channel);
+            let pending = attachments
+                .remove(&message.id)
+                .unwrap_or_default()
+                .into_iter()
+                .map(|attachment| PendingAttachment {
+                    file_path: attachment.file_path,
+                    file_name: attachment.file_name,
+                    file_size: attachment.file_size,
+                    kind: attachment.kind,
+                    file_hash: attachment.file_hash,
+                })
+                .collect();
+            if let Err(err) = self.post_message(channel_id, body, pending) {
+                log_warn!("forward stopped: {err}");
+                break;
+            }
+        }
+        self.clear_selection();
+    }
+
+    /// Deletes selected messages of yours from this device. Attachment files stay in
+    /// the store, since forwarded copies can point at the same file.
+    fn delete_own_messages(&mut self, message_ids: &[i64]) {
+        let deleted = self.db_is_fallback || {
+            match delete_messages_by_id(&mut self.db, message_ids) {
+                Ok(()) => true,
+                Err(err) => {
+                    log_error!("db message delete error: {err}");
+                    self.maintenance_status = Some(format!("Could not delete messages: {err}"));
+                    false
                 }
-                for path in touched_thumbnails {
-                    self.touch_thumbnail_cache(&path);
+            }
+        };
+        if !deleted {
+            return;
+        }
+        let removed: HashSet<i64> = message_ids.iter().copied().collect();
+        self.forget_messages(&removed);
+        self.clear_selection();
+    }
+
+    /// Drops deleted messages from everything loaded in memory.
+    fn forget_messages(&mut self, removed: &HashSet<i64>) {
+        self.messages.retain(|message| !removed.contains(&message.id));
+        let results_before = self.search_results.len();
+        self.search_results
+            .retain(|message| !removed.contains(&message.id));
+        self.recount_search_matches(results_before - self.search_results.len());
+        self.selected_messages.retain(|message_id| !removed.contains(message_id));
+        for message_id in removed {
+            self.saved_messages.remove(message_id);
+            self.pinned_messages.remove(message_id);
+            self.message_attachments.remove(message_id);
+            self.message_reactions.remove(message_id);
+        }
+    }
+
+    fn clear_selection(&mut self) {
+        self.selected_messages.clear();
+        self.selection_anchor = None;
+        self.selection_focus = None;
+        self.selection_delete_confirm = false;
+    }
+
+    fn clear_channel_history(&mut self, channel_id: i64, delete_files: bool) {
+        self.history_clear_confirm = None;
+        let cleared = if self.db_is_fallback {
+            let message_ids = self
+                .messages
+                .iter()
+                .filter(|message| message.channel_id == channel_id)
+                .map(|message| message.id)
+                .collect();
+            Ok((message_ids, Vec::new()))
+        } else {
+            clear_channel_messages(&mut self.db, channel_id)
+        };
+        let (message_ids, attachment_paths) = match cleared {
+            Ok(cleared) => cleared,
+            Err(err) => {
+                log_error!("db clear history error: {err}");
+                self.maintenance_status = Some(format!("Could not clear history: {err}"));
+                return;
+            }
+        };
+        let removed: HashSet<i64> = message_ids.into_iter().collect();
+        self.saved_messages.retain(|id| !removed.contains(id));
+        self.pinned_messages.retain(|id| !removed.contains(id));
+        self.last_read.remove(&channel_id);
+        self.unread_title_dirty = true;
+        let results_before = self.search_results.len();
+        self.search_results
+            .retain(|message| message.channel_id != channel_id);
+        self.recount_search_matches(results_before - self.search_results.len());
+        if self.gallery.as_ref().is_some_and(|gallery| gallery.channel_id == channel_id) {
+            self.gallery = None;
+        }
+        if channel_id == self.selected_channel_id {
+            self.messages.clear();
+            self.message_attachments.clear();
+            self.message_reactions.clear();
+            self.unread_marker = None;
+            self.scroll_to_message = None;
+        }
+        let count = removed.len();
+        let mut status = format!(
+            "Cleared {count} message{} from {}.",
+            if count == 1 { "" } else { "s" },
+            self.channel_label(channel_id)
+        );
+        if let (true, Some(store)) = (delete_files, self.app_config.attachment_store.as_deref()) {
+            let pending = self.pending_attachment_paths();
+            match remove_store_files(&self.db, store, &attachment_paths, &pending) {
+                Ok((files, bytes)) => status.push_str(&format!(
+                    " Removed {files} attachment file{} ({} freed).",
+                    if files == 1 { "" } else { "s" },
+                    format_bytes(bytes as i64)
+                )),
+                Err(err) => status.push_str(&format!(" File cleanup failed: {err}")),
+            }
+        }
+        log_info!("{status}");
+        self.maintenance_status = Some(status);
+    }
+
+    /// Stores a presence transition when presence history is turned on.
+    fn record_presence_change(&mut self, username: &str, status: PresenceStatus) {
+        if !self.app_config.presence_history
+            || self.db_is_fallback
+            || status == PresenceStatus::Unknown
+        {
+            return;
+        }
+        if let Err(err) = record_presence_change(
+            &self.db,
+            username,
+            status.label(),
+            &format_timestamp_utc(),
+            PRESENCE_HISTORY_KEEP,
+        ) {
+            log_error!("db presence history error: {err}");
+        }
+    }
+
+    /// Saves or unsaves a message, keeping an open saved messages view current.
+    fn toggle_saved(&mut self, message_id: i64) {
+        if self.saved_messages.contains(&message_id) {
+            match remove_saved_message(&self.db, message_id) {
+                Ok(()) => {
+                    self.saved_messages.remove(&message_id);
+                    self.saved_action_error = None;
                 }
-                for path in touched_errors {
-                    self.touch_thumbnail_error(&path);
+                Err(err) => {
+                    self.saved_action_error =
+                        Some(format!("Could not remove saved message: {err}"));
                 }
-                if let Some(error) = &self.attachment_action_error {
-                    ui.label(
-                        egui::RichText::new(error)
-                            .small()
-                            .color(egui::Color32::from_rgb(220, 120, 120)),
-                    );
+            }
+        } else {
+            let saved_at = format_timestamp_utc();
+            match save_message(&self.db, message_id, &saved_at) {
+                Ok(()) => {
+                    self.saved_messages.insert(message_id);
+                    self.saved_action_error = None;
+                }
+                Err(err) => {
+                    self.saved_action_error = Some(format!("Could not save message: {err}"));
+                }
+            }
+        }
+        self.reload_saved_view();
+    }
+
+    fn reload_saved_view(&mut self) {
+        let Some(entries) = self.saved_view.as_mut() else {
+            return;
+        };
+        match load_saved_messages(&self.db) {
+            Ok(loaded) => *entries = loaded,
+            Err(err) => {
+                log_error!("db saved messages load error: {err}");
+                entries.clear();
+            }
+        }
+    }
+
+    fn reload_presence_history(&mut self) {
+        let Some(view) = self.presence_history.as_mut() else {
+            return;
+        };
+        match load_presence_history(
+            &self.db,
+            view.name_filter.trim().trim_start_matches('@'),
+            view.status_filter.map(PresenceStatus::label),
+            PRESENCE_HISTORY_VIEW_LIMIT,
+        ) {
+            Ok(entries) => view.entries = entries,
+            Err(err) => log_error!("db presence history load error: {err}"),
+        }
+    }
+
+    fn toggle_gallery(&mut self) {
+        if self
+            .gallery
+            .take()
+            .is_some_and(|gallery| gallery.channel_id == self.selected_channel_id)
+        {
+            return;
+        }
+        self.gallery = Some(Gallery {
+            channel_id: self.selected_channel_id,
+            items: Vec::new(),
+            has_more: true,
+        });
+        self.load_more_gallery();
+    }
+
+    fn load_more_gallery(&mut self) {
+        let Some(gallery) = self.gallery.as_mut() else {
+            return;
+        };
+        let before_id = gallery.items.last().map(|(id, _)| *id);
+        match load_gallery_page(&self.db, gallery.channel_id, before_id, GALLERY_PAGE_SIZE) {
+            Ok(page) => {
+                gallery.has_more = page.len() as i64 == GALLERY_PAGE_SIZE;
+                gallery.items.extend(page);
+            }
+            Err(err) => {
+                log_error!("db gallery load error: {err}");
+                gallery.has_more = false;
+            }
+        }
+    }
+
+    fn start_screenshot(&mut self) {
+        if self.screenshot_receiver.is_some() {
+            return;
+        }
+        let (sender, receiver) = mpsc::channel();
+        self.screenshot_receiver = Some(receiver);
+        let channel_id = self.selected_channel_id;
+        let event_proxy = self.event_proxy.clone();
+        let target = std::env::temp_dir().join(format!(
+            "Screenshot {}.png",
+            Local::now().format("%Y-%m-%d at %H.%M.%S")
+        ));
+        thread::spawn(move || {
+            let capture = capture_screen_region(&target).map(|saved| saved.then_some(target));
+            let _ = sender.send(ScreenshotResult {
+                channel_id,
+                capture,
+            });
+            let _ = event_proxy.send_event(UserEvent::Wake);
+        });
+    }
+
+    /// Asks where to save a copy of `attachment` and copies it there off the UI thread,
+    /// since the platform dialogs block until they close.
+    fn start_save_copy(&mut self, attachment: &Attachment) {
+        if self.save_copy_receiver.is_some() {
+            return;
+        }
+        self.attachment_action_notice = None;
+        let source = PathBuf::from(&attachment.file_path);
+        if !source.is_file() {
+            self.attachment_action_error = Some(format!(
+                "{} is missing from {}; it may have been moved or deleted.",
+                attachment.file_name,
+                source.parent().unwrap_or(&source).display()
+            ));
+            return;
+        }
+        self.attachment_action_error = None;
+        let (sender, receiver) = mpsc::channel();
+        self.save_copy_receiver = Some(receiver);
+        let event_proxy = self.event_proxy.clone();
+        let file_name = attachment.file_name.clone();
+        thread::spawn(move || {
+            let saved = choose_save_path(&file_name).and_then(|target| {
+                let Some(target) = target else {
+                    return Ok(None);
+                };
+                fs::copy(&source, &target)
+                    .map(|_| Some(target))
+                    .map_err(|err| format!("Could not save a copy: {err}"))
+            });
+            let _ = sender.send(saved);
+            let _ = event_proxy.send_event(UserEvent::Wake);
+        });
+    }
+
+    /// Runs `enforce_retention` batch by batch on its own connection until a short
+    /// batch shows nothing is left. The worker stops early if the sweep is dropped,
+    /// as on a workspace switch.
+    fn start_retention_sweep(&mut self) {
+        let (sender, receiver) = mpsc::channel();
+        self.retention_sweep = Some(RetentionSweep {
+            removed: 0,
+            attachment_paths: Vec::new(),
+            receiver,
+        });
+        let event_proxy = self.event_proxy.clone();
+        let db_path = self.active_workspace().db_path.clone();
+        thread::spawn(move || {
+            let conn = Connection::open_with_flags(&db_path, OpenFlags::SQLITE_OPEN_READ_WRITE)
+                .and_then(|conn| conn.busy_timeout(Duration::from_secs(5)).map(|()| conn));
+            let mut conn = match conn {
+                Ok(conn) => conn,
+                Err(err) => {
+                    let _ = sender.send(Err(format!("{}: {err}", db_path.display())));
+                    let _ = event_proxy.send_event(UserEvent::Wake);
+                    return;
                 }
-                if let Some(error) = &self.saved_action_error {
-                    ui.label(
-                        egui::RichText::new(error)
-                            .small()
-                            .color(egui::Color32::from_rgb(220, 120, 120)),
-                    );
+            };
+            loop {
+                let batch =
+                    enforce_retention(&mut conn, RETENTION_BATCH).map_err(|err| err.to_string());
+                let done = batch
+                    .as_ref()
+                    .map_or(true, |(message_ids, _)| message_ids.len() < RETENTION_BATCH);
+                if sender.send(batch).is_err() {
+                    return;
                 }
-                if let Some(error) = &self.pinned_action_error {
-                    ui.label(
-                        egui::RichText::new(error)
-                            .small()
-                            .color(egui::Color32::from_rgb(220, 120, 120)),
-                    );
+                let _ = event_proxy.send_event(UserEvent::Wake);
+                if done {
+                    return;
                 }
-                if let Some(error) = &self.reaction_action_error {
-                    ui.label(
-                        egui::RichText::new(error)
-                            .small()
-                            .color(egui::Color32::from_rgb(220, 120, 120)),
-                    );
+            }
+        });
+    }
+
+    /// Lists the applications registered for `path` off the UI thread, since
+    /// `xdg-mime` and the desktop entry scan can be slow. One lookup runs at a time;
+    /// the menu asks again for another file once it is done.
+    fn start_open_with_lookup(&mut self, path: String) {
+        if self.open_with_apps_receiver.is_some() {
+            return;
+        }
+        let (sender, receiver) = mpsc::channel();
+        self.open_with_apps_receiver = Some(receiver);
+        let event_proxy = self.event_proxy.clone();
+        thread::spawn(move || {
+            let apps = applications_for_file(&path);
+            let _ = sender.send((path, apps));
+            let _ = event_proxy.send_event(UserEvent::Wake);
+        });
+    }
+
+    /// Shows the platform's application chooser for `path` off the UI thread, since
+    /// it blocks until the user picks an application or cancels.
+    fn start_open_with_chooser(&mut self, path: &str) {
+        if self.chooser_receiver.is_some() {
+            return;
+        }
+        let (sender, receiver) = mpsc::channel();
+        self.chooser_receiver = Some(receiver);
+        let event_proxy = self.event_proxy.clone();
+        let path = path.to_string();
+        thread::spawn(move || {
+            let _ = sender.send(open_attachment_with_chooser(&path));
+            let _ = event_proxy.send_event(UserEvent::Wake);
+        });
+    }
+
+    /// Checkpoints the workspace database on a background thread with its own
+    /// connection, so a large log doesn't stall the UI.
+    fn start_flush(&mut self) {
+        if self.flush_receiver.is_some() {
+            return;
+        }
+        if self.db_is_fallback {
+            self.flush_result = Some(Ok(
+                "Messages are only in memory this session; there is nothing to flush.".to_string(),
+            ));
+            return;
+        }
+        self.flush_result = Some(Ok("Flushing the database…".to_string()));
+        let (sender, receiver) = mpsc::channel();
+        self.flush_receiver = Some(receiver);
+        let event_proxy = self.event_proxy.clone();
+        let db_path = self.active_workspace().db_path.clone();
+        thread::spawn(move || {
+            let result = Connection::open_with_flags(&db_path, OpenFlags::SQLITE_OPEN_READ_WRITE)
+                .and_then(|conn| checkpoint_wal(&conn))
+                .map(|checkpoint| wal_checkpoint_summary(&checkpoint, &db_path))
+                .map_err(|err| format!("Could not flush {}: {err}", db_path.display()));
+            let _ = sender.send(result);
+            let _ = event_proxy.send_event(UserEvent::Wake);
+        });
+    }
+
+    fn open_lightbox(&mut self, attachment: Attachment) {
+        let sender = self.lightbox_sender.clone();
+        let event_proxy = self.event_proxy.clone();
+        let path = attachment.file_path.clone();
+        let max_dimension =
+            (LIGHTBOX_MAX_DIMENSION as f32 * self.egui_ctx.pixels_per_point()).ceil() as u32;
+        self.lightbox = Some(Lightbox {
+            attachment,
+            texture: None,
+            error: None,
+        });
+        thread::spawn(move || {
+            let image = load_attachment_thumbnail_image(&path, max_dimension);
+            let _ = sender.send(LightboxResult { path, image });
+            let _ = event_proxy.send_event(UserEvent::Wake);
+        });
+    }
+
+    /// Scrolls to `message_id` in the selected channel. A message outside the loaded
+    /// window replaces it with a page of history on either side, so old messages are
+    /// reachable too; scrolling then pages on from there as usual. Returns false when
+    /// the message isn't in the channel.
+    fn jump_to_message(&mut self, message_id: i64) -> bool {
+        if !self.messages.iter().any(|message| message.id == message_id) {
+            let channel_id = self.selected_channel_id;
+            // The older page includes the target itself, hence the extra row.
+            let older =
+                load_older_messages(&self.db, channel_id, message_id + 1, HISTORY_PAGE_SIZE + 1);
+            let around = older.and_then(|older| {
+                let newer =
+                    load_newer_messages(&self.db, channel_id, message_id, HISTORY_PAGE_SIZE)?;
+                Ok((older, newer))
+            });
+            let (older, newer) = match around {
+                Ok(pages) => pages,
+                Err(err) => {
+                    log_error!("db load error: {err}");
+                    return false;
                 }
-                ui.separator();
-                ui.add_enabled_ui(self.messages_loaded, |ui| {
-                    let (composer_placeholder, typing_stub) = self
-                        .composer_meta
-                        .get(&self.selected_channel_id)
-                        .map(|meta| (meta.placeholder.as_str(), meta.typing_stub.as_str()))
-                        .unwrap_or(("Send a message", "Typing..."));
-                    let draft = self
-                        .composer_drafts
-                        .entry(self.selected_channel_id)
-                        .or_default();
-                    let typing_active =
-                        match self.typing_state.get(&self.selected_channel_id).copied() {
-                            Some(last_edit) if last_edit.elapsed() < Duration::from_secs(3) => true,
-                            Some(_) => {
-                                self.typing_state.remove(&self.selected_channel_id);
-                                false
-                            }
-                            None => false,
-                        };
-                    let typing_label = if typing_active && !draft.trim().is_empty() {
-                        "You are typing..."
-                    } else {
-                        typing_stub
-                    };
-                    ui.label(
-                        egui::RichText::new(typing_label)
-                            .small()
-                            .color(egui::Color32::from_rgb(140, 150, 170)),
-                    );
-                    let attachment_path = self
-                        .attachment_path_drafts
-                        .entry(self.selected_channel_id)
-                        .or_default();
-                    let pending_list = self
-                        .pending_attachments
-                        .entry(self.selected_channel_id)
-                        .or_default();
-                    ui.horizontal(|row| {
-                        row.label("Attach");
-                        row.add(
-                            egui::TextEdit::singleline(attachment_path)
-                                .hint_text("Path to file")
-                                .desired_width(320.0),
-                        );
-                        if row.button("Add").clicked() {
-                            let trimmed = attachment_path.trim();
-                            if trimmed.is_empty() {
-                                self.attachment_error =
-                                    Some("Attachment path is empty.".to_string());
-                            } else {
-                                match ingest_attachment(trimmed) {
-                                    Ok(attachment) => {
-                                        pending_list.push(attachment);
-                                        attachment_path.clear();
-                                        self.attachment_error = None;
-                                    }
-                                    Err(err) => {
-                                        self.attachment_error = Some(err);
-                                    }
+            };
+            if !older.iter().any(|message| message.id == message_id) {
+                return false;
+            }
+            let older_complete = (older.len() as i64) <= HISTORY_PAGE_SIZE;
+            let newer_complete = (newer.len() as i64) < HISTORY_PAGE_SIZE;
+            let mut messages = older;
+            messages.extend(newer);
+            messages.sort_by(compare_message_order);
+            self.messages = messages;
+            self.unread_marker = None;
+            self.reload_message_extras();
+            if let Some((oldest, newest)) = self.loaded_id_range() {
+                self.older_messages_exhausted = older_complete.then_some((channel_id, oldest));
+                self.newer_messages_trimmed = (!newer_complete).then_some((channel_id, newest));
+            }
+        }
+        self.scroll_to_message = Some(message_id);
+        true
+    }
+
+    /// Requests a thumbnail for this frame, or refreshes the request of one still
+    /// waiting for a worker. Call it every frame the image is laid out and unloaded.
+    fn queue_thumbnail_load(&mut self, path: &str, visible: bool) {
+        let frame = self.thumbnail_frame;
+        let mut jobs = self.thumbnail_queue.jobs();
+        if let Some(job) = jobs.iter_mut().find(|job| job.path == path) {
+            // Several places can show the same image; any visible one counts.
+            if job.requested_frame == frame {
+                job.visible |= visible;
+            } else {
+                job.visible = visible;
+                job.requested_frame = frame;
+            }
+            return;
+        }
+        // Already being decoded.
+        if !self.thumbnail_in_flight.insert(path.to_string()) {
+            return;
+        }
+        // Decode enough pixels for the on-screen size at the current zoom and density.
+        let longest_edge = self.app_config.thumbnail_size.bounds().max_elem() as u32;
+        let max_dimension = (THUMBNAIL_MAX_DIMENSION.max(longest_edge) as f32
+            * self.egui_ctx.pixels_per_point())
+        .ceil() as u32;
+        jobs.push(ThumbnailJob {
+            path: path.to_string(),
+            max_dimension,
+            visible,
+            requested_frame: frame,
+        });
+        self.thumbnail_queue.ready.notify_one();
+    }
+
+    /// Drops queued thumbnails nobody asked for this frame, such as images scrolled
+    /// well out of the loaded view or left behind by a channel switch.
+    fn prune_thumbnail_queue(&mut self) {
+        let frame = self.thumbnail_frame;
+        self.thumbnail_frame += 1;
+        let mut jobs = self.thumbnail_queue.jobs();
+        jobs.retain(|job| {
+            let wanted = job.requested_frame == frame;
+            if !wanted {
+                self.thumbnail_in_flight.remove(&job.path);
+            }
+            wanted
+        });
+    }
+
+    /// Replaces the attachments and reactions with those of the loaded messages.
+    fn reload_message_extras(&mut self) {
+        let message_ids: Vec<i64> = self.messages.iter().map(|message| message.id).collect();
+        match load_attachments_for_message_ids(&self.db, &message_ids) {
+            Ok(attachments) => self.message_attachments = attachments,
+            Err(err) => log_error!("db attachments load error: {err}"),
+        }
+        match load_reactions_for_message_ids(&self.db, &message_ids) {
+            Ok(reactions) => self.message_reactions = reactions,
+            Err(err) => log_error!("db reactions load error: {err}"),
+        }
+    }
+
+    /// Positions the view on the first unread message of the selected channel,
+    /// widening the loaded window when `allow_reload` is set and the first unread
+    /// message falls outside it, then marks the channel as read.
+    fn prepare_unread_jump(&mut self, allow_reload: bool) {
+        let channel_id = self.selected_channel_id;
+        self.unread_marker = None;
+        self.scroll_to_message = None;
+        if let Some(&last_read) = self.last_read.get(&channel_id) {
+            if allow_reload {
+                match count_unread_messages(&self.db, channel_id, last_read) {
+                    // More unread than one load holds: open a page around the first
+                    // unread message, and read forward by paging down from there.
+                    Ok(unread) if unread > UNREAD_FETCH_LIMIT => {
+                        match load_newer_messages(&self.db, channel_id, last_read, 1) {
+                            Ok(first) => {
+                                if let Some(first) = first.first() {
+                                    self.jump_to_message(first.id);
                                 }
                             }
+                            Err(err) => log_error!("db load error: {err}"),
                         }
-                    });
-                    let mut remove_attachment: Option<usize> = None;
-                    for (idx, attachment) in pending_list.iter().enumerate() {
-                        ui.horizontal(|row| {
-                            row.label(
-                                egui::RichText::new(format!(
-                                    "{} ({}, {})",
-                                    attachment.file_name,
-                                    format_bytes(attachment.file_size),
-                                    attachment.kind
-                                ))
-                                .small()
-                                .color(egui::Color32::from_rgb(160, 170, 190)),
-                            );
-                            if row.button("Remove").clicked() {
-                                remove_attachment = Some(idx);
-                            }
-                        });
-                    }
-                    if let Some(idx) = remove_attachment {
-                        if idx < pending_list.len() {
-                            pending_list.remove(idx);
-                        }
-                    }
-                    if let Some(error) = &self.attachment_error {
-                        ui.label(
-                            egui::RichText::new(error)
-                                .small()
-                                .color(egui::Color32::from_rgb(220, 120, 120)),
-                        );
                     }
-                    ui.horizontal(|row| {
-                        let composer = row.add(
-                            egui::TextEdit::singleline(draft)
-                                .hint_text(composer_placeholder)
-                                .desired_width(f32::INFINITY),
-                        );
-                        if self.composer_focus_requested {
-                            composer.request_focus();
-                            self.composer_focus_requested = false;
-                        }
-                        let send_clicked = row.button("Send").clicked();
-                        let send_enter = composer.has_focus()
-                            && row.input(|input| input.key_pressed(egui::Key::Enter));
-                        let send_now = send_clicked || send_enter;
-                        if send_clicked {
-                            self.composer_focus_requested = true;
-                        }
-                        if composer.changed() {
-                            if draft.trim().is_empty() {
-                                self.typing_state.remove(&self.selected_channel_id);
-                                if let Err(err) = delete_draft(&self.db, self.selected_channel_id) {
-                                    eprintln!("db draft delete error: {err}");
-                                }
-                            } else {
-                                self.typing_state
-                                    .insert(self.selected_channel_id, Instant::now());
-                                if let Err(err) = save_draft(
-                                    &self.db,
-                                    self.selected_channel_id,
-                                    draft,
-                                    &format_timestamp_utc(),
-                                ) {
-                                    eprintln!("db draft save error: {err}");
-                                }
-                            }
-                        }
-                        if send_now {
-                            let body = draft.trim().to_string();
-                            if !body.is_empty() || !pending_list.is_empty() {
-                                pending_send = Some(body);
-                                pending_attachments_send = pending_list.clone();
-                                pending_list.clear();
-                                draft.clear();
-                                self.typing_state.remove(&self.selected_channel_id);
-                                self.composer_focus_requested = true;
-                                if let Err(err) = delete_draft(&self.db, self.selected_channel_id) {
-                                    eprintln!("db draft delete error: {err}");
-                                }
+                    Ok(unread) if unread >= self.messages.len() as i64 && unread > 0 => {
+                        let limit = (unread + 1).min(UNREAD_FETCH_LIMIT);
+                        match load_messages_with_limit(&self.db, channel_id, limit) {
+                            Ok(messages) => {
+                                self.messages = messages;
+                                self.reload_message_extras();
                             }
+                            Err(err) => log_error!("db load error: {err}"),
                         }
-                    });
-                });
-                if !self.messages_loaded {
-                    ui.label(
-                        egui::RichText::new("Composer available once messages finish loading.")
-                            .small()
-                            .color(egui::Color32::from_rgb(140, 150, 170)),
-                    );
+                    }
+                    Ok(_) => {}
+                    Err(err) => log_error!("db unread count error: {err}"),
                 }
-            });
-        });
-        let mut repaint_delay = full_output
-            .viewport_output
-            .get(&egui::ViewportId::ROOT)
-            .map(|output| output.repaint_delay)
-            .unwrap_or(Duration::from_millis(16));
-        let suppress_repaint = self.window_occluded || !self.window_focused;
-        if suppress_repaint {
-            repaint_delay = BACKGROUND_REPAINT_DELAY;
-        } else if !has_input_events && !state_dirty && repaint_delay < IDLE_REPAINT_DELAY {
-            repaint_delay = IDLE_REPAINT_DELAY;
-        }
-        let now = Instant::now();
-        self.next_repaint_at = if repaint_delay == Duration::MAX {
-            now + Duration::from_secs(60 * 60 * 24)
-        } else {
-            now.checked_add(repaint_delay)
-                .unwrap_or_else(|| now + Duration::from_secs(1))
-        };
-        self.needs_repaint = repaint_delay.is_zero();
-        if realtime_connect {
-            self.realtime.connect();
-        }
-        if realtime_disconnect {
-            self.realtime.disconnect();
-        }
-
-        self.egui_state
-            .handle_platform_output(self.window.as_ref(), full_output.platform_output);
-
-        for (id, delta) in &full_output.textures_delta.set {
-            self.egui_renderer
-                .update_texture(&self.device, &self.queue, *id, delta);
+            }
+            self.unread_marker = self
+                .messages
+                .iter()
+                .find(|message| message.id > last_read)
+                .map(|message| message.id);
+            self.scroll_to_message = self.unread_marker;
         }
+        self.mark_selected_channel_read();
+    }
 
-        let screen_descriptor = ScreenDescriptor {
-            size_in_pixels: [self.config.width, self.config.height],
-            pixels_per_point: full_output.pixels_per_point,
+    fn mark_selected_channel_read(&mut self) {
+        let channel_id = self.selected_channel_id;
+        let latest = match self.messages.iter().map(|message| message.id).max() {
+            Some(latest) if latest > 0 => latest,
+            _ => return,
         };
+        if self.last_read.get(&channel_id).copied().unwrap_or(0) >= latest {
+            return;
+        }
+        self.last_read.insert(channel_id, latest);
+        self.unread_title_dirty = true;
+        if !self.db_is_fallback {
+            if let Err(err) = save_last_read(&self.db, channel_id, latest) {
+                log_error!("db read state save error: {err}");
+            }
+        }
+    }
 
-        let clipped_primitives = self
-            .egui_ctx
-            .tessellate(full_output.shapes, full_output.pixels_per_point);
-        let mut encoder =
-            self.device
-                .create_command_encoder(&wgpu::CommandEncoderDescriptor {
-                    label: Some("ralph-encoder"),
-                });
-        self.egui_renderer.update_buffers(
-            &self.device,
-            &self.queue,
-            &mut encoder,
-            &clipped_primitives,
-            &screen_descriptor,
-        );
+    /// Sets the selected channel's announcement, or clears it when `text` is empty.
+    fn save_announcement(&mut self, text: String) {
+        let channel_id = self.selected_channel_id;
+        let set_at = format_timestamp_utc();
+        let saved = if self.db_is_fallback {
+            Ok(())
+        } else if text.is_empty() {
+            clear_channel_announcement(&self.db, channel_id)
+        } else {
+            set_channel_announcement(&self.db, channel_id, &text, &set_at)
+        };
+        if let Err(err) = saved {
+            log_error!("db announcement save error: {err}");
+            return;
+        }
+        let state = self.channel_state.entry(channel_id).or_default();
+        if text.is_empty() {
+            state.announcement = None;
+            state.announcement_set_at = None;
+            state.announcement_collapsed_at = None;
+        } else {
+            state.announcement = Some(text);
+            state.announcement_set_at = Some(set_at);
+        }
+        self.announcement_draft = None;
+    }
 
-        let frame = match self.surface.get_current_texture() {
-            Ok(frame) => frame,
-            Err(SurfaceError::Lost) => {
-                self.resize(PhysicalSize::new(self.config.width, self.config.height));
-                return;
-            }
-            Err(SurfaceError::OutOfMemory) => {
+    fn set_read_only(&mut self, read_only: bool) {
+        let channel_id = self.selected_channel_id;
+        if !self.db_is_fallback {
+            if let Err(err) = save_channel_read_only(&self.db, channel_id, read_only) {
+                log_error!("db read-only save error: {err}");
                 return;
             }
-            Err(err) => {
-                eprintln!("surface error: {err}");
+        }
+        self.channel_state.entry(channel_id).or_default().read_only = read_only;
+        if read_only && self.send_confirm == Some(channel_id) {
+            self.send_confirm = None;
+        }
+    }
+
+    fn set_retention(&mut self, retention_days: Option<u32>) {
+        let channel_id = self.selected_channel_id;
+        if !self.db_is_fallback {
+            if let Err(err) = save_channel_retention(&self.db, channel_id, retention_days) {
+                log_error!("db retention save error: {err}");
                 return;
             }
-        };
-        let view = frame
-            .texture
-            .create_view(&wgpu::TextureViewDescriptor::default());
-        {
-            let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
-                label: Some("ralph-render-pass"),
-                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
-                    view: &view,
-                    resolve_target: None,
-                    ops: wgpu::Operations {
-                        load: wgpu::LoadOp::Clear(wgpu::Color {
-                            r: 0.06,
-                            g: 0.07,
-                            b: 0.09,
-                            a: 1.0,
-                        }),
-                        store: wgpu::StoreOp::Store,
-                    },
-                })],
-                depth_stencil_attachment: None,
-                timestamp_writes: None,
-                occlusion_query_set: None,
-            });
-            self.egui_renderer.render(
-                &mut render_pass,
-                &clipped_primitives,
-                &screen_descriptor,
-            );
         }
-
-        self.queue.submit(Some(encoder.finish()));
-        frame.present();
-
-        for id in &full_output.textures_delta.free {
-            self.egui_renderer.free_texture(id);
+        self.channel_state.entry(channel_id).or_default().retention_days = retention_days;
+        // Apply a tighter policy now rather than at the next hourly sweep.
+        if retention_days.is_some() {
+            self.retention_swept_at = None;
         }
+    }
 
-        if let Some(channel_id) = channel_switch {
-            if self.messages_loaded && channel_id != self.selected_channel_id {
-                self.selected_channel_id = channel_id;
-                self.messages = match load_messages(&self.db, channel_id) {
-                    Ok(messages) => messages,
-                    Err(err) => {
-                        eprintln!("db load error: {err}");
-                        Vec::new()
-                    }
-                };
-                self.messages_loaded = true;
-                self.message_attachments = match load_attachments_for_message_ids(
-                    &self.db,
-                    &self.messages.iter().map(|message| message.id).collect::<Vec<_>>(),
-                ) {
-                    Ok(attachments) => attachments,
-                    Err(err) => {
-                        eprintln!("db attachments load error: {err}");
-                        HashMap::new()
+    /// Deletes messages that have outlived their channel's retention policy. A sweep
+    /// starts once history has loaded and then every `RETENTION_SWEEP_INTERVAL`; it
+    /// runs on a worker thread with its own connection, and the batches it removes
+    /// are dropped from view here as they arrive.
+    fn drain_retention_sweep(&mut self) -> bool {
+        if self.retention_sweep.is_none() {
+            let due = self
+                .retention_swept_at
+                .is_none_or(|swept_at| swept_at.elapsed() >= RETENTION_SWEEP_INTERVAL);
+            if !due
+                || !self.messages_loaded
+                || self.db_is_fallback
+                || self.read_only_instance
+                || self.db_newer_schema.is_some()
+            {
+                return false;
+            }
+            self.retention_swept_at = Some(Instant::now());
+            self.start_retention_sweep();
+            return false;
+        }
+        let mut removed = HashSet::new();
+        let mut finished = false;
+        if let Some(sweep) = self.retention_sweep.as_mut() {
+            loop {
+                let (message_ids, attachment_paths) = match sweep.receiver.try_recv() {
+                    Ok(Ok(batch)) => batch,
+                    Ok(Err(err)) => {
+                        log_error!("db retention sweep error: {err}");
+                        finished = true;
+                        break;
                     }
-                };
-                self.message_reactions = match load_reactions_for_message_ids(
-                    &self.db,
-                    &self.messages.iter().map(|message| message.id).collect::<Vec<_>>(),
-                ) {
-                    Ok(reactions) => reactions,
-                    Err(err) => {
-                        eprintln!("db reactions load error: {err}");
-                        HashMap::new()
+                    Err(mpsc::TryRecvError::Empty) => break,
+                    Err(mpsc::TryRecvError::Disconnected) => {
+                        finished = true;
+                        break;
                     }
                 };
-                self.composer_focus_requested = true;
-                if self.search_channel_only && !self.search_query.trim().is_empty() {
-                    let query = self.search_query.trim().to_string();
-                    match search_messages(&self.db, &query, Some(channel_id)) {
-                        Ok(results) => {
-                            self.search_last_query = query;
-                            self.search_last_channel_only = true;
-                            self.search_results = results;
-                            self.message_attachments = match load_attachments_for_message_ids(
-                                &self.db,
-                                &self
-                                    .search_results
-                                    .iter()
-                                    .map(|message| message.id)
-                                    .collect::<Vec<_>>(),
-                            ) {
-                                Ok(attachments) => attachments,
-                                Err(err) => {
-                                    eprintln!("db attachments load error: {err}");
-                                    HashMap::new()
-                                }
-                            };
-                            self.message_reactions = match load_reactions_for_message_ids(
-                                &self.db,
-                                &self
-                                    .search_results
-                                    .iter()
-                                    .map(|message| message.id)
-                                    .collect::<Vec<_>>(),
-                            ) {
-                                Ok(reactions) => reactions,
-                                Err(err) => {
-                                    eprintln!("db reactions load error: {err}");
-                                    HashMap::new()
-                                }
-                            };
-                        }
-                        Err(err) => {
-                            eprintln!("db search error: {err}");
-                            self.search_last_query.clear();
-                            self.search_last_channel_only = self.search_channel_only;
-                            self.search_results.clear();
-                        }
-                    }
+                finished = message_ids.len() < RETENTION_BATCH;
+                sweep.removed += message_ids.len();
+                sweep.attachment_paths.extend(attachment_paths);
+                removed.extend(message_ids);
+                if finished {
+                    break;
                 }
             }
         }
+        if !removed.is_empty() {
+            self.forget_messages(&removed);
+        }
+        if !finished {
+            return !removed.is_empty();
+        }
+        let Some(sweep) = self.retention_sweep.take() else {
+            return false;
+        };
+        if sweep.removed == 0 {
+            return false;
+        }
+        let mut status = format!(
+            "Retention pruned {} message{}.",
+            sweep.removed,
+            if sweep.removed == 1 { "" } else { "s" }
+        );
+        if let Some(store) = self.app_config.attachment_store.as_deref() {
+            let pending = self.pending_attachment_paths();
+            match remove_store_files(&self.db, store, &sweep.attachment_paths, &pending) {
+                Ok((files, bytes)) if files > 0 => status.push_str(&format!(
+                    " Removed {files} attachment file{} ({} freed).",
+                    if files == 1 { "" } else { "s" },
+                    format_bytes(bytes as i64)
+                )),
+                Ok(_) => {}
+                Err(err) => status.push_str(&format!(" File cleanup failed: {err}")),
+            }
+        }
+        log_info!("{status}");
+        true
+    }
 
-        if let Some(message_id) = saved_toggle {
-            if self.saved_messages.contains(&message_id) {
-                match remove_saved_message(&self.db, message_id) {
-                    Ok(()) => {
-                        self.saved_messages.remove(&message_id);
-                        self.saved_action_error = None;
-                    }
-                    Err(err) => {
-                        self.saved_action_error =
-                            Some(format!("Could not remove saved message: {err}"));
-                    }
-                }
-            } else {
-                let saved_at = format_timestamp_utc();
-                match save_message(&self.db, message_id, &saved_at) {
-                    Ok(()) => {
-                        self.saved_messages.insert(message_id);
-                        self.saved_action_error = None;
-                    }
-                    Err(err) => {
-                        self.saved_action_error = Some(format!("Could not save message: {err}"));
-                    }
-                }
+    fn is_read_only(&self, channel_id: i64) -> bool {
+        self.channel_state
+            .get(&channel_id)
+            .is_some_and(|state| state.read_only)
+    }
+
+    fn set_confirm_send(&mut self, confirm: bool) {
+        let channel_id = self.selected_channel_id;
+        if !self.db_is_fallback {
+            if let Err(err) = save_confirm_send(&self.db, channel_id, confirm) {
+                log_error!("db confirm send save error: {err}");
+                return;
             }
         }
+        self.channel_state.entry(channel_id).or_default().confirm_send = confirm;
+        if !confirm && self.send_confirm == Some(channel_id) {
+            self.send_confirm = None;
+        }
+    }
 
-        if let Some(message_id) = pinned_toggle {
-            if self.pinned_messages.contains(&message_id) {
-                match remove_pinned_message(&self.db, message_id) {
-                    Ok(()) => {
-                        self.pinned_messages.remove(&message_id);
-                        self.pinned_action_error = None;
-                    }
-                    Err(err) => {
-                        self.pinned_action_error =
-                            Some(format!("Could not unpin message: {err}"));
-                    }
-                }
-            } else {
-                let pinned_at = format_timestamp_utc();
-                match pin_message(&self.db, message_id, &pinned_at, "you") {
-                    Ok(()) => {
-                        self.pinned_messages.insert(message_id);
-                        self.pinned_action_error = None;
-                    }
-                    Err(err) => {
-                        self.pinned_action_error = Some(format!("Could not pin message: {err}"));
-                    }
-                }
+    fn set_announcement_collapsed(&mut self, collapsed: bool) {
+        let channel_id = self.selected_channel_id;
+        let Some(state) = self.channel_state.get_mut(&channel_id) else {
+            return;
+        };
+        state.announcement_collapsed_at =
+            collapsed.then(|| state.announcement_set_at.clone()).flatten();
+        if !self.db_is_fallback {
+            if let Err(err) = save_announcement_collapsed(
+                &self.db,
+                channel_id,
+                state.announcement_collapsed_at.as_deref(),
+            ) {
+                log_error!("db announcement state save error: {err}");
             }
         }
+    }
 
-        if let Some((message_id, emoji, reacted)) = reaction_toggle {
-            if reacted {
-                match remove_reaction(&self.db, message_id, &emoji, "you") {
-                    Ok(()) => {
-                        if let Some(reactions) = self.message_reactions.get_mut(&message_id) {
-                            reactions.retain(|reaction| {
-                                !(reaction.emoji == emoji
-                                    && reaction.author.eq_ignore_ascii_case("you"))
-                            });
-                            if reactions.is_empty() {
-                                self.message_reactions.remove(&message_id);
-                            }
-                        }
-                        self.reaction_action_error = None;
-                    }
-                    Err(err) => {
-                        self.reaction_action_error =
-                            Some(format!("Could not remove reaction: {err}"));
-                    }
-                }
-            } else {
-                let reacted_at = format_timestamp_utc();
-                match add_reaction(&self.db, message_id, &emoji, "you", &reacted_at) {
-                    Ok(()) => {
-                        self.message_reactions
-                            .entry(message_id)
-                            .or_default()
-                            .push(MessageReaction {
-                                message_id,
-                                emoji,
-                                author: "you".to_string(),
-                                reacted_at,
-                            });
-                        self.reaction_action_error = None;
-                    }
-                    Err(err) => {
-                        self.reaction_action_error =
-                            Some(format!("Could not add reaction: {err}"));
-                    }
+    /// Puts the unread total across the sidebar's channels into the window title,
+    /// where the taskbar or dock shows it while the app is in the background.
+    /// Channels never opened have no read position and don't count.
+    fn refresh_window_title(&mut self) {
+        if !self.unread_title_dirty {
+            return;
+        }
+        self.unread_title_dirty = false;
+        let username = &self.app_config.identity.username;
+        let unread: i64 = self
+            .channels
+            .iter()
+            .filter(|channel| !channel.archived)
+            .filter_map(|channel| Some((channel.id, *self.last_read.get(&channel.id)?)))
+            .map(|(channel_id, last_read)| {
+                count_unread_from_others(&self.db, channel_id, last_read, username)
+                    .unwrap_or_else(|err| {
+                        log_error!("db unread count error: {err}");
+                        0
+                    })
+            })
+            .sum();
+        let title = window_title(unread);
+        if title != self.window_title {
+            self.window.set_title(&title);
+            self.window_title = title;
+        }
+    }
+
+    /// Moves every channel's read position to its newest message.
+    fn mark_all_channels_read(&mut self) {
+        let latest = match load_latest_message_ids(&self.db) {
+            Ok(latest) => latest,
+            Err(err) => {
+                log_error!("db latest message load error: {err}");
+                return;
+            }
+        };
+        for (channel_id, latest) in latest {
+            if self.last_read.get(&channel_id).copied().unwrap_or(0) >= latest {
+                continue;
+            }
+            self.last_read.insert(channel_id, latest);
+            self.unread_title_dirty = true;
+            if !self.db_is_fallback {
+                if let Err(err) = save_last_read(&self.db, channel_id, latest) {
+                    log_error!("db read state save error: {err}");
                 }
             }
         }
+        self.unread_marker = None;
+    }
 
-        if search_clear {
-            self.search_query.clear();
-            self.search_last_query.clear();
-            self.search_last_channel_only = self.search_channel_only;
-            self.search_results.clear();
-            if self.messages_loaded {
-                self.message_attachments = match load_attachments_for_message_ids(
-                    &self.db,
-                    &self.messages.iter().map(|message| message.id).collect::<Vec<_>>(),
-                ) {
-                    Ok(attachments) => attachments,
-                    Err(err) => {
-                        eprintln!("db attachments load error: {err}");
-                        HashMap::new()
-                    }
-                };
-                self.message_reactions = match load_reactions_for_message_ids(
-                    &self.db,
-                    &self.messages.iter().map(|message| message.id).collect::<Vec<_>>(),
-                ) {
-                    Ok(reactions) => reactions,
-                    Err(err) => {
-                        eprintln!("db reactions load error: {err}");
-                        HashMap::new()
-                    }
-                };
+    /// Channels of `kind` matching the sidebar filter, best first, each with the byte
+    /// offsets in its name that the filter matched.
+    fn filtered_channels(&self, kind: ChannelKind) -> Vec<(&Channel, Vec<usize>)> {
+        let mut ranked: Vec<(FuzzyMatch, &Channel)> = self
+            .channels
+            .iter()
+            .filter(|channel| channel.kind == kind && !channel.archived)
+            .filter_map(|channel| {
+                fuzzy_match(&self.sidebar_filter, &channel.name).map(|found| (found, channel))
+            })
+            .collect();
+        if !self.sidebar_filter.trim().is_empty() {
+            ranked.sort_by_key(|(found, _)| std::cmp::Reverse(found.score));
+        }
+        ranked
+            .into_iter()
+            .map(|(found, channel)| (channel, found.positions))
+            .collect()
+    }
+
+    fn channel_label(&self, channel_id: i64) -> String {
+        self.channels
+            .iter()
+            .find(|channel| channel.id == channel_id)
+            .map(|channel| match channel.kind {
+                ChannelKind::Channel => format!("#{}", channel.name),
+                ChannelKind::DirectMessage => format!("@{}", channel.name),
+            })
+            .unwrap_or_else(|| format!("#{}", channel_id))
+    }
+
+    /// Files attached in a composer but not yet sent; they have no attachment row, so
+    /// store cleanup has to be told to keep them.
+    fn pending_attachment_paths(&self) -> HashSet<&str> {
+        self.pending_attachments
+            .values()
+            .flatten()
+            .chain(self.attachment_duplicate.as_ref().map(|duplicate| &duplicate.attachment))
+            .map(|attachment| attachment.file_path.as_str())
+            .collect()
+    }
+
+    /// Attachment paths referenced by the other configured workspaces, which share the
+    /// attachment store with this one. A database that doesn't exist yet references
+    /// nothing; any other failure is an error, so cleanup never guesses.
+    fn other_workspace_attachment_paths(&self) -> Result<Vec<String>, String> {
+        let active = &self.active_workspace().db_path;
+        let mut paths = Vec::new();
+        for workspace in &self.app_config.workspaces {
+            if workspace.db_path == *active || !workspace.db_path.exists() {
+                continue;
+            }
+            let referenced =
+                Connection::open_with_flags(&workspace.db_path, OpenFlags::SQLITE_OPEN_READ_ONLY)
+                    .and_then(|conn| load_attachment_paths(&conn));
+            match referenced {
+                Ok(referenced) => paths.extend(referenced),
+                Err(err) => return Err(format!("workspace {} unreadable: {err}", workspace.name)),
+            }
+        }
+        Ok(paths)
+    }
+
+    /// Records a username seen on the wire the first time it shows up.
+    fn note_user(&mut self, username: &str) {
+        if !self.roster.observe(username) || self.db_is_fallback {
+            return;
+        }
+        if let Some(user) = self.roster.get(username) {
+            if let Err(err) = upsert_user(&self.db, user) {
+                log_error!("db user save error: {err}");
             }
         }
+    }
+
+    fn track_member(&mut self, message: &Message) {
+        let added = self
+            .channel_members
+            .entry(message.channel_id)
+            .or_default()
+            .insert(message.author.clone());
+        if added {
+            self.presence_counts_dirty = true;
+        }
+    }
 
-        if let Some(request) = search_request {
-            if self.messages_loaded {
-                let query = request.query;
-                let channel_filter = if request.channel_only {
-                    Some(self.selected_channel_id)
-                } else {
-                    None
-                };
-                match search_messages(&self.db, &query, channel_filter) {
-                    Ok(results) => {
-                        self.search_last_query = query;
-                        self.search_last_channel_only = request.channel_only;
-                        self.search_results = results;
-                        self.message_attachments = match load_attachments_for_message_ids(
-                            &self.db,
-                            &self
-                                .search_results
-                                .iter()
-                                .map(|message| message.id)
-                                .collect::<Vec<_>>(),
-                        ) {
-                            Ok(attachments) => attachments,
-                            Err(err) => {
-                                eprintln!("db attachments load error: {err}");
-                                HashMap::new()
-                            }
-                        };
-                        self.message_reactions = match load_reactions_for_message_ids(
-                            &self.db,
-                            &self
-                                .search_results
-                                .iter()
-                                .map(|message| message.id)
-                                .collect::<Vec<_>>(),
-                        ) {
-                            Ok(reactions) => reactions,
-                            Err(err) => {
-                                eprintln!("db reactions load error: {err}");
-                                HashMap::new()
-                            }
-                        };
-                    }
-                    Err(err) => {
-                        eprintln!("db search error: {err}");
-                        self.search_last_query.clear();
-                        self.search_last_channel_only = request.channel_only;
-                        self.search_results.clear();
-                    }
-                }
+    fn is_member(&self, channel_id: i64) -> bool {
+        self.channel_members
+            .get(&channel_id)
+            .is_some_and(|members| members.contains(&self.app_config.identity.username))
+    }
+
+    /// Records an explicit join or leave for the configured user. Posting counts as
+    /// joining, so sending into a channel you left rejoins it.
+    fn set_membership(&mut self, channel_id: i64, joined: bool) {
+        let username = self.app_config.identity.username.clone();
+        if !self.db_is_fallback {
+            let updated_at = format_timestamp_utc();
+            if let Err(err) =
+                set_channel_membership(&self.db, channel_id, &username, joined, &updated_at)
+            {
+                log_error!("db membership save error: {err}");
+                return;
             }
         }
+        let members = self.channel_members.entry(channel_id).or_default();
+        if joined {
+            members.insert(username);
+        } else {
+            members.remove(&username);
+        }
+        self.presence_counts_dirty = true;
+    }
 
-        if let Some(body) = pending_send {
-            if self.messages_loaded {
-                let content = if body.is_empty() && !pending_attachments_send.is_empty() {
-                    "Attachment".to_string()
+    fn presence_for_user(&self, user: &str) -> PresenceStatus {
+        self.presence_state
+            .get(user)
+            .map(|state| state.status)
+            .unwrap_or(PresenceStatus::Unknown)
+    }
+
+    /// Online and total member counts from the cache kept by `refresh_presence_counts`.
+    fn channel_presence_counts(&self, channel_id: i64) -> (usize, usize) {
+        self.presence_counts
+            .get(&channel_id)
+            .copied()
+            .unwrap_or((0, 0))
+    }
+
+    /// Recounts every channel. Runs only after a presence update or membership change
+    /// has marked the cache dirty, instead of walking all members every frame.
+    fn refresh_presence_counts(&mut self) {
+        self.presence_counts = self
+            .channel_members
+            .iter()
+            .map(|(channel_id, members)| {
+                let online = members
+                    .iter()
+                    .filter(|member| self.presence_for_user(member) == PresenceStatus::Online)
+                    .count();
+                (*channel_id, (online, members.len()))
+            })
+            .collect();
+        self.presence_counts_dirty = false;
+    }
+
+    fn channel_presence_details(&self) -> Option<String> {
+        let channel = self
+            .channels
+            .iter()
+            .find(|channel| channel.id == self.selected_channel_id)?;
+        match channel.kind {
+            ChannelKind::DirectMessage => {
+                let state = self.presence_state.get(&channel.name);
+                let status = state
+                    .map(|state| state.status)
+                    .unwrap_or(PresenceStatus::Unknown);
+                let detail = if let Some(state) = state {
+                    format!(
+                        "@{} is {} (updated {})",
+                        channel.name,
+                        status.label(),
+                        format_relative_duration(state.last_seen.elapsed())
+                    )
                 } else {
-                    body
-                };
-                let mut message = Message {
-                    id: 0,
-                    author: "you".to_string(),
-                    body: content,
-                    sent_at: format_timestamp_utc(),
-                    channel_id: self.selected_channel_id,
+                    format!("@{} status: {}", channel.name, status.label())
                 };
-                match insert_message(&self.db, &message) {
-                    Ok(id) => {
-                        message.id = id;
-                        let outgoing_attachments =
-                            pending_to_realtime_attachments(&pending_attachments_send);
-                        if !pending_attachments_send.is_empty() {
-                            if let Err(err) = insert_attachments(
-                                &mut self.db,
-                                message.id,
-                                &pending_attachments_send,
-                            ) {
-                                eprintln!("db attachments insert error: {err}");
-                            }
-                            self.message_attachments
-                                .entry(message.id)
-                                .or_default()
-                                .extend(pending_attachments_send.into_iter().map(|pending| {
-                                    Attachment {
-                                        message_id: message.id,
-                                        file_path: pending.file_path,
-                                        file_name: pending.file_name,
-                                        file_size: pending.file_size,
-                                        kind: pending.kind,
-                                    }
-                                }));
-                        }
-                        self.track_member(&message);
-                        self.messages.push(message);
-                        self.realtime.send_message(
-                            self.messages.last().expect("message"),
-                            outgoing_attachments,
-                        );
-                    }
-                    Err(err) => {
-                        eprintln!("db insert error: {err}");
-                    }
-                }
+                Some(detail)
             }
-        }
-
-        if !incoming.is_empty() {
-            for incoming_message in incoming {
-                if self.messages_loaded {
-                    let mut inbound = incoming_message.message;
-                    let inbound_attachments = incoming_message.attachments;
-                    match insert_message(&self.db, &inbound) {
-                        Ok(id) => {
-                            inbound.id = id;
-                            if !inbound_attachments.is_empty() {
-                                let pending = realtime_to_pending_attachments(&inbound_attachments);
-                                if let Err(err) =
-                                    insert_attachments(&mut self.db, inbound.id, &pending)
-                                {
-                                    eprintln!("db attachments insert error: {err}");
-                                }
-                                self.message_attachments
-                                    .entry(inbound.id)
-                                    .or_default()
-                                    .extend(pending.into_iter().map(|pending| Attachment {
-                                        message_id: inbound.id,
-                                        file_path: pending.file_path,
-                                        file_name: pending.file_name,
-                                        file_size: pending.file_size,
-                                        kind: pending.kind,
-                                    }));
-                            }
-                        }
-                        Err(err) => {
-                            eprintln!("db insert error: {err}");
-                        }
-                    }
-                    self.track_member(&inbound);
-                    if inbound.channel_id == self.selected_channel_id {
-                        self.messages.push(inbound);
-                    }
+            ChannelKind::Channel => {
+                let (online, total) = self.channel_presence_counts(channel.id);
+                if total == 0 {
+                    Some("No member activity yet.".to_string())
+                } else {
+                    Some(format!("{online}/{total} members online"))
                 }
             }
         }
+    }
+}
 
-        self.maybe_start_deferred_load();
+/// Names who reacted, in reaction order: "alice, bob, and 2 others". Your own
+/// reaction shows under your display name, and long names are shortened.
+fn format_reactors(authors: &[&str], identity: &Identity, roster: &Roster) -> String {
+    let names: Vec<Cow<str>> = authors
+        .iter()
+        .take(if authors.len() > 3 { 2 } else { 3 })
+        .map(|author| ellipsize(roster.label(identity, author), AUTHOR_NAME_MAX_CHARS))
+        .collect();
+    match (names.as_slice(), authors.len()) {
+        ([only], 1) => only.to_string(),
+        ([first, second], 2) => format!("{first} and {second}"),
+        ([first, second, third], 3) => format!("{first}, {second}, and {third}"),
+        ([first, second], total) => format!("{first}, {second}, and {} others", total - 2),
+        _ => String::new(),
     }
 }
 
-impl App {
-    fn maybe_start_deferred_load(&mut self) {
-        if self.exit_after_first_frame || !self.first_frame_logged {
-            return;
+/// A successful fuzzy match: its rank and the byte offsets in the candidate of the
+/// characters that matched, so results can show why they matched.
+#[derive(Debug, PartialEq)]
+struct FuzzyMatch {
+    score: i32,
+    positions: Vec<usize>,
+}
+
+/// Scores `candidate` as a case-insensitive subsequence match for `query`.
+/// Consecutive runs, a matching prefix, and word-boundary hits raise the score;
+/// skipped characters lower it. Returns `None` when `query` is not a subsequence.
+fn fuzzy_score(query: &str, candidate: &str) -> Option<i32> {
+    let query: Vec<char> = query.trim().chars().collect();
+    let mut matched = 0;
+    let mut score = 0;
+    let mut last_match: Option<usize> = None;
+    let mut previous: Option<char> = None;
+    for (idx, ch) in candidate.chars().enumerate() {
+        let Some(wanted) = query.get(matched) else {
+            break;
+        };
+        let boundary = previous.is_some_and(|prev| matches!(prev, '-' | '_' | ' ' | '.'));
+        previous = Some(ch);
+        if !ch.to_lowercase().eq(wanted.to_lowercase()) {
+            continue;
         }
-        if self.deferred_load_plan.is_none() || self.deferred_load_receiver.is_some() {
-            return;
+        score += 10;
+        match last_match {
+            Some(prev) if prev + 1 == idx => score += 15,
+            Some(prev) => score -= (idx - prev - 1).min(10) as i32,
+            None => score -= idx.min(10) as i32,
         }
-        self.start_deferred_load();
+        if idx == 0 {
+            score += 20;
+        } else if boundary {
+            score += 10;
+        }
+        last_match = Some(idx);
+        matched += 1;
     }
+    (matched == query.len()).then_some(score)
+}
 
-    fn start_deferred_load(&mut self) {
-        let plan = match self.deferred_load_plan.take() {
-            Some(plan) => plan,
-            None => return,
-        };
-        let (deferred_load_sender, deferred_load_receiver) = mpsc::channel();
-        self.deferred_load_receiver = Some(deferred_load_receiver);
-        let event_proxy = self.event_proxy.clone();
-        thread::spawn(move || {
-            let deferred_channel_id = plan.channel_id;
-            let channels_for_load = plan.channels;
-            let mut db = match Connection::open("ralph.db") {
-                Ok(conn) => conn,
-                Err(err) => {
-                    eprintln!("db open error (deferred): {err}");
-                    let messages = seed_messages()
-                        .into_iter()
-                        .filter(|message| message.channel_id == deferred_channel_id)
-                        .collect();
-                    let _ = deferred_load_sender.send(DeferredLoadResult {
-                        channel_id: deferred_channel_id,
-                        channels: channels_for_load.clone(),
-                        messages,
-                        attachments: HashMap::new(),
-                        channel_members: HashMap::new(),
-                        saved_messages: HashSet::new(),
-                        pinned_messages: HashSet::new(),
-                        message_reactions: HashMap::new(),
-                        drafts: HashMap::new(),
-                        db_ready: false,
-                    });
-                    let _ = event_proxy.send_event(UserEvent::Wake);
-                    return;
-                }
-            };
-            let mut db_ready = true;
-            if let Err(err) = ensure_schema(&db) {
-                eprintln!("db schema error (deferred): {err}");
-                db_ready = false;
-            }
-            if let Err(err) = seed_channels_if_empty(&mut db) {
-                eprintln!("db seed channels error (deferred): {err}");
-            }
-            if let Err(err) = seed_messages_if_empty(&mut db) {
-                eprintln!("db seed error (deferred): {err}");
-            }
-            if let Err(err) = seed_saved_messages_if_empty(&mut db) {
-                eprintln!("db seed saved error (deferred): {err}");
+/// `fuzzy_score`, plus the byte offsets of the characters it matched. Characters
+/// are compared one at a time so positions stay valid when lowercasing changes a
+/// character's length.
+fn fuzzy_match(query: &str, candidate: &str) -> Option<FuzzyMatch> {
+    let score = fuzzy_score(query, candidate)?;
+    let mut query = query.trim().chars().peekable();
+    let positions = candidate
+        .char_indices()
+        .filter(|(_, ch)| {
+            let hit = query
+                .peek()
+                .is_some_and(|wanted| ch.to_lowercase().eq(wanted.to_lowercase()));
+            if hit {
+                query.next();
             }
-            if let Err(err) = seed_pinned_messages_if_empty(&mut db) {
-                eprintln!("db seed pinned error (deferred): {err}");
-            }
-            if let Err(err) = seed_reactions_if_empty(&mut db) {
-                eprintln!("db seed reactions error (deferred): {err}");
-            }
-            let channels = match load_channels(&db) {
-                Ok(channels) if !channels.is_empty() => channels,
-                Ok(_) => channels_for_load.clone(),
-                Err(err) => {
-                    eprintln!("db channels load error (deferred): {err}");
-                    channels_for_load.clone()
-                }
-            };
-            let load_channel_id = channels
-                .iter()
-                .find(|channel| channel.id == deferred_channel_id)
-                .map(|channel| channel.id)
-                .or_else(|| channels.first().map(|channel| channel.id))
-                .unwrap_or(deferred_channel_id);
-            let messages = match load_messages(&db, load_channel_id) {
-                Ok(messages) => messages,
-                Err(err) => {
-                    eprintln!("db load error (deferred): {err}");
-                    seed_messages()
-                        .into_iter()
-                        .filter(|message| message.channel_id == load_channel_id)
-                        .collect()
-                }
-            };
-            let message_ids: Vec<i64> = messages.iter().map(|message| message.id).collect();
-            let attachments = match load_attachments_for_message_ids(&db, &message_ids) {
-                Ok(attachments) => attachments,
-                Err(err) => {
-                    eprintln!("db attachments load error (deferred): {err}");
-                    HashMap::new()
-                }
-            };
-            let message_reactions = match load_reactions_for_message_ids(&db, &message_ids) {
-                Ok(reactions) => reactions,
-                Err(err) => {
-                    eprintln!("db reactions load error (deferred): {err}");
-                    HashMap::new()
-                }
-            };
-            let channel_members = match load_channel_members(&db, &channels) {
-                Ok(members) => members,
-                Err(err) => {
-                    eprintln!("db members load error (deferred): {err}");
-                    HashMap::new()
-                }
-            };
-            let saved_messages = match load_saved_message_ids(&db) {
-                Ok(saved) => saved,
-                Err(err) => {
-                    eprintln!("db saved load error (deferred): {err}");
-                    HashSet::new()
-                }
-            };
-            let pinned_messages = match load_pinned_message_ids(&db) {
-                Ok(pinned) => pinned,
-                Err(err) => {
-                    eprintln!("db pinned load error (deferred): {err}");
-                    HashSet::new()
-                }
-            };
-            let drafts = match load_drafts(&db) {
-                Ok(drafts) => drafts,
-                Err(err) => {
-                    eprintln!("db drafts load error (deferred): {err}");
-                    HashMap::new()
-                }
-            };
-            let _ = deferred_load_sender.send(DeferredLoadResult {
-                channel_id: load_channel_id,
-                channels,
-                messages,
-                attachments,
-                channel_members,
-                saved_messages,
-                pinned_messages,
-                message_reactions,
-                drafts,
-                db_ready,
-            });
-            let _ = event_proxy.send_event(UserEvent::Wake);
-        });
+            hit
+        })
+        .map(|(offset, _)| offset)
+        .collect();
+    Some(FuzzyMatch { score, positions })
+}
+
+/// Lays out `prefix` then `text`, drawing the characters of `text` at the byte
+/// offsets in `matched` strong and underlined. Unmatched text keeps `color`, or the
+/// widget's own color when `None`.
+fn highlight_matches(
+    style: &egui::Style,
+    prefix: &str,
+    text: &str,
+    matched: &[usize],
+    color: Option<egui::Color32>,
+) -> egui::text::LayoutJob {
+    let mut job = egui::text::LayoutJob::default();
+    let mut append = |run: &str, hit: bool| {
+        let mut rich = egui::RichText::new(run);
+        if let Some(color) = color {
+            rich = rich.color(color);
+        }
+        if hit {
+            rich = rich.strong().underline();
+        }
+        rich.append_to(&mut job, style, egui::FontSelection::Default, egui::Align::Center);
+    };
+    if !prefix.is_empty() {
+        append(prefix, false);
     }
+    let mut run_start = 0;
+    let mut run_hit = false;
+    for (offset, _) in text.char_indices() {
+        let hit = matched.contains(&offset);
+        if hit != run_hit && offset > run_start {
+            append(&text[run_start..offset], run_hit);
+            run_start = offset;
+        }
+        run_hit = hit;
+    }
+    if run_start < text.len() {
+        append(&text[run_start..], run_hit);
+    }
+    job
+}
 
-    fn drain_thumbnail_results(&mut self) -> bool {
-        let mut changed = false;
-        while let Ok(result) = self.thumbnail_receiver.try_recv() {
-            self.thumbnail_in_flight.remove(&result.path);
-            if let Some(error) = result.error {
-                self.attachment_thumbnail_errors
-                    .insert(result.path.clone(), error);
-                self.touch_thumbnail_error(&result.path);
-                self.enforce_thumbnail_cache_limits();
-                changed = true;
-                continue;
+/// Consecutive messages count as repeats when the same author posted the same body
+/// within `REPEAT_WINDOW` of each other. Legacy `HH:MM` stamps only say the minute, so
+/// two of those count when they match exactly.
+fn is_repeat_message(previous: &Message, next: &Message) -> bool {
+    let close = match (parse_timestamp(&previous.sent_at), parse_timestamp(&next.sent_at)) {
+        (Some(previous), Some(next)) => {
+            (next - previous).abs() <= chrono::Duration::seconds(REPEAT_WINDOW.as_secs() as i64)
+        }
+        (None, None) => previous.sent_at == next.sent_at,
+        _ => false,
+    };
+    previous.channel_id == next.channel_id
+        && previous.author == next.author
+        && previous.body == next.body
+        && close
+}
+
+fn pending_to_realtime_attachments(
+    attachments: &[PendingAttachment],
+) -> Vec<RealtimeAttachment> {
+    attachments
+        .iter()
+        .map(|attachment| RealtimeAttachment {
+            file_path: attachment.file_path.clone(),
+            file_name: attachment.file_name.clone(),
+            file_size: attachment.file_size,
+            kind: attachment.kind.clone(),
+            file_hash: attachment.file_hash.clone(),
+        })
+        .collect()
+}
+
+fn realtime_to_pending_attachments(
+    attachments: &[RealtimeAttachment],
+) -> Vec<PendingAttachment> {
+    attachments
+        .iter()
+        .map(|attachment| {
+            let file_name = if attachment.file_name.is_empty() {
+                file_name_from_path(&attachment.file_path)
+            } else {
+                attachment.file_name.clone()
+            };
+            PendingAttachment {
+                file_path: attachment.file_path.clone(),
+                file_name,
+                file_size: attachment.file_size,
+                kind: attachment.kind.clone(),
+                file_hash: attachment.file_hash.clone(),
             }
-            if let Some(image) = result.image {
-                let texture = self.egui_ctx.load_texture(
-                    format!("attachment:{}", result.path),
-                    image,
-                    egui::TextureOptions::LINEAR,
-                );
-                self.attachment_thumbnails
-                    .insert(result.path.clone(), texture);
-                self.touch_thumbnail_cache(&result.path);
-                self.enforce_thumbnail_cache_limits();
-                changed = true;
+        })
+        .collect()
+}
+
+/// Collapses repeat requests for a path into its first, marked visible if any of
+/// them was, so an image shown twice in one frame is queued once.
+fn dedup_thumbnail_requests(requests: Vec<(String, bool)>) -> Vec<(String, bool)> {
+    let mut positions: HashMap<String, usize> = HashMap::new();
+    let mut unique: Vec<(String, bool)> = Vec::with_capacity(requests.len());
+    for (path, visible) in requests {
+        match positions.get(&path) {
+            Some(&position) => unique[position].1 |= visible,
+            None => {
+                positions.insert(path.clone(), unique.len());
+                unique.push((path, visible));
             }
         }
-        changed
     }
+    unique
+}
 
-    fn touch_thumbnail_cache(&mut self, path: &str) {
-        Self::touch_cache_order(&mut self.thumbnail_cache_order, path);
+/// Reads an image's size from its header without decoding the pixels, swapped
+/// when its EXIF orientation turns it on its side.
+fn read_image_dimensions(path: &str) -> Option<(u32, u32)> {
+    let mut decoder =
+        ImageReader::open(path).ok()?.with_guessed_format().ok()?.into_decoder().ok()?;
+    let (width, height) = decoder.dimensions();
+    if width == 0 || height == 0 {
+        return None;
+    }
+    match decoder.orientation().unwrap_or(Orientation::NoTransforms) {
+        Orientation::Rotate90
+        | Orientation::Rotate270
+        | Orientation::Rotate90FlipH
+        | Orientation::Rotate270FlipH => Some((height, width)),
+        _ => Some((width, height)),
     }
+}
 
-    fn touch_thumbnail_error(&mut self, path: &str) {
-        Self::touch_cache_order(&mut self.thumbnail_error_order, path);
+/// Fits an image into `bounds` keeping its aspect ratio, never enlarging it. Unknown
+/// dimensions get a 4:3 box.
+fn thumbnail_display_size(dimensions: Option<(u32, u32)>, bounds: egui::Vec2) -> egui::Vec2 {
+    let (width, height) = match dimensions {
+        Some((width, height)) if width > 0 && height > 0 => (width as f32, height as f32),
+        _ => (bounds.y * 4.0 / 3.0, bounds.y),
+    };
+    let scale = (bounds.x / width).min(bounds.y / height).min(1.0);
+    egui::Vec2::new((width * scale).max(1.0), (height * scale).max(1.0))
+}
+
+fn load_attachment_thumbnail_image(
+    path: &str,
+    max_dimension: u32,
+) -> Result<egui::ColorImage, String> {
+    let reader = ImageReader::open(path)
+        .map_err(|err| format!("could not open file: {err}"))?
+        .with_guessed_format()
+        .map_err(|err| format!("could not read file: {err}"))?;
+    if reader.format().is_none() {
+        return Err("not an image file".to_string());
+    }
+    // Without a recognisable header the format came from the extension alone, so a
+    // decode failure means a mislabelled file rather than a damaged image.
+    let mut header = Vec::new();
+    let recognized = fs::File::open(path)
+        .and_then(|file| file.take(64).read_to_end(&mut header))
+        .is_ok_and(|_| image::guess_format(&header).is_ok());
+    let describe = |err| describe_image_error(err, recognized);
+    let mut decoder = reader.into_decoder().map_err(describe)?;
+    // Phone photos are often stored sideways with an EXIF tag saying how to turn them;
+    // the decoder reports it but leaves applying it to us.
+    let orientation = decoder.orientation().unwrap_or(Orientation::NoTransforms);
+    let mut image = DynamicImage::from_decoder(decoder).map_err(describe)?;
+    image.apply_orientation(orientation);
+    let (width, height) = image.dimensions();
+    let max_axis = width.max(height);
+    if max_axis > max_dimension {
+        let scale = max_dimension as f32 / max_axis as f32;
+        let new_width = (width as f32 * scale).round().max(1.0) as u32;
+        let new_height = (height as f32 * scale).round().max(1.0) as u32;
+        image = image.resize(new_width, new_height, FilterType::Triangle);
     }
+    let rgba = image.to_rgba8();
+    let size = [rgba.width() as usize, rgba.height() as usize];
+    let pixels = rgba.into_raw();
+    Ok(egui::ColorImage::from_rgba_unmultiplied(size, &pixels))
+}
 
-    fn touch_cache_order(order: &mut VecDeque<String>, path: &str) {
-        if let Some(pos) = order.iter().position(|entry| entry == path) {
-            order.remove(pos);
+/// Sorts decoder failures into what a user can act on: a format this build can't
+/// read, a damaged file, something that isn't an image at all, or one too large to
+/// preview. `recognized` says whether the file starts with a known image header.
+fn describe_image_error(err: ImageError, recognized: bool) -> String {
+    match err {
+        ImageError::Unsupported(err) => format!("unsupported image format ({err})"),
+        ImageError::Decoding(_) if !recognized => "not an image file".to_string(),
+        ImageError::Decoding(_) => "image is truncated or corrupt".to_string(),
+        ImageError::IoError(err) if err.kind() == std::io::ErrorKind::UnexpectedEof => {
+            "image is truncated or corrupt".to_string()
         }
-        order.push_back(path.to_string());
+        ImageError::IoError(err) => format!("could not read file: {err}"),
+        ImageError::Limits(_) => "image is too large to preview".to_string(),
+        err => err.to_string(),
     }
+}
 
-    fn enforce_thumbnail_cache_limits(&mut self) {
-        while self.thumbnail_cache_order.len() > THUMBNAIL_CACHE_LIMIT {
-            if let Some(evicted) = self.thumbnail_cache_order.pop_front() {
-                self.attachment_thumbnails.remove(&evicted);
-            }
+fn normalize_channel_name(name: &str) -> String {
+    name.trim()
+        .trim_start_matches('#')
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join("-")
+        .to_lowercase()
+}
+
+fn file_mtime(path: &str) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|metadata| metadata.modified()).ok()
+}
+
+fn is_text_attachment(file_name: &str) -> bool {
+    let extension = Path::new(file_name)
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .unwrap_or("")
+        .to_ascii_lowercase();
+    matches!(extension.as_str(), "txt" | "md" | "markdown")
+}
+
+/// Reads up to `TEXT_PREVIEW_MAX_BYTES` of a text attachment. Returns `None` when the
+/// file can't be read or doesn't look like UTF-8 text, so mislabeled binaries get no preview.
+fn load_text_preview(path: &str) -> Option<String> {
+    let file = fs::File::open(path).ok()?;
+    let mut bytes = Vec::new();
+    file.take(TEXT_PREVIEW_MAX_BYTES)
+        .read_to_end(&mut bytes)
+        .ok()?;
+    if bytes.contains(&0) {
+        return None;
+    }
+    let text = match String::from_utf8(bytes) {
+        Ok(text) => text,
+        // The cap can split a multi-byte character; keep everything before it.
+        Err(err) if err.utf8_error().error_len().is_none() => {
+            let valid = err.utf8_error().valid_up_to();
+            let mut bytes = err.into_bytes();
+            bytes.truncate(valid);
+            String::from_utf8(bytes).ok()?
         }
-        while self.thumbnail_error_order.len() > THUMBNAIL_ERROR_LIMIT {
-            if let Some(evicted) = self.thumbnail_error_order.pop_front() {
-                self.attachment_thumbnail_errors.remove(&evicted);
-            }
+        Err(_) => return None,
+    };
+    Some(text)
+}
+
+fn open_attachment(path: &str) -> Result<(), String> {
+    open_attachment_with_args(path, &[], None)
+}
+
+/// Lets the user pick the program from the platform's own chooser. Linux has no
+/// standard one, so there the registered applications are listed in the menu instead.
+fn open_attachment_with_chooser(path: &str) -> Result<(), String> {
+    if cfg!(target_os = "macos") {
+        let output = Command::new("osascript")
+            .args(["-e", "POSIX path of (choose application)"])
+            .output()
+            .map_err(|err| format!("Failed to show application chooser: {err}"))?;
+        if !output.status.success() {
+            // Cancelling the chooser exits non-zero; nothing to report.
+            return Ok(());
+        }
+        let app = String::from_utf8_lossy(&output.stdout).trim().to_string();
+        open_attachment_with_args(path, &[], Some(&app))
+    } else if cfg!(target_os = "windows") {
+        if !Path::new(path).exists() {
+            return Err("Attachment path does not exist.".to_string());
         }
+        Command::new("rundll32")
+            .arg("shell32.dll,OpenAs_RunDLL")
+            .arg(path)
+            .spawn()
+            .map(|_| ())
+            .map_err(|err| format!("Failed to show application chooser: {err}"))
+    } else {
+        Err("Pick an application from the list.".to_string())
     }
+}
 
-    fn apply_deferred_loads(&mut self) -> bool {
-        let result = match self.deferred_load_receiver.as_ref() {
-            Some(receiver) => receiver.try_recv().ok(),
-            None => None,
+/// An application registered for a file's MIME type, from the freedesktop
+/// `mimeinfo.cache` files.
+struct DesktopApp {
+    /// Desktop entry id, as passed to `gtk-launch`.
+    id: String,
+    name: String,
+}
+
+fn applications_for_file(path: &str) -> Vec<DesktopApp> {
+    let mime = Command::new("xdg-mime")
+        .args(["query", "filetype"])
+        .arg(path)
+        .output()
+        .ok()
+        .filter(|output| output.status.success())
+        .map(|output| String::from_utf8_lossy(&output.stdout).trim().to_string())
+        .filter(|mime| !mime.is_empty());
+    let Some(mime) = mime else {
+        return Vec::new();
+    };
+    let prefix = format!("{mime}=");
+    let mut apps: Vec<DesktopApp> = Vec::new();
+    for dir in application_dirs() {
+        let Ok(cache) = fs::read_to_string(dir.join("mimeinfo.cache")) else {
+            continue;
         };
-        if let Some(result) = result {
-            let mut changed = false;
-            if result.db_ready && self.db_is_fallback {
-                match Connection::open("ralph.db") {
-                    Ok(conn) => {
-                        self.db = conn;
-                        self.db_is_fallback = false;
-                        changed = true;
-                    }
-                    Err(err) => {
-                        eprintln!("db open error (deferred swap): {err}");
-                    }
-                }
-            }
-            let selected_before = self.selected_channel_id;
-            if !result.channels.is_empty() {
-                self.channels = result.channels;
-                self.composer_meta = build_composer_meta(&self.channels);
-                changed = true;
-                if !self
-                    .channels
-                    .iter()
-                    .any(|channel| channel.id == self.selected_channel_id)
-                {
-                    if let Some(channel) = self.channels.first() {
-                        self.selected_channel_id = channel.id;
-                        self.composer_focus_requested = true;
-                        changed = true;
-                    }
-                }
+        let ids = cache.lines().filter_map(|line| line.strip_prefix(&prefix));
+        for id in ids.flat_map(|ids| ids.split(';')).filter(|id| !id.is_empty()) {
+            if apps.iter().any(|app| app.id == id) {
+                continue;
             }
-            if result.channel_id == self.selected_channel_id {
-                self.messages = result.messages;
-                self.message_attachments = result.attachments;
-                self.message_reactions = result.message_reactions;
-                self.messages_loaded = true;
-                changed = true;
-            } else if self.selected_channel_id != selected_before {
-                self.messages = match load_messages(&self.db, self.selected_channel_id) {
-                    Ok(messages) => messages,
-                    Err(err) => {
-                        eprintln!("db load error: {err}");
-                        Vec::new()
-                    }
-                };
-                self.messages_loaded = true;
-                self.message_attachments = match load_attachments_for_message_ids(
-                    &self.db,
-                    &self.messages.iter().map(|message| message.id).collect::<Vec<_>>(),
-                ) {
-                    Ok(attachments) => attachments,
-                    Err(err) => {
-                        eprintln!("db attachments load error: {err}");
-                        HashMap::new()
-                    }
-                };
-                self.message_reactions = match load_reactions_for_message_ids(
-                    &self.db,
-                    &self.messages.iter().map(|message| message.id).collect::<Vec<_>>(),
-                ) {
-                    Ok(reactions) => reactions,
-                    Err(err) => {
-                        eprintln!("db reactions load error: {err}");
-                        HashMap::new()
+            let name = desktop_entry_name(&dir.join(id))
+                .unwrap_or_else(|| id.trim_end_matches(".desktop").to_string());
+            apps.push(DesktopApp {
+                id: id.to_string(),
+                name,
+            });
+        }
+    }
+    apps
+}
+
+/// `applications` directories in XDG precedence order: the user's data dir first.
+fn application_dirs() -> Vec<PathBuf> {
+    let data_home = env_string("XDG_DATA_HOME")
+        .map(PathBuf::from)
+        .or_else(|| env_string("HOME").map(|home| Path::new(&home).join(".local/share")));
+    let data_dirs = env_string("XDG_DATA_DIRS")
+        .unwrap_or_else(|| "/usr/local/share:/usr/share".to_string());
+    data_home
+        .into_iter()
+        .chain(data_dirs.split(':').filter(|dir| !dir.is_empty()).map(PathBuf::from))
+        .map(|dir| dir.join("applications"))
+        .collect()
+}
+
+fn desktop_entry_name(path: &Path) -> Option<String> {
+    fs::read_to_string(path)
+        .ok()?
+        .lines()
+        .find_map(|line| line.strip_prefix("Name="))
+        .map(|name| name.trim().to_string())
+}
+
+fn reveal_attachment(path: &str) -> Result<(), String> {
+    #[cfg(target_os = "macos")]
+    {
+        open_attachment_with_args(path, &["-R"], None)
+    }
+    #[cfg(not(target_os = "macos"))]
+    {
+        let parent = Path::new(path)
+            .parent()
+            .ok_or_else(|| "Attachment path has no parent directory.".to_string())?;
+        open_attachment_with_args(parent.to_str().unwrap_or_default(), &[], None)
+    }
+}
+
+/// This process's memory use in KiB, and whether it is the peak (`VmHWM` from
+/// `/proc`) rather than the current resident size (from `ps`).
+fn peak_rss_kb() -> Option<(u64, bool)> {
+    if let Ok(status) = fs::read_to_string("/proc/self/status") {
+        let peak = status
+            .lines()
+            .find_map(|line| line.strip_prefix("VmHWM:"))
+            .and_then(|value| value.trim().trim_end_matches("kB").trim().parse().ok());
+        if let Some(peak) = peak {
+            return Some((peak, true));
+        }
+    }
+    let output = Command::new("ps")
+        .args(["-o", "rss=", "-p", &std::process::id().to_string()])
+        .output()
+        .ok()?;
+    let rss = String::from_utf8_lossy(&output.stdout).trim().parse().ok()?;
+    Some((rss, false))
+}
+
+/// "Ralph", with the unread count in parentheses when there is one.
+fn window_title(unread: i64) -> String {
+    if unread > 0 {
+        format!("Ralph ({unread})")
+    } else {
+        "Ralph".to_string()
+    }
+}
+
+/// Shows the platform's save dialog with `file_name` filled in. Returns `None` when
+/// it is cancelled. Linux uses zenity, or kdialog where zenity isn't installed.
+fn choose_save_path(file_name: &str) -> Result<Option<PathBuf>, String> {
+    let output = if cfg!(target_os = "macos") {
+        let script = format!(
+            "POSIX path of (choose file name default name \"{}\")",
+            file_name.replace('\\', "\\\\").replace('"', "\\\"")
+        );
+        Command::new("osascript").args(["-e", &script]).output()
+    } else if cfg!(target_os = "windows") {
+        let script = format!(
+            "Add-Type -AssemblyName System.Windows.Forms; \
+             $dialog = New-Object System.Windows.Forms.SaveFileDialog; \
+             $dialog.FileName = '{}'; \
+             if ($dialog.ShowDialog() -eq 'OK') {{ $dialog.FileName }}",
+            file_name.replace('\'', "''")
+        );
+        Command::new("powershell")
+            .args(["-NoProfile", "-STA", "-Command", &script])
+            .output()
+    } else {
+        let zenity = Command::new("zenity")
+            .args(["--file-selection", "--save", "--confirm-overwrite"])
+            .arg(format!("--filename={file_name}"))
+            .output();
+        match zenity {
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Command::new("kdialog")
+                .args(["--getsavefilename", file_name])
+                .output()
+                .map_err(|err| {
+                    if err.kind() == std::io::ErrorKind::NotFound {
+                        std::io::Error::other("install zenity or kdialog to choose where to save")
+                    } else {
+                        err
                     }
-                };
-                changed = true;
-            }
-            for (channel_id, members) in result.channel_members {
-                self.channel_members
-                    .entry(channel_id)
-                    .or_default()
-                    .extend(members);
-                changed = true;
-            }
-            self.saved_messages = result.saved_messages;
-            self.pinned_messages = result.pinned_messages;
-            if !result.drafts.is_empty() {
-                self.composer_drafts = result.drafts;
-                changed = true;
+                }),
+            other => other,
+        }
+    }
+    .map_err(|err| format!("Failed to show save dialog: {err}"))?;
+    // Cancelling exits non-zero on every platform but Windows, which prints nothing.
+    let chosen = String::from_utf8_lossy(&output.stdout).trim().to_string();
+    if !output.status.success() || chosen.is_empty() {
+        return Ok(None);
+    }
+    Ok(Some(PathBuf::from(chosen)))
+}
+
+/// Launches `path` with the platform opener, or with `app` when given: an
+/// application name or path on macOS, an executable on Windows, and a desktop entry
+/// id on Linux.
+fn open_attachment_with_args(
+    path: &str,
+    extra_args: &[&str],
+    app: Option<&str>,
+) -> Result<(), String> {
+    let path_ref = Path::new(path);
+    if !path_ref.exists() {
+        return Err("Attachment path does not exist.".to_string());
+    }
+    let mut command = if cfg!(target_os = "macos") {
+        let mut cmd = Command::new("open");
+        if let Some(app) = app {
+            cmd.args(["-a", app]);
+        }
+        cmd.args(extra_args);
+        cmd
+    } else if cfg!(target_os = "windows") {
+        Command::new(app.unwrap_or("explorer"))
+    } else if let Some(app) = app {
+        let mut cmd = Command::new("gtk-launch");
+        cmd.arg(app);
+        cmd
+    } else {
+        Command::new("xdg-open")
+    };
+    if cfg!(target_os = "windows") && extra_args.contains(&"-R") {
+        command.arg(format!("/select,{}", path_ref.display()));
+    } else {
+        command.arg(path_ref);
+    }
+    command
+        .status()
+        .map_err(|err| format!("Failed to launch attachment: {err}"))
+        .and_then(|status| {
+            if status.success() {
+                Ok(())
+            } else {
+                Err("Attachment launcher exited with failure.".to_string())
             }
-            if !result.db_ready || self.db_is_fallback {
-                if let Err(err) = ensure_schema(&self.db) {
-                    eprintln!("db schema error: {err}");
+        })
+}
+
+/// The file a paste refers to, when the whole paste is a path (or `file://` URL) to
+/// an existing file. Bare file names are ignored so ordinary words never match a file
+/// in the working directory.
+fn pasted_file_path(text: &str) -> Option<PathBuf> {
+    let text = text.trim();
+    if text.is_empty() || text.contains('\n') {
+        return None;
+    }
+    let path = if text.starts_with("file://") {
+        Url::parse(text).ok()?.to_file_path().ok()?
+    } else {
+        PathBuf::from(text)
+    };
+    if path.components().count() < 2 || !path.is_file() {
+        return None;
+    }
+    Some(path)
+}
+
+/// Reads `path` into a pending attachment, copying it into `store` when one is set.
+/// The file is only ever read through `path` itself. A name that isn't valid UTF-8
+/// is shown lossily, and the store copy is written under that lossy name so its path
+/// round-trips through the database. Without a store such a path can't be recorded
+/// faithfully, so it is refused rather than saved as a path that doesn't exist.
+fn ingest_attachment(
+    path: &Path,
+    store: Option<&Path>,
+    kind_overrides: &HashMap<String, String>,
+) -> Result<PendingAttachment, String> {
+    let metadata = fs::metadata(path).map_err(|err| format!("File error: {err}"))?;
+    if !metadata.is_file() {
+        return Err("Attachment path is not a file.".to_string());
+    }
+    let file_name = file_name_from_path(path);
+    let file_size = metadata.len() as i64;
+    let kind = detect_attachment_kind(path, kind_overrides);
+    if store.is_none() && path.to_str().is_none() {
+        return Err(format!(
+            "{file_name} can't be attached in place because its path isn't valid UTF-8; \
+             set RALPH_ATTACHMENT_STORE to attach a copy."
+        ));
+    }
+    let file_hash = hash_file(path).map_err(|err| format!("File error: {err}"))?;
+    let file_path = match store {
+        Some(store) => copy_into_attachment_store(store, path, &file_name)?,
+        None => path.to_path_buf(),
+    }
+    .to_string_lossy()
+    .into_owned();
+    Ok(PendingAttachment {
+        file_path,
+        file_name,
+        file_size,
+        kind,
+        file_hash: Some(file_hash),
+    })
+}
+
+/// Region-capture tools tried on Linux, in order, with the arguments that make each
+/// save the selection straight to a file. The target path is appended.
+const LINUX_SCREENSHOT_TOOLS: &[(&str, &[&str])] = &[
+    ("spectacle", &["--region", "--background", "--nonotify", "--output"]),
+    ("gnome-screenshot", &["--area", "--file"]),
+    ("maim", &["--select"]),
+];
+
+/// Lets the user select a screen region with the platform's capture tool and saves
+/// it to `target` as PNG. Returns false when the capture was cancelled, which the
+/// tools signal by writing no file (and, for some, exiting non-zero).
+fn capture_screen_region(target: &Path) -> Result<bool, String> {
+    let _ = fs::remove_file(target);
+    if cfg!(target_os = "macos") {
+        Command::new("screencapture")
+            .arg("-i")
+            .arg(target)
+            .status()
+            .map_err(|err| format!("Screenshot error: {err}"))?;
+    } else if cfg!(target_os = "windows") {
+        // The Snipping Tool only captures to the clipboard, so start it and save the
+        // first image that lands there, giving up after two minutes. Whatever the
+        // clipboard held beforehand is copied aside and put back afterwards.
+        let script = format!(
+            "Add-Type -AssemblyName System.Windows.Forms; \
+             $clipboard = [System.Windows.Forms.Clipboard]; \
+             $saved = New-Object System.Windows.Forms.DataObject; \
+             $current = $clipboard::GetDataObject(); \
+             if ($current) {{ \
+               foreach ($format in $current.GetFormats($false)) {{ \
+                 try {{ $saved.SetData($format, $current.GetData($format)) }} catch {{ }} \
+               }} \
+             }}; \
+             $clipboard::Clear(); \
+             try {{ \
+               Start-Process 'ms-screenclip:'; \
+               for ($i = 0; $i -lt 1200; $i++) {{ \
+                 Start-Sleep -Milliseconds 100; \
+                 $image = $clipboard::GetImage(); \
+                 if ($image) {{ \
+                   $image.Save('{}', [System.Drawing.Imaging.ImageFormat]::Png); break \
+                 }} \
+               }} \
+             }} finally {{ \
+               if ($saved.GetFormats().Length -gt 0) {{ \
+                 $clipboard::SetDataObject($saved, $true) \
+               }} else {{ $clipboard::Clear() }} \
+             }}",
+            target.to_string_lossy().replace('\'', "''")
+        );
+        Command::new("powershell")
+            .args(["-NoProfile", "-STA", "-Command", &script])
+            .status()
+            .map_err(|err| format!("Screenshot error: {err}"))?;
+    } else {
+        let mut found = false;
+        for (tool, args) in LINUX_SCREENSHOT_TOOLS {
+            match Command::new(tool).args(*args).arg(target).status() {
+                Ok(_) => {
+                    found = true;
+                    break;
                 }
+                Err(err) if err.kind() == std::io::ErrorKind::NotFound => continue,
+                Err(err) => return Err(format!("Screenshot error ({tool}): {err}")),
             }
-            self.deferred_load_receiver = None;
-            return changed;
         }
-        false
+        if !found {
+            return Err("No screenshot tool found; install spectacle, gnome-screenshot, or maim."
+                .to_string());
+        }
     }
+    Ok(fs::metadata(target).is_ok_and(|metadata| metadata.len() > 0))
+}
 
-    fn queue_thumbnail_load(&mut self, path: &str) {
-        if !self.thumbnail_in_flight.insert(path.to_string()) {
-            return;
+/// Explains why the file read from `path` into `candidate` looks already attached:
+/// the same path or contents as a pending attachment, or the same contents as an
+/// attachment in `recent`.
+fn duplicate_attachment_reason<'a>(
+    path: &str,
+    candidate: &PendingAttachment,
+    pending: &[PendingAttachment],
+    recent: impl IntoIterator<Item = &'a Attachment>,
+) -> Option<String> {
+    let file_name = &candidate.file_name;
+    if pending.iter().any(|attachment| attachment.file_path == path) {
+        return Some(format!("{file_name} is already attached to this message."));
+    }
+    let hash = candidate.file_hash.as_deref()?;
+    if let Some(existing) = pending
+        .iter()
+        .find(|attachment| attachment.file_hash.as_deref() == Some(hash))
+    {
+        return Some(format!(
+            "{file_name} matches {}, which is already attached to this message.",
+            existing.file_name
+        ));
+    }
+    recent
+        .into_iter()
+        .find(|attachment| attachment.file_hash.as_deref() == Some(hash))
+        .map(|existing| {
+            format!(
+                "{file_name} matches {}, which was already shared in this channel.",
+                existing.file_name
+            )
+        })
+}
+
+/// FNV-1a over the file contents, hex-encoded. Cheap enough to run on ingest and open;
+/// it detects edits and truncation, not tampering.
+fn hash_file(path: &Path) -> Result<String, std::io::Error> {
+    let mut file = fs::File::open(path)?;
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    let mut buffer = [0u8; 64 * 1024];
+    loop {
+        let read = file.read(&mut buffer)?;
+        if read == 0 {
+            break;
+        }
+        for byte in &buffer[..read] {
+            hash ^= u64::from(*byte);
+            hash = hash.wrapping_mul(0x0000_0100_0000_01b3);
         }
-        let sender = self.thumbnail_sender.clone();
-        let event_proxy = self.event_proxy.clone();
-        let path = path.to_string();
-        thread::spawn(move || {
-            let result = match load_attachment_thumbnail_image(&path) {
-                Ok(image) => ThumbnailResult {
-                    path,
-                    image: Some(image),
-                    error: None,
-                },
-                Err(error) => ThumbnailResult {
-                    path,
-                    image: None,
-                    error: Some(error),
-                },
-            };
-            let _ = sender.send(result);
-            let _ = event_proxy.send_event(UserEvent::Wake);
-        });
     }
+    Ok(format!("fnv1a64:{hash:016x}"))
+}
 
-    fn channel_label(&self, channel_id: i64) -> String {
-        self.channels
-            .iter()
-            .find(|channel| channel.id == channel_id)
-            .map(|channel| match channel.kind {
-                ChannelKind::Channel => format!("#{}", channel.name),
-                ChannelKind::DirectMessage => format!("@{}", channel.name),
-            })
-            .unwrap_or_else(|| format!("#{}", channel_id))
+fn copy_into_attachment_store(
+    store: &Path,
+    source: &Path,
+    file_name: &str,
+) -> Result<PathBuf, String> {
+    fs::create_dir_all(store).map_err(|err| format!("Attachment store error: {err}"))?;
+    let stamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_nanos();
+    let target = store.join(format!("{stamp}-{file_name}"));
+    fs::copy(source, &target).map_err(|err| format!("Attachment copy error: {err}"))?;
+    Ok(target)
+}
+
+/// Removes those of `paths` that live in the attachment store and that no remaining
+/// attachment row or `keep` path refers to. Returns files removed and bytes freed.
+fn remove_store_files(
+    conn: &Connection,
+    store: &Path,
+    paths: &[String],
+    keep: &HashSet<&str>,
+) -> Result<(usize, u64), String> {
+    let store = match store.canonicalize() {
+        Ok(store) => store,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok((0, 0)),
+        Err(err) => return Err(format!("Attachment store error: {err}")),
+    };
+    let referenced: HashSet<String> = load_attachment_paths(conn)
+        .map_err(|err| format!("db error: {err}"))?
+        .into_iter()
+        .collect();
+    let mut removed = 0;
+    let mut freed = 0;
+    for path in paths {
+        if referenced.contains(path) || keep.contains(path.as_str()) {
+            continue;
+        }
+        let Ok(canonical) = Path::new(path).canonicalize() else {
+            continue;
+        };
+        if !canonical.starts_with(&store) {
+            continue;
+        }
+        let size = fs::metadata(&canonical).map(|metadata| metadata.len()).unwrap_or(0);
+        match fs::remove_file(&canonical) {
+            Ok(()) => {
+                removed += 1;
+                freed += size;
+            }
+            Err(err) => log_warn!("attachment cleanup error ({}): {err}", canonical.display()),
+        }
     }
+    Ok((removed, freed))
+}
 
-    fn track_member(&mut self, message: &Message) {
-        self.channel_members
-            .entry(message.channel_id)
-            .or_default()
-            .insert(message.author.clone());
+/// Deletes regular files directly inside `store` that no attachment row or `keep` path
+/// references; `keep` covers composer attachments that have no row yet. Returns the
+/// number of files removed and the bytes freed.
+fn cleanup_attachment_store(
+    conn: &Connection,
+    store: &Path,
+    keep: &HashSet<&str>,
+) -> Result<(usize, u64), String> {
+    let store = match store.canonicalize() {
+        Ok(store) => store,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok((0, 0)),
+        Err(err) => return Err(format!("Attachment store error: {err}")),
+    };
+    let referenced: HashSet<PathBuf> = load_attachment_paths(conn)
+        .map_err(|err| format!("db error: {err}"))?
+        .into_iter()
+        .chain(keep.iter().map(|path| path.to_string()))
+        .filter_map(|path| Path::new(&path).canonicalize().ok())
+        .filter(|path| path.starts_with(&store))
+        .collect();
+    let entries = fs::read_dir(&store).map_err(|err| format!("Attachment store error: {err}"))?;
+    let mut removed = 0;
+    let mut freed = 0;
+    for entry in entries.flatten() {
+        let is_file = entry
+            .file_type()
+            .map(|file_type| file_type.is_file())
+            .unwrap_or(false);
+        if !is_file {
+            continue;
+        }
+        let path = match entry.path().canonicalize() {
+            Ok(path) if path.starts_with(&store) => path,
+            _ => continue,
+        };
+        if referenced.contains(&path) {
+            continue;
+        }
+        let size = entry.metadata().map(|metadata| metadata.len()).unwrap_or(0);
+        match fs::remove_file(&path) {
+            Ok(()) => {
+                removed += 1;
+                freed += size;
+            }
+            Err(err) => log_warn!("attachment cleanup error ({}): {err}", path.display()),
+        }
     }
+    Ok((removed, freed))
+}
 
-    fn presence_for_user(&self, user: &str) -> PresenceStatus {
-        self.presence_state
-            .get(user)
-            .map(|state| state.status)
-            .unwrap_or(PresenceStatus::Unknown)
+/// The last component of `path`, with any invalid UTF-8 shown as U+FFFD rather than
+/// falling back to the whole path. Paths without a file name are returned as given.
+fn file_name_from_path(path: impl AsRef<Path>) -> String {
+    let path = path.as_ref();
+    path.file_name()
+        .unwrap_or(path.as_os_str())
+        .to_string_lossy()
+        .into_owned()
+}
+
+/// Glyph shown for an attachment of `kind` where there is no preview.
+fn attachment_kind_icon(kind: &str) -> &'static str {
+    match kind {
+        "image" => "🖼",
+        "document" => "📄",
+        _ => "📎",
     }
+}
 
-    fn channel_presence_counts(&self, channel_id: i64) -> (usize, usize) {
-        let members = match self.channel_members.get(&channel_id) {
-            Some(members) => members,
-            None => return (0, 0),
+/// Classifies by extension. Overrides from `RALPH_ATTACHMENT_KINDS` are checked first;
+/// the built-in table is the fallback.
+fn detect_attachment_kind(path: &Path, overrides: &HashMap<String, String>) -> String {
+    let extension = path
+        .extension()
+        .map(|ext| ext.to_string_lossy().to_ascii_lowercase())
+        .unwrap_or_default();
+    if let Some(kind) = overrides.get(&extension) {
+        return kind.clone();
+    }
+    match extension.as_str() {
+        "png" | "jpg" | "jpeg" | "gif" | "webp" => "image",
+        "pdf" | "txt" | "md" | "doc" | "docx" | "rtf" => "document",
+        _ => "file",
+    }
+    .to_string()
+}
+
+/// Parses `ext=kind` pairs separated by commas, e.g. `heic=image,log=document`.
+fn parse_attachment_kinds(value: &str) -> HashMap<String, String> {
+    let mut kinds = HashMap::new();
+    for pair in value.split(',').map(str::trim).filter(|pair| !pair.is_empty()) {
+        match pair.split_once('=') {
+            Some((extension, kind)) if !extension.trim().is_empty() && !kind.trim().is_empty() => {
+                kinds.insert(
+                    extension.trim().trim_start_matches('.').to_ascii_lowercase(),
+                    kind.trim().to_ascii_lowercase(),
+                );
+            }
+            _ => log_warn!("ignoring attachment kind mapping {pair:?}: expected ext=kind"),
+        }
+    }
+    kinds
+}
+
+/// Keeps the scale in range and on the 0.1 grid so repeated zoom steps don't drift.
+fn clamp_ui_scale(scale: f32) -> f32 {
+    ((scale * 10.0).round() / 10.0).clamp(UI_SCALE_MIN, UI_SCALE_MAX)
+}
+
+/// Parses `RALPH_WORKSPACES` entries of the form `name=db_path` or
+/// `name=db_path@ws://host:port`, separated by commas, after the built-in default.
+fn parse_workspaces(value: Option<&str>) -> Vec<Workspace> {
+    let mut workspaces = vec![Workspace {
+        name: "default".to_string(),
+        db_path: PathBuf::from(DEFAULT_DB_PATH),
+        server_url: None,
+    }];
+    let entries = value.unwrap_or_default().split(',').map(str::trim);
+    for entry in entries.filter(|entry| !entry.is_empty()) {
+        let Some((name, target)) = entry.split_once('=') else {
+            log_warn!("ignoring workspace {entry:?}: expected name=db_path");
+            continue;
         };
-        let total = members.len();
-        let online = members
-            .iter()
-            .filter(|member| self.presence_for_user(member) == PresenceStatus::Online)
-            .count();
-        (online, total)
+        let (name, target) = (name.trim(), target.trim());
+        let (db_path, server_url) = match target.split_once('@') {
+            Some((db_path, server_url)) => (db_path.trim(), Some(server_url.trim())),
+            None => (target, None),
+        };
+        if name.is_empty() || db_path.is_empty() {
+            log_warn!("ignoring workspace {entry:?}: expected name=db_path");
+            continue;
+        }
+        if workspaces.iter().any(|workspace| workspace.name == name) {
+            log_warn!("ignoring duplicate workspace {name:?}");
+            continue;
+        }
+        if let Some(server_url) = server_url {
+            if !Url::parse(server_url).is_ok_and(|url| matches!(url.scheme(), "ws" | "wss")) {
+                log_warn!("ignoring workspace {name:?}: server URL must be ws:// or wss://");
+                continue;
+            }
+        }
+        workspaces.push(Workspace {
+            name: name.to_string(),
+            db_path: PathBuf::from(db_path),
+            server_url: server_url.map(str::to_string),
+        });
     }
+    workspaces
+}
 
-    fn channel_presence_details(&self) -> Option<String> {
-        let channel = self
-            .channels
-            .iter()
-            .find(|channel| channel.id == self.selected_channel_id)?;
-        match channel.kind {
-            ChannelKind::DirectMessage => {
-                let state = self.presence_state.get(&channel.name);
-                let status = state
-                    .map(|state| state.status)
-                    .unwrap_or(PresenceStatus::Unknown);
-                let detail = if let Some(state) = state {
-                    format!(
-                        "@{} is {} (updated {}s ago)",
-                        channel.name,
-                        status.label(),
-                        state.last_seen.elapsed().as_secs()
-                    )
-                } else {
-                    format!("@{} status: {}", channel.name, status.label())
-                };
-                Some(detail)
+fn format_bytes(size: i64) -> String {
+    let size = size as f64;
+    let units = ["B", "KB", "MB", "GB"];
+    let mut value = size;
+    let mut idx = 0;
+    while value >= 1024.0 && idx < units.len() - 1 {
+        value /= 1024.0;
+        idx += 1;
+    }
+    format!("{value:.1}{}", units[idx])
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+enum RichSegmentStyle {
+    Normal,
+    Bold,
+    Italic,
+    Code,
+    Mention,
+    /// A `#name` reference to a known channel, with that channel's id.
+    ChannelLink(i64),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+struct RichSegment {
+    text: String,
+    style: RichSegmentStyle,
+}
+
+fn parse_rich_segments(body: &str) -> Vec<RichSegment> {
+    let chars: Vec<char> = body.chars().collect();
+    let mut segments = Vec::new();
+    let mut buffer = String::new();
+    let mut i = 0;
+
+    while i < chars.len() {
+        let ch = chars[i];
+        if ch == '`' {
+            if let Some(end) = chars[i + 1..].iter().position(|c| *c == '`') {
+                let end = i + 1 + end;
+                if end == i + 1 {
+                    buffer.push('`');
+                    i += 1;
+                    continue;
+                }
+                flush_rich_buffer(&mut buffer, &mut segments);
+                let text: String = chars[i + 1..end].iter().collect();
+                segments.push(RichSegment {
+                    text,
+                    style: RichSegmentStyle::Code,
+                });
+                i = end + 1;
+                continue;
+            }
+            buffer.push('`');
+            i += 1;
+            continue;
+        }
+
+        if ch == '*' && i + 1 < chars.len() && chars[i + 1] == '*' {
+            if let Some(end) = chars[i + 2..]
+                .windows(2)
+                .position(|pair| pair[0] == '*' && pair[1] == '*')
+            {
+                let end = i + 2 + end;
+                if end == i + 2 {
+                    buffer.push('*');
+                    buffer.push('*');
+                    i += 2;
+                    continue;
+                }
+                flush_rich_buffer(&mut buffer, &mut segments);
+                let text: String = chars[i + 2..end].iter().collect();
+                segments.push(RichSegment {
+                    text,
+                    style: RichSegmentStyle::Bold,
+                });
+                i = end + 2;
+                continue;
+            }
+            buffer.push('*');
+            buffer.push('*');
+            i += 2;
+            continue;
+        }
+
+        if ch == '*' || ch == '_' {
+            if let Some(end) = chars[i + 1..].iter().position(|c| *c == ch) {
+                let end = i + 1 + end;
+                if end == i + 1 {
+                    buffer.push(ch);
+                    i += 1;
+                    continue;
+                }
+                flush_rich_buffer(&mut buffer, &mut segments);
+                let text: String = chars[i + 1..end].iter().collect();
+                segments.push(RichSegment {
+                    text,
+                    style: RichSegmentStyle::Italic,
+                });
+                i = end + 1;
+                continue;
+            }
+            buffer.push(ch);
+            i += 1;
+            continue;
+        }
+
+        buffer.push(ch);
+        i += 1;
+    }
+
+    flush_rich_buffer(&mut buffer, &mut segments);
+    segments
+}
+
+/// Splits plain segments so `@username` tokens become `Mention` segments.
+fn split_mentions(segments: Vec<RichSegment>, username: &str) -> Vec<RichSegment> {
+    if username.is_empty() {
+        return segments;
+    }
+    let mut split = Vec::with_capacity(segments.len());
+    for segment in segments {
+        if segment.style != RichSegmentStyle::Normal {
+            split.push(segment);
+            continue;
+        }
+        let text = segment.text;
+        let mut plain_start = 0;
+        let mut cursor = 0;
+        while let Some(offset) = text[cursor..].find('@') {
+            let at = cursor + offset;
+            let end = at + 1 + username.len();
+            let matches = text
+                .get(at + 1..end)
+                .is_some_and(|candidate| candidate.eq_ignore_ascii_case(username))
+                && !text[..at]
+                    .chars()
+                    .next_back()
+                    .is_some_and(is_mention_char)
+                && !text[end..].chars().next().is_some_and(is_mention_char);
+            if !matches {
+                cursor = at + 1;
+                continue;
             }
-            ChannelKind::Channel => {
-                let (online, total) = self.channel_presence_counts(channel.id);
-                if total == 0 {
-                    Some("No member activity yet.".to_string())
-                } else {
-                    Some(format!("{online}/{total} members online"))
-                }
+            if plain_start < at {
+                split.push(RichSegment {
+                    text: text[plain_start..at].to_string(),
+                    style: RichSegmentStyle::Normal,
+                });
             }
+            split.push(RichSegment {
+                text: text[at..end].to_string(),
+                style: RichSegmentStyle::Mention,
+            });
+            plain_start = end;
+            cursor = end;
+        }
+        if plain_start < text.len() {
+            split.push(RichSegment {
+                text: text[plain_start..].to_string(),
+                style: RichSegmentStyle::Normal,
+            });
         }
     }
+    split
+}
+
+/// Checks a username typed into the new direct message field, returning it without a
+/// leading `@`. Usernames follow the same characters `@mentions` match.
+fn validate_dm_username(input: &str, identity: &Identity) -> Result<String, String> {
+    let username = input.trim().trim_start_matches('@');
+    if username.is_empty() {
+        return Err("Enter a username.".to_string());
+    }
+    if username.chars().count() > USERNAME_MAX_CHARS {
+        return Err(format!("Usernames are at most {USERNAME_MAX_CHARS} characters."));
+    }
+    if !username.chars().all(is_mention_char) {
+        return Err("Usernames can only use letters, numbers, _ and -.".to_string());
+    }
+    if identity.is_self(username) {
+        return Err("You can't start a direct message with yourself.".to_string());
+    }
+    Ok(username.to_string())
 }
 
-fn escape_like(input: &str) -> String {
-    input
-        .replace('\\', "\\\\")
-        .replace('%', "\\%")
-        .replace('_', "\\_")
+fn is_mention_char(ch: char) -> bool {
+    ch.is_alphanumeric() || ch == '_' || ch == '-'
 }
 
-fn search_messages(
-    conn: &Connection,
-    query: &str,
-    channel_id: Option<i64>,
-) -> Result<Vec<Message>, rusqlite::Error> {
-    let escaped = escape_like(query);
-    let pattern = format!("%{}%", escaped);
-    let mut messages = Vec::new();
-    if let Some(channel_id) = channel_id {
-        let mut stmt = conn.prepare(
-            "SELECT id, author, body, sent_at, channel_id
-            FROM messages
-            WHERE channel_id = ?1
-              AND (author LIKE ?2 ESCAPE '\\' OR body LIKE ?2 ESCAPE '\\')
-            ORDER BY id DESC
-            LIMIT 200",
-        )?;
-        let rows = stmt.query_map(params![channel_id, pattern], |row| {
-            Ok(Message {
-                id: row.get(0)?,
-                author: row.get(1)?,
-                body: row.get(2)?,
-                sent_at: row.get(3)?,
-                channel_id: row.get(4)?,
-            })
-        })?;
-        for message in rows {
-            messages.push(message?);
+/// Byte length of `word` at the start of `text`, compared case-insensitively, or
+/// `None` when `text` doesn't start with it.
+fn match_word_at(text: &str, word: &str) -> Option<usize> {
+    let mut chars = text.char_indices();
+    for expected in word.chars() {
+        let (_, actual) = chars.next()?;
+        if !actual.to_lowercase().eq(expected.to_lowercase()) {
+            return None;
         }
-    } else {
-        let mut stmt = conn.prepare(
-            "SELECT id, author, body, sent_at, channel_id
-            FROM messages
-            WHERE author LIKE ?1 ESCAPE '\\' OR body LIKE ?1 ESCAPE '\\'
-            ORDER BY id DESC
-            LIMIT 200",
-        )?;
-        let rows = stmt.query_map([pattern], |row| {
-            Ok(Message {
-                id: row.get(0)?,
-                author: row.get(1)?,
-                body: row.get(2)?,
-                sent_at: row.get(3)?,
-                channel_id: row.get(4)?,
-            })
-        })?;
-        for message in rows {
-            messages.push(message?);
+    }
+    Some(chars.next().map_or(text.len(), |(end, _)| end))
+}
+
+/// Splits plain segments so whole-word, case-insensitive matches of any highlight
+/// word become `Mention` segments. "deploy" matches "Deploy!" but not "redeploy".
+fn split_highlight_words(segments: Vec<RichSegment>, words: &[String]) -> Vec<RichSegment> {
+    if words.is_empty() {
+        return segments;
+    }
+    let mut split = Vec::with_capacity(segments.len());
+    for segment in segments {
+        if segment.style != RichSegmentStyle::Normal {
+            split.push(segment);
+            continue;
+        }
+        let text = segment.text;
+        let mut plain_start = 0;
+        let mut cursor = 0;
+        while cursor < text.len() {
+            let starts_word = !text[..cursor].chars().next_back().is_some_and(is_mention_char);
+            let end = starts_word
+                .then(|| {
+                    words.iter().find_map(|word| {
+                        let end = cursor + match_word_at(&text[cursor..], word)?;
+                        let ends_word = !text[end..].chars().next().is_some_and(is_mention_char);
+                        ends_word.then_some(end)
+                    })
+                })
+                .flatten();
+            let Some(end) = end else {
+                cursor += text[cursor..].chars().next().map_or(1, char::len_utf8);
+                continue;
+            };
+            if plain_start < cursor {
+                split.push(RichSegment {
+                    text: text[plain_start..cursor].to_string(),
+                    style: RichSegmentStyle::Normal,
+                });
+            }
+            split.push(RichSegment {
+                text: text[cursor..end].to_string(),
+                style: RichSegmentStyle::Mention,
+            });
+            plain_start = end;
+            cursor = end;
+        }
+        if plain_start < text.len() {
+            split.push(RichSegment {
+                text: text[plain_start..].to_string(),
+                style: RichSegmentStyle::Normal,
+            });
         }
     }
-    Ok(messages)
+    split
 }
 
-fn insert_attachments(
-    conn: &mut Connection,
-    message_id: i64,
-    attachments: &[PendingAttachment],
-) -> Result<(), rusqlite::Error> {
-    let tx = conn.transaction()?;
-    for attachment in attachments {
-        tx.execute(
-            "INSERT INTO attachments (message_id, file_path, file_name, file_size, kind)
-            VALUES (?1, ?2, ?3, ?4, ?5)",
-            params![
-                message_id,
-                attachment.file_path,
-                attachment.file_name,
-                attachment.file_size,
-                attachment.kind
-            ],
-        )?;
+/// Splits plain segments so `#name` tokens naming a known channel, in any case,
+/// become `ChannelLink` segments. Code spans and unknown names stay as they are.
+fn split_channel_links(segments: Vec<RichSegment>, channels: &[Channel]) -> Vec<RichSegment> {
+    let mut split = Vec::with_capacity(segments.len());
+    for segment in segments {
+        if segment.style != RichSegmentStyle::Normal || !segment.text.contains('#') {
+            split.push(segment);
+            continue;
+        }
+        let text = segment.text;
+        let mut plain_start = 0;
+        let mut cursor = 0;
+        while let Some(offset) = text[cursor..].find('#') {
+            let hash = cursor + offset;
+            let name_len = text[hash + 1..]
+                .find(|ch: char| !is_mention_char(ch))
+                .unwrap_or(text.len() - hash - 1);
+            let end = hash + 1 + name_len;
+            let channel = (name_len > 0
+                && !text[..hash].chars().next_back().is_some_and(is_mention_char))
+            .then(|| {
+                channels.iter().find(|channel| {
+                    channel.kind == ChannelKind::Channel
+                        && channel.name.eq_ignore_ascii_case(&text[hash + 1..end])
+                })
+            })
+            .flatten();
+            let Some(channel) = channel else {
+                cursor = hash + 1;
+                continue;
+            };
+            if plain_start < hash {
+                split.push(RichSegment {
+                    text: text[plain_start..hash].to_string(),
+                    style: RichSegmentStyle::Normal,
+                });
+            }
+            split.push(RichSegment {
+                text: text[hash..end].to_string(),
+                style: RichSegmentStyle::ChannelLink(channel.id),
+            });
+            plain_start = end;
+            cursor = end;
+        }
+        if plain_start < text.len() {
+            split.push(RichSegment {
+                text: text[plain_start..].to_string(),
+                style: RichSegmentStyle::Normal,
+            });
+        }
     }
-    tx.commit()?;
-    Ok(())
+    split
 }
 
-fn pending_to_realtime_attachments(
-    attachments: &[PendingAttachment],
-) -> Vec<RealtimeAttachment> {
-    attachments
-        .iter()
-        .map(|attachment| RealtimeAttachment {
-            file_path: attachment.file_path.clone(),
-            file_name: attachment.file_name.clone(),
-            file_size: attachment.file_size,
-            kind: attachment.kind.clone(),
-        })
-        .collect()
+/// A message body as rendered: markup, then `@username` mentions, then highlight words.
+fn message_segments(body: &str, username: &str, highlight_words: &[String]) -> Vec<RichSegment> {
+    split_highlight_words(split_mentions(parse_rich_segments(body), username), highlight_words)
 }
 
-fn realtime_to_pending_attachments(
-    attachments: &[RealtimeAttachment],
-) -> Vec<PendingAttachment> {
-    attachments
+/// Whether a message mentions you or contains one of your highlight words.
+fn mentions_user(body: &str, username: &str, highlight_words: &[String]) -> bool {
+    message_segments(body, username, highlight_words)
         .iter()
-        .map(|attachment| {
-            let file_name = if attachment.file_name.is_empty() {
-                file_name_from_path(&attachment.file_path)
-            } else {
-                attachment.file_name.clone()
-            };
-            PendingAttachment {
-                file_path: attachment.file_path.clone(),
-                file_name,
-                file_size: attachment.file_size,
-                kind: attachment.kind.clone(),
-            }
-        })
-        .collect()
+        .any(|segment| segment.style == RichSegmentStyle::Mention)
 }
 
-fn load_attachments_for_message_ids(
-    conn: &Connection,
-    message_ids: &[i64],
-) -> Result<HashMap<i64, Vec<Attachment>>, rusqlite::Error> {
-    if message_ids.is_empty() {
-        return Ok(HashMap::new());
-    }
-    let placeholders = std::iter::repeat("?")
-        .take(message_ids.len())
-        .collect::<Vec<_>>()
-        .join(",");
-    let query = format!(
-        "SELECT message_id, file_path, file_name, file_size, kind
-        FROM attachments
-        WHERE message_id IN ({placeholders})
-        ORDER BY id ASC"
-    );
-    let mut stmt = conn.prepare(&query)?;
-    let rows = stmt.query_map(params_from_iter(message_ids.iter().copied()), |row| {
-        Ok(Attachment {
-            message_id: row.get(0)?,
-            file_path: row.get(1)?,
-            file_name: row.get(2)?,
-            file_size: row.get(3)?,
-            kind: row.get(4)?,
-        })
-    })?;
-    let mut map: HashMap<i64, Vec<Attachment>> = HashMap::new();
-    for attachment in rows {
-        let attachment = attachment?;
-        map.entry(attachment.message_id)
-            .or_default()
-            .push(attachment);
+fn flush_rich_buffer(buffer: &mut String, segments: &mut Vec<RichSegment>) {
+    if !buffer.is_empty() {
+        segments.push(RichSegment {
+            text: std::mem::take(buffer),
+            style: RichSegmentStyle::Normal,
+        });
     }
-    Ok(map)
 }
 
-fn load_saved_message_ids(conn: &Connection) -> Result<HashSet<i64>, rusqlite::Error> {
-    let mut stmt = conn.prepare("SELECT message_id FROM saved_messages")?;
-    let rows = stmt.query_map([], |row| row.get::<_, i64>(0))?;
-    let mut saved = HashSet::new();
-    for row in rows {
-        saved.insert(row?);
+/// Returns the leading lines of a body longer than `max_lines`, or `None` when it fits
+/// (or `max_lines` is 0). The cut is always on a line boundary and backs up further
+/// while it would leave a `` ` `` or `**` span open. A cut inside a ```` ``` ```` code
+/// block closes the block, so a long snippet still previews as code.
+fn collapsed_preview(body: &str, max_lines: usize) -> Option<Cow<'_, str>> {
+    if max_lines == 0 {
+        return None;
+    }
+    let cut = body.match_indices('\n').nth(max_lines - 1)?.0;
+    if body[cut..].trim().is_empty() {
+        return None;
+    }
+    let mut preview = &body[..cut];
+    let fence_open = loop {
+        let (fence_open, span_open) = open_markup(preview);
+        if !span_open {
+            break fence_open;
+        }
+        match preview.rfind('\n') {
+            Some(previous) => preview = &preview[..previous],
+            None => return None,
+        }
+    };
+    let preview = preview.trim_end();
+    if preview.is_empty() {
+        return None;
     }
-    Ok(saved)
+    Some(if fence_open {
+        Cow::Owned(format!("{preview}\n```"))
+    } else {
+        Cow::Borrowed(preview)
+    })
 }
 
-fn load_pinned_message_ids(conn: &Connection) -> Result<HashSet<i64>, rusqlite::Error> {
-    let mut stmt = conn.prepare("SELECT message_id FROM pinned_messages")?;
-    let rows = stmt.query_map([], |row| row.get::<_, i64>(0))?;
-    let mut pinned = HashSet::new();
-    for row in rows {
-        pinned.insert(row?);
+/// Whether `text` ends inside a ```` ``` ```` code block, and whether it leaves a
+/// `` ` `` or `**` span open outside of code blocks.
+fn open_markup(text: &str) -> (bool, bool) {
+    let mut in_fence = false;
+    let mut ticks = 0;
+    let mut bold = 0;
+    for line in text.lines() {
+        if line.trim_start().starts_with("```") {
+            in_fence = !in_fence;
+        } else if !in_fence {
+            ticks += line.matches('`').count();
+            bold += line.matches("**").count();
+        }
     }
-    Ok(pinned)
+    (in_fence, ticks % 2 == 1 || bold % 2 == 1)
 }
 
-fn load_drafts(conn: &Connection) -> Result<HashMap<i64, String>, rusqlite::Error> {
-    let mut stmt = conn.prepare("SELECT channel_id, body FROM message_drafts")?;
-    let rows = stmt.query_map([], |row| Ok((row.get::<_, i64>(0)?, row.get::<_, String>(1)?)))?;
-    let mut drafts = HashMap::new();
-    for row in rows {
-        let (channel_id, body) = row?;
-        drafts.insert(channel_id, body);
-    }
-    Ok(drafts)
+/// Draws the shortcut sheet over a dimmed backdrop. Returns true when the backdrop was
+/// clicked and the sheet should close.
+fn render_shortcut_help(ctx: &egui::Context) -> bool {
+    let screen = ctx.screen_rect();
+    let backdrop = egui::Area::new(egui::Id::new("shortcut_help_backdrop"))
+        .order(egui::Order::Foreground)
+        .fixed_pos(screen.min)
+        .show(ctx, |ui| {
+            ui.painter()
+                .rect_filled(screen, 0.0, egui::Color32::from_black_alpha(140));
+            ui.allocate_response(screen.size(), egui::Sense::click())
+        })
+        .inner;
+    egui::Area::new(egui::Id::new("shortcut_help"))
+        .order(egui::Order::Tooltip)
+        .anchor(egui::Align2::CENTER_CENTER, egui::Vec2::ZERO)
+        .show(ctx, |ui| {
+            egui::Frame::popup(ui.style())
+                .inner_margin(12.0)
+                .show(ui, |ui| {
+                    ui.heading("Keyboard shortcuts");
+                    let listed = || {
+                        SHORTCUTS
+                            .iter()
+                            .filter(|entry| !matches!(entry.keys, ShortcutKeys::Unbound(_)))
+                    };
+                    let mut categories: Vec<&str> = Vec::new();
+                    for entry in listed() {
+                        if !categories.contains(&entry.category) {
+                            categories.push(entry.category);
+                        }
+                    }
+                    for category in categories {
+                        ui.add_space(8.0);
+                        ui.label(egui::RichText::new(category).strong());
+                        egui::Grid::new(("shortcut_help", category))
+                            .num_columns(2)
+                            .spacing([16.0, 4.0])
+                            .show(ui, |grid| {
+                                for entry in listed().filter(|entry| entry.category == category) {
+                                    let keys = match &entry.keys {
+                                        ShortcutKeys::Global { shortcut, .. } => {
+                                            ctx.format_shortcut(shortcut)
+                                        }
+                                        ShortcutKeys::InField(keys) => keys.to_string(),
+                                        ShortcutKeys::Unbound(_) => continue,
+                                    };
+                                    grid.label(egui::RichText::new(keys).monospace());
+                                    grid.label(entry.description);
+                                    grid.end_row();
+                                }
+                            });
+                    }
+                    ui.add_space(8.0);
+                    ui.label(
+                        egui::RichText::new("Esc or click outside to close.")
+                            .small()
+                            .color(egui::Color32::from_rgb(120, 130, 150)),
+                    );
+                });
+        });
+    backdrop.clicked()
 }
 
-fn save_draft(
-    conn: &Connection,
-    channel_id: i64,
-    body: &str,
-    updated_at: &str,
-) -> Result<(), rusqlite::Error> {
-    conn.execute(
-        "INSERT INTO message_drafts (channel_id, body, updated_at)
-        VALUES (?1, ?2, ?3)
-        ON CONFLICT(channel_id)
-        DO UPDATE SET body = excluded.body, updated_at = excluded.updated_at",
-        params![channel_id, body, updated_at],
-    )?;
-    Ok(())
-}
-
-fn delete_draft(conn: &Connection, channel_id: i64) -> Result<(), rusqlite::Error> {
-    conn.execute(
-        "DELETE FROM message_drafts WHERE channel_id = ?1",
-        params![channel_id],
-    )?;
-    Ok(())
-}
-
-fn save_message(
-    conn: &Connection,
-    message_id: i64,
-    saved_at: &str,
-) -> Result<(), rusqlite::Error> {
-    conn.execute(
-        "INSERT OR IGNORE INTO saved_messages (message_id, saved_at) VALUES (?1, ?2)",
-        params![message_id, saved_at],
-    )?;
-    Ok(())
+/// The runnable entries of `SHORTCUTS` that fuzzy-match `query`, best first. Each
+/// action appears once, under its first entry, so its first keybinding is the one
+/// shown.
+fn palette_entries(query: &str) -> Vec<(&'static Shortcut, ShortcutAction, Vec<usize>)> {
+    let mut seen: Vec<ShortcutAction> = Vec::new();
+    let mut ranked: Vec<(i32, &'static Shortcut, ShortcutAction, Vec<usize>)> = Vec::new();
+    for entry in SHORTCUTS {
+        let Some(action) = entry.keys.action() else {
+            continue;
+        };
+        if matches!(action, ShortcutAction::CloseOverlay | ShortcutAction::OpenCommandPalette)
+            || seen.contains(&action)
+        {
+            continue;
+        }
+        seen.push(action);
+        // Positions are byte offsets into the label drawn by `palette_label`.
+        let offset = entry.category.len() + 2;
+        let in_description = fuzzy_match(query, entry.description).map(|found| {
+            let positions = found.positions.iter().map(|pos| pos + offset).collect();
+            (found.score, positions)
+        });
+        let in_label = fuzzy_match(query, &palette_label(entry))
+            .map(|found| (found.score - 5, found.positions));
+        let best = match (in_description, in_label) {
+            (Some(description), Some(label)) if label.0 > description.0 => Some(label),
+            (Some(description), _) => Some(description),
+            (None, label) => label,
+        };
+        if let Some((score, positions)) = best {
+            ranked.push((score, entry, action, positions));
+        }
+    }
+    ranked.sort_by_key(|(score, _, _, _)| std::cmp::Reverse(*score));
+    ranked
+        .into_iter()
+        .map(|(_, entry, action, positions)| (entry, action, positions))
+        .collect()
 }
 
-fn remove_saved_message(conn: &Connection, message_id: i64) -> Result<(), rusqlite::Error> {
-    conn.execute(
-        "DELETE FROM saved_messages WHERE message_id = ?1",
-        params![message_id],
-    )?;
-    Ok(())
+fn palette_label(entry: &Shortcut) -> String {
+    format!("{}: {}", entry.category, entry.description)
 }
 
-fn pin_message(
-    conn: &Connection,
-    message_id: i64,
-    pinned_at: &str,
-    pinned_by: &str,
-) -> Result<(), rusqlite::Error> {
-    conn.execute(
-        "INSERT OR IGNORE INTO pinned_messages (message_id, pinned_at, pinned_by)
-        VALUES (?1, ?2, ?3)",
-        params![message_id, pinned_at, pinned_by],
-    )?;
-    Ok(())
-}
-
-fn remove_pinned_message(conn: &Connection, message_id: i64) -> Result<(), rusqlite::Error> {
-    conn.execute(
-        "DELETE FROM pinned_messages WHERE message_id = ?1",
-        params![message_id],
-    )?;
-    Ok(())
-}
-
-fn load_attachment_thumbnail_image(path: &str) -> Result<egui::ColorImage, String> {
-    let reader = ImageReader::open(path)
-        .map_err(|err| format!("file open: {err}"))?
-        .with_guessed_format()
-        .map_err(|err| format!("format error: {err}"))?;
-    let mut image = reader.decode().map_err(|err| format!("decode error: {err}"))?;
-    let max_dimension = 240u32;
-    let (width, height) = image.dimensions();
-    let max_axis = width.max(height);
-    if max_axis > max_dimension {
-        let scale = max_dimension as f32 / max_axis as f32;
-        let new_width = (width as f32 * scale).round().max(1.0) as u32;
-        let new_height = (height as f32 * scale).round().max(1.0) as u32;
-        image = image.resize(new_width, new_height, FilterType::Triangle);
+/// Draws the command palette over a dimmed backdrop. Arrow keys move the highlight
+/// and Enter runs it; clicking a row runs that one, clicking outside dismisses.
+fn render_command_palette(
+    ctx: &egui::Context,
+    palette: &mut CommandPalette,
+) -> Option<PaletteChoice> {
+    let entries = palette_entries(&palette.query);
+    let (down, up, enter) = ctx.input_mut(|input| {
+        (
+            input.consume_key(egui::Modifiers::NONE, egui::Key::ArrowDown),
+            input.consume_key(egui::Modifiers::NONE, egui::Key::ArrowUp),
+            input.consume_key(egui::Modifiers::NONE, egui::Key::Enter),
+        )
+    });
+    if down {
+        palette.selected += 1;
     }
-    let rgba = image.to_rgba8();
-    let size = [rgba.width() as usize, rgba.height() as usize];
-    let pixels = rgba.into_raw();
-    Ok(egui::ColorImage::from_rgba_unmultiplied(size, &pixels))
+    if up {
+        palette.selected = palette.selected.saturating_sub(1);
+    }
+    palette.selected = palette.selected.min(entries.len().saturating_sub(1));
+    let mut choice = None;
+    if enter {
+        choice = entries
+            .get(palette.selected)
+            .map(|(_, action, _)| PaletteChoice::Run(*action));
+    }
+    let screen = ctx.screen_rect();
+    let backdrop = egui::Area::new(egui::Id::new("command_palette_backdrop"))
+        .order(egui::Order::Foreground)
+        .fixed_pos(screen.min)
+        .show(ctx, |ui| {
+            ui.painter()
+                .rect_filled(screen, 0.0, egui::Color32::from_black_alpha(140));
+            ui.allocate_response(screen.size(), egui::Sense::click())
+        })
+        .inner;
+    egui::Area::new(egui::Id::new("command_palette"))
+        .order(egui::Order::Tooltip)
+        .anchor(egui::Align2::CENTER_TOP, egui::vec2(0.0, 80.0))
+        .show(ctx, |ui| {
+            egui::Frame::popup(ui.style())
+                .inner_margin(12.0)
+                .show(ui, |ui| {
+                    ui.set_width(360.0);
+                    let field = ui.add(
+                        egui::TextEdit::singleline(&mut palette.query)
+                            .hint_text("Type a command")
+                            .desired_width(f32::INFINITY),
+                    );
+                    if !field.has_focus() {
+                        field.request_focus();
+                    }
+                    if field.changed() {
+                        palette.selected = 0;
+                    }
+                    ui.add_space(6.0);
+                    if entries.is_empty() {
+                        ui.label(
+                            egui::RichText::new("No matching commands.")
+                                .small()
+                                .color(egui::Color32::from_rgb(120, 130, 150)),
+                        );
+                    }
+                    for (index, (entry, action, matched)) in entries.iter().enumerate() {
+                        ui.horizontal(|row| {
+                            let label = palette_label(entry);
+                            let label = highlight_matches(row.style(), "", &label, matched, None);
+                            if row
+                                .selectable_label(index == palette.selected, label)
+                                .clicked()
+                            {
+                                choice = Some(PaletteChoice::Run(*action));
+                            }
+                            if let ShortcutKeys::Global { shortcut, .. } = &entry.keys {
+                                row.with_layout(
+                                    egui::Layout::right_to_left(egui::Align::Center),
+                                    |row| {
+                                        row.label(
+                                            egui::RichText::new(ctx.format_shortcut(shortcut))
+                                                .monospace()
+                                                .small()
+                                                .color(egui::Color32::from_rgb(120, 130, 150)),
+                                        );
+                                    },
+                                );
+                            }
+                        });
+                    }
+                });
+        });
+    if backdrop.clicked() {
+        choice = Some(PaletteChoice::Dismiss);
+    }
+    choice
 }
 
-fn open_attachment(path: &str) -> Result<(), String> {
-    open_attachment_with_args(path, &[])
+/// Shortens `text` to `max_chars` visible characters, the last being "…". Combining
+/// marks, variation selectors, skin tones, flag pairs, and zero-width-joined emoji
+/// count with the character they modify, so a cut never splits a visible symbol.
+fn ellipsize(text: &str, max_chars: usize) -> Cow<'_, str> {
+    let mut clusters = 0;
+    let mut keep_end = 0;
+    let mut previous: Option<char> = None;
+    let mut regional_run = 0;
+    for (index, c) in text.char_indices() {
+        let regional = ('\u{1F1E6}'..='\u{1F1FF}').contains(&c);
+        let joins = previous.is_some_and(|previous| {
+            previous == '\u{200D}'
+                || (regional && regional_run % 2 == 1)
+                || matches!(
+                    c,
+                    '\u{0300}'..='\u{036F}'
+                        | '\u{1AB0}'..='\u{1AFF}'
+                        | '\u{1DC0}'..='\u{1DFF}'
+                        | '\u{200D}'
+                        | '\u{20D0}'..='\u{20FF}'
+                        | '\u{FE00}'..='\u{FE0F}'
+                        | '\u{FE20}'..='\u{FE2F}'
+                        | '\u{1F3FB}'..='\u{1F3FF}'
+                        | '\u{E0020}'..='\u{E007F}'
+                )
+        });
+        regional_run = if regional { regional_run + 1 } else { 0 };
+        previous = Some(c);
+        if joins {
+            continue;
+        }
+        if clusters + 1 == max_chars {
+            keep_end = index;
+        }
+        if clusters == max_chars {
+            return Cow::Owned(format!("{}…", text[..keep_end].trim_end()));
+        }
+        clusters += 1;
+    }
+    Cow::Borrowed(text)
 }
 
-fn reveal_attachment(path: &str) -> Result<(), String> {
-    #[cfg(target_os = "macos")]
-    {
-        open_attachment_with_args(path, &["-R"])
+/// Shows `full` on hover when `shown` is an `ellipsize`d copy of it.
+fn hover_full_text(response: egui::Response, shown: &str, full: &str) -> egui::Response {
+    if shown == full {
+        response
+    } else {
+        response.on_hover_text(full)
     }
-    #[cfg(not(target_os = "macos"))]
-    {
-        let parent = Path::new(path)
-            .parent()
-            .ok_or_else(|| "Attachment path has no parent directory.".to_string())?;
-        open_attachment_with_args(parent.to_str().unwrap_or_default(), &[])
+}
+
+/// Asks what to do when another running app holds the workspace database: `true` to
+/// open it read-only, `false` to exit.
+fn render_instance_conflict(ctx: &egui::Context, pid: u32, db_path: &Path) -> Option<bool> {
+    let screen = ctx.screen_rect();
+    egui::Area::new(egui::Id::new("instance_conflict_backdrop"))
+        .order(egui::Order::Foreground)
+        .fixed_pos(screen.min)
+        .show(ctx, |ui| {
+            ui.painter()
+                .rect_filled(screen, 0.0, egui::Color32::from_black_alpha(180));
+            ui.allocate_response(screen.size(), egui::Sense::click())
+        });
+    let mut choice = None;
+    egui::Area::new(egui::Id::new("instance_conflict"))
+        .order(egui::Order::Tooltip)
+        .anchor(egui::Align2::CENTER_CENTER, egui::Vec2::ZERO)
+        .show(ctx, |ui| {
+            egui::Frame::popup(ui.style())
+                .inner_margin(12.0)
+                .show(ui, |ui| {
+                    ui.set_max_width(420.0);
+                    ui.label(egui::RichText::new("Ralph is already open").strong());
+                    ui.add_space(4.0);
+                    ui.label(format!(
+                        "Another Ralph window (process {pid}) is using {}. Two windows \
+                         writing to it can fail with \"database is locked\" errors.",
+                        db_path.display()
+                    ));
+                    ui.add_space(8.0);
+                    ui.horizontal(|row| {
+                        if row.button("Open read-only").clicked() {
+                            choice = Some(true);
+                        }
+                        if row.button("Exit").clicked() {
+                            choice = Some(false);
+                        }
+                    });
+                });
+        });
+    choice
+}
+
+/// Draws the lightbox image over a dimmed backdrop, scaled to fit the window, with
+/// its file name and actions. Clicking the backdrop closes it.
+fn render_lightbox(ctx: &egui::Context, lightbox: &Lightbox) -> Option<LightboxAction> {
+    let screen = ctx.screen_rect();
+    let mut action = None;
+    let backdrop = egui::Area::new(egui::Id::new("lightbox_backdrop"))
+        .order(egui::Order::Foreground)
+        .fixed_pos(screen.min)
+        .show(ctx, |ui| {
+            ui.painter()
+                .rect_filled(screen, 0.0, egui::Color32::from_black_alpha(180));
+            ui.allocate_response(screen.size(), egui::Sense::click())
+        })
+        .inner;
+    if backdrop.clicked() {
+        action = Some(LightboxAction::Close);
     }
+    egui::Area::new(egui::Id::new("lightbox"))
+        .order(egui::Order::Tooltip)
+        .anchor(egui::Align2::CENTER_CENTER, egui::Vec2::ZERO)
+        .show(ctx, |ui| {
+            egui::Frame::popup(ui.style())
+                .inner_margin(12.0)
+                .show(ui, |ui| {
+                    let max_size = screen.size() * 0.8;
+                    match (&lightbox.texture, &lightbox.error) {
+                        (Some(texture), _) => {
+                            ui.add(
+                                egui::Image::from_texture(egui::load::SizedTexture::from_handle(
+                                    texture,
+                                ))
+                                .max_size(max_size),
+                            );
+                        }
+                        (None, Some(error)) => {
+                            ui.label(
+                                egui::RichText::new(format!("Image unavailable: {error}"))
+                                    .color(egui::Color32::from_rgb(220, 120, 120)),
+                            );
+                        }
+                        (None, None) => {
+                            ui.label(
+                                egui::RichText::new("Loading image...")
+                                    .color(egui::Color32::from_rgb(130, 140, 160)),
+                            );
+                        }
+                    }
+                    ui.add_space(6.0);
+                    ui.horizontal(|row| {
+                        row.label(
+                            egui::RichText::new(&lightbox.attachment.file_name)
+                                .color(egui::Color32::from_rgb(190, 200, 215)),
+                        );
+                        if row.button("Jump to message").clicked() {
+                            action = Some(LightboxAction::JumpToMessage);
+                        }
+                        if row.button("Open").clicked() {
+                            action = Some(LightboxAction::Open);
+                        }
+                        if row.button("Close").clicked() {
+                            action = Some(LightboxAction::Close);
+                        }
+                    });
+                });
+        });
+    action
 }
 
-fn open_attachment_with_args(path: &str, extra_args: &[&str]) -> Result<(), String> {
-    let path_ref = Path::new(path);
-    if !path_ref.exists() {
-        return Err("Attachment path does not exist.".to_string());
+/// A searchable grid of `EMOJI_SET`, with `recent` emoji listed first while the search
+/// is empty. Returns the emoji clicked this frame. The search text lives in egui's
+/// memory under the current id, so each popup keeps its own.
+fn emoji_picker(ui: &mut egui::Ui, recent: &[String]) -> Option<String> {
+    let query_id = ui.id().with("emoji_query");
+    let mut query: String = ui.data_mut(|data| data.get_temp(query_id).unwrap_or_default());
+    let search = ui.add(
+        egui::TextEdit::singleline(&mut query)
+            .hint_text("Search emoji")
+            .desired_width(220.0),
+    );
+    if search.changed() {
+        ui.data_mut(|data| data.insert_temp(query_id, query.clone()));
     }
-    let mut command = if cfg!(target_os = "macos") {
-        let mut cmd = Command::new("open");
-        cmd.args(extra_args);
-        cmd
-    } else if cfg!(target_os = "windows") {
-        Command::new("explorer")
-    } else {
-        Command::new("xdg-open")
-    };
-    if cfg!(target_os = "windows") && extra_args.iter().any(|arg| *arg == "-R") {
-        command.arg(format!("/select,{}", path_ref.display()));
-    } else {
-        command.arg(path_ref);
+    let needle = query.trim().trim_matches(':').to_lowercase();
+    let mut chosen = None;
+    if needle.is_empty() && !recent.is_empty() {
+        ui.label(
+            egui::RichText::new("Recent")
+                .small()
+                .color(egui::Color32::from_rgb(120, 130, 150)),
+        );
+        ui.horizontal_wrapped(|row| {
+            for emoji in recent {
+                if row.button(emoji.as_str()).clicked() {
+                    chosen = Some(emoji.clone());
+                }
+            }
+        });
     }
-    command
-        .status()
-        .map_err(|err| format!("Failed to launch attachment: {err}"))
-        .and_then(|status| {
-            if status.success() {
-                Ok(())
-            } else {
-                Err("Attachment launcher exited with failure.".to_string())
+    let matches: Vec<&(&str, &str)> = EMOJI_SET
+        .iter()
+        .filter(|(_, name)| needle.is_empty() || name.contains(&needle))
+        .collect();
+    if matches.is_empty() {
+        ui.label(
+            egui::RichText::new("No matching emoji")
+                .small()
+                .color(egui::Color32::from_rgb(120, 130, 150)),
+        );
+    }
+    egui::ScrollArea::vertical().max_height(180.0).show(ui, |ui| {
+        egui::Grid::new(ui.id().with("emoji_grid")).show(ui, |grid| {
+            for (index, (emoji, name)) in matches.iter().enumerate() {
+                if grid.button(*emoji).on_hover_text(format!(":{name}:")).clicked() {
+                    chosen = Some(emoji.to_string());
+                }
+                if index % 8 == 7 {
+                    grid.end_row();
+                }
+            }
+        });
+    });
+    if chosen.is_some() {
+        ui.data_mut(|data| data.remove::<String>(query_id));
+    }
+    chosen
+}
+
+/// Replaces each completed `:shortcode:` from `EMOJI_SET` in `text` with its emoji.
+/// Returns `None` when nothing matched, so callers only touch the draft on a change.
+fn expand_shortcodes(text: &str) -> Option<String> {
+    let mut expanded = String::with_capacity(text.len());
+    let mut rest = text;
+    let mut changed = false;
+    while let Some(start) = rest.find(':') {
+        let after = &rest[start + 1..];
+        let emoji = after.find(':').and_then(|end| {
+            let name = &after[..end];
+            EMOJI_SET
+                .iter()
+                .find(|(_, code)| *code == name)
+                .map(|(emoji, _)| (*emoji, end))
+        });
+        match emoji {
+            Some((emoji, end)) => {
+                expanded.push_str(&rest[..start]);
+                expanded.push_str(emoji);
+                rest = &after[end + 1..];
+                changed = true;
             }
-        })
+            None => {
+                expanded.push_str(&rest[..start + 1]);
+                rest = after;
+            }
+        }
+    }
+    expanded.push_str(rest);
+    changed.then_some(expanded)
 }
 
-fn ingest_attachment(path: &str) -> Result<PendingAttachment, String> {
-    let metadata = fs::metadata(path).map_err(|err| format!("File error: {err}"))?;
-    let file_name = file_name_from_path(path);
-    let file_size = metadata.len() as i64;
-    let kind = detect_attachment_kind(path).to_string();
-    Ok(PendingAttachment {
-        file_path: path.to_string(),
-        file_name,
-        file_size,
-        kind,
-    })
+/// Moves `emoji` to the front of `recent`, keeping at most `RECENT_EMOJI_LIMIT`.
+fn push_recent_emoji(recent: &mut Vec<String>, emoji: &str) {
+    recent.retain(|existing| existing != emoji);
+    recent.insert(0, emoji.to_string());
+    recent.truncate(RECENT_EMOJI_LIMIT);
 }
 
-fn file_name_from_path(path: &str) -> String {
-    std::path::Path::new(path)
-        .file_name()
-        .and_then(|name| name.to_str())
-        .unwrap_or(path)
-        .to_string()
+/// Fades the bottom of a collapsed message into the panel background.
+fn paint_bottom_fade(ui: &egui::Ui, rect: egui::Rect) {
+    const BANDS: usize = 6;
+    let height = rect.height().min(24.0);
+    let fill = ui.visuals().panel_fill;
+    for band in 0..BANDS {
+        let top = rect.bottom() - height * (1.0 - band as f32 / BANDS as f32);
+        let bottom = rect.bottom() - height * (1.0 - (band + 1) as f32 / BANDS as f32);
+        let alpha = (220.0 * (band + 1) as f32 / BANDS as f32) as u8;
+        ui.painter().rect_filled(
+            egui::Rect::from_x_y_ranges(rect.x_range(), top..=bottom),
+            0.0,
+            egui::Color32::from_rgba_unmultiplied(fill.r(), fill.g(), fill.b(), alpha),
+        );
+    }
 }
 
-fn detect_attachment_kind(path: &str) -> &'static str {
-    let extension = std::path::Path::new(path)
-        .extension()
-        .and_then(|ext| ext.to_str())
-        .unwrap_or("")
-        .to_ascii_lowercase();
-    match extension.as_str() {
-        "png" | "jpg" | "jpeg" | "gif" | "webp" => "image",
-        "pdf" | "txt" | "md" | "doc" | "docx" | "rtf" => "document",
-        _ => "file",
+/// Byte ranges of ASCII-case-insensitive, non-overlapping occurrences of `term`.
+fn find_match_ranges(text: &str, term: &str) -> Vec<std::ops::Range<usize>> {
+    if term.is_empty() {
+        return Vec::new();
     }
+    let haystack = text.to_ascii_lowercase();
+    let needle = term.to_ascii_lowercase();
+    haystack
+        .match_indices(&needle)
+        .map(|(start, matched)| start..start + matched.len())
+        .collect()
 }
 
-fn format_bytes(size: i64) -> String {
-    let size = size as f64;
-    let units = ["B", "KB", "MB", "GB"];
-    let mut value = size;
-    let mut idx = 0;
-    while value >= 1024.0 && idx < units.len() - 1 {
-        value /= 1024.0;
-        idx += 1;
+/// Counts find-in-view matches in the rendered text, so the ordinals line up with the
+/// highlights `render_message_body` draws.
+fn count_find_matches(
+    body: &str,
+    username: &str,
+    highlight_words: &[String],
+    term: &str,
+) -> usize {
+    message_segments(body, username, highlight_words)
+        .iter()
+        .map(|segment| find_match_ranges(&segment.text, term).len())
+        .sum()
+}
+
+/// Shows `user`'s presence in the configured style. The shape and the word each carry
+/// a label for screen readers and a hover text, so neither relies on color.
+fn presence_indicator(ui: &mut egui::Ui, status: PresenceStatus, style: PresenceStyle, user: &str) {
+    let description = format!("{user} is {}", status.label());
+    if style != PresenceStyle::Label {
+        let size = egui::Vec2::splat(ui.text_style_height(&egui::TextStyle::Small));
+        let (rect, response) = ui.allocate_exact_size(size, egui::Sense::hover());
+        status.paint_shape(ui.painter(), rect.center(), size.y * 0.35);
+        response.widget_info(|| egui::WidgetInfo::labeled(egui::WidgetType::Label, &description));
+        response.on_hover_text(&description);
+    }
+    if style != PresenceStyle::Shape {
+        ui.label(
+            egui::RichText::new(status.label())
+                .small()
+                .color(status.color()),
+        )
+        .on_hover_text(&description);
     }
-    format!("{value:.1}{}", units[idx])
 }
 
-#[derive(Copy, Clone, Debug, PartialEq)]
-enum RichSegmentStyle {
-    Normal,
-    Bold,
-    Italic,
-    Code,
+/// The sidebar badge for a channel with an unseen mention or highlight word.
+fn highlight_marker(ui: &mut egui::Ui) {
+    ui.label(
+        egui::RichText::new("@")
+            .small()
+            .strong()
+            .color(egui::Color32::from_rgb(250, 225, 160))
+            .background_color(egui::Color32::from_rgb(90, 70, 30)),
+    )
+    .on_hover_text("Mentioned you or used a highlight word");
 }
 
-#[derive(Clone, Debug, PartialEq)]
-struct RichSegment {
-    text: String,
-    style: RichSegmentStyle,
+fn styled_segment_text(text: &str, style: RichSegmentStyle) -> egui::RichText {
+    let text = egui::RichText::new(text);
+    match style {
+        RichSegmentStyle::Normal => text,
+        RichSegmentStyle::Bold => text.strong(),
+        RichSegmentStyle::Italic => text.italics(),
+        RichSegmentStyle::Code => text.monospace(),
+        RichSegmentStyle::Mention => text
+            .strong()
+            .color(egui::Color32::from_rgb(250, 225, 160))
+            .background_color(egui::Color32::from_rgb(90, 70, 30)),
+        RichSegmentStyle::ChannelLink(_) => text,
+    }
 }
 
-fn parse_rich_segments(body: &str) -> Vec<RichSegment> {
-    let chars: Vec<char> = body.chars().collect();
-    let mut segments = Vec::new();
-    let mut buffer = String::new();
-    let mut i = 0;
+/// Whether these input events include IME composition: one being started, updated,
+/// or committed.
+fn has_ime_composition_event(events: &[egui::Event]) -> bool {
+    events.iter().any(|event| {
+        matches!(
+            event,
+            egui::Event::CompositionStart
+                | egui::Event::CompositionUpdate(_)
+                | egui::Event::CompositionEnd(_)
+        )
+    })
+}
 
-    while i < chars.len() {
-        let ch = chars[i];
-        if ch == '`' {
-            if let Some(end) = chars[i + 1..].iter().position(|c| *c == '`') {
-                let end = i + 1 + end;
-                if end == i + 1 {
-                    buffer.push('`');
-                    i += 1;
-                    continue;
-                }
-                flush_rich_buffer(&mut buffer, &mut segments);
-                let text: String = chars[i + 1..end].iter().collect();
-                segments.push(RichSegment {
-                    text,
-                    style: RichSegmentStyle::Code,
-                });
-                i = end + 1;
-                continue;
-            }
-            buffer.push('`');
-            i += 1;
-            continue;
-        }
+/// Renders a message body and returns the channel whose `#name` link was clicked.
+/// `find` carries the find-in-view term and, when the current match is in this
+/// message, its ordinal within the message.
+fn render_message_body(
+    ui: &mut egui::Ui,
+    body: &str,
+    username: &str,
+    highlight_words: &[String],
+    channels: &[Channel],
+    find: Option<(&str, Option<usize>)>,
+) -> Option<i64> {
+    let segments = split_channel_links(message_segments(body, username, highlight_words), channels);
+    if segments.is_empty() {
+        ui.label(body);
+        return None;
+    }
 
-        if ch == '*' && i + 1 < chars.len() && chars[i + 1] == '*' {
-            if let Some(end) = chars[i + 2..]
-                .windows(2)
-                .position(|pair| pair[0] == '*' && pair[1] == '*')
-            {
-                let end = i + 2 + end;
-                if end == i + 2 {
-                    buffer.push('*');
-                    buffer.push('*');
-                    i += 2;
-                    continue;
-                }
-                flush_rich_buffer(&mut buffer, &mut segments);
-                let text: String = chars[i + 2..end].iter().collect();
-                segments.push(RichSegment {
-                    text,
-                    style: RichSegmentStyle::Bold,
-                });
-                i = end + 2;
-                continue;
+    let mut clicked = None;
+    let mut show = |ui: &mut egui::Ui, text: egui::RichText, style: RichSegmentStyle| {
+        if let RichSegmentStyle::ChannelLink(channel_id) = style {
+            if ui.link(text).on_hover_text("Open this channel").clicked() {
+                clicked = Some(channel_id);
             }
-            buffer.push('*');
-            buffer.push('*');
-            i += 2;
-            continue;
+        } else {
+            ui.label(text);
         }
-
-        if ch == '*' || ch == '_' {
-            if let Some(end) = chars[i + 1..].iter().position(|c| *c == ch) {
-                let end = i + 1 + end;
-                if end == i + 1 {
-                    buffer.push(ch);
-                    i += 1;
-                    continue;
-                }
-                flush_rich_buffer(&mut buffer, &mut segments);
-                let text: String = chars[i + 1..end].iter().collect();
-                segments.push(RichSegment {
-                    text,
-                    style: RichSegmentStyle::Italic,
-                });
-                i = end + 1;
-                continue;
+    };
+    let mut ordinal = 0;
+    for segment in segments {
+        let ranges = match find {
+            Some((term, _)) => find_match_ranges(&segment.text, term),
+            None => Vec::new(),
+        };
+        let mut cursor = 0;
+        for range in ranges {
+            if cursor < range.start {
+                let text = styled_segment_text(&segment.text[cursor..range.start], segment.style);
+                show(ui, text, segment.style);
             }
-            buffer.push(ch);
-            i += 1;
-            continue;
+            let current = find.and_then(|(_, current)| current) == Some(ordinal);
+            let text = styled_segment_text(&segment.text[range.clone()], segment.style)
+                .color(egui::Color32::BLACK)
+                .background_color(if current {
+                    egui::Color32::from_rgb(255, 160, 60)
+                } else {
+                    egui::Color32::from_rgb(200, 190, 90)
+                });
+            show(ui, text, segment.style);
+            ordinal += 1;
+            cursor = range.end;
+        }
+        if cursor < segment.text.len() {
+            show(ui, styled_segment_text(&segment.text[cursor..], segment.style), segment.style);
         }
-
-        buffer.push(ch);
-        i += 1;
     }
-
-    flush_rich_buffer(&mut buffer, &mut segments);
-    segments
+    clicked
 }
 
-fn flush_rich_buffer(buffer: &mut String, segments: &mut Vec<RichSegment>) {
-    if !buffer.is_empty() {
-        segments.push(RichSegment {
-            text: std::mem::take(buffer),
-            style: RichSegmentStyle::Normal,
-        });
+fn wal_checkpoint_summary(checkpoint: &WalCheckpoint, db_path: &Path) -> String {
+    match checkpoint {
+        WalCheckpoint::NotWal => format!(
+            "{} doesn't use a write-ahead log; every write is already in the file.",
+            db_path.display()
+        ),
+        WalCheckpoint::Busy => format!(
+            "{} is busy; some writes are still in its log. Try again in a moment.",
+            db_path.display()
+        ),
+        WalCheckpoint::Done => {
+            format!("Flushed {}; it's safe to copy on its own.", db_path.display())
+        }
     }
 }
 
-fn render_message_body(ui: &mut egui::Ui, body: &str) {
-    let segments = parse_rich_segments(body);
-    if segments.is_empty() {
-        ui.label(body);
-        return;
-    }
-
-    for segment in segments {
-        let mut text = egui::RichText::new(segment.text);
-        match segment.style {
-            RichSegmentStyle::Normal => {}
-            RichSegmentStyle::Bold => {
-                text = text.strong();
-            }
-            RichSegmentStyle::Italic => {
-                text = text.italics();
-            }
-            RichSegmentStyle::Code => {
-                text = text.monospace();
+/// `ralph flush [--workspace <name>]`: checkpoints a workspace database from the
+/// command line, for backup scripts.
+fn run_flush(args: &[String]) -> i32 {
+    let config = AppConfig::from_env();
+    let workspace = match args {
+        [] => &config.workspaces[0],
+        [flag, name] if flag == "--workspace" => {
+            match config.workspaces.iter().find(|workspace| workspace.name == *name) {
+                Some(workspace) => workspace,
+                None => {
+                    eprintln!("no workspace named {name:?}; see RALPH_WORKSPACES");
+                    return 1;
+                }
             }
         }
-        ui.label(text);
+        _ => {
+            eprintln!("usage: ralph flush [--workspace <name>]");
+            return 1;
+        }
+    };
+    let db_path = &workspace.db_path;
+    let flushed = Connection::open_with_flags(db_path, OpenFlags::SQLITE_OPEN_READ_WRITE)
+        .and_then(|conn| checkpoint_wal(&conn));
+    match flushed {
+        Ok(checkpoint) => {
+            println!("{}", wal_checkpoint_summary(&checkpoint, db_path));
+            i32::from(matches!(checkpoint, WalCheckpoint::Busy))
+        }
+        Err(err) => {
+            eprintln!("could not flush {}: {err}", db_path.display());
+            1
+        }
     }
 }
 
 fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    match args.first().map(String::as_str) {
+        Some("send") => std::process::exit(send::run(&args[1..])),
+        Some("flush") => std::process::exit(run_flush(&args[1..])),
+        _ => {}
+    }
     let boot_started = Instant::now();
-    println!("ralph: booting");
-    let exit_after_first_frame = env::var("RALPH_STARTUP_BENCH").is_ok();
+    log_info!("booting");
+    let startup_bench = env::var("RALPH_STARTUP_BENCH").is_ok();
 
     let event_loop = EventLoopBuilder::<UserEvent>::with_user_event()
         .build()
         .expect("event loop");
     let event_proxy = event_loop.create_proxy();
-    let mut app = App::new(&event_loop, event_proxy, boot_started, exit_after_first_frame);
+    let mut app_config = AppConfig::from_env();
+    let demo_requested = args.iter().any(|arg| arg == "--demo");
+    let no_seed = env_string("RALPH_NO_SEED")
+        .is_some_and(|value| value != "0" && !value.eq_ignore_ascii_case("false"));
+    if demo_requested && no_seed {
+        log_warn!("RALPH_NO_SEED is set; ignoring --demo");
+    }
+    app_config.demo_mode = demo_requested && !no_seed;
+    let mut app = App::new(
+        &event_loop,
+        event_proxy,
+        boot_started,
+        startup_bench,
+        app_config,
+    );
 
     let _ = event_loop.run(move |event, elwt| match event {
         Event::UserEvent(UserEvent::Wake) => {
@@ -3429,3 +11997,446 @@ fn main() {
         _ => {}
     });
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A fresh directory under the system temp dir, unique to this process and test.
+    fn scratch_dir(name: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("ralph-test-{}-{name}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    /// A client wired to channels the test drives in place of the worker thread.
+    fn detached_client() -> (
+        RealtimeClient,
+        mpsc::Receiver<RealtimeCommand>,
+        mpsc::Sender<RealtimeEvent>,
+    ) {
+        let identity = Identity {
+            username: "you".to_string(),
+            display_name: "you".to_string(),
+            token: "test".to_string(),
+        };
+        let mut client =
+            RealtimeClient::new(DEFAULT_SERVER_URL.to_string(), identity, None, None, false);
+        let (cmd_tx, cmd_rx) = mpsc::channel();
+        let (evt_tx, evt_rx) = mpsc::channel();
+        client.cmd_tx = Some(cmd_tx);
+        client.evt_rx = Some(evt_rx);
+        (client, cmd_rx, evt_tx)
+    }
+
+    fn worker_event(status: RealtimeStatus) -> RealtimeEvent {
+        RealtimeEvent {
+            status,
+            message: None,
+            error: None,
+            inbound: None,
+            presence: None,
+            acked: None,
+            metrics: ConnectionMetrics::default(),
+        }
+    }
+
+    fn test_message(id: i64, channel_id: i64, body: &str) -> Message {
+        Message {
+            id,
+            author: "you".to_string(),
+            body: body.to_string(),
+            sent_at: "2024-05-01T12:00:00Z".to_string(),
+            channel_id,
+            edited_at: None,
+        }
+    }
+
+    /// The `(channel_id, seq)` of every message handed to the worker so far.
+    fn sent_sequences(cmd_rx: &mpsc::Receiver<RealtimeCommand>) -> Vec<(i64, u64)> {
+        cmd_rx
+            .try_iter()
+            .filter_map(|command| match command {
+                RealtimeCommand::SendMessage {
+                    channel_id, seq, ..
+                } => Some((channel_id, seq)),
+                _ => None,
+            })
+            .collect()
+    }
+
+    fn expire_ack_deadlines(client: &mut RealtimeClient) {
+        for awaiting in client.awaiting_ack.values_mut() {
+            awaiting.deadline = Instant::now();
+        }
+    }
+
+    #[test]
+    fn missing_ack_is_resent_once_then_failed() {
+        let (mut client, cmd_rx, evt_tx) = detached_client();
+        evt_tx.send(worker_event(RealtimeStatus::Connected)).unwrap();
+        client.poll();
+        client.send_message(&test_message(1, 1, "acked"), Vec::new());
+        client.send_message(&test_message(2, 1, "lost"), Vec::new());
+        assert_eq!(sent_sequences(&cmd_rx), [(1, 0), (1, 1)]);
+        let mut ack = worker_event(RealtimeStatus::Connected);
+        ack.acked = Some(AckedMessage::Confirmed(1, 0));
+        evt_tx.send(ack).unwrap();
+        client.poll();
+
+        expire_ack_deadlines(&mut client);
+        client.poll();
+        assert_eq!(sent_sequences(&cmd_rx), [(1, 1)]);
+        assert!(client.take_failed_sends().is_empty());
+
+        expire_ack_deadlines(&mut client);
+        client.poll();
+        assert_eq!(client.take_failed_sends(), [2]);
+        assert!(client.awaiting_ack.is_empty());
+
+        // The retry goes out under the original seq, so receivers can drop a repeat.
+        client.send_message(&test_message(2, 1, "lost"), Vec::new());
+        assert_eq!(sent_sequences(&cmd_rx), [(1, 1)]);
+    }
+
+    #[test]
+    fn acks_without_echo_never_time_out() {
+        let (mut client, cmd_rx, evt_tx) = detached_client();
+        evt_tx.send(worker_event(RealtimeStatus::Connected)).unwrap();
+        client.poll();
+        client.send_message(&test_message(1, 1, "first"), Vec::new());
+        client.send_message(&test_message(2, 1, "second"), Vec::new());
+        expire_ack_deadlines(&mut client);
+        client.poll();
+        assert_eq!(sent_sequences(&cmd_rx).len(), 2);
+        assert_eq!(client.ack_due_in(), None);
+
+        let mut ack = worker_event(RealtimeStatus::Connected);
+        ack.acked = Some(AckedMessage::Unconfirmed);
+        evt_tx.send(ack).unwrap();
+        client.poll();
+        let waiting: Vec<i64> = client.awaiting_ack.values().map(|a| a.message.id).collect();
+        assert_eq!(waiting, [2]);
+        assert!(client.take_failed_sends().is_empty());
+    }
+
+    #[test]
+    fn messages_queued_offline_go_out_in_order_on_reconnect() {
+        let (mut client, cmd_rx, evt_tx) = detached_client();
+        for (id, body) in [(1, "one"), (2, "two"), (3, "three")] {
+            let outcome = client.send_message(&test_message(id, 1, body), Vec::new());
+            assert!(outcome == SendOutcome::NotConnected);
+        }
+        assert!(cmd_rx.try_recv().is_err());
+
+        evt_tx.send(worker_event(RealtimeStatus::Connected)).unwrap();
+        client.poll();
+        let sent: Vec<(String, u64)> = cmd_rx
+            .try_iter()
+            .filter_map(|command| match command {
+                RealtimeCommand::SendMessage { body, seq, .. } => Some((body, seq)),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(
+            sent,
+            [("one".to_string(), 0), ("two".to_string(), 1), ("three".to_string(), 2)]
+        );
+        assert!(!client.is_queued(1) && !client.is_queued(3));
+    }
+
+    #[test]
+    fn received_sends_are_restored_to_sequence_order() {
+        let (mut client, _cmd_rx, evt_tx) = detached_client();
+        for (seq, body) in [(2, "three"), (0, "one"), (1, "two")] {
+            let mut event = worker_event(RealtimeStatus::Connected);
+            event.inbound = Some(IncomingMessage {
+                message: test_message(0, 1, body),
+                attachments: Vec::new(),
+                client_id: Some("mara-1".to_string()),
+                seq: Some(seq),
+            });
+            evt_tx.send(event).unwrap();
+        }
+        client.poll();
+        let bodies: Vec<String> = client
+            .take_incoming()
+            .into_iter()
+            .map(|incoming| incoming.message.body)
+            .collect();
+        assert_eq!(bodies, ["one", "two", "three"]);
+    }
+
+    #[test]
+    fn repeated_sequences_are_shown_once() {
+        let (mut client, _cmd_rx, evt_tx) = detached_client();
+        for (client_id, seq) in [("mara-1", 0), ("mara-1", 0), ("mara-1", 1), ("devin-1", 0)] {
+            let mut event = worker_event(RealtimeStatus::Connected);
+            event.inbound = Some(IncomingMessage {
+                message: test_message(0, 1, "hi"),
+                attachments: Vec::new(),
+                client_id: Some(client_id.to_string()),
+                seq: Some(seq),
+            });
+            evt_tx.send(event).unwrap();
+        }
+        client.poll();
+        let received: Vec<(Option<String>, Option<u64>)> = client
+            .take_incoming()
+            .into_iter()
+            .map(|incoming| (incoming.client_id, incoming.seq))
+            .collect();
+        assert_eq!(
+            received,
+            [
+                (Some("mara-1".to_string()), Some(0)),
+                (Some("mara-1".to_string()), Some(1)),
+                (Some("devin-1".to_string()), Some(0)),
+            ]
+        );
+    }
+
+    #[test]
+    fn acks_decode_with_and_without_confirms() {
+        let echoed = r#"{"type":"ack","kind":"message","detail":"stored",
+            "client_id":"you-1","channel_id":2,"seq":7}"#;
+        let Ok(RealtimeInbound::Ack { kind, confirms, .. }) = decode_realtime_inbound(echoed)
+        else {
+            panic!("expected an ack");
+        };
+        assert_eq!(kind, "message");
+        assert_eq!(confirms, Some(("you-1".to_string(), 2, 7)));
+
+        let plain = r#"{"type":"ack","kind":"message","detail":"stored"}"#;
+        let Ok(RealtimeInbound::Ack { confirms, .. }) = decode_realtime_inbound(plain) else {
+            panic!("expected an ack");
+        };
+        assert_eq!(confirms, None);
+    }
+
+    #[test]
+    fn refused_handshake_keeps_queued_messages() {
+        let (mut client, cmd_rx, evt_tx) = detached_client();
+        evt_tx.send(worker_event(RealtimeStatus::Connecting)).unwrap();
+        client.poll();
+        let outcome = client.send_message(&test_message(1, 1, "held"), Vec::new());
+        assert!(outcome == SendOutcome::Queued);
+
+        let refusal = r#"{"type":"auth_failed","reason":"too old","protocol_version":2}"#;
+        let Ok(RealtimeInbound::AuthFailed(error)) = decode_realtime_inbound(refusal) else {
+            panic!("auth_failed should decode as a refusal");
+        };
+        assert!(error.starts_with("Incompatible server version"), "{error}");
+        let mut closed = worker_event(RealtimeStatus::Disconnected);
+        closed.error = Some(error.clone());
+        evt_tx.send(closed).unwrap();
+        client.poll();
+
+        assert!(client.is_queued(1));
+        assert!(sent_sequences(&cmd_rx).is_empty());
+        assert_eq!(client.last_error, Some(error));
+    }
+
+    #[test]
+    fn message_order_is_total_across_timestamp_formats() {
+        let messages = [
+            test_message(1, 1, "late"),
+            Message {
+                sent_at: "09:15".to_string(),
+                ..test_message(2, 1, "legacy")
+            },
+            Message {
+                sent_at: "2024-05-01T08:00:00Z".to_string(),
+                ..test_message(3, 1, "early")
+            },
+            Message {
+                sent_at: "09:14".to_string(),
+                ..test_message(4, 1, "legacy later id")
+            },
+        ];
+        let expected = [2, 4, 3, 1];
+        for start in 0..messages.len() {
+            let mut rotated = messages.to_vec();
+            rotated.rotate_left(start);
+            let mut sorted = rotated.clone();
+            sorted.sort_by(compare_message_order);
+            let ids: Vec<i64> = sorted.iter().map(|message| message.id).collect();
+            assert_eq!(ids, expected);
+
+            let mut inserted = Vec::new();
+            for message in rotated {
+                insert_message_sorted(&mut inserted, message);
+            }
+            let ids: Vec<i64> = inserted.iter().map(|message| message.id).collect();
+            assert_eq!(ids, expected);
+        }
+    }
+
+    #[test]
+    fn store_cleanup_keeps_pending_attachments() {
+        let store = scratch_dir("store-cleanup");
+        let pending = store.join("pending.txt");
+        let orphan = store.join("orphan.txt");
+        fs::write(&pending, b"draft").unwrap();
+        fs::write(&orphan, b"gone").unwrap();
+        let conn = Connection::open_in_memory().unwrap();
+        ensure_schema(&conn).unwrap();
+
+        let pending_path = pending.to_string_lossy().into_owned();
+        let keep = HashSet::from([pending_path.as_str()]);
+        let (removed, freed) = cleanup_attachment_store(&conn, &store, &keep).unwrap();
+
+        assert_eq!((removed, freed), (1, 4));
+        assert!(pending.exists());
+        assert!(!orphan.exists());
+        fs::remove_dir_all(&store).unwrap();
+    }
+
+    #[test]
+    fn fuzzy_match_prefers_prefixes_over_scattered_hits() {
+        let prefix = fuzzy_match("gen", "general").unwrap();
+        assert_eq!(prefix.positions, vec![0, 1, 2]);
+        let scattered = fuzzy_match("gen", "design-engineering").unwrap();
+        assert!(prefix.score > scattered.score);
+        let boundary = fuzzy_match("de", "ops-deploys").unwrap();
+        assert_eq!(boundary.positions, vec![4, 5]);
+        assert!(fuzzy_match("lg", "general").is_none());
+        assert_eq!(fuzzy_match("  ", "general").unwrap().score, 0);
+    }
+
+    #[test]
+    fn fuzzy_score_ranks_matches_and_rejects_non_subsequences() {
+        let prefix = fuzzy_score("gen", "general").unwrap();
+        assert!(prefix > fuzzy_score("gen", "design-engineering").unwrap());
+        assert_eq!(fuzzy_score("gen", "general"), fuzzy_match("gen", "general").map(|m| m.score));
+        assert_eq!(fuzzy_score("lg", "general"), None);
+        assert_eq!(fuzzy_score("", "general"), Some(0));
+    }
+
+    #[test]
+    fn fuzzy_match_ignores_case() {
+        let lower = fuzzy_match("rnd", "Random").unwrap();
+        let upper = fuzzy_match("RND", "random").unwrap();
+        assert_eq!(lower, upper);
+        assert_eq!(lower.positions, vec![0, 2, 3]);
+    }
+
+    #[test]
+    fn fuzzy_match_reports_byte_offsets_for_non_ascii_names() {
+        let found = fuzzy_match("çé", "Français-équipe").unwrap();
+        assert_eq!(found.positions, vec![4, 10]);
+        assert!(found.positions.iter().all(|&pos| "Français-équipe".is_char_boundary(pos)));
+        let found = fuzzy_match("ök", "ÖKONOMIE").unwrap();
+        assert_eq!(found.positions, vec![0, 2]);
+    }
+
+    #[test]
+    fn ingest_hashes_once_and_duplicates_reuse_that_hash() {
+        let dir = scratch_dir("ingest-duplicates");
+        let original = dir.join("report.txt");
+        let copy = dir.join("report copy.txt");
+        fs::write(&original, "quarterly numbers").unwrap();
+        fs::write(&copy, "quarterly numbers").unwrap();
+        let kinds = HashMap::new();
+        let first = ingest_attachment(&original, None, &kinds).unwrap();
+        let second = ingest_attachment(&copy, None, &kinds).unwrap();
+        assert_eq!(first.file_hash, second.file_hash);
+        assert!(first.file_hash.is_some());
+
+        let pending = vec![first.clone()];
+        let same_path = duplicate_attachment_reason(&first.file_path, &first, &pending, []);
+        assert_eq!(same_path.unwrap(), "report.txt is already attached to this message.");
+        let same_contents = duplicate_attachment_reason(&second.file_path, &second, &pending, []);
+        assert_eq!(
+            same_contents.unwrap(),
+            "report copy.txt matches report.txt, which is already attached to this message."
+        );
+        let shared = Attachment {
+            message_id: 1,
+            file_path: first.file_path.clone(),
+            file_name: "old-report.txt".to_string(),
+            file_size: first.file_size,
+            kind: first.kind.clone(),
+            file_hash: first.file_hash.clone(),
+        };
+        let in_channel = duplicate_attachment_reason(&second.file_path, &second, &[], [&shared]);
+        assert_eq!(
+            in_channel.unwrap(),
+            "report copy.txt matches old-report.txt, which was already shared in this channel."
+        );
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn repeats_collapse_within_seconds_not_by_minute() {
+        let at = |sent_at: &str| Message {
+            sent_at: sent_at.to_string(),
+            ..test_message(1, 1, "lunch?")
+        };
+        let across_minute = at("2024-05-01T12:00:59Z");
+        assert!(is_repeat_message(&across_minute, &at("2024-05-01T12:01:00Z")));
+        let same_minute = at("2024-05-01T12:00:00Z");
+        assert!(!is_repeat_message(&same_minute, &at("2024-05-01T12:00:59Z")));
+        assert!(is_repeat_message(&at("12:00"), &at("12:00")));
+        assert!(!is_repeat_message(&at("12:00"), &at("2024-05-01T12:00:00Z")));
+        let other_body = Message {
+            body: "dinner?".to_string(),
+            ..at("2024-05-01T12:00:01Z")
+        };
+        assert!(!is_repeat_message(&same_minute, &other_body));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn non_utf8_file_names_are_shown_lossily_and_read_by_their_real_path() {
+        use std::os::unix::ffi::OsStrExt;
+
+        let dir = scratch_dir("non-utf8-names");
+        let store = dir.join("store");
+        let source = dir.join(std::ffi::OsStr::from_bytes(b"caf\xe9.txt"));
+        fs::write(&source, "menu").unwrap();
+        assert_eq!(file_name_from_path(&source), "caf\u{fffd}.txt");
+
+        let kinds = HashMap::new();
+        let stored = ingest_attachment(&source, Some(&store), &kinds).unwrap();
+        assert_eq!(stored.file_name, "caf\u{fffd}.txt");
+        assert!(Path::new(&stored.file_path).starts_with(&store));
+        assert_eq!(fs::read_to_string(&stored.file_path).unwrap(), "menu");
+        assert_eq!(stored.file_hash.unwrap(), hash_file(&source).unwrap());
+
+        let Err(in_place) = ingest_attachment(&source, None, &kinds) else {
+            panic!("a non-UTF-8 path was recorded in place");
+        };
+        assert!(in_place.contains("isn't valid UTF-8"));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn utc_offsets_parse_and_reject_non_digits_without_panicking() {
+        let offset = |value| parse_utc_offset(value).map(|offset| offset.local_minus_utc());
+        assert_eq!(offset("UTC"), Some(0));
+        assert_eq!(offset("+05:30"), Some(5 * 3600 + 30 * 60));
+        assert_eq!(offset("-0800"), Some(-8 * 3600));
+        assert_eq!(offset("+9"), Some(9 * 3600));
+        assert_eq!(offset("+1é1"), None);
+        assert_eq!(offset("+é1"), None);
+        assert_eq!(offset("+-5"), None);
+        assert_eq!(offset("+15"), None);
+    }
+
+    #[test]
+    fn collapsed_previews_close_a_cut_code_block() {
+        let body = "```\nlet a = 1;\nlet b = '`';\nlet c = 3;\n```\ndone";
+        assert_eq!(
+            collapsed_preview(body, 3).as_deref(),
+            Some("```\nlet a = 1;\nlet b = '`';\n```")
+        );
+        let closed = "```\nx\n```\nafter\nmore";
+        assert_eq!(collapsed_preview(closed, 4).as_deref(), Some("```\nx\n```\nafter"));
+        let spans = "one `code\nstill` two\nthree\nfour";
+        assert_eq!(collapsed_preview(spans, 1), None);
+        assert_eq!(collapsed_preview(spans, 2).as_deref(), Some("one `code\nstill` two"));
+        assert_eq!(collapsed_preview("short", 3), None);
+    }
+}
@@ -0,0 +1,148 @@
+//! The chat data the app shows and the database stores: messages, channels, users,
+//! and attachments, plus the order messages are displayed in.
+
+use chrono::{DateTime, Utc};
+
+#[derive(Clone)]
+pub struct Message {
+    pub id: i64,
+    pub author: String,
+    pub body: String,
+    pub sent_at: String,
+    pub channel_id: i64,
+    /// When the body was last changed after posting; `None` for untouched messages.
+    pub edited_at: Option<String>,
+}
+
+#[derive(Clone)]
+pub struct MessageReaction {
+    pub message_id: i64,
+    pub emoji: String,
+    pub author: String,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum ChannelKind {
+    Channel,
+    DirectMessage,
+}
+
+impl ChannelKind {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ChannelKind::Channel => "channel",
+            ChannelKind::DirectMessage => "dm",
+        }
+    }
+
+    /// The kind stored as `value`; anything unrecognized reads as a channel.
+    pub fn parse(value: &str) -> Self {
+        match value {
+            "channel" => ChannelKind::Channel,
+            "dm" => ChannelKind::DirectMessage,
+            _ => ChannelKind::Channel,
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct Channel {
+    pub id: i64,
+    pub name: String,
+    pub kind: ChannelKind,
+    pub topic: String,
+    /// Hidden from the main sidebar lists; messages and membership are untouched.
+    pub archived: bool,
+}
+
+/// Per-channel preferences kept in the `channel_state` table.
+#[derive(Clone, Default)]
+pub struct ChannelState {
+    /// Replaces the generated "Message #name" composer hint when set.
+    pub composer_placeholder: Option<String>,
+    /// Banner shown above the channel's messages until it is cleared.
+    pub announcement: Option<String>,
+    /// When the announcement was last set; a new value brings back a collapsed banner.
+    pub announcement_set_at: Option<String>,
+    /// The `announcement_set_at` the banner was collapsed at, if it was.
+    pub announcement_collapsed_at: Option<String>,
+    /// Ask "you're posting in #name" before each send, for channels where a misfire
+    /// would hurt.
+    pub confirm_send: bool,
+    /// Posting is turned off, as in announcement-only channels; reading, search, and
+    /// reactions still work.
+    pub read_only: bool,
+    /// Messages older than this many days are deleted; `None` keeps them forever.
+    pub retention_days: Option<u32>,
+}
+
+impl ChannelState {
+    /// The announcement text and whether it is shown collapsed.
+    pub fn active_announcement(&self) -> Option<(&str, bool)> {
+        let text = self.announcement.as_deref()?;
+        let collapsed = self.announcement_collapsed_at.is_some()
+            && self.announcement_collapsed_at == self.announcement_set_at;
+        Some((text, collapsed))
+    }
+}
+
+/// Someone seen as a message author or in presence, as stored in the `users` table.
+#[derive(Clone)]
+pub struct User {
+    pub username: String,
+    pub display_name: Option<String>,
+    /// `#rrggbb`; without one, a color is picked from the username.
+    pub color: Option<String>,
+    pub avatar_path: Option<String>,
+}
+
+/// One row of the opt-in presence history: someone went to `status` at `changed_at`.
+pub struct PresenceChange {
+    pub username: String,
+    pub status: String,
+    pub changed_at: String,
+}
+
+/// A message you saved, as listed in the saved messages view.
+pub struct SavedMessage {
+    pub message: Message,
+    pub saved_at: String,
+}
+
+#[derive(Clone)]
+pub struct Attachment {
+    pub message_id: i64,
+    pub file_path: String,
+    pub file_name: String,
+    pub file_size: i64,
+    pub kind: String,
+    pub file_hash: Option<String>,
+}
+
+#[derive(Clone)]
+pub struct PendingAttachment {
+    pub file_path: String,
+    pub file_name: String,
+    pub file_size: i64,
+    pub kind: String,
+    pub file_hash: Option<String>,
+}
+
+/// An RFC 3339 `sent_at` or `edited_at` in UTC; `None` for legacy `HH:MM` stamps.
+pub fn parse_timestamp(ts: &str) -> Option<DateTime<Utc>> {
+    DateTime::parse_from_rfc3339(ts)
+        .ok()
+        .map(|instant| instant.with_timezone(&Utc))
+}
+
+/// Display order for messages: by `sent_at`, then by id when timestamps collide.
+/// Rows whose `sent_at` doesn't parse, like legacy `HH:MM` stamps, predate full
+/// timestamps and come first, by id; keeping them in one block makes this a total
+/// order, which sorting and `insert_message_sorted` rely on.
+pub fn compare_message_order(a: &Message, b: &Message) -> std::cmp::Ordering {
+    let key = |message: &Message| {
+        let sent = parse_timestamp(&message.sent_at);
+        (sent.is_some(), sent, message.id)
+    };
+    key(a).cmp(&key(b))
+}
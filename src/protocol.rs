@@ -0,0 +1,187 @@
+//! The JSON frames exchanged over the realtime WebSocket. Both the client and the
+//! mock server build on these definitions, so a field added here reaches both ends.
+
+use serde::{Deserialize, Serialize};
+
+/// Version of these frames a client speaks, sent in `Auth`. Bump it with any change
+/// an older peer would misread.
+pub const PROTOCOL_VERSION: u32 = 1;
+/// Oldest client version a server built from this tree still accepts. Clients from
+/// before versioning read as 0; their frames are the same as version 1's, so they are
+/// still welcome until the wire format actually changes.
+pub const MIN_PROTOCOL_VERSION: u32 = 0;
+
+/// Whether a server built from this tree can talk to a client on `version`.
+pub fn is_supported_protocol(version: u32) -> bool {
+    (MIN_PROTOCOL_VERSION..=PROTOCOL_VERSION).contains(&version)
+}
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum RealtimePayload {
+    Message {
+        author: String,
+        body: String,
+        sent_at: String,
+        channel_id: i64,
+        client_id: Option<String>,
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        attachments: Vec<RealtimeAttachment>,
+        /// Position among the sender's messages in this channel, counted per `client_id`.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        seq: Option<u64>,
+    },
+    Auth {
+        token: String,
+        user: String,
+        /// Clients from before versioning send none, which reads as 0.
+        #[serde(default)]
+        protocol_version: u32,
+        /// The presence the server announces for `user` on joining, so an invisible
+        /// client never shows as online. Clients that send none join as "online".
+        #[serde(default = "online_status")]
+        status: String,
+    },
+    /// The server refused an `Auth` and is closing the connection.
+    AuthFailed {
+        reason: String,
+        /// The newest protocol version the server speaks.
+        protocol_version: u32,
+    },
+    Ack {
+        kind: String,
+        detail: String,
+        /// For a message ack, the `client_id`, channel and `seq` of the message it confirms.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        client_id: Option<String>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        channel_id: Option<i64>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        seq: Option<u64>,
+    },
+    Presence {
+        user: String,
+        status: String,
+    },
+}
+
+fn online_status() -> String {
+    "online".to_string()
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct RealtimeAttachment {
+    pub file_path: String,
+    pub file_name: String,
+    pub file_size: i64,
+    pub kind: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub file_hash: Option<String>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn round_trip(payload: RealtimePayload) {
+        let text = serde_json::to_string(&payload).unwrap();
+        let decoded: RealtimePayload = serde_json::from_str(&text).unwrap();
+        assert_eq!(decoded, payload, "{text}");
+    }
+
+    #[test]
+    fn every_variant_round_trips() {
+        round_trip(RealtimePayload::Message {
+            author: "mara".to_string(),
+            body: "see attached".to_string(),
+            sent_at: "2024-05-01T12:00:00Z".to_string(),
+            channel_id: 2,
+            client_id: Some("mara-1".to_string()),
+            attachments: vec![RealtimeAttachment {
+                file_path: "/tmp/a.png".to_string(),
+                file_name: "a.png".to_string(),
+                file_size: 42,
+                kind: "image".to_string(),
+                file_hash: Some("fnv1a64:0123456789abcdef".to_string()),
+            }],
+            seq: Some(7),
+        });
+        round_trip(RealtimePayload::Auth {
+            token: "t".to_string(),
+            user: "mara".to_string(),
+            protocol_version: PROTOCOL_VERSION,
+            status: "offline".to_string(),
+        });
+        round_trip(RealtimePayload::AuthFailed {
+            reason: "too old".to_string(),
+            protocol_version: PROTOCOL_VERSION,
+        });
+        round_trip(RealtimePayload::Ack {
+            kind: "message".to_string(),
+            detail: "stored".to_string(),
+            client_id: Some("mara-1".to_string()),
+            channel_id: Some(2),
+            seq: Some(7),
+        });
+        round_trip(RealtimePayload::Presence {
+            user: "mara".to_string(),
+            status: "away".to_string(),
+        });
+    }
+
+    #[test]
+    fn optional_fields_are_left_out_and_default_when_missing() {
+        let plain = RealtimePayload::Message {
+            author: "mara".to_string(),
+            body: "hi".to_string(),
+            sent_at: "09:12".to_string(),
+            channel_id: 1,
+            client_id: None,
+            attachments: Vec::new(),
+            seq: None,
+        };
+        let text = serde_json::to_string(&plain).unwrap();
+        assert!(!text.contains("attachments") && !text.contains("seq"), "{text}");
+        round_trip(plain);
+
+        let ack: RealtimePayload =
+            serde_json::from_str(r#"{"type":"ack","kind":"message","detail":"stored"}"#).unwrap();
+        assert_eq!(
+            ack,
+            RealtimePayload::Ack {
+                kind: "message".to_string(),
+                detail: "stored".to_string(),
+                client_id: None,
+                channel_id: None,
+                seq: None,
+            }
+        );
+        let text = serde_json::to_string(&ack).unwrap();
+        assert!(!text.contains("client_id") && !text.contains("seq"), "{text}");
+
+        let attachment: RealtimeAttachment = serde_json::from_str(
+            r#"{"file_path":"/tmp/a","file_name":"a","file_size":1,"kind":"file"}"#,
+        )
+        .unwrap();
+        assert_eq!(attachment.file_hash, None);
+    }
+
+    #[test]
+    fn unversioned_clients_are_still_accepted() {
+        let auth: RealtimePayload =
+            serde_json::from_str(r#"{"type":"auth","token":"t","user":"mara"}"#).unwrap();
+        let RealtimePayload::Auth {
+            protocol_version,
+            status,
+            ..
+        } = auth
+        else {
+            panic!("expected an auth frame");
+        };
+        assert_eq!(protocol_version, 0);
+        assert_eq!(status, "online");
+        assert!(is_supported_protocol(protocol_version));
+        assert!(is_supported_protocol(PROTOCOL_VERSION));
+        assert!(!is_supported_protocol(PROTOCOL_VERSION + 1));
+    }
+}
@@ -0,0 +1,340 @@
+//! `ralph send`: posts a message from the command line without opening a window,
+//! for scripts that share build artifacts or screenshots. The message is stored in
+//! the workspace database and, when the server answers, broadcast over realtime.
+
+use std::{
+    io::{self, BufRead, IsTerminal, Read},
+    path::PathBuf,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use rusqlite::Connection;
+use tungstenite::{client::connect_with_config, stream::MaybeTlsStream, Message as WsMessage};
+use url::Url;
+
+use ralph::db::{
+    ensure_schema, insert_message_from_client, load_channel_state, load_channels,
+    load_settings, schema_version, SCHEMA_VERSION,
+};
+use crate::{
+    decode_realtime_inbound, encode_realtime_message, format_timestamp_utc, ingest_attachment,
+    pending_to_realtime_attachments, realtime_socket_config, AppConfig, PresenceStatus,
+    RealtimeInbound, RealtimePayload, ACK_TIMEOUT, PROTOCOL_VERSION,
+};
+use ralph::model::{Message, PendingAttachment};
+
+const USAGE: &str = "usage: ralph send --channel <id> [--attach <path>]... [--workspace <name>] \
+                     [message...]
+
+The message is read from standard input when none is given. `--attach -` reads
+attachment paths from standard input instead, one per line.";
+
+struct SendArgs {
+    channel_id: i64,
+    attach: Vec<PathBuf>,
+    attach_from_stdin: bool,
+    workspace: Option<String>,
+    body: Vec<String>,
+}
+
+fn parse_args(args: &[String]) -> Result<SendArgs, String> {
+    let mut channel_id = None;
+    let mut parsed = SendArgs {
+        channel_id: 0,
+        attach: Vec::new(),
+        attach_from_stdin: false,
+        workspace: None,
+        body: Vec::new(),
+    };
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let mut value = |flag: &str| {
+            args.next()
+                .cloned()
+                .ok_or_else(|| format!("{flag} needs a value"))
+        };
+        match arg.as_str() {
+            "--channel" => {
+                let value = value("--channel")?;
+                channel_id = Some(
+                    value
+                        .parse::<i64>()
+                        .map_err(|_| format!("--channel expects a channel id, got {value:?}"))?,
+                );
+            }
+            "--attach" => match value("--attach")?.as_str() {
+                "-" => parsed.attach_from_stdin = true,
+                path => parsed.attach.push(PathBuf::from(path)),
+            },
+            "--workspace" => parsed.workspace = Some(value("--workspace")?),
+            "-h" | "--help" => return Err(USAGE.to_string()),
+            "--" => parsed.body.extend(args.by_ref().cloned()),
+            flag if flag.starts_with("--") => return Err(format!("unknown option {flag}")),
+            word => parsed.body.push(word.to_string()),
+        }
+    }
+    parsed.channel_id = channel_id.ok_or("--channel is required")?;
+    Ok(parsed)
+}
+
+/// Runs the subcommand with the arguments after `send` and returns the exit code:
+/// 0 once the message is stored, even if it couldn't be broadcast; 1 when nothing
+/// was posted, including when any attachment couldn't be read.
+pub(crate) fn run(args: &[String]) -> i32 {
+    let args = match parse_args(args) {
+        Ok(args) => args,
+        Err(err) => {
+            eprintln!("{err}");
+            if err != USAGE {
+                eprintln!("{USAGE}");
+            }
+            return 1;
+        }
+    };
+    let mut config = AppConfig::from_env();
+    let workspace = match &args.workspace {
+        None => config.workspaces[0].clone(),
+        Some(name) => match config.workspaces.iter().find(|workspace| workspace.name == *name) {
+            Some(workspace) => workspace.clone(),
+            None => {
+                eprintln!("no workspace named {name:?}; see RALPH_WORKSPACES");
+                return 1;
+            }
+        },
+    };
+
+    let mut attach = args.attach;
+    let stdin = io::stdin();
+    if args.attach_from_stdin {
+        for line in stdin.lock().lines() {
+            match line {
+                Ok(line) if !line.trim().is_empty() => attach.push(PathBuf::from(line.trim())),
+                Ok(_) => {}
+                Err(err) => {
+                    eprintln!("could not read attachment paths: {err}");
+                    return 1;
+                }
+            }
+        }
+    }
+    let mut body = args.body.join(" ");
+    if body.is_empty() && !args.attach_from_stdin && !stdin.is_terminal() {
+        if let Err(err) = stdin.lock().read_to_string(&mut body) {
+            eprintln!("could not read message: {err}");
+            return 1;
+        }
+    }
+    let body = body.trim_end().to_string();
+    if body.is_empty() && attach.is_empty() {
+        eprintln!("nothing to send: give a message or at least one --attach");
+        return 1;
+    }
+
+    // The app may be writing to the same file; wait for it rather than failing.
+    let db = Connection::open(&workspace.db_path)
+        .and_then(|db| db.busy_timeout(Duration::from_secs(5)).map(|()| db));
+    let mut db = match db {
+        Ok(db) => db,
+        Err(err) => {
+            eprintln!("could not open {}: {err}", workspace.db_path.display());
+            return 1;
+        }
+    };
+    match schema_version(&db) {
+        Ok(version) if version > SCHEMA_VERSION => {
+            eprintln!(
+                "{} was written by a newer version of Ralph (schema {version}, this build \
+                 knows up to {SCHEMA_VERSION}); upgrade Ralph to post to it",
+                workspace.db_path.display()
+            );
+            return 1;
+        }
+        Ok(_) => {}
+        Err(err) => {
+            eprintln!("could not read {}: {err}", workspace.db_path.display());
+            return 1;
+        }
+    }
+    if let Err(err) = ensure_schema(&db) {
+        eprintln!("could not prepare {}: {err}", workspace.db_path.display());
+        return 1;
+    }
+    match load_settings(&db) {
+        Ok(mut settings) => {
+            if workspace.server_url.is_some() {
+                settings.remove("server_url");
+            }
+            config.apply_saved_settings(&settings);
+            // Join as invisible when the app would, so a send doesn't flash you online.
+            if let Some(invisible) = settings
+                .get("invisible")
+                .filter(|_| !config.invisible_from_env)
+            {
+                config.invisible = invisible == "1";
+            }
+        }
+        Err(err) => eprintln!("warning: could not load saved settings: {err}"),
+    }
+    let server_url = workspace.server_url.clone().unwrap_or(config.server_url.clone());
+    match load_channels(&db) {
+        Ok(channels) => match channels.iter().find(|channel| channel.id == args.channel_id) {
+            Some(channel) if channel.archived => {
+                eprintln!("#{} is archived; unarchive it in the app first", channel.name);
+                return 1;
+            }
+            Some(channel)
+                if load_channel_state(&db).is_ok_and(|state| {
+                    state.get(&channel.id).is_some_and(|state| state.read_only)
+                }) =>
+            {
+                eprintln!("#{} is read-only; posting is turned off there", channel.name);
+                return 1;
+            }
+            Some(_) => {}
+            None => {
+                eprintln!("no channel with id {}", args.channel_id);
+                return 1;
+            }
+        },
+        Err(err) => {
+            eprintln!("could not load channels: {err}");
+            return 1;
+        }
+    }
+
+    // Every attachment is required: report each one that fails, then post nothing.
+    let mut pending: Vec<PendingAttachment> = Vec::new();
+    let mut failed = false;
+    for path in &attach {
+        let ingested = config
+            .attachment_limits
+            .admit(&pending, path)
+            .and_then(|()| {
+                ingest_attachment(
+                    path,
+                    config.attachment_store.as_deref(),
+                    &config.attachment_kinds,
+                )
+            });
+        match ingested {
+            Ok(attachment) => pending.push(attachment),
+            Err(err) => {
+                eprintln!("{}: {err}", path.display());
+                failed = true;
+            }
+        }
+    }
+    if failed {
+        eprintln!("message not sent: some attachments could not be added");
+        return 1;
+    }
+
+    let mut message = Message {
+        id: 0,
+        author: config.identity.username.clone(),
+        body,
+        sent_at: format_timestamp_utc(),
+        channel_id: args.channel_id,
+        edited_at: None,
+    };
+    // Stored under the id it is broadcast with, so an app on this same database
+    // recognizes the broadcast and doesn't store it a second time. The start time
+    // keeps the id unique per run; a reused pid alone would look like a repeat.
+    let started = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_nanos();
+    let client_id = format!(
+        "{}-send-{started:x}-{:x}",
+        config.identity.username,
+        std::process::id()
+    );
+    message.id = match insert_message_from_client(&mut db, &message, &pending, &client_id, 0) {
+        Ok(id) => id,
+        Err(err) => {
+            eprintln!("could not store message: {err}");
+            return 1;
+        }
+    };
+    match broadcast(&server_url, &config, &message, &pending, &client_id) {
+        Ok(()) => println!("sent message {} to channel {}", message.id, message.channel_id),
+        Err(err) => {
+            println!("stored message {} in channel {}", message.id, message.channel_id);
+            eprintln!("warning: not broadcast to {server_url}: {err}");
+        }
+    }
+    0
+}
+
+/// Sends `message` over a short-lived realtime connection and waits for the server
+/// to ack it.
+fn broadcast(
+    server_url: &str,
+    config: &AppConfig,
+    message: &Message,
+    attachments: &[PendingAttachment],
+    client_id: &str,
+) -> Result<(), String> {
+    let url = Url::parse(server_url).map_err(|err| err.to_string())?;
+    let (mut socket, _response) = connect_with_config(url, Some(realtime_socket_config()), 3)
+        .map_err(|err| err.to_string())?;
+    if let MaybeTlsStream::Plain(stream) = socket.get_mut() {
+        let _ = stream.set_read_timeout(Some(ACK_TIMEOUT));
+    }
+    let status = if config.invisible { PresenceStatus::Offline } else { PresenceStatus::Online };
+    let auth = RealtimePayload::Auth {
+        token: config.identity.token.clone(),
+        user: config.identity.username.clone(),
+        protocol_version: PROTOCOL_VERSION,
+        status: status.label().to_string(),
+    };
+    let payloads = [
+        serde_json::to_string(&auth),
+        encode_realtime_message(
+            message,
+            pending_to_realtime_attachments(attachments),
+            client_id,
+            0,
+        ),
+    ];
+    for payload in payloads {
+        let payload = payload.map_err(|err| err.to_string())?;
+        socket
+            .send(WsMessage::Text(payload))
+            .map_err(|err| err.to_string())?;
+    }
+    let started = Instant::now();
+    let acked = loop {
+        if started.elapsed() >= ACK_TIMEOUT {
+            break false;
+        }
+        match socket.read() {
+            Ok(WsMessage::Text(text)) => match decode_realtime_inbound(&text) {
+                // Servers that don't echo which message they stored ack it plainly.
+                Ok(RealtimeInbound::Ack { kind, confirms, .. }) => match confirms {
+                    Some((acked_id, _, _)) if acked_id == client_id => break true,
+                    None if kind == "message" => break true,
+                    _ => {}
+                },
+                Ok(RealtimeInbound::AuthFailed(error)) => return Err(error),
+                _ => {}
+            },
+            Ok(_) => {}
+            Err(tungstenite::Error::Io(err))
+                if matches!(
+                    err.kind(),
+                    io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+                ) =>
+            {
+                break false;
+            }
+            Err(err) => return Err(err.to_string()),
+        }
+    };
+    let _ = socket.close(None);
+    if acked {
+        Ok(())
+    } else {
+        Err("the server didn't acknowledge the message".to_string())
+    }
+}